@@ -7,8 +7,12 @@ use martin::dev::{mock_function_sources, mock_state, mock_table_sources};
 use martin::server::router;
 
 fn criterion_benchmark(c: &mut Criterion) {
-    let state = test::run_on(|| mock_state(mock_table_sources(), mock_function_sources()));
-    let mut app = test::init_service(App::new().app_data(state).configure(router));
+    let mut sys = actix_rt::System::new("bench");
+
+    let mut app = sys.block_on(async {
+        let state = mock_state(mock_table_sources(), mock_function_sources(), false);
+        test::init_service(App::new().data(state).configure(router)).await
+    });
 
     c.bench_function("/public.table_source/0/0/0.pbf", |b| {
         b.iter(|| {
@@ -16,8 +20,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                 .uri("/public.table_source/0/0/0.pbf")
                 .to_request();
 
-            let future = test::run_on(|| app.call(req));
-            let _response = test::block_on(future).unwrap();
+            let _response = sys.block_on(app.call(req)).unwrap();
         })
     });
 
@@ -27,8 +30,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                 .uri("/rpc/public.function_source/0/0/0.pbf")
                 .to_request();
 
-            let future = test::run_on(|| app.call(req));
-            let _response = test::block_on(future).unwrap();
+            let _response = sys.block_on(app.call(req)).unwrap();
         })
     });
 }
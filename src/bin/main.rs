@@ -3,13 +3,21 @@ extern crate log;
 
 use docopt::Docopt;
 use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{env, io};
 
-use martin::config::{read_config, Config, ConfigBuilder};
-use martin::db::{check_postgis_version, get_connection, setup_connection_pool, Pool};
+use martin::composite_source::CompositeSource;
+use martin::config::{read_config, with_env_overrides, Config, ConfigBuilder, ListenAddresses};
+use martin::db::{
+    check_postgis_version, get_connection, setup_connection_pool, warm_up_pool, Pool,
+};
 use martin::function_source::get_function_sources;
+use martin::seed::{parse_bbox, seed, SeedOptions};
 use martin::server;
-use martin::table_source::get_table_sources;
+use martin::source::Xyz;
+use martin::table_source::{get_table_sources, TableSourceDefaults};
+use martin::telemetry::{init_tracing, shutdown_tracing};
 use martin::utils::prettify_error;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -20,24 +28,44 @@ Martin - PostGIS Mapbox Vector Tiles server.
 
 Usage:
   martin [options] [<connection>]
+  martin explain [options] <source> <z> <x> <y> [<connection>]
+  martin seed [options] --maxzoom=<n> <source> [<connection>]
   martin -h | --help
   martin -v | --version
 
 Options:
   -h --help                         Show this screen.
   -v --version                      Show version.
+  --analyze                         Run the tile query with EXPLAIN ANALYZE in explain.
+  --bbox=<bbox>                     Bounds of seeded tiles in degrees, west,south,east,north [default: -180,-85.05113,180,85.05113].
+  --check-config                    Validate the config, database connectivity and sources, then exit without starting the server.
   --config=<path>                   Path to config file.
   --keep-alive=<n>                  Connection keep alive timeout [default: 75].
-  --listen-addresses=<n>            The socket address to bind [default: 0.0.0.0:3000].
+  --minzoom=<n>                     Lowest zoom of seeded tiles [default: 0].
+  --maxzoom=<n>                     Highest zoom of seeded tiles.
+  --output=<dir>                    Directory seeded tiles are written to as {z}/{x}/{y}.pbf [default: tiles].
+  --listen-addresses=<n>            Comma-separated socket addresses or unix:/path sockets to bind [default: 0.0.0.0:3000].
   --pool-size=<n>                   Maximum connections pool size [default: 20].
   --watch                           Scan for new sources on sources list requests.
-  --workers=<n>                     Number of web server workers.
+  --workers=<n>                     Number of web server workers, 0 means one worker per CPU.
   --danger-accept-invalid-certs     Trust invalid certificates. This introduces significant vulnerabilities, and should only be used as a last resort.
 ";
 
 #[derive(Debug, Deserialize)]
 pub struct Args {
+    pub cmd_explain: bool,
+    pub cmd_seed: bool,
+    pub arg_source: Option<String>,
+    pub arg_z: Option<i32>,
+    pub arg_x: Option<i32>,
+    pub arg_y: Option<i32>,
     pub arg_connection: Option<String>,
+    pub flag_analyze: bool,
+    pub flag_bbox: Option<String>,
+    pub flag_check_config: bool,
+    pub flag_minzoom: Option<u8>,
+    pub flag_maxzoom: Option<u8>,
+    pub flag_output: Option<String>,
     pub flag_config: Option<String>,
     pub flag_help: bool,
     pub flag_keep_alive: Option<usize>,
@@ -49,32 +77,88 @@ pub struct Args {
     pub flag_danger_accept_invalid_certs: bool,
 }
 
-pub fn generate_config(args: Args, pool: &Pool) -> io::Result<Config> {
-    let connection_string = args.arg_connection.clone().ok_or_else(|| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            "Database connection string is not set",
-        )
-    })?;
+/// Config of the command-line arguments, without sources
+fn args_config(args: &Args) -> ConfigBuilder {
+    ConfigBuilder {
+        connection_string: args.arg_connection.clone().unwrap_or_default(),
+        read_replica_urls: None,
+        watch: Some(args.flag_watch),
+        watch_config: None,
+        notify_channel: None,
+        strict_source_validation: None,
+        default_extent: None,
+        default_buffer: None,
+        default_clip_geom: None,
+        geometry_type_sample_size: None,
+        keep_alive: args.flag_keep_alive,
+        client_request_timeout: None,
+        client_disconnect_timeout: None,
+        shutdown_timeout: None,
+        request_timeout_ms: None,
+        rate_limit: None,
+        tile_cache: None,
+        listen_addresses: args.flag_listen_addresses.clone().map(ListenAddresses::One),
+        tls: None,
+        grpc_listen_address: None,
+        static_files: None,
+        fonts_directory: None,
+        pool_size: args.flag_pool_size,
+        pool_timeout_ms: None,
+        worker_processes: args.flag_workers,
+        table_sources: None,
+        function_sources: None,
+        pmtiles_sources: None,
+        source_filter: None,
+        danger_accept_invalid_certs: Some(args.flag_danger_accept_invalid_certs),
+        debug_grid: None,
+        list_sources_on_not_found: None,
+        overzoom: None,
+        mvt_content_type: None,
+        empty_tile_mode: None,
+        allow_tile_param_overrides: None,
+        max_function_param_length: None,
+        max_tile_bytes: None,
+        min_allowed_zoom: None,
+        debug_feature_count: None,
+        zstd_level: None,
+        enable_table_sources: None,
+        enable_function_sources: None,
+        admin_token: None,
+        tenant_schemas: None,
+        geojson_tiles: None,
+        log_format: None,
+        max_age: None,
+        public_base_url: None,
+        trust_proxy_headers: None,
+        pool_test_on_check_out: None,
+        db_min_idle: None,
+        db_workers: None,
+        blocking_threads: None,
+        db_retries: None,
+        db_retry_backoff_ms: None,
+        db_search_path: None,
+        slow_query_ms: None,
+        circuit_breaker: None,
+        otlp_endpoint: None,
+    }
+}
 
+/// Config of the command-line arguments and the sources found in the database,
+/// with `FALCON_` environment variables applied over it
+pub fn generate_config(args: Args, pool: &Pool) -> io::Result<Config> {
     let mut connection = get_connection(pool)?;
-    let table_sources = get_table_sources(&mut connection)?;
+    let table_sources = get_table_sources(&mut connection, &TableSourceDefaults::default())?;
     let function_sources = get_function_sources(&mut connection)?;
 
     let config = ConfigBuilder {
-        connection_string,
-        watch: Some(args.flag_watch),
-        keep_alive: args.flag_keep_alive,
-        listen_addresses: args.flag_listen_addresses,
-        pool_size: args.flag_pool_size,
-        worker_processes: args.flag_workers,
         table_sources: Some(table_sources),
         function_sources: Some(function_sources),
-        danger_accept_invalid_certs: Some(args.flag_danger_accept_invalid_certs),
+        ..args_config(&args)
     };
 
-    let config = config.finalize();
-    Ok(config)
+    with_env_overrides(config, env::vars())?
+        .finalize()
+        .validate()
 }
 
 fn setup_from_config(file_name: String) -> io::Result<(Config, Pool)> {
@@ -83,7 +167,11 @@ fn setup_from_config(file_name: String) -> io::Result<(Config, Pool)> {
     let pool = setup_connection_pool(
         &config.connection_string,
         Some(config.pool_size),
+        Some(Duration::from_millis(config.pool_timeout_ms)),
         config.danger_accept_invalid_certs,
+        config.pool_test_on_check_out,
+        config.db_min_idle,
+        config.db_search_path.as_deref(),
     )
     .map_err(prettify_error("Can't setup connection pool"))?;
 
@@ -93,18 +181,24 @@ fn setup_from_config(file_name: String) -> io::Result<(Config, Pool)> {
 }
 
 fn setup_from_args(args: Args) -> io::Result<(Config, Pool)> {
-    let connection_string = args.arg_connection.clone().ok_or_else(|| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            "Database connection string is not set",
-        )
-    })?;
+    // The database settings can be set with `FALCON_` variables as well
+    let config = with_env_overrides(args_config(&args), env::vars())
+        .map_err(prettify_error("Can't read environment variables"))?
+        .finalize();
+
+    if config.connection_string.is_empty() {
+        return Err(io::Error::other("Database connection string is not set"));
+    }
 
     info!("Connecting to database");
     let pool = setup_connection_pool(
-        &connection_string,
-        args.flag_pool_size,
-        args.flag_danger_accept_invalid_certs,
+        &config.connection_string,
+        Some(config.pool_size),
+        Some(Duration::from_millis(config.pool_timeout_ms)),
+        config.danger_accept_invalid_certs,
+        config.pool_test_on_check_out,
+        config.db_min_idle,
+        config.db_search_path.as_deref(),
     )
     .map_err(prettify_error("Can't setup connection pool"))?;
 
@@ -132,19 +226,201 @@ fn parse_env(args: Args) -> Args {
     }
 }
 
-fn start(args: Args) -> io::Result<actix::SystemRunner> {
-    info!("Starting martin v{}", VERSION);
-
-    let (config, pool) = match args.flag_config {
+fn setup(args: Args) -> io::Result<(Config, Pool)> {
+    match args.flag_config {
         Some(config_file_name) => {
             info!("Using {}", config_file_name);
-            setup_from_config(config_file_name)?
+            setup_from_config(config_file_name)
         }
         None => {
             info!("Config is not set");
-            setup_from_args(args)?
+            setup_from_args(args)
         }
+    }
+}
+
+/// Table or composite source of comma-separated table source ids
+fn composite_source(config: &Config, source_ids: String) -> io::Result<CompositeSource> {
+    let table_sources = config.table_sources.clone().unwrap_or_default();
+
+    let sources = source_ids
+        .split(',')
+        .map(|source_id| {
+            table_sources
+                .get(source_id)
+                .map(|source| source.as_ref().clone())
+                .ok_or_else(|| io::Error::other(format!("Table source '{}' not found", source_id)))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(CompositeSource {
+        id: source_ids,
+        table_sources: sources,
+        count_features: false,
+    })
+}
+
+/// Writes the tiles of a function, table or composite source within the bbox to a directory
+fn seed_tiles(args: Args) -> io::Result<()> {
+    let source_id = args.arg_source.clone().unwrap_or_default();
+    let bbox = parse_bbox(args.flag_bbox.as_deref().unwrap_or_default())?;
+    let output = PathBuf::from(args.flag_output.clone().unwrap_or_default());
+    let mut minzoom = args.flag_minzoom.unwrap_or_default();
+    let mut maxzoom = args.flag_maxzoom.unwrap_or_default();
+
+    let (config, pool) = setup(args)?;
+
+    let function_source = config
+        .function_sources
+        .as_ref()
+        .and_then(|function_sources| function_sources.get(&source_id));
+
+    // Function sources have no tiles outside of their zoom range
+    if let Some(source) = function_source {
+        minzoom = minzoom.max(source.minzoom.unwrap_or(minzoom));
+        maxzoom = maxzoom.min(source.maxzoom.unwrap_or(maxzoom));
+    }
+
+    if minzoom > maxzoom {
+        return Err(io::Error::other(format!(
+            "No zooms to seed between minzoom {} and maxzoom {}",
+            minzoom, maxzoom
+        )));
+    }
+
+    let options = SeedOptions {
+        minzoom,
+        maxzoom,
+        bbox,
+        output,
+        concurrency: pool.max_size() as usize,
+    };
+
+    match function_source {
+        Some(source) => seed(source.as_ref(), &pool, &options)?,
+        None => seed(&composite_source(&config, source_id)?, &pool, &options)?,
+    };
+
+    Ok(())
+}
+
+/// Prints the tile query of the table or composite source, as run by the tile handler
+fn explain(args: Args) -> io::Result<()> {
+    let source_ids = args.arg_source.clone().unwrap_or_default();
+    let requested_xyz = Xyz {
+        z: args.arg_z.unwrap_or_default(),
+        x: args.arg_x.unwrap_or_default(),
+        y: args.arg_y.unwrap_or_default(),
     };
+    let analyze = args.flag_analyze;
+
+    let (config, pool) = setup(args)?;
+    let source = composite_source(&config, source_ids)?;
+    let xyz = source.scheme().to_xyz(&requested_xyz);
+
+    println!("{};", source.tile_query(&xyz).to_inline_sql());
+
+    if analyze {
+        let mut connection = get_connection(&pool)?;
+        let plan = source
+            .explain_tile_query(&mut connection, &xyz, true)
+            .map_err(prettify_error("Can't explain tile query"))?;
+
+        println!();
+        println!("{}", plan);
+    }
+
+    Ok(())
+}
+
+/// Logs configured sources missing in the database, returns whether all of them exist
+fn validate_sources(config: &Config, pool: &Pool) -> io::Result<bool> {
+    let mut connection = get_connection(pool)?;
+    let mut valid = true;
+
+    for source in config
+        .table_sources
+        .iter()
+        .flat_map(|sources| sources.values())
+    {
+        if let Err(error) = source.validate(&mut connection) {
+            error!("{}", error);
+            valid = false;
+        }
+    }
+
+    for source in config
+        .function_sources
+        .iter()
+        .flat_map(|sources| sources.values())
+    {
+        if let Err(error) = source.validate(&mut connection) {
+            error!("{}", error);
+            valid = false;
+        }
+    }
+
+    Ok(valid)
+}
+
+/// Reads PMTiles archive headers and root directories ahead of the first tile request,
+/// archives that can't be read yet are opened again on requests
+fn open_pmtiles_sources(config: &Config) {
+    for source in config
+        .pmtiles_sources
+        .iter()
+        .flat_map(|sources| sources.values())
+    {
+        if let Err(error) = source.open() {
+            error!("Can't open PMTiles archive {}: {}", source.id, error);
+        }
+    }
+}
+
+/// Validates the config, connects to the primary and read replica databases and checks
+/// that the PostGIS version matches and all configured sources exist
+fn check_config(args: Args) -> io::Result<()> {
+    let (config, pool) = setup(args)?;
+
+    get_connection(&pool).map_err(prettify_error("Can't connect to database"))?;
+
+    let matches = check_postgis_version(REQUIRED_POSTGIS_VERSION, &pool)
+        .map_err(prettify_error("Can't check PostGIS version"))?;
+    if !matches {
+        return Err(io::Error::other("PostGIS version doesn't match"));
+    }
+
+    let valid =
+        validate_sources(&config, &pool).map_err(prettify_error("Can't validate sources"))?;
+    if !valid {
+        return Err(io::Error::other(
+            "Configured sources are missing in the database",
+        ));
+    }
+
+    for replica_url in config.read_replica_urls.iter().flatten() {
+        let replica_pool = setup_connection_pool(
+            replica_url,
+            Some(1),
+            Some(Duration::from_millis(config.pool_timeout_ms)),
+            config.danger_accept_invalid_certs,
+            config.pool_test_on_check_out,
+            Some(0),
+            config.db_search_path.as_deref(),
+        )
+        .map_err(prettify_error("Can't setup read replica connection pool"))?;
+
+        get_connection(&replica_pool).map_err(prettify_error("Can't connect to read replica"))?;
+    }
+
+    Ok(())
+}
+
+fn start(args: Args) -> io::Result<actix::SystemRunner> {
+    info!("Starting martin v{}", VERSION);
+
+    let config_file = args.flag_config.clone();
+    let (config, pool) = setup(args)?;
 
     let matches = check_postgis_version(REQUIRED_POSTGIS_VERSION, &pool)
         .map_err(prettify_error("Can't check PostGIS version"))?;
@@ -153,9 +429,55 @@ fn start(args: Args) -> io::Result<actix::SystemRunner> {
         std::process::exit(-1);
     }
 
+    if config_file.is_some() {
+        let valid =
+            validate_sources(&config, &pool).map_err(prettify_error("Can't validate sources"))?;
+
+        if !valid && config.strict_source_validation {
+            return Err(io::Error::other(
+                "Configured sources are missing in the database",
+            ));
+        }
+    }
+
+    open_pmtiles_sources(&config);
+
+    // Source listings scan the database in watch mode
+    if config.watch {
+        warm_up_pool(&pool, &config).map_err(prettify_error("Can't warm up connection pool"))?;
+    }
+
+    if let Some(otlp_endpoint) = &config.otlp_endpoint {
+        info!("Exporting traces to {}", otlp_endpoint);
+        init_tracing(otlp_endpoint).map_err(prettify_error("Can't initialize tracing"))?;
+    }
+
+    let mut replica_pools = Vec::new();
+    for replica_url in config.read_replica_urls.iter().flatten() {
+        let replica_pool = setup_connection_pool(
+            replica_url,
+            Some(config.pool_size),
+            Some(Duration::from_millis(config.pool_timeout_ms)),
+            config.danger_accept_invalid_certs,
+            config.pool_test_on_check_out,
+            config.db_min_idle,
+            config.db_search_path.as_deref(),
+        )
+        .map_err(prettify_error("Can't setup read replica connection pool"))?;
+
+        replica_pools.push(replica_pool);
+    }
+
+    if !replica_pools.is_empty() {
+        info!("Serving tiles from {} read replicas", replica_pools.len());
+    }
+
     let listen_addresses = config.listen_addresses.clone();
-    let server = server::new(pool, config);
-    info!("Martin has been started on {}.", listen_addresses);
+    let server = server::new(pool, replica_pools, config, config_file);
+    info!(
+        "Martin has been started on {}.",
+        listen_addresses.join(", ")
+    );
 
     Ok(server)
 }
@@ -184,6 +506,34 @@ fn main() -> io::Result<()> {
         warn!("Danger accept invalid certs enabled. You should think very carefully before using this option. If invalid certificates are trusted, any certificate for any site will be trusted for use. This includes expired certificates. This introduces significant vulnerabilities, and should only be used as a last resort.");
     }
 
+    if args.cmd_seed {
+        if let Err(error) = seed_tiles(args) {
+            error!("{}", error);
+            std::process::exit(-1);
+        }
+
+        return Ok(());
+    }
+
+    if args.flag_check_config {
+        if let Err(error) = check_config(args) {
+            error!("{}", error);
+            std::process::exit(-1);
+        }
+
+        info!("Config is valid");
+        return Ok(());
+    }
+
+    if args.cmd_explain {
+        if let Err(error) = explain(args) {
+            error!("{}", error);
+            std::process::exit(-1);
+        }
+
+        return Ok(());
+    }
+
     if args.flag_watch {
         info!("Watch mode enabled");
     }
@@ -196,5 +546,7 @@ fn main() -> io::Result<()> {
         }
     };
 
-    server.run()
+    let result = server.run();
+    shutdown_tracing();
+    result
 }
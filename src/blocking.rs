@@ -0,0 +1,98 @@
+use actix_web::error::BlockingError;
+use futures::channel::oneshot;
+use std::fmt::Debug;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+
+/// Threads per CPU without `blocking_threads`, the same as the actix blocking pool
+const DEFAULT_THREADS_PER_CPU: usize = 5;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static POOL: OnceLock<BlockingPool> = OnceLock::new();
+
+/// Threads running blocking file reads off the web server workers, e.g. of the tile
+/// cache and PMTiles archives. Sized by `blocking_threads` instead of `ACTIX_THREADPOOL`.
+pub struct BlockingPool {
+    sender: mpsc::Sender<Job>,
+}
+
+impl BlockingPool {
+    pub fn new(threads: usize) -> BlockingPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for index in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("blocking-{}", index))
+                .spawn(move || loop {
+                    // The lock is released before the job runs
+                    let job = receiver
+                        .lock()
+                        .unwrap_or_else(|error| error.into_inner())
+                        .recv();
+
+                    match job {
+                        // A panicking job cancels its caller, the thread keeps running
+                        Ok(job) => drop(panic::catch_unwind(AssertUnwindSafe(job))),
+                        Err(_) => break,
+                    }
+                })
+                .unwrap_or_else(|error| panic!("Can't start blocking thread: {}", error));
+        }
+
+        BlockingPool { sender }
+    }
+
+    /// Runs `f` on a pool thread, the same as `web::block`
+    pub fn run<F, T, E>(&self, f: F) -> impl Future<Output = Result<T, BlockingError<E>>>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Debug + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            // The caller may be gone
+            let _ = sender.send(f());
+        });
+        let sent = self.sender.send(job).is_ok();
+
+        async move {
+            if !sent {
+                return Err(BlockingError::Canceled);
+            }
+
+            match receiver.await {
+                Ok(Ok(value)) => Ok(value),
+                Ok(Err(error)) => Err(BlockingError::Error(error)),
+                Err(_) => Err(BlockingError::Canceled),
+            }
+        }
+    }
+}
+
+/// Starts the shared pool with `threads` threads, returns `false` if it already runs
+pub fn init(threads: usize) -> bool {
+    let mut initialized = false;
+    POOL.get_or_init(|| {
+        initialized = true;
+        BlockingPool::new(threads)
+    });
+
+    initialized
+}
+
+/// Runs `f` on the shared pool, started with 5 threads per CPU unless `init` was called
+pub fn run<F, T, E>(f: F) -> impl Future<Output = Result<T, BlockingError<E>>>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Debug + Send + 'static,
+{
+    POOL.get_or_init(|| BlockingPool::new(num_cpus::get() * DEFAULT_THREADS_PER_CPU))
+        .run(f)
+}
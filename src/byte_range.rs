@@ -0,0 +1,63 @@
+use std::ops::Range;
+
+/// Result of matching a `Range` request header against a body length
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range, the full body is returned with `200 OK`
+    Full,
+    /// `206 Partial Content` with the given byte range, the end is exclusive
+    Partial(Range<usize>),
+    /// `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=start-end`, `bytes=start-` or `bytes=-suffix` range.
+/// Malformed and multiple ranges are ignored, as allowed by RFC 7233.
+pub fn parse_range(header: &str, length: usize) -> ByteRange {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => return ByteRange::Full,
+    };
+
+    let range = match (start.is_empty(), end.is_empty()) {
+        // Suffix range, i.e. the last `end` bytes
+        (true, false) => match end.parse::<usize>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => length.saturating_sub(suffix)..length,
+            Err(_) => return ByteRange::Full,
+        },
+        (false, _) => {
+            let start: usize = match start.parse() {
+                Ok(start) => start,
+                Err(_) => return ByteRange::Full,
+            };
+
+            let end = if end.is_empty() {
+                length
+            } else {
+                match end.parse::<usize>() {
+                    Ok(end) if end >= start => end.saturating_add(1).min(length),
+                    _ => return ByteRange::Full,
+                }
+            };
+
+            if start >= length {
+                return ByteRange::Unsatisfiable;
+            }
+
+            start..end
+        }
+        (true, true) => return ByteRange::Full,
+    };
+
+    if range.is_empty() {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(range)
+}
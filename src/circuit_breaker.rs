@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Thresholds of the database circuit breaker
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive connection failures opening the circuit [default: 5]
+    pub failure_threshold: Option<u32>,
+    /// Time in milliseconds the circuit stays open before a probe query [default: 10000]
+    pub cooldown_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Queries are run
+    Closed,
+    /// Queries fail fast until the cooldown is over
+    Open,
+    /// A single probe query is run, the others fail fast
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+struct Circuit {
+    state: CircuitState,
+    failures: u32,
+    opened_at: Instant,
+}
+
+/// Fails database queries fast after `failure_threshold` consecutive connection
+/// failures, so an unavailable database isn't hammered with reconnection attempts.
+/// Shared between database actor threads.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold: config.failure_threshold.unwrap_or(5).max(1),
+            cooldown: Duration::from_millis(config.cooldown_ms.unwrap_or(10_000)),
+            circuit: Arc::new(Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.lock().state
+    }
+
+    /// Allows a query, or returns how long the circuit stays open
+    pub fn check(&self) -> Result<(), Duration> {
+        self.check_at(Instant::now())
+    }
+
+    pub fn check_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut circuit = self.lock();
+
+        match circuit.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                let elapsed = now.saturating_duration_since(circuit.opened_at);
+                if elapsed < self.cooldown {
+                    return Err(self.cooldown - elapsed);
+                }
+
+                // This query is the probe
+                circuit.state = CircuitState::HalfOpen;
+                Ok(())
+            }
+            CircuitState::HalfOpen => Err(self.cooldown),
+        }
+    }
+
+    /// Records the outcome of an allowed query, `connection_failed` for errors
+    /// caused by an unavailable database
+    pub fn record(&self, connection_failed: bool) {
+        self.record_at(connection_failed, Instant::now())
+    }
+
+    pub fn record_at(&self, connection_failed: bool, now: Instant) {
+        let mut circuit = self.lock();
+
+        if !connection_failed {
+            if circuit.state != CircuitState::Closed {
+                info!("Database is available again, closing the circuit breaker");
+            }
+
+            circuit.state = CircuitState::Closed;
+            circuit.failures = 0;
+            return;
+        }
+
+        circuit.failures = circuit.failures.saturating_add(1);
+
+        let opens = match circuit.state {
+            CircuitState::Closed => circuit.failures >= self.failure_threshold,
+            CircuitState::Open | CircuitState::HalfOpen => true,
+        };
+
+        if opens {
+            if circuit.state == CircuitState::Closed {
+                warn!(
+                    "Opening the database circuit breaker after {} connection failures, failing queries for {:?}",
+                    circuit.failures, self.cooldown
+                );
+            }
+
+            circuit.state = CircuitState::Open;
+            circuit.opened_at = now;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
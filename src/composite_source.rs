@@ -1,10 +1,12 @@
 use itertools::Itertools;
+use serde_json::{Map, Value};
 use std::io;
+use std::time::{Duration, UNIX_EPOCH};
 
 use tilejson::{TileJSON, TileJSONBuilder};
 
 use crate::db::Connection;
-use crate::source::{Query, Source, Tile, Xyz};
+use crate::source::{Query, Scheme, Source, Tile, TileData, TileQuery, Xyz};
 use crate::table_source::TableSource;
 use crate::utils;
 
@@ -12,41 +14,145 @@ use crate::utils;
 pub struct CompositeSource {
     pub id: String,
     pub table_sources: Vec<TableSource>,
+    /// Also count the tile features, see `debug_feature_count`
+    pub count_features: bool,
 }
 
 impl CompositeSource {
-    fn get_bounds_cte(&self, xyz: &Xyz) -> String {
+    /// `tms` only if all sources use it, mixed sources are served as `xyz`
+    pub fn scheme(&self) -> Scheme {
+        let all_tms = self
+            .table_sources
+            .iter()
+            .all(|source| source.scheme == Some(Scheme::Tms));
+
+        if all_tms {
+            Scheme::Tms
+        } else {
+            Scheme::Xyz
+        }
+    }
+
+    fn get_bounds_cte(&self) -> String {
         let srid_bounds: String = self
             .table_sources
             .clone()
             .into_iter()
             .map(|source| source.srid)
             .unique()
-            .map(|srid| utils::get_srid_bounds(srid, xyz))
+            .map(utils::get_srid_bounds)
             .collect::<Vec<String>>()
             .join(", ");
 
         utils::get_bounds_cte(srid_bounds)
     }
 
-    fn get_tile_query(&self, xyz: &Xyz) -> String {
+    fn get_tile_query(&self) -> String {
         let tile_query: String = self
             .table_sources
-            .clone()
-            .into_iter()
-            .map(|source| format!("({})", source.get_tile_query(xyz)))
+            .iter()
+            .map(|source| format!("({})", source.get_tile_query()))
             .collect::<Vec<String>>()
             .join(" || ");
 
-        format!("SELECT {} AS tile", tile_query)
+        let truncated_queries: Vec<String> = self
+            .table_sources
+            .iter()
+            .filter_map(|source| source.get_truncated_query())
+            .map(|query| format!("({})", query))
+            .collect();
+
+        let truncated_query = if truncated_queries.is_empty() {
+            "false".to_string()
+        } else {
+            truncated_queries.join(" OR ")
+        };
+
+        // The tile is as old as its latest feature only if all of the sources track updates
+        let updated_at_queries: Option<Vec<String>> = self
+            .table_sources
+            .iter()
+            .map(|source| {
+                source
+                    .get_updated_at_query()
+                    .map(|query| format!("({})", query))
+            })
+            .collect();
+
+        let updated_at_query = match updated_at_queries {
+            Some(queries) if !queries.is_empty() => format!("GREATEST({})", queries.join(", ")),
+            _ => "NULL::float8".to_string(),
+        };
+
+        let feature_count_query = if self.count_features {
+            self.table_sources
+                .iter()
+                .map(|source| format!("({})", source.get_feature_count_query()))
+                .collect::<Vec<String>>()
+                .join(" + ")
+        } else {
+            "NULL::int8".to_string()
+        };
+
+        format!(
+            "SELECT {} AS tile, {} AS truncated, {} AS updated_at, {} AS feature_count",
+            tile_query, truncated_query, updated_at_query, feature_count_query
+        )
     }
 
-    pub fn build_tile_query(&self, xyz: &Xyz) -> String {
-        let bounds_cte = self.get_bounds_cte(xyz);
-        let tile_query = self.get_tile_query(xyz);
+    pub fn build_tile_query(&self) -> String {
+        let bounds_cte = self.get_bounds_cte();
+        let tile_query = self.get_tile_query();
 
         format!("{} {}", bounds_cte, tile_query)
     }
+
+    pub fn tile_query(&self, xyz: &Xyz) -> TileQuery {
+        TileQuery::new(self.build_tile_query(), xyz)
+    }
+
+    /// `EXPLAIN` output of the tile query, `analyze` runs the query
+    pub fn explain_tile_query(
+        &self,
+        conn: &mut Connection,
+        xyz: &Xyz,
+        analyze: bool,
+    ) -> Result<String, io::Error> {
+        let options = if analyze { "(ANALYZE, BUFFERS)" } else { "" };
+        let query = format!(
+            "EXPLAIN {} {}",
+            options,
+            self.tile_query(xyz).to_inline_sql()
+        );
+
+        let rows = conn.query(query.as_str(), &[]).map_err(io::Error::other)?;
+        let plan: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+
+        Ok(plan.join("\n"))
+    }
+}
+
+impl CompositeSource {
+    /// MVT extent and buffer of the tiles, when all of the sources agree on them
+    pub fn tile_extent_and_buffer(&self) -> Option<(u32, u32)> {
+        let mut sources = self
+            .table_sources
+            .iter()
+            .map(|source| (source.tile_extent(), source.tile_buffer()));
+
+        let first = sources.next()?;
+        sources.all(|other| other == first).then_some(first)
+    }
+
+    /// Whether tiles have details a cached tile can't report: `Last-Modified`
+    /// of `updated_at_column`, truncation by `feature_limit` or the feature count
+    pub fn has_tile_details(&self) -> bool {
+        self.count_features
+            || self
+                .table_sources
+                .iter()
+                .any(|source| source.updated_at_column.is_some() || source.feature_limit.is_some())
+    }
 }
 
 impl Source for CompositeSource {
@@ -57,25 +163,73 @@ impl Source for CompositeSource {
     fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
         let mut tilejson_builder = TileJSONBuilder::new();
 
-        tilejson_builder.scheme("xyz");
+        tilejson_builder.scheme(self.scheme().as_str());
         tilejson_builder.name(&self.id);
 
         Ok(tilejson_builder.finalize())
     }
 
+    /// Extension keys all of the sources agree on, and `vector_layers` of all of them
+    fn get_tilejson_extensions(&self) -> Map<String, Value> {
+        let sources: Vec<Map<String, Value>> = self
+            .table_sources
+            .iter()
+            .map(|source| source.get_tilejson_extensions())
+            .collect();
+
+        let mut extensions = sources.first().cloned().unwrap_or_default();
+        for source_extensions in sources.iter().skip(1) {
+            extensions = extensions
+                .into_iter()
+                .filter(|(key, value)| source_extensions.get(key) == Some(value))
+                .collect();
+        }
+
+        let vector_layers: Vec<Value> = sources
+            .iter()
+            .filter_map(|source_extensions| source_extensions.get("vector_layers"))
+            .filter_map(Value::as_array)
+            .flatten()
+            .cloned()
+            .collect();
+
+        extensions.remove("vector_layers");
+        if !vector_layers.is_empty() {
+            extensions.insert("vector_layers".to_owned(), Value::from(vector_layers));
+        }
+
+        extensions
+    }
+
     fn get_tile(
         &self,
         conn: &mut Connection,
         xyz: &Xyz,
-        _query: &Option<Query>,
+        query: &Option<Query>,
     ) -> Result<Tile, io::Error> {
-        let tile_query = self.build_tile_query(xyz);
-
-        let tile: Tile = conn
-            .query_one(tile_query.as_str(), &[])
-            .map(|row| row.get("tile"))
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        let tile_data = self.get_tile_data(conn, xyz, query)?;
+        Ok(tile_data.tile)
+    }
 
-        Ok(tile)
+    fn get_tile_data(
+        &self,
+        conn: &mut Connection,
+        xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<TileData, io::Error> {
+        let row = self.tile_query(xyz).query_one(conn)?;
+
+        let last_modified = row
+            .get::<_, Option<f64>>("updated_at")
+            .filter(|updated_at| updated_at.is_finite() && *updated_at >= 0.0)
+            // HTTP dates have a precision of seconds
+            .map(|updated_at| UNIX_EPOCH + Duration::from_secs(updated_at as u64));
+
+        Ok(TileData {
+            tile: row.get("tile"),
+            truncated: row.get("truncated"),
+            last_modified,
+            feature_count: row.get("feature_count"),
+        })
     }
 }
@@ -1,62 +1,629 @@
+use actix_web::http;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 
-use crate::function_source::FunctionSources;
-use crate::table_source::TableSources;
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::db::DEFAULT_CONNECTION_TIMEOUT;
+use crate::db_actor::{DEFAULT_DB_WORKERS, MAX_DB_RETRIES};
+use crate::function_source::{FunctionSources, DEFAULT_MAX_PARAM_LENGTH};
+use crate::pmtiles_source::PmtilesSources;
+use crate::rate_limiter::RateLimit;
+use crate::request_log::LogFormat;
+use crate::source::EmptyTileMode;
+use crate::source_filter::SourceFilter;
+use crate::static_files::StaticFiles;
+use crate::table_source::{
+    apply_public_ids, TableSourceDefaults, TableSources, DEFAULT_BUFFER, DEFAULT_CLIP_GEOM,
+    DEFAULT_EXTENT, TILE_PIXELS,
+};
+use crate::tenant::TenantSchemas;
+use crate::tile_cache::TileCacheConfig;
+use crate::tls::TlsConfig;
+
+pub const DEFAULT_MVT_CONTENT_TYPE: &str = "application/x-protobuf";
+pub const DEFAULT_MAX_AGE: u32 = 3600;
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:3000";
+/// Same as the actix-web defaults
+pub const DEFAULT_CLIENT_TIMEOUT_MS: u64 = 5000;
+/// Upper bound of web server workers per CPU
+pub const MAX_WORKER_PROCESSES_PER_CPU: usize = 4;
+/// Default `zstd` level, balancing the ratio and the speed
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+/// Highest `zstd` compression level
+pub const MAX_ZSTD_LEVEL: i32 = 22;
+
+/// A single address, possibly comma-separated, or a list of addresses
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListenAddresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ListenAddresses {
+    pub fn into_vec(self) -> Vec<String> {
+        let addresses = match self {
+            ListenAddresses::One(addresses) => addresses
+                .split(',')
+                .map(|address| address.trim().to_owned())
+                .collect(),
+            ListenAddresses::Many(addresses) => addresses,
+        };
+
+        addresses
+            .into_iter()
+            .filter(|address| !address.is_empty())
+            .collect()
+    }
+}
 
 #[derive(Clone, Debug, Serialize)]
 pub struct Config {
     pub watch: bool,
+    /// Reload sources when the config file changes
+    pub watch_config: bool,
+    pub strict_source_validation: bool,
+    pub notify_channel: Option<String>,
     pub pool_size: u32,
+    pub pool_timeout_ms: u64,
     pub keep_alive: usize,
+    pub client_request_timeout: u64,
+    pub client_disconnect_timeout: u64,
+    pub shutdown_timeout: u64,
+    pub request_timeout_ms: u64,
+    pub rate_limit: Option<RateLimit>,
+    pub tile_cache: TileCacheConfig,
     pub worker_processes: usize,
-    pub listen_addresses: String,
+    pub listen_addresses: Vec<String>,
+    pub tls: Option<TlsConfig>,
+    pub grpc_listen_address: Option<String>,
+    pub static_files: Vec<StaticFiles>,
+    pub fonts_directory: Option<String>,
     pub connection_string: String,
+    pub read_replica_urls: Option<Vec<String>>,
     pub table_sources: Option<TableSources>,
     pub function_sources: Option<FunctionSources>,
+    pub pmtiles_sources: Option<PmtilesSources>,
+    pub default_extent: u32,
+    pub default_buffer: u32,
+    pub default_clip_geom: bool,
+    /// Rows sampled for the geometry types of discovered `GEOMETRY` columns
+    pub geometry_type_sample_size: Option<u32>,
+    pub source_filter: SourceFilter,
     pub danger_accept_invalid_certs: bool,
+    pub debug_grid: bool,
+    pub list_sources_on_not_found: bool,
+    pub overzoom: bool,
+    pub mvt_content_type: String,
+    pub empty_tile_mode: EmptyTileMode,
+    pub allow_tile_param_overrides: bool,
+    pub max_function_param_length: usize,
+    pub max_tile_bytes: Option<usize>,
+    pub min_allowed_zoom: Option<u8>,
+    pub debug_feature_count: bool,
+    /// Compression level of `zstd` encoded tiles, `0` disables `zstd` encoding
+    pub zstd_level: i32,
+    pub enable_table_sources: bool,
+    pub enable_function_sources: bool,
+    /// Bearer token of the admin endpoints, they are disabled without one
+    pub admin_token: Option<String>,
+    /// Tenant schemas selected by a request header, tenants are disabled without it
+    pub tenant_schemas: Option<TenantSchemas>,
+    pub geojson_tiles: bool,
+    pub log_format: LogFormat,
+    pub max_age: u32,
+    pub public_base_url: Option<String>,
+    pub trust_proxy_headers: bool,
+    pub pool_test_on_check_out: bool,
+    /// Connections opened on startup and kept idle, `None` means `pool_size`
+    pub db_min_idle: Option<u32>,
+    /// Database actor threads running tile queries, per database (primary and each replica)
+    pub db_workers: usize,
+    /// Threads of the blocking pool, e.g. for tile cache and PMTiles reads,
+    /// `None` starts 5 per CPU, see `blocking::init`
+    pub blocking_threads: Option<usize>,
+    pub db_retries: u32,
+    pub db_retry_backoff_ms: u64,
+    /// `search_path` schemas set on each database connection
+    pub db_search_path: Option<Vec<String>>,
+    /// Tile queries taking longer are logged at WARN, `None` disables the log
+    pub slow_query_ms: Option<u64>,
+    /// Fails queries fast while the database is unavailable, disabled without it
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    pub fn table_source_defaults(&self) -> TableSourceDefaults {
+        TableSourceDefaults {
+            extent: self.default_extent,
+            buffer: self.default_buffer,
+            clip_geom: self.default_clip_geom,
+            geometry_type_sample_size: self.geometry_type_sample_size,
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ConfigBuilder {
     pub watch: Option<bool>,
+    pub watch_config: Option<bool>,
+    pub strict_source_validation: Option<bool>,
+    pub notify_channel: Option<String>,
     pub pool_size: Option<u32>,
+    pub pool_timeout_ms: Option<u64>,
     pub keep_alive: Option<usize>,
+    pub client_request_timeout: Option<u64>,
+    pub client_disconnect_timeout: Option<u64>,
+    pub shutdown_timeout: Option<u64>,
+    pub request_timeout_ms: Option<u64>,
+    pub rate_limit: Option<RateLimit>,
+    pub tile_cache: Option<TileCacheConfig>,
     pub worker_processes: Option<usize>,
-    pub listen_addresses: Option<String>,
+    pub listen_addresses: Option<ListenAddresses>,
+    pub tls: Option<TlsConfig>,
+    pub grpc_listen_address: Option<String>,
+    pub static_files: Option<Vec<StaticFiles>>,
+    pub fonts_directory: Option<String>,
     pub connection_string: String,
+    pub read_replica_urls: Option<Vec<String>>,
     pub table_sources: Option<TableSources>,
     pub function_sources: Option<FunctionSources>,
+    pub pmtiles_sources: Option<PmtilesSources>,
+    pub default_extent: Option<u32>,
+    pub default_buffer: Option<u32>,
+    pub default_clip_geom: Option<bool>,
+    pub geometry_type_sample_size: Option<u32>,
+    pub source_filter: Option<SourceFilter>,
     pub danger_accept_invalid_certs: Option<bool>,
+    pub debug_grid: Option<bool>,
+    pub list_sources_on_not_found: Option<bool>,
+    pub overzoom: Option<bool>,
+    pub mvt_content_type: Option<String>,
+    pub empty_tile_mode: Option<EmptyTileMode>,
+    pub allow_tile_param_overrides: Option<bool>,
+    pub max_function_param_length: Option<usize>,
+    pub max_tile_bytes: Option<usize>,
+    pub min_allowed_zoom: Option<u8>,
+    pub debug_feature_count: Option<bool>,
+    pub zstd_level: Option<i32>,
+    pub enable_table_sources: Option<bool>,
+    pub enable_function_sources: Option<bool>,
+    pub admin_token: Option<String>,
+    pub tenant_schemas: Option<TenantSchemas>,
+    pub geojson_tiles: Option<bool>,
+    pub log_format: Option<LogFormat>,
+    pub max_age: Option<u32>,
+    pub public_base_url: Option<String>,
+    pub trust_proxy_headers: Option<bool>,
+    pub pool_test_on_check_out: Option<bool>,
+    pub db_min_idle: Option<u32>,
+    pub db_workers: Option<usize>,
+    pub blocking_threads: Option<usize>,
+    pub db_retries: Option<u32>,
+    pub db_retry_backoff_ms: Option<u64>,
+    pub db_search_path: Option<Vec<String>>,
+    pub slow_query_ms: Option<u64>,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub otlp_endpoint: Option<String>,
 }
 
 impl ConfigBuilder {
     pub fn finalize(self) -> Config {
+        let source_filter = self.source_filter.unwrap_or_default();
+
+        let table_source_defaults = TableSourceDefaults {
+            extent: self.default_extent.unwrap_or(DEFAULT_EXTENT),
+            buffer: self.default_buffer.unwrap_or(DEFAULT_BUFFER),
+            clip_geom: self.default_clip_geom.unwrap_or(DEFAULT_CLIP_GEOM),
+            geometry_type_sample_size: self.geometry_type_sample_size,
+        };
+
+        // Sources of disabled kinds are dropped, their routes aren't registered
+        let enable_table_sources = self.enable_table_sources.unwrap_or(true);
+        let enable_function_sources = self.enable_function_sources.unwrap_or(true);
+
+        let mut table_sources = self.table_sources.filter(|_| enable_table_sources);
+        if let Some(table_sources) = table_sources.as_mut() {
+            source_filter.apply(table_sources);
+
+            for source in table_sources.values_mut() {
+                table_source_defaults.apply(source);
+            }
+        }
+
+        let mut function_sources = self.function_sources.filter(|_| enable_function_sources);
+        if let Some(function_sources) = function_sources.as_mut() {
+            source_filter.apply(function_sources);
+        }
+
         Config {
             watch: self.watch.unwrap_or(false),
+            watch_config: self.watch_config.unwrap_or(false),
+            strict_source_validation: self.strict_source_validation.unwrap_or(false),
+            notify_channel: self.notify_channel,
             pool_size: self.pool_size.unwrap_or(20),
+            pool_timeout_ms: self
+                .pool_timeout_ms
+                .unwrap_or(DEFAULT_CONNECTION_TIMEOUT.as_millis() as u64),
             keep_alive: self.keep_alive.unwrap_or(75),
+            client_request_timeout: self
+                .client_request_timeout
+                .unwrap_or(DEFAULT_CLIENT_TIMEOUT_MS),
+            client_disconnect_timeout: self
+                .client_disconnect_timeout
+                .unwrap_or(DEFAULT_CLIENT_TIMEOUT_MS),
+            shutdown_timeout: self.shutdown_timeout.unwrap_or(0),
+            request_timeout_ms: self.request_timeout_ms.unwrap_or(0),
+            rate_limit: self.rate_limit,
+            tile_cache: self.tile_cache.unwrap_or_default(),
             worker_processes: self.worker_processes.unwrap_or_else(num_cpus::get),
             listen_addresses: self
                 .listen_addresses
-                .unwrap_or_else(|| "0.0.0.0:3000".to_owned()),
+                .map(ListenAddresses::into_vec)
+                .unwrap_or_else(|| vec![DEFAULT_LISTEN_ADDRESS.to_owned()]),
+            tls: self.tls,
+            grpc_listen_address: self.grpc_listen_address,
+            static_files: self.static_files.unwrap_or_default(),
+            fonts_directory: self.fonts_directory,
             connection_string: self.connection_string,
-            table_sources: self.table_sources,
-            function_sources: self.function_sources,
+            read_replica_urls: self.read_replica_urls,
+            table_sources,
+            function_sources,
+            pmtiles_sources: self.pmtiles_sources,
+            default_extent: table_source_defaults.extent,
+            default_buffer: table_source_defaults.buffer,
+            default_clip_geom: table_source_defaults.clip_geom,
+            geometry_type_sample_size: self.geometry_type_sample_size,
+            source_filter,
             danger_accept_invalid_certs: self.danger_accept_invalid_certs.unwrap_or(false),
+            debug_grid: self.debug_grid.unwrap_or(false),
+            list_sources_on_not_found: self.list_sources_on_not_found.unwrap_or(false),
+            overzoom: self.overzoom.unwrap_or(false),
+            mvt_content_type: self
+                .mvt_content_type
+                .unwrap_or_else(|| DEFAULT_MVT_CONTENT_TYPE.to_owned()),
+            empty_tile_mode: self.empty_tile_mode.unwrap_or_default(),
+            allow_tile_param_overrides: self.allow_tile_param_overrides.unwrap_or(false),
+            max_function_param_length: self
+                .max_function_param_length
+                .unwrap_or(DEFAULT_MAX_PARAM_LENGTH),
+            max_tile_bytes: self.max_tile_bytes,
+            min_allowed_zoom: self.min_allowed_zoom,
+            debug_feature_count: self.debug_feature_count.unwrap_or(false),
+            zstd_level: self.zstd_level.unwrap_or(DEFAULT_ZSTD_LEVEL),
+            enable_table_sources,
+            enable_function_sources,
+            admin_token: self.admin_token,
+            tenant_schemas: self.tenant_schemas,
+            geojson_tiles: self.geojson_tiles.unwrap_or(false),
+            log_format: self.log_format.unwrap_or(LogFormat::Text),
+            max_age: self.max_age.unwrap_or(DEFAULT_MAX_AGE),
+            public_base_url: self.public_base_url,
+            trust_proxy_headers: self.trust_proxy_headers.unwrap_or(false),
+            pool_test_on_check_out: self.pool_test_on_check_out.unwrap_or(true),
+            db_min_idle: self.db_min_idle,
+            db_workers: self.db_workers.unwrap_or(DEFAULT_DB_WORKERS),
+            blocking_threads: self.blocking_threads,
+            db_retries: self.db_retries.unwrap_or(3),
+            db_retry_backoff_ms: self.db_retry_backoff_ms.unwrap_or(100),
+            db_search_path: self.db_search_path,
+            slow_query_ms: self.slow_query_ms,
+            circuit_breaker: self.circuit_breaker,
+            otlp_endpoint: self.otlp_endpoint,
+        }
+    }
+}
+
+/// Rejects `response_headers` of sources that aren't valid HTTP headers
+fn validate_response_header(source_id: &str, name: &str, value: &str) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+
+    if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return invalid(format!(
+            "Invalid response header name '{}' of {}",
+            name, source_id
+        ));
+    }
+
+    if http::HeaderValue::from_str(value).is_err() {
+        return invalid(format!(
+            "Invalid value of response header {} of {}",
+            name, source_id
+        ));
+    }
+
+    Ok(())
+}
+
+/// Clamps the number of web server workers to `[1, num_cpus * 4]`, `0` means one worker per CPU
+pub fn clamp_worker_processes(worker_processes: usize) -> usize {
+    let cpus = num_cpus::get();
+    let max_worker_processes = cpus * MAX_WORKER_PROCESSES_PER_CPU;
+
+    if worker_processes == 0 {
+        cpus
+    } else if worker_processes > max_worker_processes {
+        warn!(
+            "worker_processes {} exceeds {} workers per CPU, using {}",
+            worker_processes, MAX_WORKER_PROCESSES_PER_CPU, max_worker_processes
+        );
+        max_worker_processes
+    } else {
+        worker_processes
+    }
+}
+
+impl Config {
+    /// Rejects settings the server can't start with and clamps the ones it would misbehave with
+    pub fn validate(mut self) -> io::Result<Config> {
+        if self.listen_addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "listen_addresses can't be empty",
+            ));
+        }
+
+        self.worker_processes = clamp_worker_processes(self.worker_processes);
+
+        if !(0..=MAX_ZSTD_LEVEL).contains(&self.zstd_level) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("zstd_level must be between 0 and {}", MAX_ZSTD_LEVEL),
+            ));
+        }
+
+        if self.db_workers == 0 || self.blocking_threads == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "db_workers and blocking_threads must be above 0",
+            ));
+        }
+
+        if self.db_retries > MAX_DB_RETRIES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("db_retries must be at most {}", MAX_DB_RETRIES),
+            ));
+        }
+
+        // Each database worker holds a pooled connection while querying
+        if self.db_workers > self.pool_size as usize {
+            warn!(
+                "db_workers {} exceeds pool_size {}, the extra workers wait for connections",
+                self.db_workers, self.pool_size
+            );
+        }
+
+        let table_response_headers = self
+            .table_sources
+            .iter()
+            .flat_map(|sources| sources.values())
+            .map(|source| (&source.id, &source.response_headers));
+        let function_response_headers = self
+            .function_sources
+            .iter()
+            .flat_map(|sources| sources.values())
+            .map(|source| (&source.id, &source.response_headers));
+
+        for (source_id, response_headers) in table_response_headers.chain(function_response_headers)
+        {
+            for (name, value) in response_headers.iter().flatten() {
+                validate_response_header(source_id, name, value)?;
+            }
+        }
+
+        if let Some(table_sources) = self.table_sources.take() {
+            self.table_sources = Some(apply_public_ids(table_sources)?);
         }
+
+        for source in self
+            .table_sources
+            .iter()
+            .flat_map(|sources| sources.values())
+        {
+            if let Some(buffer_pixels) = source.buffer_pixels {
+                if !(0.0..=TILE_PIXELS).contains(&buffer_pixels) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} buffer_pixels {} is not between 0 and {}",
+                            source.id, buffer_pixels, TILE_PIXELS
+                        ),
+                    ));
+                }
+            }
+
+            // The column is replaced by its keys in the property list, so it must be in it
+            if let Some(flatten_column) = &source.flatten_column {
+                if !source.properties.contains_key(flatten_column) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} flatten_column {} is not one of its properties",
+                            source.id, flatten_column
+                        ),
+                    ));
+                }
+            }
+        }
+
+        // The pool can't keep more idle connections than it has
+        if let Some(db_min_idle) = self.db_min_idle {
+            if db_min_idle > self.pool_size {
+                warn!(
+                    "db_min_idle {} exceeds pool_size {}, using {}",
+                    db_min_idle, self.pool_size, self.pool_size
+                );
+                self.db_min_idle = Some(self.pool_size);
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+/// Prefix of environment variables overriding config file settings
+pub const ENV_PREFIX: &str = "FALCON_";
+
+/// Separates keys of nested settings in variable names, e.g. `FALCON_TLS__CERT_PATH`
+const ENV_KEY_SEPARATOR: &str = "__";
+
+/// Settings of string values, e.g. `FALCON_ADMIN_TOKEN=123456` is a string, not a number
+const STRING_SETTINGS: &[&str] = &[
+    "notify_channel",
+    "grpc_listen_address",
+    "fonts_directory",
+    "connection_string",
+    "mvt_content_type",
+    "admin_token",
+    "public_base_url",
+    "otlp_endpoint",
+    "tls__cert_path",
+    "tls__key_path",
+    "tile_cache__directory",
+    "tenant_schemas__header",
+];
+
+/// Settings of string or `[a, b]` list values, other values are taken as one string
+const STRING_LIST_SETTINGS: &[&str] = &["listen_addresses", "read_replica_urls", "db_search_path"];
+
+/// Maps of string values, e.g. `FALCON_TENANT_SCHEMAS__SCHEMAS__ACME`
+const STRING_MAP_SETTINGS: &[&str] = &["tenant_schemas__schemas"];
+
+/// Maps of user-defined keys, e.g. source or tenant ids, the keys keep their case
+const MAP_SETTINGS: &[&str] = &[
+    "table_sources",
+    "function_sources",
+    "pmtiles_sources",
+    "tenant_schemas__schemas",
+];
+
+/// Key of a variable name without the prefix, setting names are lowercased
+/// and keys of `MAP_SETTINGS` are kept as written
+fn env_key(name: &str) -> String {
+    let mut key = String::new();
+
+    for part in name.split(ENV_KEY_SEPARATOR) {
+        let is_map_key = MAP_SETTINGS.contains(&key.as_str());
+        if !key.is_empty() {
+            key.push_str(ENV_KEY_SEPARATOR);
+        }
+
+        if is_map_key {
+            key.push_str(part);
+        } else {
+            key.push_str(&part.to_lowercase());
+        }
+    }
+
+    key
+}
+
+/// Value of a variable, parsed as YAML unless the setting is a string
+fn env_value(key: &str, value: String) -> Value {
+    let is_string_map_value = |prefix: &&str| {
+        key.strip_prefix(prefix)
+            .is_some_and(|key| key.starts_with(ENV_KEY_SEPARATOR))
+    };
+
+    if STRING_SETTINGS.contains(&key) || STRING_MAP_SETTINGS.iter().any(is_string_map_value) {
+        return Value::String(value);
+    }
+
+    match serde_yaml::from_str(&value) {
+        Ok(Value::Sequence(values)) if STRING_LIST_SETTINGS.contains(&key) => {
+            let values = values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Number(number) => Value::String(number.to_string()),
+                    Value::Bool(value) => Value::String(value.to_string()),
+                    value => value,
+                })
+                .collect();
+
+            Value::Sequence(values)
+        }
+        _ if STRING_LIST_SETTINGS.contains(&key) => Value::String(value),
+        Ok(parsed) => parsed,
+        Err(_) => Value::String(value),
+    }
+}
+
+/// Sets `FALCON_`-prefixed variables on the parsed config, overriding its settings.
+/// Names without the prefix are lowercased keys, e.g. `FALCON_WORKER_PROCESSES` sets
+/// `worker_processes`, keys of source and tenant maps keep their case. Values of string
+/// settings are kept as they are, others are parsed as YAML, so numbers, booleans and
+/// `[a, b]` lists keep their types, and are taken as strings when they aren't valid YAML.
+pub fn apply_env_overrides<I>(config: &mut Value, vars: I)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (name, value) in vars {
+        let key = match name.strip_prefix(ENV_PREFIX) {
+            Some(key) if !key.is_empty() => env_key(key),
+            _ => continue,
+        };
+
+        let value = env_value(&key, value);
+
+        let mut keys: Vec<&str> = key.split(ENV_KEY_SEPARATOR).collect();
+        let last_key = keys.pop().unwrap_or_default();
+
+        let mut mapping = &mut *config;
+        for key in keys {
+            mapping = mapping_entry(mapping, key);
+        }
+
+        *mapping_entry(mapping, last_key) = value;
     }
 }
 
+/// Value of the key, a non-mapping `config` is replaced with an empty mapping first
+fn mapping_entry<'a>(config: &'a mut Value, key: &str) -> &'a mut Value {
+    if !config.is_mapping() {
+        *config = Value::Mapping(Mapping::new());
+    }
+
+    let key = Value::String(key.to_owned());
+    let mapping = config.as_mapping_mut().unwrap();
+    if !mapping.contains_key(&key) {
+        mapping.insert(key.clone(), Value::Null);
+    }
+
+    mapping.get_mut(&key).unwrap()
+}
+
+/// Config of the command-line arguments with `FALCON_` variables applied over it,
+/// the same as they are applied over the config file
+pub fn with_env_overrides<I>(config: ConfigBuilder, vars: I) -> io::Result<ConfigBuilder>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut config =
+        serde_yaml::to_value(config).map_err(|err| io::Error::other(err.to_string()))?;
+
+    apply_env_overrides(&mut config, vars);
+
+    serde_yaml::from_value(config).map_err(|err| io::Error::other(err.to_string()))
+}
+
 pub fn read_config(file_name: &str) -> io::Result<Config> {
     let mut file = File::open(file_name)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    let config_builder: ConfigBuilder = serde_yaml::from_str(contents.as_str())
-        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let mut config: Value =
+        serde_yaml::from_str(contents.as_str()).map_err(|err| io::Error::other(err.to_string()))?;
+
+    apply_env_overrides(&mut config, env::vars());
+
+    let config_builder: ConfigBuilder =
+        serde_yaml::from_value(config).map_err(|err| io::Error::other(err.to_string()))?;
 
-    Ok(config_builder.finalize())
+    config_builder.finalize().validate()
 }
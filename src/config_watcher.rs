@@ -0,0 +1,103 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use actix::Addr;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+
+use crate::config::read_config;
+use crate::coordinator_actor::CoordinatorActor;
+use crate::messages;
+use crate::utils::prettify_error;
+
+fn reload_config(file_name: &str, coordinator: &Addr<CoordinatorActor>) {
+    let config = match read_config(file_name) {
+        Ok(config) => config,
+        Err(error) => {
+            error!(
+                "Can't reload {}, keeping the current sources: {}",
+                file_name, error
+            );
+            return;
+        }
+    };
+
+    info!("Reloading sources from {}", file_name);
+
+    coordinator.do_send(messages::RefreshTableSources {
+        table_sources: config.table_sources,
+    });
+
+    coordinator.do_send(messages::RefreshFunctionSources {
+        function_sources: config.function_sources,
+    });
+}
+
+/// Reloads table and function sources from the config file on `SIGHUP`,
+/// e.g. when the file is replaced in a way the watcher can't see
+pub fn reload_on_sighup(file_name: String, coordinator: Addr<CoordinatorActor>) {
+    #[cfg(unix)]
+    actix::spawn(async move {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        let mut stream = match signal(SignalKind::hangup()) {
+            Ok(stream) => stream,
+            Err(error) => {
+                error!("Can't listen for SIGHUP: {}", error);
+                return;
+            }
+        };
+
+        while stream.recv().await.is_some() {
+            info!("Received SIGHUP");
+            reload_config(&file_name, &coordinator);
+        }
+    });
+
+    #[cfg(not(unix))]
+    let _ = (file_name, coordinator);
+}
+
+/// Watches the config file and refreshes table and function sources on every change.
+/// The parent directory is watched so that editors replacing the file are handled too.
+pub fn watch_config(file_name: String, coordinator: Addr<CoordinatorActor>) -> io::Result<()> {
+    let path = fs::canonicalize(&file_name).map_err(prettify_error("Can't watch config"))?;
+    let directory = path
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf();
+
+    let (tx, rx) = channel();
+    let mut config_watcher =
+        watcher(tx, Duration::from_millis(500)).map_err(prettify_error("Can't watch config"))?;
+
+    config_watcher
+        .watch(&directory, RecursiveMode::NonRecursive)
+        .map_err(prettify_error("Can't watch config"))?;
+
+    thread::Builder::new()
+        .name("config-watcher".to_owned())
+        .spawn(move || {
+            // The watcher stops on drop, so keep it alive for the lifetime of the thread
+            let _config_watcher = config_watcher;
+
+            for event in rx {
+                match event {
+                    DebouncedEvent::Create(changed)
+                    | DebouncedEvent::Write(changed)
+                    | DebouncedEvent::Rename(_, changed)
+                        if changed == path =>
+                    {
+                        reload_config(&file_name, &coordinator)
+                    }
+                    DebouncedEvent::Error(error, _) => error!("Config watcher error: {}", error),
+                    _ => {}
+                }
+            }
+        })?;
+
+    Ok(())
+}
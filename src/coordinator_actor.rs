@@ -1,18 +1,138 @@
-use actix::{Actor, Addr, Context, Handler};
+use actix::{Actor, Addr, Context, Handler, Recipient};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
 
+use crate::blocking;
+use crate::function_source::FunctionSources;
 use crate::messages;
+use crate::table_source::TableSources;
+use crate::tile_cache::SharedTileCache;
 use crate::worker_actor::WorkerActor;
 
+/// Sources readable outside of the web server workers, e.g. by the gRPC service.
+/// Kept current with refreshes like the `Rc` sources of workers.
+#[derive(Clone, Default)]
+pub struct SharedSources {
+    pub table_sources: Arc<RwLock<Option<TableSources>>>,
+    pub function_sources: Arc<RwLock<Option<FunctionSources>>>,
+}
+
+impl SharedSources {
+    pub fn new(
+        table_sources: Option<TableSources>,
+        function_sources: Option<FunctionSources>,
+    ) -> SharedSources {
+        SharedSources {
+            table_sources: Arc::new(RwLock::new(table_sources)),
+            function_sources: Arc::new(RwLock::new(function_sources)),
+        }
+    }
+
+    fn set_table_sources(&self, table_sources: Option<TableSources>) {
+        *self
+            .table_sources
+            .write()
+            .unwrap_or_else(|error| error.into_inner()) = table_sources;
+    }
+
+    fn set_function_sources(&self, function_sources: Option<FunctionSources>) {
+        *self
+            .function_sources
+            .write()
+            .unwrap_or_else(|error| error.into_inner()) = function_sources;
+    }
+}
+
+#[derive(Default)]
 pub struct CoordinatorActor {
     workers: Vec<Addr<WorkerActor>>,
+    shared_sources: Vec<SharedSources>,
+    subscribers: Vec<Recipient<messages::SourceChanged>>,
+    table_sources: Option<TableSources>,
+    function_sources: Option<FunctionSources>,
+    tile_cache: Option<SharedTileCache>,
 }
 
-impl Default for CoordinatorActor {
-    fn default() -> CoordinatorActor {
-        CoordinatorActor { workers: vec![] }
+impl CoordinatorActor {
+    /// Coordinator of the sources workers are started with, refreshes
+    /// are compared against them to notify subscribers of changed sources
+    pub fn new(
+        table_sources: Option<TableSources>,
+        function_sources: Option<FunctionSources>,
+        tile_cache: Option<SharedTileCache>,
+    ) -> CoordinatorActor {
+        CoordinatorActor {
+            table_sources,
+            function_sources,
+            tile_cache,
+            ..CoordinatorActor::default()
+        }
+    }
+
+    /// Evicts cached tiles of the sources off the actor's thread
+    fn invalidate(&self, source_ids: Vec<String>) {
+        let tile_cache = match &self.tile_cache {
+            Some(tile_cache) => tile_cache.clone(),
+            None => return,
+        };
+
+        actix::spawn(async move {
+            let result = blocking::run(move || {
+                source_ids
+                    .iter()
+                    .try_for_each(|source_id| tile_cache.invalidate(source_id))
+            })
+            .await;
+
+            if let Err(error) = result {
+                warn!("Can't invalidate cached tiles: {}", error);
+            }
+        });
+    }
+
+    /// Evicts cached tiles of the changed sources and notifies subscribers
+    fn notify(&mut self, source_ids: Vec<String>) {
+        if !source_ids.is_empty() {
+            self.invalidate(source_ids.clone());
+        }
+
+        for source_id in source_ids {
+            let message = messages::SourceChanged { source_id };
+
+            // Subscribers that can't be reached have disconnected
+            self.subscribers
+                .retain(|subscriber| subscriber.do_send(message.clone()).is_ok());
+        }
     }
 }
 
+/// Ids of sources added, removed or changed between `old` and `new`
+pub fn changed_source_ids<T: Serialize>(
+    old: Option<&HashMap<String, T>>,
+    new: Option<&HashMap<String, T>>,
+) -> Vec<String> {
+    let empty = HashMap::new();
+    let old = old.unwrap_or(&empty);
+    let new = new.unwrap_or(&empty);
+
+    let source_ids: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    source_ids
+        .into_iter()
+        .filter(|source_id| {
+            let old_source = old.get(*source_id).map(serde_json::to_value);
+            let new_source = new.get(*source_id).map(serde_json::to_value);
+
+            match (old_source, new_source) {
+                (Some(Ok(old_source)), Some(Ok(new_source))) => old_source != new_source,
+                _ => true,
+            }
+        })
+        .cloned()
+        .collect()
+}
+
 impl Actor for CoordinatorActor {
     type Context = Context<Self>;
 }
@@ -26,6 +146,33 @@ impl Handler<messages::Connect> for CoordinatorActor {
     }
 }
 
+impl Handler<messages::ShareSources> for CoordinatorActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::ShareSources, _: &mut Context<Self>) -> Self::Result {
+        msg.sources.set_table_sources(self.table_sources.clone());
+        msg.sources
+            .set_function_sources(self.function_sources.clone());
+        self.shared_sources.push(msg.sources);
+    }
+}
+
+impl Handler<messages::Subscribe> for CoordinatorActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::Subscribe, _: &mut Context<Self>) -> Self::Result {
+        self.subscribers.push(msg.addr);
+    }
+}
+
+impl Handler<messages::SourceChanged> for CoordinatorActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::SourceChanged, _: &mut Context<Self>) -> Self::Result {
+        self.notify(vec![msg.source_id]);
+    }
+}
+
 impl Handler<messages::RefreshTableSources> for CoordinatorActor {
     type Result = ();
 
@@ -40,6 +187,14 @@ impl Handler<messages::RefreshTableSources> for CoordinatorActor {
             };
             worker.do_send(message);
         }
+
+        for sources in &self.shared_sources {
+            sources.set_table_sources(msg.table_sources.clone());
+        }
+
+        let changed = changed_source_ids(self.table_sources.as_ref(), msg.table_sources.as_ref());
+        self.table_sources = msg.table_sources;
+        self.notify(changed);
     }
 }
 
@@ -57,5 +212,16 @@ impl Handler<messages::RefreshFunctionSources> for CoordinatorActor {
             };
             worker.do_send(message);
         }
+
+        for sources in &self.shared_sources {
+            sources.set_function_sources(msg.function_sources.clone());
+        }
+
+        let changed = changed_source_ids(
+            self.function_sources.as_ref(),
+            msg.function_sources.as_ref(),
+        );
+        self.function_sources = msg.function_sources;
+        self.notify(changed);
     }
 }
@@ -1,19 +1,134 @@
 use std::io;
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use native_tls::TlsConnector;
+use postgres::error::SqlState;
 use postgres_native_tls::MakeTlsConnector;
-use r2d2::PooledConnection;
+use r2d2::{CustomizeConnection, ManageConnection, PooledConnection};
 use r2d2_postgres::PostgresConnectionManager;
 use semver::Version;
 use semver::VersionReq;
 
+use crate::config::Config;
+use crate::function_source::get_function_sources;
+use crate::metrics;
+use crate::select_list::quote_identifier;
+use crate::table_source::get_table_sources;
 use crate::utils::prettify_error;
 
-pub type ConnectionManager = PostgresConnectionManager<MakeTlsConnector>;
+/// Postgres client that can be marked broken, so the pool drops it instead of
+/// taking it back. The client itself doesn't notice a connection closed by the server.
+pub struct PgClient {
+    client: postgres::Client,
+    broken: bool,
+}
+
+impl PgClient {
+    pub fn mark_broken(&mut self) {
+        self.broken = true;
+    }
+}
+
+impl Deref for PgClient {
+    type Target = postgres::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for PgClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+pub struct ConnectionManager(PostgresConnectionManager<MakeTlsConnector>);
+
+impl ManageConnection for ConnectionManager {
+    type Connection = PgClient;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> Result<PgClient, postgres::Error> {
+        let client = self.0.connect()?;
+        Ok(PgClient {
+            client,
+            broken: false,
+        })
+    }
+
+    fn is_valid(&self, conn: &mut PgClient) -> Result<(), postgres::Error> {
+        self.0.is_valid(&mut conn.client)
+    }
+
+    fn has_broken(&self, conn: &mut PgClient) -> bool {
+        conn.broken || self.0.has_broken(&mut conn.client)
+    }
+}
+
+/// Sets `search_path` of each new connection, so all pooled connections resolve
+/// unqualified names the same way
+#[derive(Debug)]
+struct SearchPathCustomizer {
+    query: String,
+}
+
+impl SearchPathCustomizer {
+    fn new(search_path: &[String]) -> SearchPathCustomizer {
+        let schemas: Vec<String> = search_path
+            .iter()
+            .map(|schema| quote_identifier(schema))
+            .collect();
+
+        SearchPathCustomizer {
+            query: format!("SET search_path TO {}", schemas.join(", ")),
+        }
+    }
+}
+
+impl CustomizeConnection<PgClient, postgres::Error> for SearchPathCustomizer {
+    fn on_acquire(&self, conn: &mut PgClient) -> Result<(), postgres::Error> {
+        conn.batch_execute(&self.query)
+    }
+}
+
 pub type Pool = r2d2::Pool<ConnectionManager>;
 pub type Connection = PooledConnection<ConnectionManager>;
 
+/// Runs `query` with the schema first in `search_path` of the connection, the previous
+/// `search_path` is restored afterwards. A connection it can't be restored on is
+/// marked broken, so it doesn't serve other requests with the schema.
+pub fn with_search_path<T, F>(conn: &mut Connection, schema: &str, query: F) -> io::Result<T>
+where
+    F: FnOnce(&mut Connection) -> io::Result<T>,
+{
+    let row = conn
+        .query_one(
+            "SELECT current_setting('search_path') AS previous, \
+             set_config('search_path', $1 || ', ' || current_setting('search_path'), false)",
+            &[&quote_identifier(schema)],
+        )
+        .map_err(io::Error::other)?;
+    let previous: String = row.get("previous");
+
+    let result = query(conn);
+
+    if let Err(error) = conn.execute("SELECT set_config('search_path', $1, false)", &[&previous]) {
+        conn.mark_broken();
+        return Err(io::Error::other(error));
+    }
+
+    result
+}
+
+/// Default time to wait for a pooled connection, same as r2d2
+pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Waiting longer than this for a pooled connection is logged
+const POOL_WAIT_WARNING: Duration = Duration::from_secs(1);
+
 fn make_tls_connector(danger_accept_invalid_certs: bool) -> io::Result<MakeTlsConnector> {
     let connector = TlsConnector::builder()
         .danger_accept_invalid_certs(danger_accept_invalid_certs)
@@ -24,31 +139,141 @@ fn make_tls_connector(danger_accept_invalid_certs: bool) -> io::Result<MakeTlsCo
     Ok(tls_connector)
 }
 
+/// Connection outside of the pool, for long-lived sessions like `LISTEN`
+/// that would otherwise keep a pooled connection checked out
+pub fn connect(cn_str: &str, danger_accept_invalid_certs: bool) -> io::Result<postgres::Client> {
+    let config = postgres::config::Config::from_str(cn_str)
+        .map_err(prettify_error("Can't parse connection string"))?;
+
+    let tls_connector = make_tls_connector(danger_accept_invalid_certs)?;
+    config
+        .connect(tls_connector)
+        .map_err(prettify_error("Can't connect to the database"))
+}
+
 pub fn setup_connection_pool(
     cn_str: &str,
     pool_size: Option<u32>,
+    connection_timeout: Option<Duration>,
     danger_accept_invalid_certs: bool,
+    test_on_check_out: bool,
+    min_idle: Option<u32>,
+    search_path: Option<&[String]>,
 ) -> io::Result<Pool> {
     let config = postgres::config::Config::from_str(cn_str)
         .map_err(prettify_error("Can't parse connection string"))?;
 
     let tls_connector = make_tls_connector(danger_accept_invalid_certs)?;
-    let manager = PostgresConnectionManager::new(config, tls_connector);
+    let manager = ConnectionManager(PostgresConnectionManager::new(config, tls_connector));
 
-    let pool = r2d2::Pool::builder()
+    let mut builder = r2d2::Pool::builder()
         .max_size(pool_size.unwrap_or(20))
+        .connection_timeout(connection_timeout.unwrap_or(DEFAULT_CONNECTION_TIMEOUT))
+        .test_on_check_out(test_on_check_out)
+        .min_idle(min_idle);
+
+    if let Some(search_path) = search_path {
+        builder = builder.connection_customizer(Box::new(SearchPathCustomizer::new(search_path)));
+    }
+
+    let pool = builder
         .build(manager)
         .map_err(prettify_error("Can't build connection pool"))?;
 
     Ok(pool)
 }
 
+/// Runs the source discovery queries on the connections the pool opened on startup,
+/// so the first source listing doesn't pay for cold catalog caches of the backends.
+/// Disabled source kinds are never listed, so they aren't discovered.
+pub fn warm_up_pool(pool: &Pool, config: &Config) -> io::Result<()> {
+    let table_source_defaults = config.table_source_defaults();
+    let started_at = Instant::now();
+
+    // Connections are held until all of them are warm, so each one is checked out once
+    let connections = pool.min_idle().unwrap_or_else(|| pool.max_size());
+    let mut warm_connections = Vec::new();
+    for _ in 0..connections {
+        let mut connection = get_connection(pool)?;
+        if config.enable_table_sources {
+            get_table_sources(&mut connection, &table_source_defaults)?;
+        }
+        if config.enable_function_sources {
+            get_function_sources(&mut connection)?;
+        }
+        warm_connections.push(connection);
+    }
+
+    info!(
+        "Warmed up {} database connections in {:?}",
+        warm_connections.len(),
+        started_at.elapsed()
+    );
+
+    Ok(())
+}
+
+/// Waits for a pooled connection up to the pool `connection_timeout`,
+/// recording the wait in `metrics::DB_POOL_WAIT`
 pub fn get_connection(pool: &Pool) -> io::Result<Connection> {
-    let connection = pool
-        .get()
-        .map_err(prettify_error("Can't retrieve connection from the pool"))?;
+    let started_at = Instant::now();
+    let connection = pool.get();
+
+    let wait = started_at.elapsed();
+    metrics::DB_POOL_WAIT.observe(wait);
+
+    if wait >= POOL_WAIT_WARNING {
+        let state = pool.state();
+        warn!(
+            "Waited {:?} for a database connection ({} of {} connections in use), consider increasing pool_size",
+            wait,
+            state.connections - state.idle_connections,
+            pool.max_size()
+        );
+    }
+
+    connection.map_err(|error| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Can't retrieve connection from the pool: {}", error),
+        )
+    })
+}
+
+/// Whether the error is caused by a lost or unavailable database connection
+/// (e.g. failover or restart) rather than by the query itself
+pub fn is_transient_error(error: &io::Error) -> bool {
+    if error.kind() == io::ErrorKind::TimedOut {
+        return true;
+    }
+
+    let error = match error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<postgres::Error>())
+    {
+        Some(error) => error,
+        None => return false,
+    };
+
+    if error.is_closed() {
+        return true;
+    }
+
+    error.code().is_some_and(|code| {
+        code.code().starts_with("08")
+            || *code == SqlState::ADMIN_SHUTDOWN
+            || *code == SqlState::CRASH_SHUTDOWN
+            || *code == SqlState::CANNOT_CONNECT_NOW
+    })
+}
 
-    Ok(connection)
+/// Whether the error is caused by the connection itself being closed or terminated,
+/// e.g. a pooled connection that outlived a database restart
+pub fn is_broken_connection_error(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<postgres::Error>())
+        .is_some_and(|error| error.is_closed() || error.code() == Some(&SqlState::ADMIN_SHUTDOWN))
 }
 
 pub fn select_postgis_verion(pool: &Pool) -> io::Result<String> {
@@ -63,7 +288,7 @@ pub fn select_postgis_verion(pool: &Pool) -> io::Result<String> {
 }
 
 pub fn check_postgis_version(required_postgis_version: &str, pool: &Pool) -> io::Result<bool> {
-    let postgis_version = select_postgis_verion(&pool)?;
+    let postgis_version = select_postgis_verion(pool)?;
 
     let req = VersionReq::parse(required_postgis_version)
         .map_err(prettify_error("Can't parse required PostGIS version"))?;
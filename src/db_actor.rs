@@ -1,13 +1,111 @@
 use actix::{Actor, Handler, SyncContext};
 use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::db::{get_connection, Pool};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::db::{
+    get_connection, is_broken_connection_error, is_transient_error, with_search_path, Connection,
+    Pool,
+};
 use crate::function_source::{get_function_sources, FunctionSources};
 use crate::messages;
-use crate::source::Tile;
+use crate::source::TileData;
 use crate::table_source::{get_table_sources, TableSources};
 
-pub struct DbActor(pub Pool);
+/// Default number of database actor threads per database, see `db_workers`
+pub const DEFAULT_DB_WORKERS: usize = 3;
+
+/// Most retries of a tile query, see `db_retries`
+pub const MAX_DB_RETRIES: u32 = 10;
+
+/// Longest delay between retries of a tile query
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Delay before the retry following `attempt` failed retries, `retry_backoff` doubled
+/// on each retry up to `MAX_RETRY_BACKOFF`
+pub fn retry_backoff(retry_backoff: Duration, attempt: u32) -> Duration {
+    2_u32
+        .checked_pow(attempt)
+        .and_then(|factor| retry_backoff.checked_mul(factor))
+        .map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF))
+}
+
+/// Most time a tile query sleeps between its retries, so retries during a failover
+/// don't hold the database threads: half of `request_timeout`, at most `MAX_RETRY_BACKOFF`
+pub fn max_retry_sleep(request_timeout: Option<Duration>) -> Duration {
+    request_timeout.map_or(MAX_RETRY_BACKOFF, |request_timeout| {
+        (request_timeout / 2).min(MAX_RETRY_BACKOFF)
+    })
+}
+
+pub struct DbActor {
+    pub pool: Pool,
+    /// Number of retries of tile queries failed with transient connection errors
+    pub retries: u32,
+    /// Delay before the first retry, see `retry_backoff`
+    pub retry_backoff: Duration,
+    /// Retries stop when their backoff would exceed it, see `max_retry_sleep`
+    pub max_retry_sleep: Duration,
+    /// Tile queries taking longer are logged at WARN, `None` disables the log
+    pub slow_query: Option<Duration>,
+    /// Fails queries fast while the database is unavailable, shared between the threads
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+impl DbActor {
+    /// Runs `query` unless the circuit breaker is open, connection errors of the
+    /// query are counted by the circuit breaker
+    fn query<T, F>(&self, query: F) -> io::Result<T>
+    where
+        F: Fn(&mut Connection) -> io::Result<T>,
+    {
+        let circuit_breaker = match &self.circuit_breaker {
+            Some(circuit_breaker) => circuit_breaker,
+            None => return self.run_query(query),
+        };
+
+        circuit_breaker.check().map_err(|wait| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "Database is unavailable, circuit breaker is open for {:?}",
+                    wait
+                ),
+            )
+        })?;
+
+        let result = self.run_query(query);
+        circuit_breaker.record(matches!(&result, Err(error) if is_transient_error(error)));
+
+        result
+    }
+
+    /// Runs `query` on a pooled connection. A connection found broken (e.g. after a database
+    /// restart) is dropped by the pool instead of being taken back, and the query is
+    /// retried once on a fresh connection before failing.
+    fn run_query<T, F>(&self, query: F) -> io::Result<T>
+    where
+        F: Fn(&mut Connection) -> io::Result<T>,
+    {
+        let mut connection = get_connection(&self.pool)?;
+
+        match query(&mut connection) {
+            Err(error) if is_broken_connection_error(&error) => {
+                warn!(
+                    "Database connection is broken: {}, retrying with a new connection",
+                    error
+                );
+
+                connection.mark_broken();
+                drop(connection);
+
+                query(&mut get_connection(&self.pool)?)
+            }
+            result => result,
+        }
+    }
+}
 
 impl Actor for DbActor {
     type Context = SyncContext<Self>;
@@ -16,10 +114,8 @@ impl Actor for DbActor {
 impl Handler<messages::GetTableSources> for DbActor {
     type Result = Result<TableSources, io::Error>;
 
-    fn handle(&mut self, _msg: messages::GetTableSources, _: &mut Self::Context) -> Self::Result {
-        let mut connection = get_connection(&self.0)?;
-        let table_sources = get_table_sources(&mut connection)?;
-        Ok(table_sources)
+    fn handle(&mut self, msg: messages::GetTableSources, _: &mut Self::Context) -> Self::Result {
+        self.query(|connection| get_table_sources(connection, &msg.defaults))
     }
 }
 
@@ -31,19 +127,86 @@ impl Handler<messages::GetFunctionSources> for DbActor {
         _msg: messages::GetFunctionSources,
         _: &mut Self::Context,
     ) -> Self::Result {
-        let mut connection = get_connection(&self.0)?;
-        let function_sources = get_function_sources(&mut connection)?;
-        Ok(function_sources)
+        self.query(get_function_sources)
+    }
+}
+
+impl Handler<messages::GetFeature> for DbActor {
+    type Result = Result<Option<String>, io::Error>;
+
+    fn handle(&mut self, msg: messages::GetFeature, _: &mut Self::Context) -> Self::Result {
+        self.query(|connection| msg.source.get_feature(connection, &msg.id))
+    }
+}
+
+impl Handler<messages::RefreshMatview> for DbActor {
+    type Result = Result<(), io::Error>;
+
+    fn handle(&mut self, msg: messages::RefreshMatview, _: &mut Self::Context) -> Self::Result {
+        self.query(|connection| msg.source.refresh_matview(connection))
     }
 }
 
 impl Handler<messages::GetTile> for DbActor {
-    type Result = Result<Tile, io::Error>;
+    type Result = Result<TileData, io::Error>;
 
     fn handle(&mut self, msg: messages::GetTile, _: &mut Self::Context) -> Self::Result {
-        let mut connection = get_connection(&self.0)?;
-        let tile = msg.source.get_tile(&mut connection, &msg.xyz, &msg.query)?;
+        let _span = tracing::info_span!(
+            parent: &msg.span,
+            "db_query",
+            source_id = msg.source.get_id()
+        )
+        .entered();
+
+        let mut attempt = 0;
+        let mut slept = Duration::from_secs(0);
+
+        loop {
+            let started_at = Instant::now();
+            let result = self.query(|connection| {
+                let get_tile_data = |connection: &mut Connection| {
+                    msg.source.get_tile_data(connection, &msg.xyz, &msg.query)
+                };
+
+                match &msg.search_path {
+                    Some(schema) => with_search_path(connection, schema, get_tile_data),
+                    None => get_tile_data(connection),
+                }
+            });
+
+            let elapsed = started_at.elapsed();
+            if self
+                .slow_query
+                .is_some_and(|slow_query| elapsed > slow_query)
+            {
+                warn!(
+                    "Slow tile query {}/{}/{}/{} took {:?}",
+                    msg.source.get_id(),
+                    msg.xyz.z,
+                    msg.xyz.x,
+                    msg.xyz.y,
+                    elapsed
+                );
+            }
+
+            let backoff = retry_backoff(self.retry_backoff, attempt);
+            let can_retry = attempt < self.retries && slept + backoff <= self.max_retry_sleep;
+
+            match result {
+                Err(error) if can_retry && is_transient_error(&error) => {
+                    warn!(
+                        "Can't get tile from {}: {}, retrying in {:?}",
+                        msg.source.get_id(),
+                        error,
+                        backoff
+                    );
 
-        Ok(tile)
+                    thread::sleep(backoff);
+                    slept += backoff;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
     }
 }
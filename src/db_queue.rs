@@ -0,0 +1,138 @@
+use futures::channel::oneshot;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+
+use actix_web::HttpRequest;
+
+/// Request header marking background (e.g. seeding) tile requests
+pub const PRIORITY_HEADER: &str = "x-tile-priority";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Background,
+    Interactive,
+}
+
+impl Priority {
+    /// Requests are interactive unless they carry `X-Tile-Priority: background`
+    pub fn from_request(req: &HttpRequest) -> Priority {
+        match req.headers().get(PRIORITY_HEADER) {
+            Some(value) if value.as_bytes().eq_ignore_ascii_case(b"background") => {
+                Priority::Background
+            }
+            _ => Priority::Interactive,
+        }
+    }
+}
+
+struct Waiter {
+    priority: Priority,
+    sequence: u64,
+    sender: oneshot::Sender<DbPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    /// Higher priority first, then first come first served
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+struct State {
+    available: usize,
+    sequence: u64,
+    waiters: BinaryHeap<Waiter>,
+}
+
+/// Priority queue in front of the database actor. Tile requests acquire one of
+/// `capacity` permits before sending a query, and released permits are handed
+/// to the highest priority waiter, so interactive tiles preempt seed traffic.
+#[derive(Clone)]
+pub struct DbQueue {
+    state: Arc<Mutex<State>>,
+}
+
+pub struct DbPermit {
+    queue: Option<DbQueue>,
+}
+
+impl DbQueue {
+    pub fn new(capacity: usize) -> DbQueue {
+        DbQueue {
+            state: Arc::new(Mutex::new(State {
+                available: capacity,
+                sequence: 0,
+                waiters: BinaryHeap::new(),
+            })),
+        }
+    }
+
+    pub async fn acquire(&self, priority: Priority) -> DbPermit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 {
+                state.available -= 1;
+                return DbPermit {
+                    queue: Some(self.clone()),
+                };
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let sequence = state.sequence;
+            state.sequence += 1;
+            state.waiters.push(Waiter {
+                priority,
+                sequence,
+                sender,
+            });
+
+            receiver
+        };
+
+        // The sender is only dropped together with the queue
+        receiver.await.expect("DB queue is dropped")
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        while let Some(waiter) = state.waiters.pop() {
+            let permit = DbPermit {
+                queue: Some(self.clone()),
+            };
+
+            match waiter.sender.send(permit) {
+                Ok(()) => return,
+                // The request was cancelled, try the next waiter
+                Err(mut permit) => permit.queue = None,
+            }
+        }
+
+        state.available += 1;
+    }
+}
+
+impl Drop for DbPermit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
@@ -1,16 +1,24 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::env;
 use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix::{Actor, Addr, SyncArbiter};
 
+use crate::config::{DEFAULT_MAX_AGE, DEFAULT_MVT_CONTENT_TYPE, DEFAULT_ZSTD_LEVEL};
 use crate::coordinator_actor::CoordinatorActor;
 use crate::db::setup_connection_pool;
-use crate::db_actor::DbActor;
-use crate::function_source::{FunctionSource, FunctionSources};
+use crate::db_actor::{DbActor, DEFAULT_DB_WORKERS, MAX_RETRY_BACKOFF};
+use crate::db_queue::DbQueue;
+use crate::function_source::{FunctionSource, FunctionSources, DEFAULT_MAX_PARAM_LENGTH};
+use crate::request_log::LogFormat;
 use crate::server::AppState;
-use crate::table_source::{TableSource, TableSources};
+use crate::source::{EmptyTileMode, TileJsonCache};
+use crate::source_filter::SourceFilter;
+use crate::table_source::{TableSource, TableSourceDefaults, TableSources};
 
 pub fn mock_table_sources() -> Option<TableSources> {
     let source = TableSource {
@@ -18,13 +26,39 @@ pub fn mock_table_sources() -> Option<TableSources> {
         schema: "public".to_owned(),
         table: "table_source".to_owned(),
         id_column: None,
+        primary_key: None,
+        spatial_index: Some(false),
+        is_matview: None,
+        auto_id_column: None,
         geometry_column: "geom".to_owned(),
         srid: 3857,
         extent: Some(4096),
         buffer: Some(64),
         clip_geom: Some(true),
         geometry_type: None,
+        geometry_types: None,
         properties: HashMap::new(),
+        min_polygon_pixels: None,
+        where_sql: None,
+        max_age: None,
+        mvt_content_type: None,
+        response_headers: None,
+        simplify: None,
+        simplify_zooms: None,
+        simplify_algorithm: None,
+        make_valid: None,
+        feature_limit: None,
+        updated_at_column: None,
+        layer_column: None,
+        max_layers: None,
+        flatten_column: None,
+        flatten_keys: None,
+        public_id: None,
+        buffer_pixels: None,
+        scheme: None,
+        bounds: None,
+        tile_query_cache: Arc::default(),
+        tilejson_cache: TileJsonCache::default(),
     };
 
     let table_source1 = TableSource {
@@ -32,13 +66,39 @@ pub fn mock_table_sources() -> Option<TableSources> {
         schema: "public".to_owned(),
         table: "points1".to_owned(),
         id_column: None,
+        primary_key: None,
+        spatial_index: Some(true),
+        is_matview: None,
+        auto_id_column: None,
         geometry_column: "geom".to_owned(),
         srid: 3857,
         extent: Some(4096),
         buffer: Some(64),
         clip_geom: Some(true),
         geometry_type: None,
+        geometry_types: None,
         properties: HashMap::new(),
+        min_polygon_pixels: None,
+        where_sql: None,
+        max_age: None,
+        mvt_content_type: None,
+        response_headers: None,
+        simplify: None,
+        simplify_zooms: None,
+        simplify_algorithm: None,
+        make_valid: None,
+        feature_limit: None,
+        updated_at_column: None,
+        layer_column: None,
+        max_layers: None,
+        flatten_column: None,
+        flatten_keys: None,
+        public_id: None,
+        buffer_pixels: None,
+        scheme: None,
+        bounds: None,
+        tile_query_cache: Arc::default(),
+        tilejson_cache: TileJsonCache::default(),
     };
 
     let table_source2 = TableSource {
@@ -46,13 +106,39 @@ pub fn mock_table_sources() -> Option<TableSources> {
         schema: "public".to_owned(),
         table: "points2".to_owned(),
         id_column: None,
+        primary_key: None,
+        spatial_index: Some(true),
+        is_matview: None,
+        auto_id_column: None,
         geometry_column: "geom".to_owned(),
         srid: 3857,
         extent: Some(4096),
         buffer: Some(64),
         clip_geom: Some(true),
         geometry_type: None,
+        geometry_types: None,
         properties: HashMap::new(),
+        min_polygon_pixels: None,
+        where_sql: None,
+        max_age: None,
+        mvt_content_type: None,
+        response_headers: None,
+        simplify: None,
+        simplify_zooms: None,
+        simplify_algorithm: None,
+        make_valid: None,
+        feature_limit: None,
+        updated_at_column: None,
+        layer_column: None,
+        max_layers: None,
+        flatten_column: None,
+        flatten_keys: None,
+        public_id: None,
+        buffer_pixels: None,
+        scheme: None,
+        bounds: None,
+        tile_query_cache: Arc::default(),
+        tilejson_cache: TileJsonCache::default(),
     };
 
     let mut table_sources: TableSources = HashMap::new();
@@ -71,6 +157,13 @@ pub fn mock_function_sources() -> Option<FunctionSources> {
             id: "public.function_source".to_owned(),
             schema: "public".to_owned(),
             function: "function_source".to_owned(),
+            max_age: None,
+            mvt_content_type: None,
+            response_headers: None,
+            scheme: None,
+            minzoom: None,
+            maxzoom: None,
+            tilejson_cache: TileJsonCache::default(),
         }),
     );
 
@@ -80,6 +173,13 @@ pub fn mock_function_sources() -> Option<FunctionSources> {
             id: "public.function_source_query_params".to_owned(),
             schema: "public".to_owned(),
             function: "function_source_query_params".to_owned(),
+            max_age: None,
+            mvt_content_type: None,
+            response_headers: None,
+            scheme: None,
+            minzoom: None,
+            maxzoom: None,
+            tilejson_cache: TileJsonCache::default(),
         }),
     );
 
@@ -94,10 +194,18 @@ pub fn mock_state(
     let connection_string: String = env::var("DATABASE_URL").unwrap();
     info!("Connecting to {}", connection_string);
 
-    let pool = setup_connection_pool(&connection_string, Some(1), false).unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, true, None, None).unwrap();
     info!("Connected to {}", connection_string);
 
-    let db = SyncArbiter::start(3, move || DbActor(pool.clone()));
+    let db = SyncArbiter::start(DEFAULT_DB_WORKERS, move || DbActor {
+        pool: pool.clone(),
+        retries: 0,
+        retry_backoff: Duration::from_millis(100),
+        max_retry_sleep: MAX_RETRY_BACKOFF,
+        slow_query: None,
+        circuit_breaker: None,
+    });
     let coordinator: Addr<_> = CoordinatorActor::default().start();
 
     let table_sources = Rc::new(RefCell::new(table_sources));
@@ -105,9 +213,40 @@ pub fn mock_state(
 
     AppState {
         db,
+        replicas: Vec::new(),
+        next_replica: Cell::new(0),
+        db_queue: DbQueue::new(DEFAULT_DB_WORKERS),
         coordinator,
         table_sources,
         function_sources,
+        pmtiles_sources: None,
+        source_filter: SourceFilter::default(),
         watch_mode,
+        debug_grid: false,
+        list_sources_on_not_found: false,
+        overzoom: false,
+        mvt_content_type: DEFAULT_MVT_CONTENT_TYPE.to_owned(),
+        empty_tile_mode: EmptyTileMode::default(),
+        allow_tile_param_overrides: false,
+        max_function_param_length: DEFAULT_MAX_PARAM_LENGTH,
+        max_tile_bytes: None,
+        min_allowed_zoom: None,
+        debug_feature_count: false,
+        zstd_level: DEFAULT_ZSTD_LEVEL,
+        admin_token: None,
+        circuit_breaker: None,
+        tenant_schemas: None,
+        geojson_tiles: false,
+        log_format: LogFormat::Text,
+        max_age: DEFAULT_MAX_AGE,
+        table_source_defaults: TableSourceDefaults::default(),
+        public_base_url: None,
+        fonts_directory: None,
+        trust_proxy_headers: false,
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        shutdown_timeout: 0,
+        request_timeout_ms: 0,
+        rate_limiter: None,
+        tile_cache: None,
     }
 }
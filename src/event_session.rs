@@ -0,0 +1,60 @@
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, StreamHandler};
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+
+use crate::coordinator_actor::CoordinatorActor;
+use crate::messages;
+
+/// Messages sent to `/events` subscribers
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SourceEvent {
+    /// Source was added, removed or changed, its cached tiles are stale
+    SourceChanged { source_id: String },
+}
+
+/// WebSocket connection subscribed to the coordinator source change events
+pub struct EventSession {
+    pub coordinator: Addr<CoordinatorActor>,
+}
+
+impl Actor for EventSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let addr = ctx.address().recipient();
+        self.coordinator.do_send(messages::Subscribe { addr });
+    }
+}
+
+impl Handler<messages::SourceChanged> for EventSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::SourceChanged, ctx: &mut Self::Context) -> Self::Result {
+        let event = SourceEvent::SourceChanged {
+            source_id: msg.source_id,
+        };
+
+        match serde_json::to_string(&event) {
+            Ok(event) => ctx.text(event),
+            Err(error) => error!("Can't serialize source event: {}", error),
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for EventSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(message)) => ctx.pong(&message),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => (),
+            Err(error) => {
+                warn!("Events connection error: {}", error);
+                ctx.stop();
+            }
+        }
+    }
+}
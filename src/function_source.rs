@@ -1,12 +1,13 @@
 use postgres::types::Type;
 use postgres_protocol::escape::escape_identifier;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::io;
 use tilejson::{TileJSON, TileJSONBuilder};
 
 use crate::db::Connection;
-use crate::source::{Query, Source, Tile, Xyz};
+use crate::source::{Query, Scheme, Source, Tile, TileJsonCache, Xyz};
 use crate::utils::query_to_json;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -14,10 +15,174 @@ pub struct FunctionSource {
     pub id: String,
     pub schema: String,
     pub function: String,
+    /// `Cache-Control` max-age of tiles in seconds, falls back to the global `max_age`
+    pub max_age: Option<u32>,
+    /// `Content-Type` of tiles, falls back to the global `mvt_content_type`
+    pub mvt_content_type: Option<String>,
+    /// Headers added to tile responses, replacing the default ones of the same name
+    pub response_headers: Option<HashMap<String, String>>,
+    /// Tile row numbering of requests and TileJSON, the function always gets `xyz` [default: xyz]
+    pub scheme: Option<Scheme>,
+    /// Minimum zoom level, requests below it get an empty tile without calling the function
+    pub minzoom: Option<u8>,
+    /// Maximum zoom level, requests above it get an empty tile without calling the function
+    pub maxzoom: Option<u8>,
+    #[serde(skip)]
+    pub tilejson_cache: TileJsonCache,
 }
 
 pub type FunctionSources = HashMap<String, Box<FunctionSource>>;
 
+/// Query parameters handled by the server itself (tile overrides and pagination),
+/// they are dropped instead of being passed to functions
+pub const RESERVED_PARAMS: [&str; 6] = ["extent", "buffer", "clip_geom", "page", "limit", "offset"];
+
+/// Default maximum length of a query parameter value in characters
+pub const DEFAULT_MAX_PARAM_LENGTH: usize = 256;
+
+/// Punctuation allowed in query parameter values besides letters, digits and spaces,
+/// enough for JSON values while leaving out `;`, `\`, backticks and control characters
+const PARAM_VALUE_PUNCTUATION: &str = "-_.,:+*/=()[]{}\"'@#%!?~<>|&^$";
+
+fn is_valid_param_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn is_valid_param_value(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == ' ' || PARAM_VALUE_PUNCTUATION.contains(c))
+}
+
+/// Prefix of query parameter values bound as JSON, e.g. `filter=json:{"min": 1}`
+pub const JSON_PARAM_PREFIX: &str = "json:";
+
+/// JSON value of a repeated or `json:` prefixed parameter, other values are parsed
+/// by `query_to_json` as before: as JSON when possible, as a string otherwise
+fn param_json_value(name: &str, value: &str) -> Result<Value, io::Error> {
+    match value.strip_prefix(JSON_PARAM_PREFIX) {
+        Some(json) => serde_json::from_str(json).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Query parameter '{}' is not valid JSON: {}", name, error),
+            )
+        }),
+        None => Ok(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))),
+    }
+}
+
+/// Drops reserved parameters and checks the rest before they reach a function,
+/// invalid names or values longer than `max_length` are `InvalidInput` errors.
+/// Repeated parameters are collapsed into a JSON array and `json:` prefixed values
+/// are checked to be JSON, both are passed as JSON text to be parsed by `query_to_json`.
+pub fn sanitize_query_params<I>(query: I, max_length: usize) -> Result<Query, io::Error>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, value) in query {
+        if RESERVED_PARAMS.contains(&name.as_str()) {
+            continue;
+        }
+
+        if !is_valid_param_name(&name) {
+            return Err(invalid(format!("Invalid query parameter name '{}'", name)));
+        }
+
+        if value.chars().count() > max_length {
+            return Err(invalid(format!(
+                "Query parameter '{}' is longer than {} characters",
+                name, max_length
+            )));
+        }
+
+        if !is_valid_param_value(&value) {
+            return Err(invalid(format!(
+                "Query parameter '{}' contains disallowed characters",
+                name
+            )));
+        }
+
+        values.entry(name).or_default().push(value);
+    }
+
+    let mut params = Query::new();
+    for (name, mut values) in values {
+        let value = if values.len() > 1 {
+            let array = values
+                .iter()
+                .map(|value| param_json_value(&name, value))
+                .collect::<Result<Vec<Value>, io::Error>>()?;
+
+            Value::Array(array).to_string()
+        } else {
+            let value = values.remove(0);
+            if value.starts_with(JSON_PARAM_PREFIX) {
+                param_json_value(&name, &value)?.to_string()
+            } else {
+                value
+            }
+        };
+
+        params.insert(name, value);
+    }
+
+    Ok(params)
+}
+
+impl FunctionSource {
+    pub fn is_zoom_valid(&self, zoom: i32) -> bool {
+        self.minzoom
+            .is_none_or(|minzoom| zoom >= i32::from(minzoom))
+            && self
+                .maxzoom
+                .is_none_or(|maxzoom| zoom <= i32::from(maxzoom))
+    }
+
+    /// Tile to query for the requested one. With `overzoom` tiles above `maxzoom`
+    /// are served by their `maxzoom` ancestor instead of being empty.
+    /// `None` for tiles outside of the tile grid, see `Xyz::is_valid`.
+    pub fn query_xyz(&self, xyz: &Xyz, overzoom: bool) -> Option<Xyz> {
+        if !xyz.is_valid() {
+            return None;
+        }
+
+        match self.maxzoom {
+            Some(maxzoom) if overzoom && xyz.z > i32::from(maxzoom) => {
+                Some(xyz.ancestor(i32::from(maxzoom)))
+            }
+            _ => Some(*xyz),
+        }
+    }
+
+    /// Checks that the function exists, a missing one is reported as a `NotFound` error
+    pub fn validate(&self, conn: &mut Connection) -> Result<(), io::Error> {
+        let row = conn
+            .query_one(
+                include_str!("scripts/check_function_source.sql"),
+                &[&self.schema, &self.function],
+            )
+            .map_err(io::Error::other)?;
+
+        if !row.get::<_, bool>("function_exists") {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} function {}.{} doesn't exist",
+                    self.id, self.schema, self.function
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 impl Source for FunctionSource {
     fn get_id(&self) -> &str {
         self.id.as_str()
@@ -26,13 +191,25 @@ impl Source for FunctionSource {
     fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
         let mut tilejson_builder = TileJSONBuilder::new();
 
-        tilejson_builder.scheme("xyz");
+        tilejson_builder.scheme(self.scheme.unwrap_or_default().as_str());
         tilejson_builder.name(&self.id);
         tilejson_builder.tiles(vec![]);
 
+        if let Some(minzoom) = self.minzoom {
+            tilejson_builder.minzoom(minzoom);
+        }
+
+        if let Some(maxzoom) = self.maxzoom {
+            tilejson_builder.maxzoom(maxzoom);
+        }
+
         Ok(tilejson_builder.finalize())
     }
 
+    fn tilejson_cache(&self) -> Option<&TileJsonCache> {
+        Some(&self.tilejson_cache)
+    }
+
     fn get_tile(
         &self,
         conn: &mut Connection,
@@ -66,12 +243,12 @@ impl Source for FunctionSource {
                 &raw_query,
                 &[Type::INT4, Type::INT4, Type::INT4, Type::JSON],
             )
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            .map_err(io::Error::other)?;
 
         let tile = conn
             .query_one(&query, &[&xyz.x, &xyz.y, &xyz.z, &query_json])
             .map(|row| row.get(self.function.as_str()))
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            .map_err(io::Error::other)?;
 
         Ok(tile)
     }
@@ -82,7 +259,7 @@ pub fn get_function_sources(conn: &mut Connection) -> Result<FunctionSources, io
 
     let rows = conn
         .query(include_str!("scripts/get_function_sources.sql"), &[])
-        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        .map_err(|err| io::Error::other(err.to_string()))?;
 
     for row in &rows {
         let schema: String = row.get("specific_schema");
@@ -95,6 +272,13 @@ pub fn get_function_sources(conn: &mut Connection) -> Result<FunctionSources, io
             id: id.clone(),
             schema,
             function,
+            max_age: None,
+            mvt_content_type: None,
+            response_headers: None,
+            scheme: None,
+            minzoom: None,
+            maxzoom: None,
+            tilejson_cache: TileJsonCache::default(),
         };
 
         sources.insert(id, Box::new(source));
@@ -0,0 +1,72 @@
+use itertools::Itertools;
+use std::io;
+
+use tilejson::{TileJSON, TileJSONBuilder};
+
+use crate::db::Connection;
+use crate::source::{Query, Source, Tile, TileQuery, Xyz};
+use crate::table_source::TableSource;
+use crate::utils;
+
+/// Tile features of table sources as a GeoJSON FeatureCollection, for debugging.
+/// Each feature has a `layer` member with the id of its source.
+#[derive(Clone, Debug)]
+pub struct GeoJsonSource {
+    pub id: String,
+    pub table_sources: Vec<TableSource>,
+}
+
+impl GeoJsonSource {
+    pub fn build_tile_query(&self) -> String {
+        let srid_bounds = self
+            .table_sources
+            .iter()
+            .map(|source| source.srid)
+            .unique()
+            .map(utils::get_srid_bounds)
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let features_query = self
+            .table_sources
+            .iter()
+            .map(|source| format!("({})", source.format_geojson_features_query()))
+            .collect::<Vec<String>>()
+            .join(" UNION ALL ");
+
+        format!(
+            include_str!("scripts/get_geojson_tile.sql"),
+            bounds_cte = utils::get_bounds_cte(srid_bounds),
+            features_query = features_query
+        )
+    }
+
+    pub fn tile_query(&self, xyz: &Xyz) -> TileQuery {
+        TileQuery::new(self.build_tile_query(), xyz)
+    }
+}
+
+impl Source for GeoJsonSource {
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        let mut tilejson_builder = TileJSONBuilder::new();
+
+        tilejson_builder.scheme("xyz");
+        tilejson_builder.name(&self.id);
+
+        Ok(tilejson_builder.finalize())
+    }
+
+    fn get_tile(
+        &self,
+        conn: &mut Connection,
+        xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        let tile: String = self.tile_query(xyz).query_one(conn)?.get("tile");
+        Ok(tile.into_bytes())
+    }
+}
@@ -0,0 +1,124 @@
+use prost::Message;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub mod proto {
+    tonic::include_proto!("glyphs");
+}
+
+/// Number of glyphs in a range, ranges start at multiples of it
+pub const GLYPH_RANGE_SIZE: u32 = 256;
+
+/// Last Unicode code point of the Basic Multilingual Plane covered by glyph ranges
+pub const MAX_GLYPH_ID: u32 = 65535;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Parses a `{start}-{end}` range, e.g. `0-255`, that has to be one of the 256 glyph ranges
+pub fn parse_glyph_range(range: &str) -> Result<(u32, u32), io::Error> {
+    let invalid_range = || invalid(format!("Invalid glyph range '{}'", range));
+
+    let (start, end) = range.split_once('-').ok_or_else(invalid_range)?;
+    let start: u32 = start.parse().map_err(|_| invalid_range())?;
+    let end: u32 = end.parse().map_err(|_| invalid_range())?;
+
+    if !start.is_multiple_of(GLYPH_RANGE_SIZE)
+        || end != start + GLYPH_RANGE_SIZE - 1
+        || end > MAX_GLYPH_ID
+    {
+        return Err(invalid_range());
+    }
+
+    Ok((start, end))
+}
+
+/// Font names of a comma-separated fontstack, e.g. `Open Sans Regular,Arial Unicode MS Regular`
+pub fn parse_fontstack(fontstack: &str) -> Result<Vec<&str>, io::Error> {
+    let fonts: Vec<&str> = fontstack.split(',').map(str::trim).collect();
+
+    for font in &fonts {
+        // Font names are directory names, they can't point outside of the fonts directory
+        if font.is_empty() || *font == "." || *font == ".." || font.contains(['/', '\\']) {
+            return Err(invalid(format!("Invalid font name '{}'", font)));
+        }
+    }
+
+    Ok(fonts)
+}
+
+fn font_range_path(fonts_directory: &Path, font: &str, start: u32, end: u32) -> PathBuf {
+    fonts_directory
+        .join(font)
+        .join(format!("{}-{}.pbf", start, end))
+}
+
+fn read_font_range(
+    fonts_directory: &Path,
+    font: &str,
+    start: u32,
+    end: u32,
+) -> Result<Option<Vec<u8>>, io::Error> {
+    if !fonts_directory.join(font).is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Font '{}' not found", font),
+        ));
+    }
+
+    match fs::read(font_range_path(fonts_directory, font, start, end)) {
+        Ok(data) => Ok(Some(data)),
+        // The font has no glyphs in this range
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Glyph range PBF of a fontstack. Fonts are directories of `{start}-{end}.pbf` files in
+/// `fonts_directory`. Glyphs of several fonts are combined, earlier fonts take precedence
+/// for code points present in more than one. Unknown fonts are `NotFound` errors.
+pub fn get_glyphs(
+    fonts_directory: &Path,
+    fontstack: &str,
+    start: u32,
+    end: u32,
+) -> Result<Vec<u8>, io::Error> {
+    let fonts = parse_fontstack(fontstack)?;
+
+    let mut ranges = Vec::new();
+    for font in &fonts {
+        ranges.push(read_font_range(fonts_directory, font, start, end)?);
+    }
+
+    if let [Some(data)] = ranges.as_slice() {
+        return Ok(data.clone());
+    }
+
+    let mut glyphs = BTreeMap::new();
+    for data in ranges.iter().flatten() {
+        let range = proto::Glyphs::decode(data.as_slice())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        for glyph in range.stacks.into_iter().flat_map(|stack| stack.glyphs) {
+            glyphs.entry(glyph.id).or_insert(glyph);
+        }
+    }
+
+    let combined = proto::Glyphs {
+        stacks: vec![proto::Fontstack {
+            name: fonts.join(", "),
+            range: format!("{}-{}", start, end),
+            glyphs: glyphs.into_values().collect(),
+        }],
+    };
+
+    let mut data = Vec::with_capacity(combined.encoded_len());
+    combined
+        .encode(&mut data)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    Ok(data)
+}
@@ -0,0 +1,90 @@
+use std::io;
+
+use tilejson::{TileJSON, TileJSONBuilder};
+
+use crate::db::Connection;
+use crate::mvt;
+use crate::source::{Query, Source, Tile, Xyz};
+use crate::utils;
+
+pub const GRID_SOURCE_ID: &str = "__grid";
+
+static GRID_EXTENT: u32 = 4096;
+
+/// Synthetic source that renders the boundary and coordinates of the requested tile
+/// without touching the database. Useful as a debug overlay.
+#[derive(Clone, Debug)]
+pub struct GridSource {
+    pub id: String,
+}
+
+impl Default for GridSource {
+    fn default() -> GridSource {
+        GridSource {
+            id: GRID_SOURCE_ID.to_owned(),
+        }
+    }
+}
+
+impl GridSource {
+    pub fn render_tile(&self, xyz: &Xyz) -> Tile {
+        let (xmin, ymin, xmax, ymax) = utils::tile_bounds(xyz);
+        let extent = f64::from(GRID_EXTENT);
+
+        let to_tile_space = |x: f64, y: f64| {
+            let tile_x = (x - xmin) / (xmax - xmin) * extent;
+            let tile_y = (ymax - y) / (ymax - ymin) * extent;
+            (tile_x.round() as i32, tile_y.round() as i32)
+        };
+
+        let ring = vec![
+            to_tile_space(xmin, ymax),
+            to_tile_space(xmax, ymax),
+            to_tile_space(xmax, ymin),
+            to_tile_space(xmin, ymin),
+        ];
+
+        let feature = mvt::Feature {
+            rings: vec![ring],
+            properties: vec![
+                ("z".to_owned(), mvt::Value::Int(i64::from(xyz.z))),
+                ("x".to_owned(), mvt::Value::Int(i64::from(xyz.x))),
+                ("y".to_owned(), mvt::Value::Int(i64::from(xyz.y))),
+                (
+                    "zxy".to_owned(),
+                    mvt::Value::String(format!("{}/{}/{}", xyz.z, xyz.x, xyz.y)),
+                ),
+            ],
+        };
+
+        mvt::encode(&[mvt::Layer {
+            name: self.id.clone(),
+            extent: GRID_EXTENT,
+            features: vec![feature],
+        }])
+    }
+}
+
+impl Source for GridSource {
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        let mut tilejson_builder = TileJSONBuilder::new();
+
+        tilejson_builder.scheme("xyz");
+        tilejson_builder.name(&self.id);
+
+        Ok(tilejson_builder.finalize())
+    }
+
+    fn get_tile(
+        &self,
+        _conn: &mut Connection,
+        xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        Ok(self.render_tile(xyz))
+    }
+}
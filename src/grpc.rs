@@ -0,0 +1,343 @@
+use futures::stream::{self, Stream, StreamExt};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix::Addr;
+use tonic::{transport, Code, Request, Response, Status};
+
+use crate::composite_source::CompositeSource;
+use crate::coordinator_actor::SharedSources;
+use crate::db_actor::DbActor;
+use crate::db_queue::{DbQueue, Priority};
+use crate::function_source::sanitize_query_params;
+use crate::grid_source::{GridSource, GRID_SOURCE_ID};
+use crate::messages;
+use crate::rate_limiter::RateLimiter;
+use crate::server_error::{check_tile_size, ServerError};
+use crate::source::{Source, Tile, Xyz};
+use crate::tenant::TenantSchemas;
+use crate::utils::tile_intersects_bounds;
+
+pub mod proto {
+    tonic::include_proto!("martin");
+}
+
+use proto::tiles_server::{Tiles, TilesServer};
+use proto::{TileChunk, TileRequest};
+
+/// Size of `TileChunk` messages, tiles are streamed in parts of this size
+pub const TILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// gRPC counterpart of the tile endpoints, with the same sources and checks
+pub struct TileService {
+    pub db: Addr<DbActor>,
+    /// Read replicas serving tile queries, `db` is used when empty
+    pub replicas: Vec<Addr<DbActor>>,
+    pub next_replica: AtomicUsize,
+    pub db_queue: DbQueue,
+    /// Sources kept current by the coordinator, see `messages::ShareSources`
+    pub sources: SharedSources,
+    pub debug_grid: bool,
+    /// Serve function source tiles above `maxzoom` from their `maxzoom` ancestor
+    pub overzoom: bool,
+    /// Maximum length of function source query parameter values
+    pub max_function_param_length: usize,
+    /// Larger tiles are rejected
+    pub max_tile_bytes: Option<usize>,
+    /// Database tiles of lower zooms are empty without querying
+    pub min_allowed_zoom: Option<u8>,
+    /// Shared with the HTTP server, clients are keyed by their IP address
+    pub rate_limiter: Option<RateLimiter>,
+    /// Tenants are identified by the request metadata key of the header
+    pub tenant_schemas: Option<TenantSchemas>,
+}
+
+type TileStream = Pin<Box<dyn Stream<Item = Result<TileChunk, Status>> + Send + Sync>>;
+type SourceTile = (Box<dyn Source + Send>, Xyz);
+
+impl TileService {
+    fn tile_db(&self) -> &Addr<DbActor> {
+        if self.replicas.is_empty() {
+            return &self.db;
+        }
+
+        let replica = self.next_replica.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+        &self.replicas[replica]
+    }
+
+    fn check_rate_limit<T>(&self, request: &Request<T>) -> Result<(), ServerError> {
+        let rate_limiter = match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter,
+            None => return Ok(()),
+        };
+
+        let client = request
+            .remote_addr()
+            .map_or_else(|| "unknown".to_owned(), |address| address.ip().to_string());
+
+        rate_limiter
+            .check(&client)
+            .map_err(|wait| ServerError::TooManyRequests {
+                retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+            })
+    }
+
+    /// Same as the HTTP tenant header, read from the request metadata
+    fn tenant_search_path<T>(&self, request: &Request<T>) -> Result<Option<String>, ServerError> {
+        let tenant_schemas = match &self.tenant_schemas {
+            Some(tenant_schemas) => tenant_schemas,
+            None => return Ok(None),
+        };
+
+        // Metadata keys are lowercase
+        let tenant_id = request
+            .metadata()
+            .get(tenant_schemas.header().to_ascii_lowercase().as_str())
+            .and_then(|tenant_id| tenant_id.to_str().ok());
+
+        tenant_schemas
+            .schema(tenant_id)
+            .map(|schema| Some(schema.to_owned()))
+            .ok_or_else(|| {
+                ServerError::Forbidden(match tenant_id {
+                    Some(tenant_id) => format!("Unknown tenant '{}'", tenant_id),
+                    None => format!("Missing {} metadata", tenant_schemas.header()),
+                })
+            })
+    }
+
+    fn is_below_min_allowed_zoom(&self, xyz: &Xyz) -> bool {
+        self.min_allowed_zoom
+            .is_some_and(|min_allowed_zoom| xyz.z < i32::from(min_allowed_zoom))
+    }
+
+    /// Same lookup as the HTTP tile endpoints, table or composite sources and then
+    /// function sources. `None` for tiles outside of the tile grid, below `min_allowed_zoom`,
+    /// outside of the bounds of all table sources or of the function source zoom range.
+    fn get_source(&self, request: &TileRequest) -> Result<Option<SourceTile>, ServerError> {
+        let xyz = Xyz {
+            z: request.z,
+            x: request.x,
+            y: request.y,
+        };
+
+        let table_sources: Vec<_> = {
+            let table_sources = self
+                .sources
+                .table_sources
+                .read()
+                .unwrap_or_else(|error| error.into_inner());
+
+            table_sources
+                .iter()
+                .flat_map(|table_sources| {
+                    request
+                        .source_id
+                        .split(',')
+                        .filter_map(move |source_id| table_sources.get(source_id))
+                })
+                .map(|source| source.as_ref().clone())
+                .collect()
+        };
+
+        if !table_sources.is_empty() {
+            // Tiles outside of the tile grid are empty, their `tms` rows can't be flipped
+            if !xyz.is_valid() {
+                return Ok(None);
+            }
+
+            let mut source = CompositeSource {
+                id: request.source_id.clone(),
+                table_sources,
+                count_features: false,
+            };
+
+            // Sources have no features in tiles outside of their bounds
+            let tile_xyz = source.scheme().to_xyz(&xyz);
+            source.table_sources.retain(|table_source| {
+                table_source
+                    .bounds
+                    .as_deref()
+                    .is_none_or(|bounds| tile_intersects_bounds(&tile_xyz, bounds))
+            });
+
+            if source.table_sources.is_empty() || self.is_below_min_allowed_zoom(&xyz) {
+                return Ok(None);
+            }
+
+            return Ok(Some((Box::new(source), tile_xyz)));
+        }
+
+        let function_sources = self
+            .sources
+            .function_sources
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+
+        match function_sources
+            .as_ref()
+            .and_then(|function_sources| function_sources.get(&request.source_id))
+        {
+            Some(source) => {
+                let query_xyz = match source.query_xyz(&xyz, self.overzoom) {
+                    Some(query_xyz)
+                        if source.is_zoom_valid(query_xyz.z)
+                            && !self.is_below_min_allowed_zoom(&xyz) =>
+                    {
+                        query_xyz
+                    }
+                    _ => return Ok(None),
+                };
+
+                let xyz = source.scheme.unwrap_or_default().to_xyz(&query_xyz);
+                let source: Box<dyn Source + Send> = source.clone();
+                Ok(Some((source, xyz)))
+            }
+            None => Err(ServerError::SourceNotFound(format!(
+                "Source '{}' not found",
+                request.source_id
+            ))),
+        }
+    }
+
+    async fn get_tile_data(
+        &self,
+        request: TileRequest,
+        search_path: Option<String>,
+    ) -> Result<Tile, ServerError> {
+        if self.debug_grid && request.source_id == GRID_SOURCE_ID {
+            let xyz = Xyz {
+                z: request.z,
+                x: request.x,
+                y: request.y,
+            };
+
+            let tile = GridSource::default().render_tile(&xyz);
+            check_tile_size(&tile, self.max_tile_bytes, &request.source_id, &xyz)?;
+            return Ok(tile);
+        }
+
+        let request_xyz = Xyz {
+            z: request.z,
+            x: request.x,
+            y: request.y,
+        };
+
+        let (source, xyz) = match self.get_source(&request)? {
+            Some(source) => source,
+            None => return Ok(Tile::new()),
+        };
+
+        let query = if request.query.is_empty() {
+            None
+        } else {
+            Some(sanitize_query_params(
+                request.query,
+                self.max_function_param_length,
+            )?)
+        };
+
+        let span = tracing::info_span!(
+            "grpc_tile_request",
+            source_id = %request.source_id,
+            z = request.z,
+            x = request.x,
+            y = request.y
+        );
+
+        let message = messages::GetTile {
+            xyz,
+            query,
+            source,
+            span,
+            search_path,
+        };
+
+        let _permit = self.db_queue.acquire(Priority::Interactive).await;
+
+        let tile_data = self
+            .tile_db()
+            .send(message)
+            .await
+            .map_err(|error| ServerError::Internal(error.to_string()))??;
+
+        check_tile_size(
+            &tile_data.tile,
+            self.max_tile_bytes,
+            &request.source_id,
+            &request_xyz,
+        )?;
+
+        Ok(tile_data.tile)
+    }
+}
+
+#[tonic::async_trait]
+impl Tiles for TileService {
+    type GetTileStream = TileStream;
+
+    async fn get_tile(
+        &self,
+        request: Request<TileRequest>,
+    ) -> Result<Response<Self::GetTileStream>, Status> {
+        self.check_rate_limit(&request).map_err(status)?;
+        let search_path = self.tenant_search_path(&request).map_err(status)?;
+
+        let tile = self
+            .get_tile_data(request.into_inner(), search_path)
+            .await
+            .map_err(status)?;
+
+        let chunks: Vec<TileChunk> = tile
+            .chunks(TILE_CHUNK_SIZE)
+            .map(|data| TileChunk {
+                data: data.to_vec(),
+            })
+            .collect();
+
+        Ok(Response::new(Box::pin(stream::iter(chunks).map(Ok))))
+    }
+}
+
+/// gRPC status with the same meaning as the HTTP status of the error
+fn status(error: ServerError) -> Status {
+    let code = match error {
+        ServerError::SourceNotFound(_)
+        | ServerError::SourceNotFoundAmong { .. }
+        | ServerError::FeatureNotFound(_)
+        | ServerError::FontNotFound(_) => Code::NotFound,
+        ServerError::UnsupportedFormat(_)
+        | ServerError::InvalidParams(_)
+        | ServerError::InvalidQueryParam { .. } => Code::InvalidArgument,
+        ServerError::DatabaseUnavailable(_) | ServerError::ShuttingDown { .. } => Code::Unavailable,
+        ServerError::Timeout(_) => Code::DeadlineExceeded,
+        ServerError::TooManyRequests { .. } => Code::ResourceExhausted,
+        ServerError::Unauthorized(_) => Code::Unauthenticated,
+        ServerError::Forbidden(_) => Code::PermissionDenied,
+        ServerError::TileJson(_)
+        | ServerError::RangeNotSatisfiable { .. }
+        | ServerError::TileTooLarge(_)
+        | ServerError::Database(_)
+        | ServerError::Internal(_) => Code::Internal,
+    };
+
+    if code == Code::Internal {
+        error!("{}", error);
+    }
+
+    let message = match error {
+        ServerError::Database(_) => "Can't get data from the database".to_owned(),
+        _ => error.to_string(),
+    };
+
+    Status::new(code, message)
+}
+
+/// Serves `service` on `address` until the server is stopped
+pub async fn serve(service: TileService, address: SocketAddr) -> Result<(), transport::Error> {
+    transport::Server::builder()
+        .add_service(TilesServer::new(service))
+        .serve(address)
+        .await
+}
@@ -1,16 +1,43 @@
 #[macro_use]
 extern crate log;
 
+pub mod blocking;
+pub mod byte_range;
+pub mod circuit_breaker;
 pub mod composite_source;
 pub mod config;
+pub mod config_watcher;
 pub mod coordinator_actor;
 pub mod db;
 pub mod db_actor;
+pub mod db_queue;
 pub mod dev;
+pub mod event_session;
 pub mod function_source;
+pub mod geojson_source;
+pub mod glyphs;
+pub mod grid_source;
+pub mod grpc;
 pub mod messages;
+pub mod metrics;
+pub mod mvt;
+pub mod notify_listener;
+pub mod pmtiles_source;
+pub mod query_params;
+pub mod rate_limiter;
+pub mod request_id;
+pub mod request_log;
+pub mod seed;
+pub mod select_list;
 pub mod server;
+pub mod server_error;
 pub mod source;
+pub mod source_filter;
+pub mod static_files;
 pub mod table_source;
+pub mod telemetry;
+pub mod tenant;
+pub mod tile_cache;
+pub mod tls;
 pub mod utils;
 pub mod worker_actor;
@@ -1,9 +1,10 @@
-use actix::{Addr, Message};
+use actix::{Addr, Message, Recipient};
 use std::io;
 
+use crate::coordinator_actor::SharedSources;
 use crate::function_source::FunctionSources;
-use crate::source::{Query, Source, Tile, Xyz};
-use crate::table_source::TableSources;
+use crate::source::{Query, Source, TileData, Xyz};
+use crate::table_source::{TableSource, TableSourceDefaults, TableSources};
 use crate::worker_actor::WorkerActor;
 
 pub struct Connect {
@@ -14,17 +15,50 @@ impl Message for Connect {
     type Result = Addr<WorkerActor>;
 }
 
+/// Registers sources the coordinator keeps current with refreshes
+pub struct ShareSources {
+    pub sources: SharedSources,
+}
+
+impl Message for ShareSources {
+    type Result = ();
+}
+
 pub struct GetTile {
     pub xyz: Xyz,
     pub query: Option<Query>,
     pub source: Box<dyn Source + Send>,
+    /// Request span, parent of the database query span
+    pub span: tracing::Span,
+    /// Tenant schema put first in `search_path` for the query, see `TenantSchemas`
+    pub search_path: Option<String>,
 }
 
 impl Message for GetTile {
-    type Result = Result<Tile, io::Error>;
+    type Result = Result<TileData, io::Error>;
+}
+
+pub struct GetFeature {
+    pub id: String,
+    pub source: TableSource,
+}
+
+impl Message for GetFeature {
+    type Result = Result<Option<String>, io::Error>;
+}
+
+pub struct RefreshMatview {
+    pub source: TableSource,
+}
+
+impl Message for RefreshMatview {
+    type Result = Result<(), io::Error>;
+}
+
+pub struct GetTableSources {
+    pub defaults: TableSourceDefaults,
 }
 
-pub struct GetTableSources {}
 impl Message for GetTableSources {
     type Result = Result<TableSources, io::Error>;
 }
@@ -49,3 +83,21 @@ pub struct RefreshFunctionSources {
 impl Message for RefreshFunctionSources {
     type Result = ();
 }
+
+/// Sent to subscribers when a source is added, removed or changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceChanged {
+    pub source_id: String,
+}
+
+impl Message for SourceChanged {
+    type Result = ();
+}
+
+pub struct Subscribe {
+    pub addr: Recipient<SourceChanged>,
+}
+
+impl Message for Subscribe {
+    type Result = ();
+}
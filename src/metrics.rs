@@ -0,0 +1,103 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the pool wait histogram buckets in seconds
+const POOL_WAIT_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+
+/// Time spent waiting for a pooled database connection
+pub static DB_POOL_WAIT: Histogram = Histogram::new(
+    "db_pool_wait_seconds",
+    "Time spent waiting for a pooled database connection",
+);
+
+/// Prometheus style histogram with fixed buckets, updated without locking
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    counts: [AtomicU64; POOL_WAIT_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new(name: &'static str, help: &'static str) -> Histogram {
+        Histogram {
+            name,
+            help,
+            counts: [const { AtomicU64::new(0) }; POOL_WAIT_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let micros = duration.as_micros() as u64;
+
+        for (bound, count) in POOL_WAIT_BUCKETS.iter().zip(&self.counts) {
+            if seconds <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Longest observed duration
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros.load(Ordering::Relaxed))
+    }
+
+    /// Prometheus text exposition of the histogram and its max as a `_max` gauge
+    pub fn render(&self, output: &mut String) {
+        let seconds = |micros: u64| micros as f64 / 1_000_000.0;
+
+        let _ = writeln!(output, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(output, "# TYPE {} histogram", self.name);
+
+        for (bound, count) in POOL_WAIT_BUCKETS.iter().zip(&self.counts) {
+            let _ = writeln!(
+                output,
+                "{}_bucket{{le=\"{}\"}} {}",
+                self.name,
+                bound,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let count = self.count();
+        let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", self.name, count);
+        let _ = writeln!(
+            output,
+            "{}_sum {}",
+            self.name,
+            seconds(self.sum_micros.load(Ordering::Relaxed))
+        );
+        let _ = writeln!(output, "{}_count {}", self.name, count);
+
+        let _ = writeln!(output, "# HELP {}_max Longest observed value", self.name);
+        let _ = writeln!(output, "# TYPE {}_max gauge", self.name);
+        let _ = writeln!(
+            output,
+            "{}_max {}",
+            self.name,
+            seconds(self.max_micros.load(Ordering::Relaxed))
+        );
+    }
+}
+
+/// All metrics in the Prometheus text format
+pub fn render_metrics() -> String {
+    let mut output = String::new();
+    DB_POOL_WAIT.render(&mut output);
+    output
+}
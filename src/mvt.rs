@@ -0,0 +1,155 @@
+// Minimal Mapbox Vector Tile encoder for tiles that are built without the database.
+// https://github.com/mapbox/vector-tile-spec/blob/master/2.1/vector_tile.proto
+
+use crate::source::Tile;
+
+const WIRE_TYPE_VARINT: u64 = 0;
+const WIRE_TYPE_LENGTH_DELIMITED: u64 = 2;
+
+const GEOM_TYPE_POLYGON: u64 = 3;
+
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+pub enum Value {
+    String(String),
+    Int(i64),
+}
+
+pub struct Feature {
+    /// Polygon rings in tile coordinate space, exterior ring first
+    pub rings: Vec<Vec<(i32, i32)>>,
+    pub properties: Vec<(String, Value)>,
+}
+
+pub struct Layer {
+    pub name: String,
+    pub extent: u32,
+    pub features: Vec<Feature>,
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_key(buffer: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buffer, (field << 3) | wire_type);
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_key(buffer, field, WIRE_TYPE_VARINT);
+    write_varint(buffer, value);
+}
+
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_key(buffer, field, WIRE_TYPE_LENGTH_DELIMITED);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_packed_field(buffer: &mut Vec<u8>, field: u64, values: &[u32]) {
+    let mut packed = Vec::new();
+    for value in values {
+        write_varint(&mut packed, u64::from(*value));
+    }
+    write_bytes_field(buffer, field, &packed);
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn encode_polygon(rings: &[Vec<(i32, i32)>]) -> Vec<u32> {
+    let mut geometry = Vec::new();
+    let mut cursor = (0, 0);
+
+    for ring in rings {
+        for (i, &(x, y)) in ring.iter().enumerate() {
+            if i == 0 {
+                geometry.push(command(COMMAND_MOVE_TO, 1));
+            } else if i == 1 {
+                geometry.push(command(COMMAND_LINE_TO, ring.len() as u32 - 1));
+            }
+
+            geometry.push(zigzag(x - cursor.0));
+            geometry.push(zigzag(y - cursor.1));
+            cursor = (x, y);
+        }
+        geometry.push(command(COMMAND_CLOSE_PATH, 1));
+    }
+
+    geometry
+}
+
+fn encode_value(value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    match value {
+        Value::String(value) => write_bytes_field(&mut buffer, 1, value.as_bytes()),
+        Value::Int(value) => write_varint_field(&mut buffer, 4, *value as u64),
+    }
+    buffer
+}
+
+fn encode_layer(layer: &Layer) -> Vec<u8> {
+    let mut keys: Vec<&str> = Vec::new();
+    let mut values: Vec<Vec<u8>> = Vec::new();
+    let mut features = Vec::new();
+
+    for feature in &layer.features {
+        let mut tags = Vec::new();
+        for (key, value) in &feature.properties {
+            let key_index = keys.iter().position(|k| k == key).unwrap_or_else(|| {
+                keys.push(key);
+                keys.len() - 1
+            });
+
+            let value = encode_value(value);
+            let value_index = values.iter().position(|v| v == &value).unwrap_or_else(|| {
+                values.push(value);
+                values.len() - 1
+            });
+
+            tags.push(key_index as u32);
+            tags.push(value_index as u32);
+        }
+
+        let mut buffer = Vec::new();
+        write_packed_field(&mut buffer, 2, &tags);
+        write_varint_field(&mut buffer, 3, GEOM_TYPE_POLYGON);
+        write_packed_field(&mut buffer, 4, &encode_polygon(&feature.rings));
+        features.push(buffer);
+    }
+
+    let mut buffer = Vec::new();
+    write_varint_field(&mut buffer, 15, 2);
+    write_bytes_field(&mut buffer, 1, layer.name.as_bytes());
+    for feature in &features {
+        write_bytes_field(&mut buffer, 2, feature);
+    }
+    for key in &keys {
+        write_bytes_field(&mut buffer, 3, key.as_bytes());
+    }
+    for value in &values {
+        write_bytes_field(&mut buffer, 4, value);
+    }
+    write_varint_field(&mut buffer, 5, u64::from(layer.extent));
+
+    buffer
+}
+
+pub fn encode(layers: &[Layer]) -> Tile {
+    let mut tile = Vec::new();
+    for layer in layers {
+        write_bytes_field(&mut tile, 3, &encode_layer(layer));
+    }
+    tile
+}
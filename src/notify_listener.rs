@@ -0,0 +1,93 @@
+use actix::Addr;
+use postgres::fallible_iterator::FallibleIterator;
+use postgres_protocol::escape::escape_identifier;
+use serde_json::Value;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::coordinator_actor::CoordinatorActor;
+use crate::db::connect;
+use crate::messages;
+use crate::utils::prettify_error;
+
+/// Delay before listening again after the connection is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Source id of a notification payload, either the source id itself
+/// or a JSON object with a `source_id` key
+pub fn parse_notification_payload(payload: &str) -> Option<String> {
+    let payload = payload.trim();
+
+    if payload.starts_with('{') {
+        let value: Value = serde_json::from_str(payload).ok()?;
+        return value
+            .get("source_id")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+    }
+
+    if payload.is_empty() {
+        return None;
+    }
+
+    Some(payload.to_owned())
+}
+
+/// Listens to `channel` on a dedicated connection outside of the pool and reports
+/// the notified sources as changed to the coordinator, which evicts their cached tiles
+pub fn listen_notifications(
+    connection_string: String,
+    danger_accept_invalid_certs: bool,
+    channel: String,
+    coordinator: Addr<CoordinatorActor>,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name("notify-listener".to_owned())
+        .spawn(move || loop {
+            let result = connect(&connection_string, danger_accept_invalid_certs)
+                .and_then(|mut connection| listen(&mut connection, &channel, &coordinator));
+
+            if let Err(error) = result {
+                error!("{}", error);
+            }
+
+            thread::sleep(RECONNECT_DELAY);
+        })?;
+
+    Ok(())
+}
+
+fn listen(
+    connection: &mut postgres::Client,
+    channel: &str,
+    coordinator: &Addr<CoordinatorActor>,
+) -> io::Result<()> {
+    connection
+        .batch_execute(&format!("LISTEN {}", escape_identifier(channel)))
+        .map_err(prettify_error("Can't listen to notifications"))?;
+
+    info!("Listening to {} notifications", channel);
+
+    let mut notifications = connection.notifications();
+    let mut notifications = notifications.blocking_iter();
+
+    while let Some(notification) = notifications
+        .next()
+        .map_err(prettify_error("Notifications connection lost"))?
+    {
+        match parse_notification_payload(notification.payload()) {
+            Some(source_id) => {
+                debug!("Source {} changed", source_id);
+                coordinator.do_send(messages::SourceChanged { source_id });
+            }
+            None => warn!(
+                "Can't get source id from {} notification '{}'",
+                channel,
+                notification.payload()
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,460 @@
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tilejson::{TileJSON, TileJSONBuilder};
+
+use crate::db::Connection;
+use crate::source::{Query, Source, Tile, TileJsonCache, Xyz};
+use crate::utils::{normalize_bounds, prettify_error};
+
+// https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md
+const HEADER_LENGTH: u64 = 127;
+const MAX_DIRECTORY_DEPTH: usize = 4;
+/// Tile ids of higher zooms don't fit in `u64`
+pub const MAX_ZOOM: i32 = 31;
+/// Leaf directories kept per archive, the cache is cleared when it's full
+const MAX_CACHED_LEAF_DIRECTORIES: usize = 256;
+
+const COMPRESSION_NONE: u8 = 1;
+const COMPRESSION_GZIP: u8 = 2;
+const TILE_TYPE_UNKNOWN: u8 = 0;
+const TILE_TYPE_MVT: u8 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PmtilesSource {
+    pub id: String,
+    /// Local file path or HTTP(S) URL (e.g. a public or presigned S3 object)
+    pub path: String,
+    /// `Cache-Control` max-age of tiles in seconds, falls back to the global `max_age`
+    pub max_age: Option<u32>,
+    /// `Content-Type` of tiles, falls back to the global `mvt_content_type`
+    pub mvt_content_type: Option<String>,
+    /// TileJSON is read from the archive header and metadata once
+    #[serde(skip)]
+    pub tilejson_cache: TileJsonCache,
+    /// Header and directories read from the archive, see `open`
+    #[serde(skip)]
+    pub archive_cache: ArchiveCache,
+}
+
+pub type PmtilesSources = HashMap<String, Box<PmtilesSource>>;
+
+#[derive(Debug)]
+pub struct Header {
+    pub root_dir_offset: u64,
+    pub root_dir_length: u64,
+    pub metadata_offset: u64,
+    pub metadata_length: u64,
+    pub leaf_dirs_offset: u64,
+    pub tile_data_offset: u64,
+    pub internal_compression: u8,
+    pub tile_compression: u8,
+    pub tile_type: u8,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub bounds: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+/// Header and root directory of an archive, along with the leaf directories read so far
+#[derive(Debug)]
+pub struct Archive {
+    pub header: Header,
+    root_directory: Arc<Vec<Entry>>,
+    /// Leaf directories by their offset
+    leaf_directories: Mutex<HashMap<u64, Arc<Vec<Entry>>>>,
+}
+
+/// Opened archive shared between clones of a source, so tile requests don't read
+/// the header and root directory again
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveCache(Arc<OnceLock<Archive>>);
+
+impl ArchiveCache {
+    pub fn is_opened(&self) -> bool {
+        self.0.get().is_some()
+    }
+
+    pub fn cached_leaf_directories(&self) -> usize {
+        self.0
+            .get()
+            .map_or(0, |archive| archive.leaf_directories.lock().unwrap().len())
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_i32(bytes: &[u8], at: usize) -> i32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    i32::from_le_bytes(buf)
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = bytes
+            .next()
+            .ok_or_else(|| io::Error::other("Unexpected end of PMTiles directory"))?;
+
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+
+        shift += 7;
+        if shift >= 64 {
+            return Err(io::Error::other("Malformed varint in PMTiles directory"));
+        }
+    }
+}
+
+fn decompress(data: Vec<u8>, compression: u8) -> io::Result<Vec<u8>> {
+    match compression {
+        COMPRESSION_NONE => Ok(data),
+        COMPRESSION_GZIP => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        _ => Err(io::Error::other(format!(
+            "Unsupported PMTiles compression {}",
+            compression
+        ))),
+    }
+}
+
+/// Converts tile coordinates into a PMTiles tile id
+/// (a position on the Hilbert curve, offset by the number of tiles on lower zooms).
+/// Returns `None` for zooms above `MAX_ZOOM` and coordinates outside of the zoom.
+pub fn zxy_to_tile_id(xyz: &Xyz) -> Option<u64> {
+    if !(0..=MAX_ZOOM).contains(&xyz.z) {
+        return None;
+    }
+
+    let z = xyz.z as u32;
+    let n: u64 = 1 << z;
+    if xyz.x < 0 || xyz.y < 0 || xyz.x as u64 >= n || xyz.y as u64 >= n {
+        return None;
+    }
+
+    let mut x = xyz.x as u64;
+    let mut y = xyz.y as u64;
+
+    let mut d: u64 = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        s /= 2;
+    }
+
+    let lower_zooms_tiles = ((1_u64 << (2 * z)) - 1) / 3;
+    Some(lower_zooms_tiles + d)
+}
+
+fn parse_directory(data: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut bytes = data.iter().copied();
+    let entries_count = read_varint(&mut bytes)? as usize;
+
+    let mut entries = Vec::with_capacity(entries_count);
+    let mut last_tile_id = 0;
+    for _ in 0..entries_count {
+        last_tile_id += read_varint(&mut bytes)?;
+        entries.push(Entry {
+            tile_id: last_tile_id,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        });
+    }
+
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(&mut bytes)?;
+    }
+
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(&mut bytes)?;
+    }
+
+    for i in 0..entries_count {
+        let value = read_varint(&mut bytes)?;
+        entries[i].offset = if value == 0 && i > 0 {
+            entries[i - 1].offset + entries[i - 1].length
+        } else {
+            value.saturating_sub(1)
+        };
+    }
+
+    Ok(entries)
+}
+
+fn find_entry(entries: &[Entry], tile_id: u64) -> Option<&Entry> {
+    let index = match entries.binary_search_by_key(&tile_id, |entry| entry.tile_id) {
+        Ok(index) => return Some(&entries[index]),
+        Err(0) => return None,
+        Err(index) => index - 1,
+    };
+
+    let entry = &entries[index];
+    if entry.run_length == 0 || tile_id - entry.tile_id < entry.run_length {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+impl PmtilesSource {
+    fn is_remote(&self) -> bool {
+        self.path.starts_with("http://") || self.path.starts_with("https://")
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(length as usize);
+        if length == 0 {
+            return Ok(buffer);
+        }
+
+        if self.is_remote() {
+            let range = format!("bytes={}-{}", offset, offset + length - 1);
+            let response = ureq::get(&self.path)
+                .set("Range", &range)
+                .call()
+                .map_err(prettify_error("Can't fetch PMTiles archive range"))?;
+
+            response
+                .into_reader()
+                .take(length)
+                .read_to_end(&mut buffer)?;
+        } else {
+            let mut file = File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(length).read_to_end(&mut buffer)?;
+        }
+
+        if buffer.len() as u64 != length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("PMTiles archive {} is truncated", self.id),
+            ));
+        }
+
+        Ok(buffer)
+    }
+
+    pub fn get_header(&self) -> io::Result<Header> {
+        let bytes = self.read_range(0, HEADER_LENGTH)?;
+
+        if &bytes[0..7] != b"PMTiles" {
+            return Err(io::Error::other(format!(
+                "{} is not a PMTiles archive",
+                self.id
+            )));
+        }
+
+        if bytes[7] != 3 {
+            return Err(io::Error::other(format!(
+                "{} has unsupported PMTiles version {}",
+                self.id, bytes[7]
+            )));
+        }
+
+        let bounds = [102, 106, 110, 114]
+            .iter()
+            .map(|&at| read_i32(&bytes, at) as f32 / 10_000_000.0)
+            .collect();
+
+        Ok(Header {
+            root_dir_offset: read_u64(&bytes, 8),
+            root_dir_length: read_u64(&bytes, 16),
+            metadata_offset: read_u64(&bytes, 24),
+            metadata_length: read_u64(&bytes, 32),
+            leaf_dirs_offset: read_u64(&bytes, 40),
+            tile_data_offset: read_u64(&bytes, 56),
+            internal_compression: bytes[97],
+            tile_compression: bytes[98],
+            tile_type: bytes[99],
+            min_zoom: bytes[100],
+            max_zoom: bytes[101],
+            bounds,
+        })
+    }
+
+    pub fn get_metadata(&self, header: &Header) -> io::Result<serde_json::Value> {
+        let metadata = self.read_range(header.metadata_offset, header.metadata_length)?;
+        let metadata = decompress(metadata, header.internal_compression)?;
+
+        if metadata.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+
+        serde_json::from_slice(&metadata).map_err(prettify_error("Can't parse PMTiles metadata"))
+    }
+
+    fn read_directory(&self, header: &Header, offset: u64, length: u64) -> io::Result<Vec<Entry>> {
+        let directory = self.read_range(offset, length)?;
+        let directory = decompress(directory, header.internal_compression)?;
+        parse_directory(&directory)
+    }
+
+    /// Reads the header and root directory of the archive, unless they were read already
+    pub fn open(&self) -> io::Result<&Archive> {
+        if let Some(archive) = self.archive_cache.0.get() {
+            return Ok(archive);
+        }
+
+        let header = self.get_header()?;
+        let root_directory =
+            self.read_directory(&header, header.root_dir_offset, header.root_dir_length)?;
+
+        Ok(self.archive_cache.0.get_or_init(|| Archive {
+            header,
+            root_directory: Arc::new(root_directory),
+            leaf_directories: Mutex::default(),
+        }))
+    }
+
+    fn get_leaf_directory(
+        &self,
+        archive: &Archive,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<Arc<Vec<Entry>>> {
+        if let Some(directory) = archive.leaf_directories.lock().unwrap().get(&offset) {
+            return Ok(directory.clone());
+        }
+
+        let header = &archive.header;
+        let directory =
+            Arc::new(self.read_directory(header, header.leaf_dirs_offset + offset, length)?);
+
+        let mut leaf_directories = archive.leaf_directories.lock().unwrap();
+        if leaf_directories.len() >= MAX_CACHED_LEAF_DIRECTORIES {
+            leaf_directories.clear();
+        }
+        leaf_directories.insert(offset, directory.clone());
+
+        Ok(directory)
+    }
+
+    /// Header of the opened archive, without reading it
+    pub fn opened_header(&self) -> Option<&Header> {
+        self.archive_cache.0.get().map(|archive| &archive.header)
+    }
+
+    pub fn read_tile(&self, xyz: &Xyz) -> io::Result<Tile> {
+        let archive = self.open()?;
+        let header = &archive.header;
+
+        if header.tile_type != TILE_TYPE_MVT && header.tile_type != TILE_TYPE_UNKNOWN {
+            return Err(io::Error::other(format!(
+                "{} doesn't contain vector tiles",
+                self.id
+            )));
+        }
+
+        let tile_id = match zxy_to_tile_id(xyz) {
+            Some(tile_id) => tile_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut directory = archive.root_directory.clone();
+
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            let entry = match find_entry(&directory, tile_id) {
+                Some(entry) => *entry,
+                None => return Ok(Vec::new()),
+            };
+
+            if entry.run_length > 0 {
+                let tile = self.read_range(header.tile_data_offset + entry.offset, entry.length)?;
+
+                return decompress(tile, header.tile_compression);
+            }
+
+            directory = self.get_leaf_directory(archive, entry.offset, entry.length)?;
+        }
+
+        Err(io::Error::other(format!(
+            "{} has too deeply nested directories",
+            self.id
+        )))
+    }
+}
+
+impl Source for PmtilesSource {
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        let header = &self.open()?.header;
+        let metadata = self.get_metadata(header)?;
+
+        let mut tilejson_builder = TileJSONBuilder::new();
+
+        tilejson_builder.scheme("xyz");
+        tilejson_builder.name(
+            metadata
+                .get("name")
+                .and_then(|name| name.as_str())
+                .unwrap_or(&self.id),
+        );
+
+        if let Some(description) = metadata.get("description").and_then(|v| v.as_str()) {
+            tilejson_builder.description(description);
+        }
+
+        if let Some(attribution) = metadata.get("attribution").and_then(|v| v.as_str()) {
+            tilejson_builder.attribution(attribution);
+        }
+
+        tilejson_builder.minzoom(header.min_zoom);
+        tilejson_builder.maxzoom(header.max_zoom);
+        tilejson_builder.bounds(normalize_bounds(&header.bounds));
+
+        Ok(tilejson_builder.finalize())
+    }
+
+    fn tilejson_cache(&self) -> Option<&TileJsonCache> {
+        Some(&self.tilejson_cache)
+    }
+
+    fn get_tile(
+        &self,
+        _conn: &mut Connection,
+        xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        self.read_tile(xyz)
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+/// Query string parameter that can't be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryParamError {
+    /// Name of the parameter, as sent when the name itself can't be decoded
+    pub parameter: String,
+    pub message: String,
+}
+
+impl fmt::Display for QueryParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Query parameter '{}' {}", self.parameter, self.message)
+    }
+}
+
+/// Parses an `application/x-www-form-urlencoded` query string into name and value pairs
+/// in request order. Unlike lossy decoding, malformed percent-encodings (e.g. `%zz`) and
+/// percent-encoded bytes that aren't UTF-8 (e.g. `%FF`) are errors naming the parameter.
+pub fn parse_query_params(query: &str) -> Result<Vec<(String, String)>, QueryParamError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+            let name = decode(name).map_err(|message| QueryParamError {
+                parameter: name.to_owned(),
+                message: format!("name {}", message),
+            })?;
+
+            let value = decode(value).map_err(|message| QueryParamError {
+                parameter: name.clone(),
+                message: format!("value {}", message),
+            })?;
+
+            Ok((name, value))
+        })
+        .collect()
+}
+
+fn decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("has a malformed percent-encoding at position {}", i))?;
+
+                decoded.push(byte);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8(decoded).map_err(|_| "is not valid UTF-8 when percent-decoded".to_owned())
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most clients tracked at once, buckets are dropped to stay below it
+pub const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Buckets are dropped down to this many, so the map is scanned once per
+/// `MAX_TRACKED_CLIENTS - EVICTION_TARGET` new clients at most
+const EVICTION_TARGET: usize = MAX_TRACKED_CLIENTS - MAX_TRACKED_CLIENTS / 10;
+
+/// Token bucket limits of tile requests per client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Sustained requests per second
+    pub requests_per_second: f64,
+    /// Requests allowed at once before the sustained rate applies [default: requests_per_second]
+    pub burst: Option<u32>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets keyed by client, shared between web server workers
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit) -> RateLimiter {
+        let burst = limit
+            .burst
+            .map_or(limit.requests_per_second.ceil(), f64::from)
+            .max(1.0);
+
+        RateLimiter {
+            rate: limit.requests_per_second,
+            burst,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token from the client's bucket, or returns how long to wait for one
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    pub fn check_at(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            self.evict(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+
+    /// Number of clients with a bucket
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .len()
+    }
+
+    /// Drops buckets down to `EVICTION_TARGET`, full ones first as they are the same as
+    /// new ones, then the least recently updated ones
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+
+        if buckets.len() <= EVICTION_TARGET {
+            return;
+        }
+
+        let mut clients: Vec<(Instant, String)> = buckets
+            .iter()
+            .map(|(client, bucket)| (bucket.updated_at, client.clone()))
+            .collect();
+        clients.sort_unstable();
+
+        let excess = buckets.len() - EVICTION_TARGET;
+        for (_, client) in clients.into_iter().take(excess) {
+            buckets.remove(&client);
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst)
+    }
+}
@@ -0,0 +1,105 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longer incoming ids are replaced with a generated one
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Id of the request, taken from `X-Request-Id` or generated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Incoming id if it's usable in logs and headers, a random UUID otherwise
+    pub fn from_header(header: Option<&HeaderValue>) -> RequestId {
+        let request_id = header
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid_request_id(id));
+
+        match request_id {
+            Some(request_id) => RequestId(request_id.to_owned()),
+            None => RequestId(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Request id set by the `RequestIdMiddleware`, `None` outside of it, e.g. in handler tests
+pub fn request_id(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|request_id| request_id.0.clone())
+}
+
+/// Reads or generates the request id, stores it in request extensions for handlers
+/// and logs, and echoes it in the `X-Request-Id` response header
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S> for RequestIdMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdService { service })
+    }
+}
+
+pub struct RequestIdService<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestIdService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = RequestId::from_header(req.headers().get(REQUEST_ID_HEADER));
+        req.extensions_mut().insert(request_id.clone());
+
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+
+            // Valid ids and UUIDs are visible ASCII, so they are valid header values
+            if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            Ok(response)
+        })
+    }
+}
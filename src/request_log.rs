@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::source::Xyz;
+
+/// Log target of tile request records, e.g. `RUST_LOG=martin::tiles=info`
+pub const TILE_LOG_TARGET: &str = "martin::tiles";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TileRequestLog<'a> {
+    pub source_id: &'a str,
+    pub z: i32,
+    pub x: i32,
+    pub y: i32,
+    pub bytes: usize,
+    pub empty: bool,
+    pub cache: &'static str,
+    pub db_duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl<'a> TileRequestLog<'a> {
+    pub fn new(source_id: &'a str, xyz: &Xyz, bytes: usize) -> Self {
+        TileRequestLog {
+            source_id,
+            z: xyz.z,
+            x: xyz.x,
+            y: xyz.y,
+            bytes,
+            empty: bytes == 0,
+            cache: "none",
+            db_duration_ms: None,
+            request_id: None,
+        }
+    }
+
+    pub fn db_duration(self, duration: Duration) -> Self {
+        TileRequestLog {
+            db_duration_ms: Some(duration.as_secs_f64() * 1000.0),
+            ..self
+        }
+    }
+
+    /// Tile cache status, `hit`, `miss` or `none` without a cache
+    pub fn cache(self, cache: &'static str) -> Self {
+        TileRequestLog { cache, ..self }
+    }
+
+    pub fn request_id(self, request_id: Option<String>) -> Self {
+        TileRequestLog { request_id, ..self }
+    }
+
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+            LogFormat::Text => {
+                let db_duration_ms = self
+                    .db_duration_ms
+                    .map_or("-".to_owned(), |duration| format!("{:.3}", duration));
+
+                let request_id = self
+                    .request_id
+                    .as_ref()
+                    .map_or(String::new(), |request_id| {
+                        format!(" request_id={}", request_id)
+                    });
+
+                format!(
+                    "source_id={} z={} x={} y={} bytes={} empty={} cache={} db_duration_ms={}{}",
+                    self.source_id,
+                    self.z,
+                    self.x,
+                    self.y,
+                    self.bytes,
+                    self.empty,
+                    self.cache,
+                    db_duration_ms,
+                    request_id
+                )
+            }
+        }
+    }
+
+    pub fn log(&self, format: LogFormat) {
+        info!(target: TILE_LOG_TARGET, "{}", self.format(format));
+    }
+}
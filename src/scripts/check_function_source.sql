@@ -0,0 +1,7 @@
+SELECT EXISTS (
+  SELECT 1 FROM pg_proc
+    JOIN pg_namespace ON pg_namespace.oid = pg_proc.pronamespace
+  WHERE
+    pg_namespace.nspname = $1::text
+    AND pg_proc.proname = $2::text
+) AS function_exists
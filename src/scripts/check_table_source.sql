@@ -0,0 +1,9 @@
+SELECT
+  to_regclass(format('%I.%I', $1::text, $2::text)) IS NOT NULL AS table_exists,
+  EXISTS (
+    SELECT 1 FROM pg_attribute
+    WHERE
+      attrelid = to_regclass(format('%I.%I', $1::text, $2::text))
+      AND attname = $3::text
+      AND NOT attisdropped
+  ) AS column_exists
@@ -0,0 +1,10 @@
+SELECT
+  json_build_object(
+    'type', 'Feature',
+    'id', {id_column},
+    'geometry', ST_AsGeoJSON(ST_Transform({geometry_column}, 4326))::json,
+    'properties', {properties}
+  )::text AS feature
+FROM {table}
+WHERE {id_column} = {id_param}{where_sql_filter}
+LIMIT 1
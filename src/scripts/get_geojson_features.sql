@@ -0,0 +1,9 @@
+SELECT
+  json_build_object(
+    'type', 'Feature',{id}
+    'layer', {layer},
+    'geometry', ST_AsGeoJSON(ST_Transform(ST_Intersection(ST_Transform({geometry_column}, 3857), {mercator_bounds}), 4326))::json,
+    'properties', {properties}
+  ) AS feature
+FROM {table}, bounds
+WHERE {filter}
@@ -0,0 +1,7 @@
+{bounds_cte}
+SELECT
+  json_build_object(
+    'type', 'FeatureCollection',
+    'features', COALESCE(json_agg(features.feature), '[]'::json)
+  )::text AS tile
+FROM ({features_query}) AS features
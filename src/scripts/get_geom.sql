@@ -1,4 +1,4 @@
 SELECT
-  ST_AsMVTGeom (ST_Transform ({geometry_column}, 3857), {mercator_bounds}, {extent}, {buffer}, {clip_geom}) AS geom {properties} FROM {id}, bounds
+  {select_list} FROM {table}, bounds
   WHERE
-    {geometry_column} && bounds.srid_{srid}
+    {filter}{feature_limit}
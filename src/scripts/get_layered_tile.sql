@@ -0,0 +1,10 @@
+SELECT
+  COALESCE(string_agg(mvt, ''::bytea ORDER BY layer), ''::bytea) AS st_asmvt FROM (
+    SELECT
+      tile.{layer_column} AS layer,
+      ST_AsMVT (tile, COALESCE(tile.{layer_column}::text, '{id}'), {extent}, 'geom' {id_column}) AS mvt
+    FROM ({geom_query}) AS tile
+    GROUP BY 1
+    ORDER BY 1
+    LIMIT {max_layers}
+  ) AS layers
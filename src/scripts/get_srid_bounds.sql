@@ -1 +1 @@
-ST_Transform({mercator_bounds}, {srid}) AS srid_{srid}
\ No newline at end of file
+ST_Transform(ST_Segmentize({mercator_bounds}, {segment_length}), {srid}) AS srid_{srid}
\ No newline at end of file
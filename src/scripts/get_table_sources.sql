@@ -8,16 +8,49 @@ WITH columns AS (
     JOIN pg_catalog.pg_class AS class ON class.oid = attr.attrelid
     JOIN pg_catalog.pg_namespace AS ns ON ns.oid = class.relnamespace
     JOIN pg_catalog.pg_type AS tp ON tp.oid = attr.atttypid
-  WHERE NOT attr.attisdropped AND attr.attnum > 0)
+  WHERE NOT attr.attisdropped AND attr.attnum > 0),
+primary_keys AS (
+  SELECT
+    ns.nspname AS table_schema,
+    class.relname AS table_name,
+    attr.attname AS column_name
+  FROM pg_index idx
+    JOIN pg_catalog.pg_class AS class ON class.oid = idx.indrelid
+    JOIN pg_catalog.pg_namespace AS ns ON ns.oid = class.relnamespace
+    JOIN pg_attribute attr ON attr.attrelid = idx.indrelid AND attr.attnum = idx.indkey[0]
+  WHERE idx.indisprimary AND idx.indnatts = 1
+    AND attr.atttypid IN ('int2'::regtype, 'int4'::regtype, 'int8'::regtype))
 SELECT
   f_table_schema, f_table_name, f_geometry_column, srid, type,
+    primary_keys.column_name AS primary_key,
+    count(*) OVER (PARTITION BY f_table_schema, f_table_name) AS geometry_columns_count,
+    CASE WHEN class.relkind IN ('r', 'm', 'p') THEN EXISTS (
+      SELECT 1 FROM pg_index idx
+        JOIN pg_catalog.pg_class AS index_class ON index_class.oid = idx.indexrelid
+        JOIN pg_catalog.pg_am AS am ON am.oid = index_class.relam
+        JOIN pg_attribute attr ON attr.attrelid = idx.indrelid AND attr.attnum = ANY(idx.indkey)
+      WHERE idx.indrelid = class.oid
+        AND attr.attname = f_geometry_column
+        AND am.amname IN ('gist', 'spgist', 'brin')
+    ) END AS spatial_index,
+    class.relkind = 'm' AS is_matview,
     COALESCE(
       jsonb_object_agg(columns.column_name, columns.type_name) FILTER (WHERE columns.column_name IS NOT NULL),
       '{}'::jsonb
     ) as properties
 FROM geometry_columns
+JOIN pg_catalog.pg_namespace AS ns ON ns.nspname = geometry_columns.f_table_schema
+JOIN pg_catalog.pg_class AS class ON class.relnamespace = ns.oid AND class.relname = geometry_columns.f_table_name
 LEFT JOIN columns ON
   geometry_columns.f_table_schema = columns.table_schema AND
   geometry_columns.f_table_name = columns.table_name AND
-  geometry_columns.f_geometry_column != columns.column_name
-GROUP BY f_table_schema, f_table_name, f_geometry_column, srid, type;
\ No newline at end of file
+  geometry_columns.f_geometry_column != columns.column_name AND
+  NOT EXISTS (
+    SELECT 1 FROM geometry_columns AS other_columns
+    WHERE other_columns.f_table_schema = columns.table_schema
+      AND other_columns.f_table_name = columns.table_name
+      AND other_columns.f_geometry_column = columns.column_name)
+LEFT JOIN primary_keys ON
+  geometry_columns.f_table_schema = primary_keys.table_schema AND
+  geometry_columns.f_table_name = primary_keys.table_name
+GROUP BY f_table_schema, f_table_name, f_geometry_column, srid, type, primary_keys.column_name, class.oid, class.relkind;
\ No newline at end of file
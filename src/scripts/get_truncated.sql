@@ -0,0 +1,3 @@
+SELECT count(*) > {feature_limit} FROM (
+  SELECT 1 FROM {table}, bounds WHERE {filter} LIMIT {feature_limit} + 1
+) AS features
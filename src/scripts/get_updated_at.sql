@@ -0,0 +1 @@
+SELECT extract(epoch FROM max({updated_at_column}))::float8 FROM {table}, bounds WHERE {filter}
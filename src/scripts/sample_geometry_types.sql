@@ -0,0 +1,7 @@
+SELECT DISTINCT upper(GeometryType(geom)) AS geometry_type
+FROM (
+  SELECT {geometry_column} AS geom
+  FROM {table}
+  WHERE {geometry_column} IS NOT NULL
+  LIMIT {sample_size}
+) AS sample
@@ -0,0 +1,202 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::db::{get_connection, Pool};
+use crate::source::{Source, Xyz};
+use crate::utils::WORLD_BOUNDS;
+
+/// Tiles of the `minzoom..=maxzoom` pyramid within `bbox` written to `output`
+#[derive(Clone, Debug)]
+pub struct SeedOptions {
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    /// Bounds in degrees, `[west, south, east, north]`
+    pub bbox: [f64; 4],
+    /// Directory of `{z}/{x}/{y}.pbf` tiles in xyz numbering
+    pub output: PathBuf,
+    /// Number of tiles queried at once, at most one per pool connection
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SeedStats {
+    pub tiles: usize,
+    /// Empty tiles aren't written
+    pub empty_tiles: usize,
+    pub bytes: usize,
+}
+
+/// Parses `west,south,east,north` degrees, clamped to the web mercator world bounds
+pub fn parse_bbox(bbox: &str) -> Result<[f64; 4], io::Error> {
+    let invalid_bbox = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid bbox '{}', expected west,south,east,north", bbox),
+        )
+    };
+
+    let values = bbox
+        .split(',')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid_bbox())?;
+
+    let (west, south, east, north) = match *values.as_slice() {
+        [west, south, east, north]
+            if values.iter().all(|value| value.is_finite()) && west < east && south < north =>
+        {
+            (west, south, east, north)
+        }
+        _ => return Err(invalid_bbox()),
+    };
+
+    let [min_lon, min_lat, max_lon, max_lat] = WORLD_BOUNDS.map(f64::from);
+
+    Ok([
+        west.max(min_lon),
+        south.max(min_lat),
+        east.min(max_lon),
+        north.min(max_lat),
+    ])
+}
+
+/// Column and row of the tile containing the point at the given zoom
+fn tile_of(lon: f64, lat: f64, zoom: u8) -> (i32, i32) {
+    let tiles = f64::from(1_u32 << zoom);
+    let lat = lat.to_radians();
+
+    let x = (lon + 180.0) / 360.0 * tiles;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles;
+
+    let max = tiles - 1.0;
+    (
+        x.floor().clamp(0.0, max) as i32,
+        y.floor().clamp(0.0, max) as i32,
+    )
+}
+
+/// Tiles of the zoom covering the bbox as `(min_x, min_y, max_x, max_y)`, inclusive
+pub fn tile_range(bbox: &[f64; 4], zoom: u8) -> (i32, i32, i32, i32) {
+    let [west, south, east, north] = *bbox;
+
+    // Rows are counted from the north
+    let (min_x, min_y) = tile_of(west, north, zoom);
+    let (max_x, max_y) = tile_of(east, south, zoom);
+
+    (min_x, min_y, max_x, max_y)
+}
+
+/// Tiles of the pyramid covering the bbox, zoom by zoom
+pub fn tiles(bbox: [f64; 4], minzoom: u8, maxzoom: u8) -> impl Iterator<Item = Xyz> {
+    (minzoom..=maxzoom).flat_map(move |zoom| {
+        let (min_x, min_y, max_x, max_y) = tile_range(&bbox, zoom);
+        let z = i32::from(zoom);
+
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| Xyz { z, x, y }))
+    })
+}
+
+/// Number of tiles of the pyramid covering the bbox
+pub fn count_tiles(bbox: &[f64; 4], minzoom: u8, maxzoom: u8) -> u64 {
+    (minzoom..=maxzoom)
+        .map(|zoom| {
+            let (min_x, min_y, max_x, max_y) = tile_range(bbox, zoom);
+            (max_x - min_x + 1) as u64 * (max_y - min_y + 1) as u64
+        })
+        .sum()
+}
+
+fn write_tile(output: &Path, xyz: &Xyz, tile: &[u8]) -> io::Result<()> {
+    let directory = output.join(xyz.z.to_string()).join(xyz.x.to_string());
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(format!("{}.pbf", xyz.y)), tile)
+}
+
+/// Queries the tiles of the pyramid with `concurrency` threads sharing the pool and writes
+/// the non-empty ones. The first failed tile stops seeding and is returned as the error.
+pub fn seed(
+    source: &(dyn Source + Sync),
+    pool: &Pool,
+    options: &SeedOptions,
+) -> io::Result<SeedStats> {
+    let started_at = Instant::now();
+    let total = count_tiles(&options.bbox, options.minzoom, options.maxzoom);
+    info!(
+        "Seeding {} tiles of {} at zooms {}-{} into {}",
+        total,
+        source.get_id(),
+        options.minzoom,
+        options.maxzoom,
+        options.output.display()
+    );
+
+    let pending = Mutex::new(tiles(options.bbox, options.minzoom, options.maxzoom));
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None);
+    let done = AtomicUsize::new(0);
+    let empty_tiles = AtomicUsize::new(0);
+    let bytes = AtomicUsize::new(0);
+
+    let seed_tiles = || -> io::Result<()> {
+        let mut connection = get_connection(pool)?;
+
+        while !failed.load(Ordering::SeqCst) {
+            let xyz = match pending.lock().unwrap().next() {
+                Some(xyz) => xyz,
+                None => break,
+            };
+
+            let tile = source.get_tile(&mut connection, &xyz, &None)?;
+            if tile.is_empty() {
+                empty_tiles.fetch_add(1, Ordering::SeqCst);
+            } else {
+                write_tile(&options.output, &xyz, &tile)?;
+                bytes.fetch_add(tile.len(), Ordering::SeqCst);
+            }
+
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            if done.is_multiple_of(1000) {
+                info!("Seeded {} of {} tiles", done, total);
+            }
+        }
+
+        Ok(())
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1) {
+            scope.spawn(|| {
+                if let Err(seed_error) = seed_tiles() {
+                    failed.store(true, Ordering::SeqCst);
+                    error.lock().unwrap().get_or_insert(seed_error);
+                }
+            });
+        }
+    });
+
+    if let Some(error) = error.into_inner().unwrap() {
+        return Err(error);
+    }
+
+    let stats = SeedStats {
+        tiles: done.into_inner(),
+        empty_tiles: empty_tiles.into_inner(),
+        bytes: bytes.into_inner(),
+    };
+
+    info!(
+        "Seeded {} tiles ({} empty, {} bytes) in {:?}",
+        stats.tiles,
+        stats.empty_tiles,
+        stats.bytes,
+        started_at.elapsed()
+    );
+
+    Ok(stats)
+}
@@ -0,0 +1,54 @@
+/// Builds a comma separated SELECT list, skipping empty fragments so that
+/// no leading, trailing or doubled commas end up in the query
+#[derive(Clone, Debug, Default)]
+pub struct SelectList {
+    fragments: Vec<String>,
+}
+
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+impl SelectList {
+    pub fn new() -> SelectList {
+        SelectList::default()
+    }
+
+    /// Adds a raw SQL expression, empty expressions are ignored
+    pub fn expression(mut self, expression: &str) -> SelectList {
+        let expression = expression.trim();
+        if !expression.is_empty() {
+            self.fragments.push(expression.to_owned());
+        }
+
+        self
+    }
+
+    /// Adds a quoted column reference
+    pub fn column(self, column: &str) -> SelectList {
+        if column.is_empty() {
+            return self;
+        }
+
+        let column = quote_identifier(column);
+        self.expression(&column)
+    }
+
+    /// Adds a computed property, i.e. `expression AS "alias"`
+    pub fn computed(self, expression: &str, alias: &str) -> SelectList {
+        if expression.trim().is_empty() || alias.is_empty() {
+            return self;
+        }
+
+        let expression = format!("{} AS {}", expression.trim(), quote_identifier(alias));
+        self.expression(&expression)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    pub fn build(&self) -> String {
+        self.fragments.join(", ")
+    }
+}
@@ -1,32 +1,137 @@
-use serde::Deserialize;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use flate2::write::GzEncoder;
+use flate2::{Compression, Crc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::{self, Write};
+use std::iter;
+use std::net::SocketAddr;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix::{Actor, Addr, SyncArbiter, SystemRunner};
 use actix_cors::Cors;
-use actix_web::{
-    error, http, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer, Result,
-};
+use actix_rt::time::timeout;
+use actix_web::dev::BodyEncoding;
+use actix_web::error::{BlockingError, QueryPayloadError};
+use actix_web::http::ContentEncoding;
+use actix_web::{http, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer, Result};
+use actix_web_actors::ws;
 
+use crate::blocking;
+use crate::byte_range::{parse_range, ByteRange};
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
 use crate::composite_source::CompositeSource;
-use crate::config::Config;
-use crate::coordinator_actor::CoordinatorActor;
+use crate::config::{clamp_worker_processes, Config};
+use crate::config_watcher::{reload_on_sighup, watch_config};
+use crate::coordinator_actor::{CoordinatorActor, SharedSources};
 use crate::db::Pool;
-use crate::db_actor::DbActor;
-use crate::function_source::FunctionSources;
+use crate::db_actor::{max_retry_sleep, DbActor};
+use crate::db_queue::{DbQueue, Priority};
+use crate::event_session::EventSession;
+use crate::function_source::{sanitize_query_params, FunctionSources};
+use crate::geojson_source::GeoJsonSource;
+use crate::glyphs::{self, parse_glyph_range};
+use crate::grid_source::{GridSource, GRID_SOURCE_ID};
+use crate::grpc::{self, TileService};
 use crate::messages;
-use crate::source::{Source, Xyz};
-use crate::table_source::{TableSource, TableSources};
+use crate::metrics;
+use crate::notify_listener::listen_notifications;
+use crate::pmtiles_source::PmtilesSources;
+use crate::query_params::parse_query_params;
+use crate::rate_limiter::RateLimiter;
+use crate::request_id::{request_id, RequestIdMiddleware, REQUEST_ID_HEADER};
+use crate::request_log::{LogFormat, TileRequestLog};
+use crate::server_error::{check_tile_size, ServerError};
+use crate::source::{EmptyTileMode, Source, Tile, TileData, Xyz};
+use crate::source_filter::SourceFilter;
+use crate::static_files;
+use crate::table_source::{select_properties, TableSource, TableSourceDefaults, TableSources};
+use crate::tenant::TenantSchemas;
+use crate::tile_cache::{new_tile_cache, SharedTileCache, TileCacheKey, MAX_SOURCE_ID_LENGTH};
+use crate::tls::load_server_config;
+use crate::utils::{normalize_bounds, tile_intersects_bounds};
 use crate::worker_actor::WorkerActor;
 
 pub struct AppState {
     pub db: Addr<DbActor>,
+    /// Read replicas serving tile queries, `db` is used when empty
+    pub replicas: Vec<Addr<DbActor>>,
+    pub next_replica: Cell<usize>,
+    pub db_queue: DbQueue,
     pub coordinator: Addr<CoordinatorActor>,
     pub table_sources: Rc<RefCell<Option<TableSources>>>,
     pub function_sources: Rc<RefCell<Option<FunctionSources>>>,
+    pub pmtiles_sources: Option<PmtilesSources>,
+    /// Applied to sources discovered in watch mode
+    pub source_filter: SourceFilter,
     pub watch_mode: bool,
+    pub debug_grid: bool,
+    /// Add the available source ids to `source_not_found` errors
+    pub list_sources_on_not_found: bool,
+    /// Serve function source tiles above `maxzoom` from their `maxzoom` ancestor
+    pub overzoom: bool,
+    pub mvt_content_type: String,
+    pub empty_tile_mode: EmptyTileMode,
+    pub allow_tile_param_overrides: bool,
+    /// Maximum length of function source query parameter values
+    pub max_function_param_length: usize,
+    /// Larger tiles are rejected with `500`, see `check_tile_size`
+    pub max_tile_bytes: Option<usize>,
+    /// Database tiles of lower zooms are empty without querying, see `is_below_min_allowed_zoom`
+    pub min_allowed_zoom: Option<u8>,
+    /// Add `X-Feature-Count` to queried table and composite source tiles
+    pub debug_feature_count: bool,
+    /// Compression level of `zstd` encoded tiles, `0` disables `zstd`
+    pub zstd_level: i32,
+    /// Bearer token of the admin endpoints, see `check_admin_token`
+    pub admin_token: Option<String>,
+    /// Circuit breaker of the primary database, its state is reported by `/healthz`
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Schemas of tenants identified by a request header, see `tenant_search_path`
+    pub tenant_schemas: Option<TenantSchemas>,
+    /// Serve table source tiles as GeoJSON with the `.geojson` extension
+    pub geojson_tiles: bool,
+    pub log_format: LogFormat,
+    /// Default `Cache-Control` max-age of tiles in seconds
+    pub max_age: u32,
+    /// Tile parameters of table sources discovered in watch mode
+    pub table_source_defaults: TableSourceDefaults,
+    /// Overrides the scheme and host of the request in generated URLs
+    pub public_base_url: Option<String>,
+    /// Directory of fonts served as glyph ranges, `/fonts` is disabled when not set
+    pub fonts_directory: Option<String>,
+    /// Use forwarded scheme and host headers of a reverse proxy in generated URLs
+    pub trust_proxy_headers: bool,
+    /// Set once a termination signal is received, new tile requests are rejected
+    pub shutting_down: Arc<AtomicBool>,
+    pub shutdown_timeout: u64,
+    /// Upper bound of the total tile handler time in milliseconds, 0 disables it
+    pub request_timeout_ms: u64,
+    /// Per-client limit of tile and feature requests, shared between workers
+    pub rate_limiter: Option<RateLimiter>,
+    /// Cache of tiles without query parameters, shared between workers
+    pub tile_cache: Option<SharedTileCache>,
+}
+
+impl AppState {
+    /// Database actor for tile queries, round-robin across read replicas
+    pub fn tile_db(&self) -> &Addr<DbActor> {
+        if self.replicas.is_empty() {
+            return &self.db;
+        }
+
+        let replica = self.next_replica.get() % self.replicas.len();
+        self.next_replica.set(replica + 1);
+
+        &self.replicas[replica]
+    }
 }
 
 #[derive(Deserialize)]
@@ -34,6 +139,12 @@ struct SourceRequest {
     source_id: String,
 }
 
+#[derive(Deserialize)]
+struct FeatureRequest {
+    source_id: String,
+    id: String,
+}
+
 #[derive(Deserialize)]
 struct CompositeSourceRequest {
     source_ids: String,
@@ -45,8 +156,8 @@ struct TileRequest {
     z: i32,
     x: i32,
     y: i32,
-    #[allow(dead_code)]
-    format: String,
+    /// Tile extension, the format is negotiated by `Accept` without one
+    format: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -55,154 +166,1453 @@ struct CompositeTileRequest {
     z: i32,
     x: i32,
     y: i32,
-    #[allow(dead_code)]
-    format: String,
+    /// Tile extension, the format is negotiated by `Accept` without one
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CatalogEntry {
+    id: String,
+    #[serde(rename = "type")]
+    source_type: &'static str,
+    geometry_type: Option<String>,
+    /// Sampled types of table sources with mixed geometry types
+    geometry_types: Option<Vec<String>>,
+    srid: Option<u32>,
+    minzoom: Option<u8>,
+    maxzoom: Option<u8>,
+    bounds: Option<Vec<f32>>,
+    /// Whether the geometry column of a table source has a spatial index
+    spatial_index: Option<bool>,
+    tilejson: String,
+}
+
+/// Vector tile extensions accepted by the tile routes
+static VECTOR_TILE_FORMATS: &[&str] = &["pbf", "mvt", "vector.pbf"];
+
+const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// Highest zoom of table and composite sources in the catalog, the TileJSON default
+const CATALOG_MAX_ZOOM: u8 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TileFormat {
+    Mvt,
+    /// Tile features as a GeoJSON FeatureCollection, only for table sources
+    GeoJson,
+}
+
+impl TileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            TileFormat::Mvt => "pbf",
+            TileFormat::GeoJson => "geojson",
+        }
+    }
+}
+
+fn check_tile_format(format: &str, state: &AppState) -> Result<TileFormat, ServerError> {
+    if VECTOR_TILE_FORMATS.contains(&format) {
+        return Ok(TileFormat::Mvt);
+    }
+
+    if format == "geojson" && state.geojson_tiles {
+        return Ok(TileFormat::GeoJson);
+    }
+
+    Err(ServerError::UnsupportedFormat(format!(
+        "Can't serve tiles in '{}' format",
+        format
+    )))
+}
+
+/// Tile format of the URL extension, or of the `Accept` header on extensionless tile
+/// URLs. Media ranges are tried by their quality, MVT is served without `Accept`.
+fn negotiate_tile_format(
+    req: &HttpRequest,
+    format: Option<&str>,
+    state: &AppState,
+) -> Result<TileFormat, ServerError> {
+    if let Some(format) = format {
+        return check_tile_format(format, state);
+    }
+
+    let accept = match req.headers().get(http::header::ACCEPT) {
+        Some(accept) => accept.to_str().unwrap_or_default(),
+        None => return Ok(TileFormat::Mvt),
+    };
+
+    let mut media_ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .map(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+
+            (media_type, quality)
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    media_ranges.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+    for (media_type, _) in media_ranges {
+        match media_type.to_ascii_lowercase().as_str() {
+            "application/vnd.mapbox-vector-tile"
+            | "application/x-protobuf"
+            | "application/*"
+            | "*/*" => return Ok(TileFormat::Mvt),
+            "application/json" | GEOJSON_CONTENT_TYPE if state.geojson_tiles => {
+                return Ok(TileFormat::GeoJson)
+            }
+            _ => {}
+        }
+    }
+
+    Err(ServerError::UnsupportedFormat(format!(
+        "Can't serve tiles as '{}'",
+        accept
+    )))
+}
+
+/// Negotiated tiles vary by `Accept`, so caches don't mix up their formats
+fn with_vary_accept(
+    result: Result<HttpResponse, Error>,
+    negotiated: bool,
+) -> Result<HttpResponse, Error> {
+    let mut response = result?;
+
+    if negotiated {
+        response
+            .headers_mut()
+            .append(http::header::VARY, http::HeaderValue::from_static("Accept"));
+    }
+
+    Ok(response)
+}
+
+/// GeoJSON tiles are built from table sources only
+fn check_vector_tile_format(format: TileFormat, source_id: &str) -> Result<(), ServerError> {
+    match format {
+        TileFormat::Mvt => Ok(()),
+        TileFormat::GeoJson => Err(ServerError::UnsupportedFormat(format!(
+            "Can't serve '{}' tiles as GeoJSON",
+            source_id
+        ))),
+    }
+}
+
+/// Rejects new tile requests with `503 Service Unavailable` once the server is shutting down.
+/// Requests that passed this check keep running until the shutdown timeout expires.
+fn check_shutdown(state: &AppState) -> Result<(), ServerError> {
+    if !state.shutting_down.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    Err(ServerError::ShuttingDown {
+        retry_after: state.shutdown_timeout.max(1),
+    })
+}
+
+/// `SourceNotFound` error, with `list_sources_on_not_found` it lists the sorted source ids
+fn source_not_found<'a, I>(state: &AppState, message: String, source_ids: I) -> ServerError
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    if !state.list_sources_on_not_found {
+        return ServerError::SourceNotFound(message);
+    }
+
+    let mut available_sources: Vec<String> = source_ids.into_iter().cloned().collect();
+    available_sources.sort();
+
+    ServerError::SourceNotFoundAmong {
+        message,
+        available_sources,
+    }
+}
+
+fn check_rate_limit(req: &HttpRequest, state: &AppState) -> Result<(), ServerError> {
+    let rate_limiter = match &state.rate_limiter {
+        Some(rate_limiter) => rate_limiter,
+        None => return Ok(()),
+    };
+
+    rate_limiter
+        .check(&client_address(req, state))
+        .map_err(|wait| ServerError::TooManyRequests {
+            retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+        })
+}
+
+/// Schema of the request's tenant put first in `search_path` of the tile query.
+/// With `tenant_schemas` requests of missing and unknown tenants are forbidden.
+fn tenant_search_path(req: &HttpRequest, state: &AppState) -> Result<Option<String>, ServerError> {
+    let tenant_schemas = match &state.tenant_schemas {
+        Some(tenant_schemas) => tenant_schemas,
+        None => return Ok(None),
+    };
+
+    let tenant_id = req
+        .headers()
+        .get(tenant_schemas.header())
+        .and_then(|tenant_id| tenant_id.to_str().ok());
+
+    tenant_schemas
+        .schema(tenant_id)
+        .map(|schema| Some(schema.to_owned()))
+        .ok_or_else(|| {
+            ServerError::Forbidden(match tenant_id {
+                Some(tenant_id) => format!("Unknown tenant '{}'", tenant_id),
+                None => format!("Missing {} header", tenant_schemas.header()),
+            })
+        })
+}
+
+/// Client IP address, taken from `Forwarded` or `X-Forwarded-For` with `trust_proxy_headers`
+fn client_address(req: &HttpRequest, state: &AppState) -> String {
+    if state.trust_proxy_headers {
+        if let Some(address) = req.connection_info().realip_remote_addr() {
+            return address
+                .parse::<SocketAddr>()
+                .map_or_else(|_| address.to_owned(), |address| address.ip().to_string());
+        }
+    }
+
+    req.peer_addr()
+        .map_or_else(|| "unknown".to_owned(), |address| address.ip().to_string())
+}
+
+/// Bounds the total handler time, including DB queue and actor mailbox waits,
+/// with `504 Gateway Timeout`. The query itself keeps running on the DB actor.
+async fn with_request_timeout<F>(
+    state: &AppState,
+    source_id: &str,
+    xyz: &Xyz,
+    handler: F,
+) -> Result<HttpResponse, Error>
+where
+    F: Future<Output = Result<HttpResponse, Error>>,
+{
+    if state.request_timeout_ms == 0 {
+        return handler.await;
+    }
+
+    let request_timeout = Duration::from_millis(state.request_timeout_ms);
+    match timeout(request_timeout, handler).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                "Tile request {}/{}/{}/{} timed out after {}ms",
+                source_id, xyz.z, xyz.x, xyz.y, state.request_timeout_ms
+            );
+
+            Err(ServerError::Timeout(format!(
+                "Tile request timed out after {}ms",
+                state.request_timeout_ms
+            ))
+            .into())
+        }
+    }
+}
+
+/// Logs tile requests failing with `500 Internal Server Error` with the tile, the query
+/// string and the underlying error, as the client only gets a generic error. Table and
+/// composite source errors come with the `martin explain` command reproducing the query.
+fn log_tile_error(
+    result: &Result<HttpResponse, Error>,
+    source_id: &str,
+    xyz: &Xyz,
+    query_string: &str,
+    explainable: bool,
+) {
+    let error = match result {
+        Err(error)
+            if error.as_response_error().status_code()
+                == http::StatusCode::INTERNAL_SERVER_ERROR =>
+        {
+            error
+        }
+        _ => return,
+    };
+
+    let query = if query_string.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", query_string)
+    };
+
+    let explain = if explainable {
+        format!(" (reproduce with `{}`)", explain_command(source_id, xyz))
+    } else {
+        String::new()
+    };
+
+    error!(
+        "Tile request {}/{}/{}/{}{} failed{}: {}",
+        source_id, xyz.z, xyz.x, xyz.y, query, explain, error
+    );
+}
+
+/// `martin explain` command printing the plan of the tile query, run with the server's
+/// config or connection string
+pub fn explain_command(source_id: &str, xyz: &Xyz) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-.,".contains(c);
+    let source_id = if !source_id.is_empty() && source_id.chars().all(is_safe) {
+        source_id.to_owned()
+    } else {
+        format!("'{}'", source_id.replace('\'', "'\\''"))
+    };
+
+    format!(
+        "martin explain --analyze {} {} {} {}",
+        source_id, xyz.z, xyz.x, xyz.y
+    )
+}
+
+/// Whether the tile is below `min_allowed_zoom`, e.g. a crawler requesting whole table
+/// tiles. Such tiles are empty without querying the database, blocked requests are logged.
+fn is_below_min_allowed_zoom(state: &AppState, source_id: &str, xyz: &Xyz) -> bool {
+    match state.min_allowed_zoom {
+        Some(min_allowed_zoom) if xyz.z < i32::from(min_allowed_zoom) => {
+            info!(
+                "Tile request {}/{}/{}/{} blocked below min_allowed_zoom {}",
+                source_id, xyz.z, xyz.x, xyz.y, min_allowed_zoom
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Cached tile of the key, cache errors are logged and treated as misses
+async fn get_cached_tile(state: &AppState, key: &Option<TileCacheKey>) -> Option<Tile> {
+    let (cache, key) = match (&state.tile_cache, key) {
+        (Some(cache), Some(key)) => (cache.clone(), key.clone()),
+        _ => return None,
+    };
+
+    match blocking::run(move || cache.get(&key)).await {
+        Ok(tile) => tile,
+        Err(error) => {
+            warn!("Can't read cached tile: {}", error);
+            None
+        }
+    }
+}
+
+/// Caches a queried tile, returns the cache status of the request: `miss` or `none`.
+async fn put_cached_tile(
+    state: &AppState,
+    key: Option<TileCacheKey>,
+    tile_data: &TileData,
+) -> &'static str {
+    let (cache, key) = match (&state.tile_cache, key) {
+        (Some(cache), Some(key)) => (cache.clone(), key),
+        _ => return "none",
+    };
+
+    // Truncated and oversized tiles aren't cached, hits couldn't report or reject them
+    let oversized = state
+        .max_tile_bytes
+        .is_some_and(|max_tile_bytes| tile_data.tile.len() > max_tile_bytes);
+
+    if !tile_data.truncated && !oversized {
+        let tile = tile_data.tile.clone();
+        if let Err(error) = blocking::run(move || cache.put(&key, &tile)).await {
+            warn!("Can't cache tile: {}", error);
+        }
+    }
+
+    "miss"
+}
+
+/// Content codings tiles are compressed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TileEncoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+/// Highest `Accept-Encoding` quality of the codings, `0.0` if none of them is accepted
+fn accepted_quality(req: &HttpRequest, codings: &[&str]) -> f32 {
+    let accept_encoding = match req.headers().get(http::header::ACCEPT_ENCODING) {
+        Some(accept_encoding) => accept_encoding.to_str().unwrap_or_default(),
+        None => return 0.0,
+    };
+
+    accept_encoding
+        .split(',')
+        .filter_map(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let coding = params.next().unwrap_or_default();
+            let quality = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+
+            codings
+                .iter()
+                .any(|accepted| coding.eq_ignore_ascii_case(accepted))
+                .then_some(quality)
+        })
+        .fold(0.0, f32::max)
+}
+
+/// `zstd` when the client lists it at least as high as `gzip` and `zstd_level` isn't `0`,
+/// `gzip` otherwise. `*` only matches `gzip`, which all clients can decode.
+fn negotiate_tile_encoding(req: &HttpRequest, zstd_level: i32) -> TileEncoding {
+    let gzip_quality = accepted_quality(req, &["gzip", "*"]);
+    let zstd_quality = if zstd_level == 0 {
+        0.0
+    } else {
+        accepted_quality(req, &["zstd"])
+    };
+
+    if zstd_quality > 0.0 && zstd_quality >= gzip_quality {
+        TileEncoding::Zstd
+    } else if gzip_quality > 0.0 {
+        TileEncoding::Gzip
+    } else {
+        TileEncoding::Identity
+    }
+}
+
+fn gzip(tile: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(tile.len()), Compression::default());
+    encoder.write_all(tile)?;
+    encoder.finish()
+}
+
+/// Strong ETag built from the tile length and CRC32
+fn tile_etag(tile: &[u8]) -> String {
+    let mut crc = Crc::new();
+    crc.update(tile);
+    format!("\"{:x}-{:08x}\"", tile.len(), crc.sum())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tile_response(
+    req: &HttpRequest,
+    source_id: &str,
+    xyz: &Xyz,
+    tile_data: TileData,
+    max_age: Option<u32>,
+    db_duration: Option<Duration>,
+    cache_status: &'static str,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    let tile = tile_data.tile;
+    check_tile_size(&tile, state.max_tile_bytes, source_id, xyz)?;
+
+    let mut log = TileRequestLog::new(source_id, xyz, tile.len())
+        .cache(cache_status)
+        .request_id(request_id(req));
+    if let Some(db_duration) = db_duration {
+        log = log.db_duration(db_duration);
+    }
+    log.log(state.log_format);
+
+    let content_type = state.mvt_content_type.as_str();
+
+    // Empty tiles are cached as well, so clients don't hit the database for empty areas
+    let cache_control = format!("public, max-age={}", max_age.unwrap_or(state.max_age));
+
+    let last_modified = tile_data.last_modified.map(http::header::HttpDate::from);
+    if let Some(last_modified) = last_modified {
+        if is_not_modified(req, last_modified) {
+            let mut response = HttpResponse::NotModified();
+            response
+                .header(http::header::CACHE_CONTROL, cache_control)
+                .header(http::header::LAST_MODIFIED, last_modified);
+
+            if let Some(tenant_schemas) = &state.tenant_schemas {
+                response.header(http::header::VARY, tenant_schemas.header());
+            }
+
+            return Ok(response.finish());
+        }
+    }
+
+    let range = match req.headers().get(http::header::RANGE) {
+        Some(range) if !tile.is_empty() => range
+            .to_str()
+            .map_or(ByteRange::Full, |range| parse_range(range, tile.len())),
+        _ => ByteRange::Full,
+    };
+
+    let mut response = match (&range, tile.len()) {
+        (ByteRange::Unsatisfiable, length) => {
+            return Err(ServerError::RangeNotSatisfiable { length }.into())
+        }
+        (ByteRange::Partial(_), _) => HttpResponse::PartialContent(),
+        (ByteRange::Full, 0) if state.empty_tile_mode == EmptyTileMode::NoContent => {
+            HttpResponse::NoContent()
+        }
+        (ByteRange::Full, _) => HttpResponse::Ok(),
+    };
+
+    response
+        .content_type(content_type)
+        .header(http::header::CACHE_CONTROL, cache_control)
+        .header(http::header::ACCEPT_RANGES, "bytes");
+
+    // Tiles of different tenants have the same URL
+    if let Some(tenant_schemas) = &state.tenant_schemas {
+        response.header(http::header::VARY, tenant_schemas.header());
+    }
+
+    if tile_data.truncated {
+        response.header("X-Features-Truncated", "true");
+    }
+
+    if let Some(feature_count) = tile_data.feature_count {
+        response.header("X-Feature-Count", feature_count.to_string());
+    }
+
+    if let Some(last_modified) = last_modified {
+        response.header(http::header::LAST_MODIFIED, last_modified);
+    }
+
+    // The `Compress` middleware would stream tiles with chunked transfer encoding, so tiles
+    // are gzipped here and sent with a `Content-Length`. Ranges are of the uncompressed tile.
+    response.encoding(ContentEncoding::Identity);
+
+    if let ByteRange::Partial(range) = range {
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, tile.len());
+        response
+            .header(http::header::ETAG, tile_etag(&tile))
+            .header(http::header::CONTENT_RANGE, content_range);
+
+        return Ok(response.body(tile[range].to_vec()));
+    }
+
+    if tile.is_empty() {
+        return Ok(response.body(tile));
+    }
+
+    // Encoded tiles are a different representation, so they need their own ETag
+    response.header(http::header::VARY, "Accept-Encoding");
+    let encoded = match negotiate_tile_encoding(req, state.zstd_level) {
+        TileEncoding::Identity => None,
+        TileEncoding::Gzip => Some(("gzip", gzip(&tile))),
+        TileEncoding::Zstd => Some(("zstd", zstd::encode_all(&tile[..], state.zstd_level))),
+    };
+
+    if let Some((coding, encoded)) = encoded {
+        let etag = format!("{}-{}\"", tile_etag(&tile).trim_end_matches('"'), coding);
+        let tile = encoded.map_err(ServerError::from)?;
+
+        return Ok(response
+            .header(http::header::CONTENT_ENCODING, coding)
+            .header(http::header::ETAG, etag)
+            .body(tile));
+    }
+
+    // For HEAD requests actix-web keeps Content-Length and drops the body
+    Ok(response
+        .header(http::header::ETAG, tile_etag(&tile))
+        .body(tile))
+}
+
+/// Whether the client's copy of the tile from `If-Modified-Since` is still fresh.
+/// Range requests always get the requested bytes.
+fn is_not_modified(req: &HttpRequest, last_modified: http::header::HttpDate) -> bool {
+    if req.headers().contains_key(http::header::RANGE) {
+        return false;
+    }
+
+    req.headers()
+        .get(http::header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<http::header::HttpDate>().ok())
+        .is_some_and(|if_modified_since| last_modified <= if_modified_since)
+}
+
+/// Advertises the MVT extent and buffer of the tile, so clients rendering unclipped
+/// (`clip_geom: false`) features know how far they extend beyond the tile
+fn with_tile_buffer(
+    mut response: HttpResponse,
+    extent_and_buffer: Option<(u32, u32)>,
+) -> Result<HttpResponse, Error> {
+    if let Some((extent, buffer)) = extent_and_buffer {
+        let headers = response.headers_mut();
+        headers.insert(
+            http::HeaderName::from_static("x-tile-extent"),
+            http::HeaderValue::from(extent),
+        );
+        headers.insert(
+            http::HeaderName::from_static("x-tile-buffer"),
+            http::HeaderValue::from(buffer),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Adds the `response_headers` of the source to a tile response
+fn with_response_headers(
+    mut response: HttpResponse,
+    response_headers: Option<&HashMap<String, String>>,
+) -> Result<HttpResponse, Error> {
+    for (name, value) in response_headers.into_iter().flatten() {
+        // Names and values are validated on config load
+        let name = http::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|error| ServerError::Internal(error.to_string()))?;
+        let value = http::HeaderValue::from_str(value)
+            .map_err(|error| ServerError::Internal(error.to_string()))?;
+
+        response.headers_mut().insert(name, value);
+    }
+
+    Ok(response)
+}
+
+/// Replaces the global `mvt_content_type` of a tile response with the source's own
+fn with_content_type(
+    mut response: HttpResponse,
+    content_type: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    if let Some(content_type) = content_type {
+        let content_type = http::HeaderValue::from_str(content_type)
+            .map_err(|error| ServerError::Internal(error.to_string()))?;
+
+        response
+            .headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type);
+    }
+
+    Ok(response)
+}
+
+/// Scheme, host and path prefix of the URLs in TileJSON and catalog responses
+fn base_url(req: &HttpRequest, state: &AppState) -> String {
+    if let Some(public_base_url) = &state.public_base_url {
+        return public_base_url.trim_end_matches('/').to_owned();
+    }
+
+    if state.trust_proxy_headers {
+        // Resolved from `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host` first
+        let connection_info = req.connection_info();
+        return format!("{}://{}", connection_info.scheme(), connection_info.host());
+    }
+
+    let scheme = match req.uri().scheme_str() {
+        Some(scheme) => scheme,
+        None if req.app_config().secure() => "https",
+        None => "http",
+    };
+
+    let host = req
+        .headers()
+        .get(http::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+        .unwrap_or_else(|| req.app_config().host());
+
+    format!("{}://{}", scheme, host)
+}
+
+/// Tiles URL template of the requested TileJSON, keeping its query string
+fn tiles_url(req: &HttpRequest, state: &AppState) -> Result<String, ServerError> {
+    // `public_base_url` already contains the prefix `x-rewrite-url` would add
+    let tiles_path = match req.headers().get("x-rewrite-url") {
+        Some(header) if state.public_base_url.is_none() => header
+            .to_str()
+            .map_err(|e| ServerError::TileJson(format!("Can't build TileJSON: {}", e)))?,
+        _ => req.path(),
+    };
+
+    let query_string = req.query_string();
+    let query = if query_string.is_empty() {
+        query_string.to_owned()
+    } else {
+        format!("?{}", query_string)
+    };
+
+    Ok(format!(
+        "{}{}/{{z}}/{{x}}/{{y}}.pbf{}",
+        base_url(req, state),
+        tiles_path.trim_end_matches(".json"),
+        query
+    ))
+}
+
+/// Server name, version and links to the main endpoints, without database access
+async fn get_index(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
+    let base_url = base_url(&req, &state);
+
+    HttpResponse::Ok().json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "links": {
+            "table_sources": format!("{}/index.json", base_url),
+            "function_sources": format!("{}/rpc/index.json", base_url),
+            "catalog": format!("{}/catalog", base_url),
+            "health": format!("{}/healthz", base_url),
+            "version": format!("{}/version", base_url),
+        }
+    }))
+}
+
+#[derive(Deserialize)]
+struct GlyphsRequest {
+    fontstack: String,
+    range: String,
+}
+
+async fn get_glyphs(
+    path: web::Path<GlyphsRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let fonts_directory = state
+        .fonts_directory
+        .clone()
+        .ok_or_else(|| ServerError::FontNotFound("There is no fonts directory".to_owned()))?;
+
+    let (start, end) = parse_glyph_range(&path.range).map_err(ServerError::from)?;
+    let fontstack = path.fontstack.clone();
+
+    let glyphs = blocking::run(move || {
+        glyphs::get_glyphs(Path::new(&fonts_directory), &fontstack, start, end)
+    })
+    .await
+    .map_err(|error| match error {
+        BlockingError::Error(error) if error.kind() == io::ErrorKind::NotFound => {
+            ServerError::FontNotFound(error.to_string())
+        }
+        error => ServerError::from(error),
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-protobuf")
+        .header(
+            "Cache-Control",
+            format!("public, max-age={}", state.max_age),
+        )
+        .body(glyphs))
+}
+
+/// Browsers request a favicon when the server is opened in a tab
+async fn get_favicon() -> HttpResponse {
+    HttpResponse::NoContent().finish()
+}
+
+/// Version and build info, the git commit and build time come from `build.rs`
+async fn get_version() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": env!("MARTIN_GIT_COMMIT"),
+        "build_timestamp": env!("MARTIN_BUILD_TIMESTAMP"),
+    }))
+}
+
+/// Responds with `503` while the circuit breaker of the primary database isn't closed,
+/// so load balancers route requests to other instances
+async fn get_health(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let circuit_state = state
+        .circuit_breaker
+        .as_ref()
+        .map_or(CircuitState::Closed, CircuitBreaker::state);
+
+    if circuit_state != CircuitState::Closed {
+        let response = HttpResponse::ServiceUnavailable().body(format!(
+            "Database circuit breaker is {}",
+            circuit_state.as_str()
+        ));
+
+        return Ok(response);
+    }
+
+    let response = HttpResponse::Ok().body("OK");
+    Ok(response)
+}
+
+async fn get_events(
+    req: HttpRequest,
+    stream: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let session = EventSession {
+        coordinator: state.coordinator.clone(),
+    };
+
+    ws::start(session, &req, stream)
+}
+
+async fn get_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render_metrics())
+}
+
+/// The value all of the items agree on
+fn common_value<T: PartialEq>(mut values: impl Iterator<Item = T>) -> Option<T> {
+    let first = values.next()?;
+    if values.all(|value| value == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Bounds covering all of the bounds, `None` when some of them are unknown
+fn union_bounds<'a>(bounds: impl Iterator<Item = Option<&'a [f32]>>) -> Option<Vec<f32>> {
+    bounds
+        .map(|bounds| bounds.map(normalize_bounds))
+        .try_fold(None, |union: Option<Vec<f32>>, bounds| {
+            let bounds = bounds?;
+            Some(Some(match union {
+                Some(union) => vec![
+                    union[0].min(bounds[0]),
+                    union[1].min(bounds[1]),
+                    union[2].max(bounds[2]),
+                    union[3].max(bounds[3]),
+                ],
+                None => bounds,
+            }))
+        })
+        .flatten()
+}
+
+async fn get_catalog(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let base_url = base_url(&req, &state);
+
+    let mut catalog = Vec::new();
+
+    // Database tiles below `min_allowed_zoom` are empty
+    let minzoom = Some(state.min_allowed_zoom.unwrap_or(0));
+
+    if let Some(table_sources) = state.table_sources.borrow().as_ref() {
+        catalog.extend(table_sources.values().map(|source| CatalogEntry {
+            id: source.id.clone(),
+            source_type: "table",
+            geometry_type: source.geometry_type.clone(),
+            geometry_types: source.geometry_types.clone(),
+            srid: Some(source.srid),
+            minzoom,
+            maxzoom: Some(CATALOG_MAX_ZOOM),
+            bounds: source.bounds.as_deref().map(normalize_bounds),
+            spatial_index: source.spatial_index,
+            tilejson: format!("{}/{}.json", base_url, source.id),
+        }));
+
+        if table_sources.len() > 1 {
+            let mut sources: Vec<&TableSource> = table_sources
+                .values()
+                .map(|source| source.deref())
+                .collect();
+            sources.sort_by(|a, b| a.id.cmp(&b.id));
+
+            let id = sources
+                .iter()
+                .map(|source| source.id.as_str())
+                .collect::<Vec<_>>()
+                .join(",");
+
+            catalog.push(CatalogEntry {
+                tilejson: format!("{}/{}.json", base_url, id),
+                id,
+                source_type: "composite",
+                geometry_type: common_value(sources.iter().map(|source| &source.geometry_type))
+                    .cloned()
+                    .flatten(),
+                geometry_types: None,
+                srid: common_value(sources.iter().map(|source| source.srid)),
+                minzoom,
+                maxzoom: Some(CATALOG_MAX_ZOOM),
+                bounds: union_bounds(sources.iter().map(|source| source.bounds.as_deref())),
+                spatial_index: None,
+            });
+        }
+    }
+
+    if let Some(function_sources) = state.function_sources.borrow().as_ref() {
+        catalog.extend(function_sources.values().map(|source| CatalogEntry {
+            id: source.id.clone(),
+            source_type: "function",
+            geometry_type: None,
+            geometry_types: None,
+            srid: None,
+            minzoom: source.minzoom,
+            maxzoom: source.maxzoom,
+            bounds: None,
+            spatial_index: None,
+            tilejson: format!("{}/rpc/{}.json", base_url, source.id),
+        }));
+    }
+
+    if let Some(pmtiles_sources) = state.pmtiles_sources.as_ref() {
+        catalog.extend(pmtiles_sources.values().map(|source| {
+            CatalogEntry {
+                id: source.id.clone(),
+                source_type: "pmtiles",
+                geometry_type: None,
+                geometry_types: None,
+                srid: None,
+                // Archives that couldn't be opened on startup have no header yet
+                minzoom: source.opened_header().map(|header| header.min_zoom),
+                maxzoom: source.opened_header().map(|header| header.max_zoom),
+                bounds: source
+                    .opened_header()
+                    .map(|header| normalize_bounds(&header.bounds)),
+                spatial_index: None,
+                tilejson: format!("{}/{}.json", base_url, source.id),
+            }
+        }));
+    }
+
+    if state.debug_grid {
+        catalog.push(CatalogEntry {
+            id: GRID_SOURCE_ID.to_owned(),
+            source_type: "grid",
+            geometry_type: Some("POLYGON".to_owned()),
+            geometry_types: None,
+            srid: Some(3857),
+            minzoom: None,
+            maxzoom: None,
+            bounds: None,
+            spatial_index: None,
+            tilejson: format!("{}/{}.json", base_url, GRID_SOURCE_ID),
+        });
+    }
+
+    catalog.sort_by(|a, b| (a.source_type, &a.id).cmp(&(b.source_type, &b.id)));
+
+    Ok(HttpResponse::Ok().json(catalog))
 }
 
-async fn get_health() -> Result<HttpResponse, Error> {
-    let response = HttpResponse::Ok().body("OK");
-    Ok(response)
+async fn get_table_sources(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if !state.watch_mode {
+        let table_sources = state.table_sources.borrow().clone();
+        let response = HttpResponse::Ok().json(table_sources);
+        return Ok(response);
+    }
+
+    info!("Scanning database for table sources");
+
+    let mut table_sources = state
+        .db
+        .send(messages::GetTableSources {
+            defaults: state.table_source_defaults,
+        })
+        .await
+        .map_err(|error| ServerError::Internal(error.to_string()))?
+        .map_err(ServerError::from)?;
+
+    state.source_filter.apply(&mut table_sources);
+
+    state.coordinator.do_send(messages::RefreshTableSources {
+        table_sources: Some(table_sources.clone()),
+    });
+
+    Ok(HttpResponse::Ok().json(table_sources))
+}
+
+async fn get_composite_source(
+    req: HttpRequest,
+    path: web::Path<CompositeSourceRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let pmtiles_source = state
+        .pmtiles_sources
+        .as_ref()
+        .and_then(|pmtiles_sources| pmtiles_sources.get(&path.source_ids))
+        .cloned();
+
+    let mut tilejson = match pmtiles_source {
+        None if state.debug_grid && path.source_ids == GRID_SOURCE_ID => GridSource::default()
+            .get_tilejson_value()
+            .map_err(|e| ServerError::TileJson(format!("Can't build TileJSON: {}", e)))?,
+        Some(source) => blocking::run(move || source.get_tilejson_value())
+            .await
+            .map_err(|e| ServerError::TileJson(format!("Can't build TileJSON: {}", e)))?,
+        None => {
+            let table_sources = state.table_sources.borrow().clone().ok_or_else(|| {
+                source_not_found(
+                    &state,
+                    "There is no table sources".to_owned(),
+                    iter::empty(),
+                )
+            })?;
+
+            let sources: Vec<TableSource> = path
+                .source_ids
+                .split(',')
+                .filter_map(|source_id| table_sources.get(source_id))
+                .map(|source| source.deref().clone())
+                .collect();
+
+            if sources.is_empty() {
+                return Err(source_not_found(
+                    &state,
+                    "There is no such table sources".to_owned(),
+                    table_sources.keys(),
+                )
+                .into());
+            }
+
+            // A single source is served from its own TileJSON cache
+            let tilejson = match sources.as_slice() {
+                [source] => source.get_tilejson_value(),
+                _ => CompositeSource {
+                    id: path.source_ids.clone(),
+                    table_sources: sources,
+                    count_features: false,
+                }
+                .get_tilejson_value(),
+            };
+
+            tilejson.map_err(|e| ServerError::TileJson(format!("Can't build TileJSON: {}", e)))?
+        }
+    };
+
+    tilejson["tiles"] = json!([tiles_url(&req, &state)?]);
+    Ok(HttpResponse::Ok().json(tilejson))
+}
+
+async fn get_table_source_feature(
+    req: HttpRequest,
+    path: web::Path<FeatureRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    check_rate_limit(&req, &state)?;
+
+    let table_sources = state.table_sources.borrow().clone();
+    let source = table_sources
+        .as_ref()
+        .and_then(|table_sources| table_sources.get(&path.source_id))
+        .map(|source| source.deref().clone())
+        .ok_or_else(|| {
+            source_not_found(
+                &state,
+                format!("Table source '{}' not found", path.source_id),
+                table_sources
+                    .iter()
+                    .flat_map(|table_sources| table_sources.keys()),
+            )
+        })?;
+
+    if source.get_id_column().is_none() {
+        return Err(ServerError::InvalidParams(format!(
+            "Table source '{}' has no id column",
+            path.source_id
+        ))
+        .into());
+    }
+
+    let message = messages::GetFeature {
+        id: path.id.clone(),
+        source,
+    };
+
+    let feature = state
+        .tile_db()
+        .send(message)
+        .await
+        .map_err(|error| ServerError::Internal(error.to_string()))?
+        .map_err(ServerError::from)?
+        .ok_or_else(|| {
+            ServerError::FeatureNotFound(format!(
+                "Feature '{}' not found in '{}'",
+                path.id, path.source_id
+            ))
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(feature))
 }
 
-async fn get_table_sources(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    if !state.watch_mode {
-        let table_sources = state.table_sources.borrow().clone();
-        let response = HttpResponse::Ok().json(table_sources);
-        return Ok(response);
-    }
+/// Accepts requests with the `Authorization: Bearer {admin_token}` header,
+/// admin endpoints are disabled without an `admin_token`
+fn check_admin_token(req: &HttpRequest, state: &AppState) -> Result<(), ServerError> {
+    let admin_token = state.admin_token.as_deref().ok_or_else(|| {
+        ServerError::Unauthorized("Admin endpoints are disabled without admin_token".to_owned())
+    })?;
 
-    info!("Scanning database for table sources");
+    let token = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .ok_or_else(|| ServerError::Unauthorized("Missing bearer token".to_owned()))?;
 
-    let table_sources = state
-        .db
-        .send(messages::GetTableSources {})
-        .await
-        .map_err(|_| HttpResponse::InternalServerError())?
-        .map_err(|_| HttpResponse::InternalServerError())?;
+    // Compared in constant time, so the token can't be guessed byte by byte
+    let matches = token.len() == admin_token.len()
+        && token
+            .bytes()
+            .zip(admin_token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0;
 
-    state.coordinator.do_send(messages::RefreshTableSources {
-        table_sources: Some(table_sources.clone()),
-    });
+    if !matches {
+        return Err(ServerError::Unauthorized("Invalid bearer token".to_owned()));
+    }
 
-    Ok(HttpResponse::Ok().json(table_sources))
+    Ok(())
 }
 
-async fn get_composite_source(
+/// Refreshes a materialized view table source on the primary database and drops
+/// its cached tiles, responds with the refresh duration
+async fn refresh_table_source(
     req: HttpRequest,
-    path: web::Path<CompositeSourceRequest>,
+    path: web::Path<SourceRequest>,
     state: web::Data<AppState>,
-) -> Result<HttpResponse> {
-    let table_sources = state
-        .table_sources
-        .borrow()
-        .clone()
-        .ok_or_else(|| error::ErrorNotFound("There is no table sources"))?;
+) -> Result<HttpResponse, Error> {
+    check_admin_token(&req, &state)?;
 
-    let sources: Vec<TableSource> = path
-        .source_ids
-        .split(',')
-        .filter_map(|source_id| table_sources.get(source_id))
+    let table_sources = state.table_sources.borrow().clone();
+    let source = table_sources
+        .as_ref()
+        .and_then(|table_sources| table_sources.get(&path.source_id))
         .map(|source| source.deref().clone())
-        .collect();
+        .ok_or_else(|| {
+            source_not_found(
+                &state,
+                format!("Table source '{}' not found", path.source_id),
+                table_sources
+                    .iter()
+                    .flat_map(|table_sources| table_sources.keys()),
+            )
+        })?;
 
-    if sources.is_empty() {
-        return Err(error::ErrorNotFound("There is no such table sources"));
+    if source.is_matview != Some(true) {
+        return Err(ServerError::InvalidParams(format!(
+            "Table source '{}' is not a materialized view",
+            path.source_id
+        ))
+        .into());
     }
 
-    let source = CompositeSource {
-        id: path.source_ids.clone(),
-        table_sources: sources,
-    };
-
-    let mut tilejson = source
-        .get_tilejson()
-        .map_err(|e| error::ErrorBadRequest(format!("Can't build TileJSON: {}", e)))?;
+    let started_at = Instant::now();
+    state
+        .db
+        .send(messages::RefreshMatview { source })
+        .await
+        .map_err(|error| ServerError::Internal(error.to_string()))?
+        .map_err(ServerError::from)?;
+    let duration = started_at.elapsed();
 
-    let tiles_path = req
-        .headers()
-        .get("x-rewrite-url")
-        .map_or(Ok(req.path().trim_end_matches(".json")), |header| {
-            let header_str = header.to_str()?;
-            Ok(header_str.trim_end_matches(".json"))
-        })
-        .map_err(|e: http::header::ToStrError| {
-            error::ErrorBadRequest(format!("Can't build TileJSON: {}", e))
-        })?;
+    info!("Refreshed {} in {:?}", path.source_id, duration);
 
-    let query_string = req.query_string();
-    let query = if query_string.is_empty() {
-        query_string.to_owned()
-    } else {
-        format!("?{}", query_string)
-    };
+    if let Some(tile_cache) = state.tile_cache.clone() {
+        let source_id = path.source_id.clone();
+        blocking::run(move || tile_cache.invalidate(&source_id))
+            .await
+            .map_err(ServerError::from)?;
+    }
 
-    let connection_info = req.connection_info();
+    Ok(HttpResponse::Ok().json(json!({
+        "source_id": path.source_id,
+        "duration_ms": duration.as_millis() as u64,
+    })))
+}
 
-    let tiles_url = format!(
-        "{}://{}{}/{{z}}/{{x}}/{{y}}.pbf{}",
-        connection_info.scheme(),
-        connection_info.host(),
-        tiles_path,
-        query
-    );
+async fn get_composite_source_tile(
+    req: HttpRequest,
+    path: web::Path<CompositeTileRequest>,
+    query: web::Query<HashMap<String, String>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let source_id = path.source_ids.clone();
+    let xyz = Xyz {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
 
-    tilejson.tiles = vec![tiles_url];
-    Ok(HttpResponse::Ok().json(tilejson))
+    let negotiated = path.format.is_none();
+    let query_string = req.query_string().to_owned();
+    let handler = composite_source_tile(req, path, query, state.clone());
+    let result = with_request_timeout(&state, &source_id, &xyz, handler).await;
+    log_tile_error(&result, &source_id, &xyz, &query_string, true);
+    with_vary_accept(result, negotiated)
 }
 
-async fn get_composite_source_tile(
+async fn composite_source_tile(
+    req: HttpRequest,
     path: web::Path<CompositeTileRequest>,
+    query: web::Query<HashMap<String, String>>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let table_sources = state
-        .table_sources
-        .borrow()
-        .clone()
-        .ok_or_else(|| error::ErrorNotFound("There is no table sources"))?;
+    check_shutdown(&state)?;
+    check_rate_limit(&req, &state)?;
+    let search_path = tenant_search_path(&req, &state)?;
+    let format = negotiate_tile_format(&req, path.format.as_deref(), &state)?;
 
-    let sources: Vec<TableSource> = path
-        .source_ids
-        .split(',')
-        .filter_map(|source_id| table_sources.get(source_id))
+    let span = tracing::info_span!(
+        "tile_request",
+        source_id = %path.source_ids,
+        request_id = request_id(&req).as_deref().unwrap_or("-"),
+        z = path.z,
+        x = path.x,
+        y = path.y
+    );
+
+    let xyz = Xyz {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
+
+    if state.debug_grid && path.source_ids == GRID_SOURCE_ID {
+        check_vector_tile_format(format, &path.source_ids)?;
+
+        return tile_response(
+            &req,
+            &path.source_ids,
+            &xyz,
+            TileData {
+                tile: GridSource::default().render_tile(&xyz),
+                truncated: false,
+                last_modified: None,
+                feature_count: None,
+            },
+            None,
+            None,
+            "none",
+            &state,
+        );
+    }
+
+    let pmtiles_source = state
+        .pmtiles_sources
+        .as_ref()
+        .and_then(|pmtiles_sources| pmtiles_sources.get(&path.source_ids))
+        .cloned();
+
+    if let Some(source) = pmtiles_source {
+        check_vector_tile_format(format, &path.source_ids)?;
+
+        let max_age = source.max_age;
+        let content_type = source.mvt_content_type.clone();
+        let tile = blocking::run(move || source.read_tile(&xyz))
+            .await
+            .map_err(ServerError::from)?;
+        let tile_data = TileData {
+            tile,
+            truncated: false,
+            last_modified: None,
+            feature_count: None,
+        };
+
+        let response = tile_response(
+            &req,
+            &path.source_ids,
+            &xyz,
+            tile_data,
+            max_age,
+            None,
+            "none",
+            &state,
+        )?;
+
+        return with_content_type(response, content_type.as_deref());
+    }
+
+    let table_sources = state.table_sources.borrow().clone().ok_or_else(|| {
+        source_not_found(
+            &state,
+            "There is no table sources".to_owned(),
+            iter::empty(),
+        )
+    })?;
+
+    let source_ids: Vec<&str> = path.source_ids.split(',').collect();
+    let mut sources: Vec<TableSource> = source_ids
+        .iter()
+        .filter_map(|source_id| table_sources.get(*source_id))
         .map(|source| source.deref().clone())
         .collect();
 
     if sources.is_empty() {
-        return Err(error::ErrorNotFound("There is no such table sources"));
+        return Err(source_not_found(
+            &state,
+            "There is no such table sources".to_owned(),
+            table_sources.keys(),
+        )
+        .into());
+    }
+
+    // Tiles are cached by the ids of the found sources, tiles with unknown or repeated
+    // ids aren't cached so that they don't fill the cache with copies
+    let is_cacheable_source = source_ids.len() == sources.len()
+        && source_ids.iter().collect::<HashSet<_>>().len() == source_ids.len();
+    let cache_source_id = sources
+        .iter()
+        .map(|source| source.id.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if let Some(properties) = query.get("properties") {
+        sources = select_properties(&sources, properties).map_err(ServerError::from)?;
+    }
+
+    if state.allow_tile_param_overrides {
+        sources = sources
+            .iter()
+            .map(|source| source.apply_tile_params(&query))
+            .collect::<Result<_, _>>()
+            .map_err(ServerError::from)?;
     }
 
-    let source = CompositeSource {
+    // Composite tiles are cached as long as the shortest lived source allows
+    let max_age = sources
+        .iter()
+        .map(|source| source.max_age.unwrap_or(state.max_age))
+        .min();
+
+    // Per-source content type is used only when all sources agree on it
+    let content_type = sources[0].mvt_content_type.clone().filter(|content_type| {
+        sources
+            .iter()
+            .all(|source| source.mvt_content_type.as_ref() == Some(content_type))
+    });
+    let response_headers = sources[0]
+        .response_headers
+        .clone()
+        .filter(|response_headers| {
+            sources
+                .iter()
+                .all(|source| source.response_headers.as_ref() == Some(response_headers))
+        });
+
+    let mut source = CompositeSource {
         id: path.source_ids.clone(),
         table_sources: sources,
+        count_features: state.debug_feature_count,
     };
+    let tile_extent_and_buffer = source.tile_extent_and_buffer();
 
-    let xyz = Xyz {
-        z: path.z,
-        x: path.x,
-        y: path.y,
+    // Tiles outside of the tile grid are empty without querying the database,
+    // neither their `tms` rows can be flipped nor their bounds computed
+    let tile_xyz = if xyz.is_valid() {
+        source.scheme().to_xyz(&xyz)
+    } else {
+        source.table_sources.clear();
+        xyz
     };
 
-    let message = messages::GetTile {
-        xyz,
-        query: None,
-        source: Box::new(source),
+    // Sources have no features in tiles outside of their bounds
+    source.table_sources.retain(|table_source| {
+        table_source
+            .bounds
+            .as_deref()
+            .is_none_or(|bounds| tile_intersects_bounds(&tile_xyz, bounds))
+    });
+
+    if is_below_min_allowed_zoom(&state, &source.id, &xyz) {
+        source.table_sources.clear();
+    }
+
+    // Tiles with parameter overrides, of tenants, with details or outside
+    // of the tile grid aren't cached
+    let cache_key = if query.is_empty()
+        && search_path.is_none()
+        && !source.has_tile_details()
+        && xyz.is_valid()
+        && is_cacheable_source
+        && cache_source_id.len() <= MAX_SOURCE_ID_LENGTH
+    {
+        Some(TileCacheKey::new(
+            &cache_source_id,
+            &xyz,
+            format.extension(),
+        ))
+    } else {
+        None
     };
 
-    let tile = state
-        .db
-        .send(message)
-        .await
-        .map_err(|_| HttpResponse::InternalServerError())?
-        .map_err(|_| HttpResponse::InternalServerError())?;
+    let cached_tile = if source.table_sources.is_empty() {
+        None
+    } else {
+        get_cached_tile(&state, &cache_key).await
+    };
+
+    let (tile_data, db_duration, cache_status) = match cached_tile {
+        // Empty without querying the database
+        None if source.table_sources.is_empty() => (
+            TileData {
+                tile: Tile::new(),
+                truncated: false,
+                last_modified: None,
+                feature_count: None,
+            },
+            None,
+            "none",
+        ),
+        Some(tile) => (
+            TileData {
+                tile,
+                truncated: false,
+                last_modified: None,
+                feature_count: None,
+            },
+            None,
+            "hit",
+        ),
+        None => {
+            let source: Box<dyn Source + Send> = match format {
+                TileFormat::Mvt => Box::new(source),
+                TileFormat::GeoJson => Box::new(GeoJsonSource {
+                    id: source.id,
+                    table_sources: source.table_sources,
+                }),
+            };
+
+            let message = messages::GetTile {
+                xyz: tile_xyz,
+                query: None,
+                source,
+                span,
+                search_path,
+            };
+
+            let _permit = state.db_queue.acquire(Priority::from_request(&req)).await;
+
+            let started_at = Instant::now();
+            let tile_data = state
+                .tile_db()
+                .send(message)
+                .await
+                .map_err(|error| ServerError::Internal(error.to_string()))?
+                .map_err(ServerError::from)?;
+            let db_duration = started_at.elapsed();
+
+            let cache_status = put_cached_tile(&state, cache_key, &tile_data).await;
+            (tile_data, Some(db_duration), cache_status)
+        }
+    };
 
-    match tile.len() {
-        0 => Ok(HttpResponse::NoContent()
-            .content_type("application/x-protobuf")
-            .body(tile)),
-        _ => Ok(HttpResponse::Ok()
-            .content_type("application/x-protobuf")
-            .body(tile)),
+    let mut response = tile_response(
+        &req,
+        &path.source_ids,
+        &xyz,
+        tile_data,
+        max_age,
+        db_duration,
+        cache_status,
+        &state,
+    )?;
+
+    if format == TileFormat::GeoJson {
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(GEOJSON_CONTENT_TYPE),
+        );
+
+        return Ok(response);
     }
+
+    let response = with_tile_buffer(response, tile_extent_and_buffer)?;
+    let response = with_response_headers(response, response_headers.as_ref())?;
+    with_content_type(response, content_type.as_deref())
 }
 
 async fn get_function_sources(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
@@ -214,12 +1624,14 @@ async fn get_function_sources(state: web::Data<AppState>) -> Result<HttpResponse
 
     info!("Scanning database for function sources");
 
-    let function_sources = state
+    let mut function_sources = state
         .db
         .send(messages::GetFunctionSources {})
         .await
-        .map_err(|_| HttpResponse::InternalServerError())?
-        .map_err(|_| HttpResponse::InternalServerError())?;
+        .map_err(|error| ServerError::Internal(error.to_string()))?
+        .map_err(ServerError::from)?;
+
+    state.source_filter.apply(&mut function_sources);
 
     state.coordinator.do_send(messages::RefreshFunctionSources {
         function_sources: Some(function_sources.clone()),
@@ -233,119 +1645,270 @@ async fn get_function_source(
     path: web::Path<SourceRequest>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let function_sources = state
-        .function_sources
-        .borrow()
-        .clone()
-        .ok_or_else(|| error::ErrorNotFound("There is no function sources"))?;
+    let function_sources = state.function_sources.borrow().clone().ok_or_else(|| {
+        source_not_found(
+            &state,
+            "There is no function sources".to_owned(),
+            iter::empty(),
+        )
+    })?;
 
     let source = function_sources.get(&path.source_id).ok_or_else(|| {
-        error::ErrorNotFound(format!("Function source '{}' not found", path.source_id))
+        source_not_found(
+            &state,
+            format!("Function source '{}' not found", path.source_id),
+            function_sources.keys(),
+        )
     })?;
 
     let mut tilejson = source
-        .get_tilejson()
-        .map_err(|e| error::ErrorBadRequest(format!("Can't build TileJSON: {}", e)))?;
+        .get_tilejson_value()
+        .map_err(|e| ServerError::TileJson(format!("Can't build TileJSON: {}", e)))?;
 
-    let tiles_path = req
-        .headers()
-        .get("x-rewrite-url")
-        .map_or(Ok(req.path().trim_end_matches(".json")), |header| {
-            let header_str = header.to_str()?;
-            Ok(header_str.trim_end_matches(".json"))
-        })
-        .map_err(|e: http::header::ToStrError| {
-            error::ErrorBadRequest(format!("Can't build TileJSON: {}", e))
-        })?;
+    tilejson["tiles"] = json!([tiles_url(&req, &state)?]);
+    Ok(HttpResponse::Ok().json(tilejson))
+}
 
-    let query_string = req.query_string();
-    let query = if query_string.is_empty() {
-        query_string.to_owned()
-    } else {
-        format!("?{}", query_string)
+async fn get_function_source_tile(
+    req: HttpRequest,
+    path: web::Path<TileRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let source_id = path.source_id.clone();
+    let xyz = Xyz {
+        z: path.z,
+        x: path.x,
+        y: path.y,
     };
 
-    let connection_info = req.connection_info();
-
-    let tiles_url = format!(
-        "{}://{}{}/{{z}}/{{x}}/{{y}}.pbf{}",
-        connection_info.scheme(),
-        connection_info.host(),
-        tiles_path,
-        query
-    );
-
-    tilejson.tiles = vec![tiles_url];
-    Ok(HttpResponse::Ok().json(tilejson))
+    let negotiated = path.format.is_none();
+    let query_string = req.query_string().to_owned();
+    let handler = function_source_tile(req, path, state.clone());
+    let result = with_request_timeout(&state, &source_id, &xyz, handler).await;
+    log_tile_error(&result, &source_id, &xyz, &query_string, false);
+    with_vary_accept(result, negotiated)
 }
 
-async fn get_function_source_tile(
+async fn function_source_tile(
+    req: HttpRequest,
     path: web::Path<TileRequest>,
-    query: web::Query<HashMap<String, String>>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let function_sources = state
-        .function_sources
-        .borrow()
-        .clone()
-        .ok_or_else(|| error::ErrorNotFound("There is no function sources"))?;
+    check_shutdown(&state)?;
+    check_rate_limit(&req, &state)?;
+    let search_path = tenant_search_path(&req, &state)?;
+    let format = negotiate_tile_format(&req, path.format.as_deref(), &state)?;
+    check_vector_tile_format(format, &path.source_id)?;
+
+    let span = tracing::info_span!(
+        "tile_request",
+        source_id = %path.source_id,
+        request_id = request_id(&req).as_deref().unwrap_or("-"),
+        z = path.z,
+        x = path.x,
+        y = path.y
+    );
+
+    let function_sources = state.function_sources.borrow().clone().ok_or_else(|| {
+        source_not_found(
+            &state,
+            "There is no function sources".to_owned(),
+            iter::empty(),
+        )
+    })?;
 
     let source = function_sources.get(&path.source_id).ok_or_else(|| {
-        error::ErrorNotFound(format!("Function source '{}' not found", path.source_id))
+        source_not_found(
+            &state,
+            format!("Function source '{}' not found", path.source_id),
+            function_sources.keys(),
+        )
     })?;
 
+    // Parsed here instead of with `web::Query`, so errors name the failing parameter
+    let query = parse_query_params(req.query_string()).map_err(ServerError::from)?;
+
     let xyz = Xyz {
         z: path.z,
         x: path.x,
         y: path.y,
     };
 
-    let message = messages::GetTile {
-        xyz,
-        query: Some(query.into_inner()),
-        source: source.clone(),
+    let max_age = source.max_age;
+    let content_type = source.mvt_content_type.as_deref();
+    let query_xyz = source
+        .query_xyz(&xyz, state.overzoom)
+        .filter(|query_xyz| source.is_zoom_valid(query_xyz.z));
+
+    let query_xyz = match query_xyz {
+        Some(query_xyz) if !is_below_min_allowed_zoom(&state, &path.source_id, &xyz) => query_xyz,
+        _ => {
+            let tile_data = TileData {
+                tile: Vec::new(),
+                truncated: false,
+                last_modified: None,
+                feature_count: None,
+            };
+
+            let response = tile_response(
+                &req,
+                &path.source_id,
+                &xyz,
+                tile_data,
+                max_age,
+                None,
+                "none",
+                &state,
+            )?;
+
+            let response = with_response_headers(response, source.response_headers.as_ref())?;
+            return with_content_type(response, content_type);
+        }
+    };
+
+    let query =
+        sanitize_query_params(query, state.max_function_param_length).map_err(ServerError::from)?;
+
+    // Tiles of function parameters or of tenants aren't cached
+    let cache_key = if query.is_empty() && search_path.is_none() {
+        Some(TileCacheKey::new(
+            &path.source_id,
+            &xyz,
+            TileFormat::Mvt.extension(),
+        ))
+    } else {
+        None
     };
 
-    let tile = state
-        .db
-        .send(message)
-        .await
-        .map_err(|_| HttpResponse::InternalServerError())?
-        .map_err(|_| HttpResponse::InternalServerError())?;
+    let (tile_data, db_duration, cache_status) = match get_cached_tile(&state, &cache_key).await {
+        Some(tile) => (
+            TileData {
+                tile,
+                truncated: false,
+                last_modified: None,
+                feature_count: None,
+            },
+            None,
+            "hit",
+        ),
+        None => {
+            let message = messages::GetTile {
+                xyz: source.scheme.unwrap_or_default().to_xyz(&query_xyz),
+                query: Some(query),
+                source: source.clone(),
+                span,
+                search_path,
+            };
 
-    match tile.len() {
-        0 => Ok(HttpResponse::NoContent()
-            .content_type("application/x-protobuf")
-            .body(tile)),
-        _ => Ok(HttpResponse::Ok()
-            .content_type("application/x-protobuf")
-            .body(tile)),
-    }
+            let _permit = state.db_queue.acquire(Priority::from_request(&req)).await;
+
+            let started_at = Instant::now();
+            let tile_data = state
+                .tile_db()
+                .send(message)
+                .await
+                .map_err(|error| ServerError::Internal(error.to_string()))?
+                .map_err(ServerError::from)?;
+            let db_duration = started_at.elapsed();
+
+            let cache_status = put_cached_tile(&state, cache_key, &tile_data).await;
+            (tile_data, Some(db_duration), cache_status)
+        }
+    };
+
+    let response = tile_response(
+        &req,
+        &path.source_id,
+        &xyz,
+        tile_data,
+        max_age,
+        db_duration,
+        cache_status,
+        &state,
+    )?;
+
+    let response = with_response_headers(response, source.response_headers.as_ref())?;
+    with_content_type(response, content_type)
+}
+
+/// Responds to query strings `web::Query` can't deserialize with a JSON `invalid_params` error
+fn query_error_handler(error: QueryPayloadError, _req: &HttpRequest) -> Error {
+    ServerError::InvalidParams(format!("Invalid query string: {}", error)).into()
 }
 
+/// Routes of all source kinds
 pub fn router(cfg: &mut web::ServiceConfig) {
-    cfg.route("/healthz", web::get().to(get_health))
-        .route("/index.json", web::get().to(get_table_sources))
-        .route("/{source_ids}.json", web::get().to(get_composite_source))
-        .route(
-            "/{source_ids}/{z}/{x}/{y}.{format}",
-            web::get().to(get_composite_source_tile),
-        )
-        .route("/rpc/index.json", web::get().to(get_function_sources))
-        .route("/rpc/{source_id}.json", web::get().to(get_function_source))
-        .route(
-            "/rpc/{source_id}/{z}/{x}/{y}.{format}",
-            web::get().to(get_function_source_tile),
+    configure_router(cfg, true, true);
+}
+
+/// Routes without the table source listing and features or the `/rpc` function source
+/// routes of disabled source kinds, their requests respond with `404`. Composite tile
+/// routes stay, as they serve PMTiles and the debug grid as well.
+pub fn configure_router(
+    cfg: &mut web::ServiceConfig,
+    enable_table_sources: bool,
+    enable_function_sources: bool,
+) {
+    cfg.app_data(web::QueryConfig::default().error_handler(query_error_handler));
+
+    cfg.route("/", web::get().to(get_index))
+        .route("/favicon.ico", web::get().to(get_favicon))
+        .route("/healthz", web::get().to(get_health))
+        .route("/version", web::get().to(get_version))
+        .route("/metrics", web::get().to(get_metrics))
+        .route("/catalog", web::get().to(get_catalog))
+        .route("/events", web::get().to(get_events))
+        .route("/fonts/{fontstack}/{range}.pbf", web::get().to(get_glyphs));
+
+    if enable_table_sources {
+        cfg.route("/index.json", web::get().to(get_table_sources))
+            .route("/{source_id}/refresh", web::post().to(refresh_table_source))
+            .route(
+                "/{source_id}/feature/{id}.json",
+                web::get().to(get_table_source_feature),
+            );
+    }
+
+    cfg.route("/{source_ids}.json", web::get().to(get_composite_source))
+        .service(
+            web::resource([
+                "/{source_ids}/{z}/{x}/{y:\\d+}.{format}",
+                "/{source_ids}/{z}/{x}/{y:\\d+}",
+            ])
+            .route(web::get().to(get_composite_source_tile))
+            .route(web::head().to(get_composite_source_tile)),
         );
+
+    if enable_function_sources {
+        cfg.route("/rpc/index.json", web::get().to(get_function_sources))
+            .route("/rpc/{source_id}.json", web::get().to(get_function_source))
+            .service(
+                web::resource([
+                    "/rpc/{source_id}/{z}/{x}/{y:\\d+}.{format}",
+                    "/rpc/{source_id}/{z}/{x}/{y:\\d+}",
+                ])
+                .route(web::get().to(get_function_source_tile))
+                .route(web::head().to(get_function_source_tile)),
+            );
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_state(
     db: Addr<DbActor>,
+    replicas: Vec<Addr<DbActor>>,
+    db_queue: DbQueue,
     coordinator: Addr<CoordinatorActor>,
+    shutting_down: Arc<AtomicBool>,
+    rate_limiter: Option<RateLimiter>,
+    tile_cache: Option<SharedTileCache>,
+    circuit_breaker: Option<CircuitBreaker>,
     config: Config,
 ) -> AppState {
+    let table_source_defaults = config.table_source_defaults();
     let table_sources = Rc::new(RefCell::new(config.table_sources));
     let function_sources = Rc::new(RefCell::new(config.function_sources));
+    let pmtiles_sources = config.pmtiles_sources;
 
     let worker_actor = WorkerActor {
         table_sources: table_sources.clone(),
@@ -357,25 +1920,297 @@ fn create_state(
 
     AppState {
         db,
+        replicas,
+        next_replica: Cell::new(0),
+        db_queue,
         coordinator,
         table_sources,
         function_sources,
+        pmtiles_sources,
+        source_filter: config.source_filter,
         watch_mode: config.watch,
+        debug_grid: config.debug_grid,
+        list_sources_on_not_found: config.list_sources_on_not_found,
+        overzoom: config.overzoom,
+        mvt_content_type: config.mvt_content_type,
+        empty_tile_mode: config.empty_tile_mode,
+        allow_tile_param_overrides: config.allow_tile_param_overrides,
+        max_function_param_length: config.max_function_param_length,
+        max_tile_bytes: config.max_tile_bytes,
+        min_allowed_zoom: config.min_allowed_zoom,
+        debug_feature_count: config.debug_feature_count,
+        zstd_level: config.zstd_level,
+        admin_token: config.admin_token,
+        circuit_breaker,
+        tenant_schemas: config.tenant_schemas,
+        geojson_tiles: config.geojson_tiles,
+        log_format: config.log_format,
+        max_age: config.max_age,
+        table_source_defaults,
+        fonts_directory: config.fonts_directory,
+        public_base_url: config.public_base_url,
+        trust_proxy_headers: config.trust_proxy_headers,
+        shutting_down,
+        shutdown_timeout: config.shutdown_timeout,
+        request_timeout_ms: config.request_timeout_ms,
+        rate_limiter,
+        tile_cache,
     }
 }
 
-pub fn new(pool: Pool, config: Config) -> SystemRunner {
+fn start_shutdown(shutting_down: &AtomicBool) {
+    if !shutting_down.swap(true, Ordering::SeqCst) {
+        info!("Shutting down, rejecting new tile requests");
+    }
+}
+
+/// Sets the shutdown flag on the same signals the web server stops on
+fn watch_shutdown_signals(shutting_down: Arc<AtomicBool>) {
+    let ctrl_c_shutting_down = shutting_down.clone();
+    actix::spawn(async move {
+        if actix_rt::signal::ctrl_c().await.is_ok() {
+            start_shutdown(&ctrl_c_shutting_down);
+        }
+    });
+
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        for kind in [SignalKind::terminate(), SignalKind::quit()] {
+            let shutting_down = shutting_down.clone();
+            actix::spawn(async move {
+                if let Ok(mut stream) = signal(kind) {
+                    if stream.recv().await.is_some() {
+                        start_shutdown(&shutting_down);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Prefix of listen addresses binding a Unix domain socket, e.g. `unix:/run/martin.sock`
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+#[derive(Debug, PartialEq)]
+pub enum ListenAddress {
+    Tcp(String),
+    /// Served as plain HTTP/1.1 even with `tls`, e.g. behind a reverse proxy
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    pub fn parse(address: &str) -> ListenAddress {
+        match address.strip_prefix(UNIX_SOCKET_PREFIX) {
+            Some(path) => ListenAddress::Unix(PathBuf::from(path)),
+            None => ListenAddress::Tcp(address.to_owned()),
+        }
+    }
+}
+
+/// Resolves the number of web server workers, `0` means one worker per CPU
+pub fn resolve_worker_processes(worker_processes: usize) -> usize {
+    clamp_worker_processes(worker_processes)
+}
+
+fn start_db_actor(
+    pool: Pool,
+    config: &Config,
+    circuit_breaker: Option<CircuitBreaker>,
+) -> Addr<DbActor> {
+    let retries = config.db_retries;
+    let retry_backoff = Duration::from_millis(config.db_retry_backoff_ms);
+    let request_timeout = Some(config.request_timeout_ms)
+        .filter(|request_timeout_ms| *request_timeout_ms > 0)
+        .map(Duration::from_millis);
+    let max_retry_sleep = max_retry_sleep(request_timeout);
+    let slow_query = config.slow_query_ms.map(Duration::from_millis);
+
+    SyncArbiter::start(config.db_workers, move || DbActor {
+        pool: pool.clone(),
+        retries,
+        retry_backoff,
+        max_retry_sleep,
+        slow_query,
+        circuit_breaker: circuit_breaker.clone(),
+    })
+}
+
+fn start_grpc_server(
+    listen_address: &str,
+    db: &Addr<DbActor>,
+    replicas: &[Addr<DbActor>],
+    db_queue: &DbQueue,
+    coordinator: &Addr<CoordinatorActor>,
+    rate_limiter: Option<RateLimiter>,
+    config: &Config,
+) {
+    let address: SocketAddr = match listen_address.parse() {
+        Ok(address) => address,
+        Err(error) => {
+            error!("Invalid gRPC listen address {}: {}", listen_address, error);
+            return;
+        }
+    };
+
+    let sources = SharedSources::new(
+        config.table_sources.clone(),
+        config.function_sources.clone(),
+    );
+
+    coordinator.do_send(messages::ShareSources {
+        sources: sources.clone(),
+    });
+
+    let service = TileService {
+        db: db.clone(),
+        replicas: replicas.to_vec(),
+        next_replica: AtomicUsize::new(0),
+        db_queue: db_queue.clone(),
+        sources,
+        debug_grid: config.debug_grid,
+        overzoom: config.overzoom,
+        max_function_param_length: config.max_function_param_length,
+        max_tile_bytes: config.max_tile_bytes,
+        min_allowed_zoom: config.min_allowed_zoom,
+        rate_limiter,
+        tenant_schemas: config.tenant_schemas.clone(),
+    };
+
+    info!("Serving tiles over gRPC on {}", address);
+    actix::spawn(async move {
+        if let Err(error) = grpc::serve(service, address).await {
+            error!("gRPC server failed: {}", error);
+        }
+    });
+}
+
+/// Source discovery runs on the primary `pool`, tile queries are spread across
+/// `replica_pools` if any
+pub fn new(
+    pool: Pool,
+    replica_pools: Vec<Pool>,
+    config: Config,
+    config_file: Option<String>,
+) -> SystemRunner {
+    if let Some(blocking_threads) = config.blocking_threads {
+        if !blocking::init(blocking_threads) {
+            warn!("Ignoring blocking_threads, the blocking pool is already running");
+        }
+    }
+
     let sys = actix::System::new("server");
 
-    let db = SyncArbiter::start(3, move || DbActor(pool.clone()));
-    let coordinator: Addr<_> = CoordinatorActor::default().start();
+    let new_circuit_breaker = || config.circuit_breaker.as_ref().map(CircuitBreaker::new);
+    let circuit_breaker = new_circuit_breaker();
+    let db = start_db_actor(pool, &config, circuit_breaker.clone());
+    let replicas: Vec<Addr<DbActor>> = replica_pools
+        .into_iter()
+        .map(|pool| start_db_actor(pool, &config, new_circuit_breaker()))
+        .collect();
+    let db_queue = DbQueue::new(config.db_workers * replicas.len().max(1));
+    let tile_cache = new_tile_cache(&config.tile_cache)
+        .unwrap_or_else(|error| panic!("Can't set up tile cache: {}", error));
+
+    let coordinator: Addr<_> = CoordinatorActor::new(
+        config.table_sources.clone(),
+        config.function_sources.clone(),
+        tile_cache.clone(),
+    )
+    .start();
+
+    if let Some(channel) = &config.notify_channel {
+        if let Err(error) = listen_notifications(
+            config.connection_string.clone(),
+            config.danger_accept_invalid_certs,
+            channel.clone(),
+            coordinator.clone(),
+        ) {
+            error!("{}", error);
+        }
+    }
+
+    if let Some(file_name) = config_file {
+        reload_on_sighup(file_name.clone(), coordinator.clone());
+
+        if config.watch_config {
+            info!("Watching {} for source changes", file_name);
+            if let Err(error) = watch_config(file_name, coordinator.clone()) {
+                error!("{}", error);
+            }
+        }
+    }
+
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    watch_shutdown_signals(shutting_down.clone());
+
+    let rate_limiter = match &config.rate_limit {
+        Some(rate_limit) if rate_limit.requests_per_second > 0.0 => {
+            Some(RateLimiter::new(rate_limit))
+        }
+        Some(_) => {
+            warn!("Ignoring rate_limit with requests_per_second not above 0");
+            None
+        }
+        None => None,
+    };
+
+    if let Some(grpc_listen_address) = &config.grpc_listen_address {
+        start_grpc_server(
+            grpc_listen_address,
+            &db,
+            &replicas,
+            &db_queue,
+            &coordinator,
+            rate_limiter.clone(),
+            &config,
+        );
+    }
 
     let keep_alive = config.keep_alive;
-    let worker_processes = config.worker_processes;
+    let client_request_timeout = config.client_request_timeout;
+    let client_disconnect_timeout = config.client_disconnect_timeout;
+    let shutdown_timeout = config.shutdown_timeout;
+    let worker_processes = resolve_worker_processes(config.worker_processes);
+    info!(
+        "Using {} web server workers and {} database workers per database",
+        worker_processes, config.db_workers
+    );
     let listen_addresses = config.listen_addresses.clone();
+    let enable_table_sources = config.enable_table_sources;
+    let enable_function_sources = config.enable_function_sources;
+    let static_directories = config.static_files.clone();
+    for files in &static_directories {
+        info!(
+            "Serving {} at {}",
+            files.directory,
+            files.normalized_mount_path()
+        );
+    }
+
+    let tls_config = config.tls.as_ref().map(|tls| {
+        load_server_config(tls).unwrap_or_else(|error| panic!("Can't load TLS config: {}", error))
+    });
+
+    // Same as the default format with the request id from the response header
+    let access_log_format = format!(
+        "%a \"%r\" %s %b \"%{{Referer}}i\" \"%{{User-Agent}}i\" %T request_id=%{{{}}}o",
+        REQUEST_ID_HEADER
+    );
 
-    HttpServer::new(move || {
-        let state = create_state(db.clone(), coordinator.clone(), config.clone());
+    let server = HttpServer::new(move || {
+        let state = create_state(
+            db.clone(),
+            replicas.clone(),
+            db_queue.clone(),
+            coordinator.clone(),
+            shutting_down.clone(),
+            rate_limiter.clone(),
+            tile_cache.clone(),
+            circuit_breaker.clone(),
+            config.clone(),
+        );
 
         let cors_middleware = Cors::default().allow_any_origin();
 
@@ -385,16 +2220,44 @@ pub fn new(pool: Pool, config: Config) -> SystemRunner {
             .wrap(middleware::NormalizePath::new(
                 middleware::normalize::TrailingSlash::MergeOnly,
             ))
-            .wrap(middleware::Logger::default())
+            // Wrapped inside the logger, so the logger sees the response header
+            .wrap(RequestIdMiddleware)
+            .wrap(middleware::Logger::new(&access_log_format))
             .wrap(middleware::Compress::default())
-            .configure(router)
-    })
-    .bind(listen_addresses.clone())
-    .unwrap_or_else(|_| panic!("Can't bind to {}", listen_addresses))
-    .keep_alive(keep_alive)
-    .shutdown_timeout(0)
-    .workers(worker_processes)
-    .run();
+            .configure(|cfg| static_files::configure(cfg, &static_directories))
+            .configure(|cfg| configure_router(cfg, enable_table_sources, enable_function_sources))
+    });
+
+    if tls_config.is_some() {
+        info!("Serving HTTPS and HTTP/2 on TCP addresses");
+    }
+
+    let mut server = server;
+    for address in &listen_addresses {
+        let bound = match ListenAddress::parse(address) {
+            ListenAddress::Tcp(address) => match &tls_config {
+                Some(tls_config) => server.bind_rustls(address, tls_config.clone()),
+                None => server.bind(address),
+            },
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => server.bind_uds(path),
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported",
+            )),
+        };
+
+        server = bound.unwrap_or_else(|error| panic!("Can't bind to {}: {}", address, error));
+    }
+
+    server
+        .keep_alive(keep_alive)
+        .client_timeout(client_request_timeout)
+        .client_shutdown(client_disconnect_timeout)
+        .shutdown_timeout(shutdown_timeout)
+        .workers(worker_processes)
+        .run();
 
     sys
 }
@@ -0,0 +1,262 @@
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+use actix_web::error::BlockingError;
+use actix_web::{http, HttpResponse, ResponseError};
+
+use crate::query_params::QueryParamError;
+use crate::source::Xyz;
+
+/// Errors returned by the HTTP endpoints as `{"error": "...", "code": "..."}`
+#[derive(Debug)]
+pub enum ServerError {
+    /// None of the requested sources exist
+    SourceNotFound(String),
+    /// `SourceNotFound` listing the ids of the existing sources, for debugging
+    SourceNotFoundAmong {
+        message: String,
+        available_sources: Vec<String>,
+    },
+    /// There is no feature with the requested id
+    FeatureNotFound(String),
+    /// A font of the requested fontstack doesn't exist
+    FontNotFound(String),
+    /// The tile extension is not a vector tile format
+    UnsupportedFormat(String),
+    /// Invalid request parameters
+    InvalidParams(String),
+    /// A query string parameter can't be parsed, the response names the parameter
+    InvalidQueryParam {
+        message: String,
+        parameter: String,
+    },
+    /// TileJSON can't be built for the source
+    TileJson(String),
+    /// The database is unreachable, e.g. no pooled connection is available
+    DatabaseUnavailable(String),
+    /// Tile or source query failed
+    Database(String),
+    /// New tile requests are rejected during graceful shutdown
+    ShuttingDown {
+        retry_after: u64,
+    },
+    /// The tile request took longer than `request_timeout_ms`
+    Timeout(String),
+    /// The admin endpoint request has no valid `admin_token`
+    Unauthorized(String),
+    /// The tenant of the request has no schema in `tenant_schemas`
+    Forbidden(String),
+    /// The client exceeded `rate_limit`
+    TooManyRequests {
+        retry_after: u64,
+    },
+    /// The `Range` header is outside of the tile of the given length
+    RangeNotSatisfiable {
+        length: usize,
+    },
+    /// The tile is larger than `max_tile_bytes`, the source is likely misconfigured
+    TileTooLarge(String),
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available_sources: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameter: Option<String>,
+}
+
+impl ServerError {
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::SourceNotFound(_) | ServerError::SourceNotFoundAmong { .. } => {
+                "source_not_found"
+            }
+            ServerError::FeatureNotFound(_) => "feature_not_found",
+            ServerError::FontNotFound(_) => "font_not_found",
+            ServerError::UnsupportedFormat(_) => "unsupported_format",
+            ServerError::InvalidParams(_) | ServerError::InvalidQueryParam { .. } => {
+                "invalid_params"
+            }
+            ServerError::TileJson(_) => "invalid_tilejson",
+            ServerError::DatabaseUnavailable(_) => "database_unavailable",
+            ServerError::Database(_) => "database_error",
+            ServerError::ShuttingDown { .. } => "shutting_down",
+            ServerError::Timeout(_) => "timeout",
+            ServerError::Unauthorized(_) => "unauthorized",
+            ServerError::Forbidden(_) => "forbidden",
+            ServerError::TooManyRequests { .. } => "rate_limited",
+            ServerError::RangeNotSatisfiable { .. } => "range_not_satisfiable",
+            ServerError::TileTooLarge(_) => "tile_too_large",
+            ServerError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::SourceNotFound(message)
+            | ServerError::FeatureNotFound(message)
+            | ServerError::FontNotFound(message)
+            | ServerError::UnsupportedFormat(message)
+            | ServerError::InvalidParams(message)
+            | ServerError::TileJson(message)
+            | ServerError::DatabaseUnavailable(message)
+            | ServerError::Database(message)
+            | ServerError::Timeout(message)
+            | ServerError::TileTooLarge(message)
+            | ServerError::Unauthorized(message)
+            | ServerError::Forbidden(message)
+            | ServerError::Internal(message)
+            | ServerError::SourceNotFoundAmong { message, .. }
+            | ServerError::InvalidQueryParam { message, .. } => write!(f, "{}", message),
+            ServerError::ShuttingDown { .. } => write!(f, "Server is shutting down"),
+            ServerError::TooManyRequests { retry_after } => {
+                write!(f, "Too many requests, retry in {} seconds", retry_after)
+            }
+            ServerError::RangeNotSatisfiable { length } => {
+                write!(f, "Range is outside of the {} bytes tile", length)
+            }
+        }
+    }
+}
+
+/// Rejects tiles larger than `max_tile_bytes`, the error names the source and the tile
+pub fn check_tile_size(
+    tile: &[u8],
+    max_tile_bytes: Option<usize>,
+    source_id: &str,
+    xyz: &Xyz,
+) -> Result<(), ServerError> {
+    match max_tile_bytes {
+        Some(max_tile_bytes) if tile.len() > max_tile_bytes => {
+            Err(ServerError::TileTooLarge(format!(
+                "Tile {}/{}/{} of '{}' is {} bytes, larger than max_tile_bytes of {}",
+                xyz.z,
+                xyz.x,
+                xyz.y,
+                source_id,
+                tile.len(),
+                max_tile_bytes
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Tile query errors, `InvalidInput` comes from request parameter validation,
+/// `TimedOut` from the connection pool and `ConnectionRefused` from the open circuit breaker
+impl From<io::Error> for ServerError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::InvalidInput => ServerError::InvalidParams(error.to_string()),
+            io::ErrorKind::TimedOut | io::ErrorKind::ConnectionRefused => {
+                ServerError::DatabaseUnavailable(error.to_string())
+            }
+            _ => ServerError::Database(error.to_string()),
+        }
+    }
+}
+
+impl From<QueryParamError> for ServerError {
+    fn from(error: QueryParamError) -> Self {
+        ServerError::InvalidQueryParam {
+            message: error.to_string(),
+            parameter: error.parameter,
+        }
+    }
+}
+
+impl From<BlockingError<io::Error>> for ServerError {
+    fn from(error: BlockingError<io::Error>) -> Self {
+        match error {
+            BlockingError::Error(error) => ServerError::from(error),
+            BlockingError::Canceled => ServerError::Internal("Thread pool is gone".to_owned()),
+        }
+    }
+}
+
+impl ResponseError for ServerError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            ServerError::SourceNotFound(_)
+            | ServerError::SourceNotFoundAmong { .. }
+            | ServerError::FeatureNotFound(_)
+            | ServerError::FontNotFound(_) => http::StatusCode::NOT_FOUND,
+            ServerError::UnsupportedFormat(_) => http::StatusCode::NOT_ACCEPTABLE,
+            ServerError::InvalidParams(_)
+            | ServerError::InvalidQueryParam { .. }
+            | ServerError::TileJson(_) => http::StatusCode::BAD_REQUEST,
+            ServerError::DatabaseUnavailable(_) | ServerError::ShuttingDown { .. } => {
+                http::StatusCode::SERVICE_UNAVAILABLE
+            }
+            ServerError::Timeout(_) => http::StatusCode::GATEWAY_TIMEOUT,
+            ServerError::Unauthorized(_) => http::StatusCode::UNAUTHORIZED,
+            ServerError::Forbidden(_) => http::StatusCode::FORBIDDEN,
+            ServerError::TooManyRequests { .. } => http::StatusCode::TOO_MANY_REQUESTS,
+            ServerError::RangeNotSatisfiable { .. } => http::StatusCode::RANGE_NOT_SATISFIABLE,
+            ServerError::Database(_) | ServerError::TileTooLarge(_) | ServerError::Internal(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status_code = self.status_code();
+        if status_code == http::StatusCode::INTERNAL_SERVER_ERROR {
+            error!("{}", self);
+        }
+
+        // Database errors may expose connection details or SQL
+        let message = match self {
+            ServerError::Database(_) => "Can't get data from the database".to_owned(),
+            ServerError::DatabaseUnavailable(_) => "Database is unavailable".to_owned(),
+            _ => self.to_string(),
+        };
+
+        let mut response = HttpResponse::build(status_code);
+
+        match self {
+            ServerError::ShuttingDown { retry_after } => {
+                response
+                    .force_close()
+                    .header(http::header::RETRY_AFTER, retry_after.to_string());
+            }
+            ServerError::TooManyRequests { retry_after } => {
+                response.header(http::header::RETRY_AFTER, retry_after.to_string());
+            }
+            ServerError::Unauthorized(_) => {
+                response.header(http::header::WWW_AUTHENTICATE, "Bearer");
+            }
+            ServerError::RangeNotSatisfiable { length } => {
+                response.header(http::header::CONTENT_RANGE, format!("bytes */{}", length));
+            }
+            _ => {}
+        }
+
+        let available_sources = match self {
+            ServerError::SourceNotFoundAmong {
+                available_sources, ..
+            } => Some(available_sources.clone()),
+            _ => None,
+        };
+
+        let parameter = match self {
+            ServerError::InvalidQueryParam { parameter, .. } => Some(parameter.clone()),
+            _ => None,
+        };
+
+        response.json(ErrorBody {
+            error: message,
+            code: self.code(),
+            available_sources,
+            parameter,
+        })
+    }
+}
@@ -1,14 +1,25 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::io;
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
 
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use tilejson::TileJSON;
 
+use postgres::Row;
+
 use crate::db::Connection;
+use crate::utils;
 
 pub type Tile = Vec<u8>;
 pub type Query = HashMap<String, String>;
 
+/// Highest zoom with tile coordinates that fit in `i32`
+pub const MAX_ZOOM: i32 = 30;
+
 #[derive(Copy, Clone)]
 pub struct Xyz {
     pub z: i32,
@@ -16,15 +27,195 @@ pub struct Xyz {
     pub y: i32,
 }
 
+impl Xyz {
+    /// Whether the zoom is at most `MAX_ZOOM` and the tile is inside of its grid
+    pub fn is_valid(&self) -> bool {
+        if !(0..=MAX_ZOOM).contains(&self.z) {
+            return false;
+        }
+
+        let tiles = 1 << self.z;
+        (0..tiles).contains(&self.x) && (0..tiles).contains(&self.y)
+    }
+
+    /// Tile at the lower zoom `z` containing this one
+    pub fn ancestor(&self, z: i32) -> Xyz {
+        // Shifting by the width of `i32` or more would overflow
+        let shift = u32::try_from(self.z - z).unwrap_or(0);
+
+        Xyz {
+            z,
+            x: self.x.checked_shr(shift).unwrap_or(0),
+            y: self.y.checked_shr(shift).unwrap_or(0),
+        }
+    }
+}
+
+/// Tile row numbering, `tms` counts rows from the bottom
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Xyz,
+    Tms,
+}
+
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Xyz => "xyz",
+            Scheme::Tms => "tms",
+        }
+    }
+
+    /// Converts requested coordinates to `xyz`, flipping y for `tms`
+    pub fn to_xyz(&self, xyz: &Xyz) -> Xyz {
+        match self {
+            Scheme::Xyz => *xyz,
+            Scheme::Tms => utils::flip_y(xyz),
+        }
+    }
+}
+
+/// Response to tiles without features
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyTileMode {
+    /// `204 No Content`
+    #[default]
+    NoContent,
+    /// `200 OK` with an empty MVT, i.e. a tile without layers, which encodes to zero bytes
+    EmptyMvt,
+}
+
+/// Tile SQL along with the tile bounds bound as `$1..$4`, see `utils::MERCATOR_BOUNDS`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileQuery {
+    pub sql: String,
+    /// `xmin`, `ymin`, `xmax` and `ymax` in web mercator meters
+    pub params: [f64; 4],
+}
+
+impl TileQuery {
+    pub fn new(sql: String, xyz: &Xyz) -> TileQuery {
+        let (xmin, ymin, xmax, ymax) = utils::tile_bounds(xyz);
+
+        TileQuery {
+            sql,
+            params: [xmin, ymin, xmax, ymax],
+        }
+    }
+
+    /// SQL with the tile bounds inlined, so it can be run as is, e.g. in psql
+    pub fn to_inline_sql(&self) -> String {
+        self.params
+            .iter()
+            .enumerate()
+            .fold(self.sql.clone(), |sql, (index, value)| {
+                sql.replace(
+                    &format!("${}::float8", index + 1),
+                    &format!("({})::float8", value),
+                )
+            })
+    }
+
+    pub fn query_one(&self, conn: &mut Connection) -> Result<Row, io::Error> {
+        let [xmin, ymin, xmax, ymax] = &self.params;
+
+        conn.query_one(self.sql.as_str(), &[xmin, ymin, xmax, ymax])
+            .map_err(io::Error::other)
+    }
+}
+
+/// Tile along with details on how it was produced
+#[derive(Clone, Debug, Default)]
+pub struct TileData {
+    pub tile: Tile,
+    /// Some features were dropped because of `feature_limit`
+    pub truncated: bool,
+    /// Latest `updated_at_column` value of the tile features, when all sources have one
+    pub last_modified: Option<SystemTime>,
+    /// Number of the tile features, counted with `debug_feature_count`
+    pub feature_count: Option<i64>,
+}
+
+/// Serialized TileJSON without the request dependent `tiles` URL,
+/// built once and shared between clones of the source
+#[derive(Clone, Debug, Default)]
+pub struct TileJsonCache(Arc<OnceLock<Value>>);
+
+impl TileJsonCache {
+    pub fn get_or_build<F>(&self, build: F) -> Result<Value, io::Error>
+    where
+        F: FnOnce() -> Result<Value, io::Error>,
+    {
+        if let Some(tilejson) = self.0.get() {
+            return Ok(tilejson.clone());
+        }
+
+        let tilejson = build()?;
+        Ok(self.0.get_or_init(|| tilejson).clone())
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
 pub trait Source: Debug {
     fn get_id(&self) -> &str;
 
     fn get_tilejson(&self) -> Result<TileJSON, io::Error>;
 
+    fn tilejson_cache(&self) -> Option<&TileJsonCache> {
+        None
+    }
+
+    /// Non-standard TileJSON keys, prefixed with `falcon:`, and `vector_layers`
+    /// the `tilejson` crate doesn't have
+    fn get_tilejson_extensions(&self) -> Map<String, Value> {
+        Map::new()
+    }
+
+    /// TileJSON with the extension keys
+    fn build_tilejson_value(&self) -> Result<Value, io::Error> {
+        let mut tilejson = serde_json::to_value(self.get_tilejson()?).map_err(io::Error::other)?;
+
+        if let Some(tilejson) = tilejson.as_object_mut() {
+            tilejson.extend(self.get_tilejson_extensions());
+        }
+
+        Ok(tilejson)
+    }
+
+    /// Serialized TileJSON, served from the source cache when it has one
+    fn get_tilejson_value(&self) -> Result<Value, io::Error> {
+        match self.tilejson_cache() {
+            Some(cache) => cache.get_or_build(|| self.build_tilejson_value()),
+            None => self.build_tilejson_value(),
+        }
+    }
+
     fn get_tile(
         &self,
         conn: &mut Connection,
         xyz: &Xyz,
         query: &Option<Query>,
     ) -> Result<Tile, io::Error>;
+
+    fn get_tile_data(
+        &self,
+        conn: &mut Connection,
+        xyz: &Xyz,
+        query: &Option<Query>,
+    ) -> Result<TileData, io::Error> {
+        let tile = self.get_tile(conn, xyz, query)?;
+
+        Ok(TileData {
+            tile,
+            truncated: false,
+            last_modified: None,
+            feature_count: None,
+        })
+    }
 }
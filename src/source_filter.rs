@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Include and exclude glob patterns matched against `schema.name` source ids,
+/// e.g. `public.*` or `tenant_?.orders`. `*` matches any characters and `?`
+/// matches exactly one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SourceFilter {
+    /// Only sources matching one of these patterns are kept [default: all]
+    pub include: Option<Vec<String>>,
+    /// Sources matching one of these patterns are dropped, even if included
+    pub exclude: Option<Vec<String>>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    pub fn matches(&self, source_id: &str) -> bool {
+        let included = self.include.as_ref().is_none_or(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, source_id))
+        });
+
+        let excluded = self.exclude.as_ref().is_some_and(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, source_id))
+        });
+
+        included && !excluded
+    }
+
+    /// Drops sources not matching the filter
+    pub fn apply<S>(&self, sources: &mut HashMap<String, S>) {
+        if self.is_empty() {
+            return;
+        }
+
+        sources.retain(|source_id, _| {
+            let matches = self.matches(source_id);
+            if !matches {
+                debug!(
+                    "Skipping {} not matching include/exclude patterns",
+                    source_id
+                );
+            }
+
+            matches
+        });
+    }
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it is matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` consume one more character
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
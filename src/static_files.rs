@@ -0,0 +1,26 @@
+use actix_files::Files;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+
+/// Local directory served at a mount path, e.g. styles, sprites or glyphs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StaticFiles {
+    /// URL path the directory is mounted at, e.g. `/styles`
+    pub mount_path: String,
+    pub directory: String,
+}
+
+impl StaticFiles {
+    /// Mount path with a leading and without a trailing slash
+    pub fn normalized_mount_path(&self) -> String {
+        format!("/{}", self.mount_path.trim_matches('/'))
+    }
+}
+
+/// Registers a `Files` service for each directory. Has to be configured before `router`,
+/// otherwise tile routes could match files nested a few levels deep.
+pub fn configure(cfg: &mut web::ServiceConfig, static_files: &[StaticFiles]) {
+    for files in static_files {
+        cfg.service(Files::new(&files.normalized_mount_path(), &files.directory));
+    }
+}
@@ -1,84 +1,646 @@
+use postgres::error::SqlState;
+use postgres_protocol::escape::escape_literal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::io;
+use std::sync::{Arc, OnceLock};
 
 use tilejson::{TileJSON, TileJSONBuilder};
 
 use crate::db::Connection;
-use crate::source::{Query, Source, Tile, Xyz};
+use crate::select_list::{quote_identifier, SelectList};
+use crate::source::{Query, Scheme, Source, Tile, TileJsonCache, TileQuery, Xyz};
 use crate::utils;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TableSource {
     pub id: String,
+    /// Id the source is served with instead of `schema.table`, see `apply_public_ids`
+    pub public_id: Option<String>,
     pub schema: String,
     pub table: String,
     pub id_column: Option<String>,
+    /// Single integer primary key column, found on source discovery
+    pub primary_key: Option<String>,
+    /// Whether the geometry column has a spatial index, found on source discovery
+    pub spatial_index: Option<bool>,
+    /// Materialized view refreshed by `POST /{source_id}/refresh`, found on source discovery
+    /// [default: false]
+    pub is_matview: Option<bool>,
+    /// Use `primary_key` as feature id when `id_column` is not set [default: true]
+    pub auto_id_column: Option<bool>,
     pub geometry_column: String,
     pub srid: u32,
     pub extent: Option<u32>,
     pub buffer: Option<u32>,
+    /// Buffer in screen pixels of 256px tiles, overrides `buffer`, see `tile_buffer`
+    pub buffer_pixels: Option<f64>,
     pub clip_geom: Option<bool>,
     pub geometry_type: Option<String>,
+    /// Concrete types found by sampling a `GEOMETRY` column on discovery, see
+    /// `geometry_type_sample_size`
+    pub geometry_types: Option<Vec<String>>,
     pub properties: HashMap<String, String>,
+    /// Minimum polygon area in screen pixels (of 256px tiles) at the requested zoom.
+    /// Smaller polygons are dropped from the tile; points and lines are always kept.
+    pub min_polygon_pixels: Option<f64>,
+    /// Permanent SQL condition on the table rows, e.g. `published = true`, added to tile
+    /// and feature queries as is. It comes from the operator's config and is trusted,
+    /// never build it from request input.
+    pub where_sql: Option<String>,
+    /// `Cache-Control` max-age of tiles in seconds, falls back to the global `max_age`
+    pub max_age: Option<u32>,
+    /// `Content-Type` of tiles, falls back to the global `mvt_content_type`
+    pub mvt_content_type: Option<String>,
+    /// Headers added to tile responses, replacing the default ones of the same name
+    pub response_headers: Option<HashMap<String, String>>,
+    /// Simplify geometries to the tile resolution with `simplify_algorithm` [default: false]
+    pub simplify: Option<bool>,
+    /// Simplification tolerance in tile coordinate units by zoom, replacing `simplify`.
+    /// Each zoom uses the tolerance of the nearest listed zoom at or above it,
+    /// geometries are not simplified above the highest listed zoom.
+    pub simplify_zooms: Option<BTreeMap<u8, f64>>,
+    /// [default: preserve_topology]
+    pub simplify_algorithm: Option<SimplifyAlgorithm>,
+    /// Repair invalid geometries with `ST_MakeValid` before encoding, features that
+    /// still can't be encoded are dropped from the tile [default: false]
+    pub make_valid: Option<bool>,
+    /// Maximum number of features per tile, the largest features are kept
+    pub feature_limit: Option<u32>,
+    /// Timestamp column of the row updates, tiles are `Last-Modified` at its latest
+    /// value among the tile features
+    pub updated_at_column: Option<String>,
+    /// Column splitting tiles into one MVT layer per distinct value, named after the value.
+    /// Features with `NULL` values are in the layer named after the source id.
+    pub layer_column: Option<String>,
+    /// Maximum number of `layer_column` layers per tile, the first values in sort order
+    /// are kept [default: 16]
+    pub max_layers: Option<u32>,
+    /// `jsonb`, `json` or `hstore` property column replaced by its top-level keys
+    pub flatten_column: Option<String>,
+    /// Keys of `flatten_column` to keep, all keys of each feature by default
+    pub flatten_keys: Option<Vec<String>>,
+    /// Tile row numbering of requests and TileJSON [default: xyz]
+    pub scheme: Option<Scheme>,
+    /// TileJSON bounds in degrees, `[west, south, east, north]`
+    pub bounds: Option<Vec<f32>>,
+    /// Lazily built tile queries, shared between clones of the source.
+    /// Only the tile bounds are bound per request, see `utils::MERCATOR_BOUNDS`.
+    #[serde(skip)]
+    pub tile_query_cache: Arc<OnceLock<TileQueries>>,
+    #[serde(skip)]
+    pub tilejson_cache: TileJsonCache,
+}
+
+/// PostGIS function simplifying tile geometries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimplifyAlgorithm {
+    /// `ST_SimplifyPreserveTopology`, the tolerance is a distance
+    #[default]
+    PreserveTopology,
+    /// `ST_SimplifyVW`, the tolerance is squared into an area
+    Vw,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TileQueries {
+    pub tile: String,
+    pub truncated: Option<String>,
+    pub updated_at: Option<String>,
+    pub feature_count: String,
 }
 
 pub type TableSources = HashMap<String, Box<TableSource>>;
 
 impl TableSource {
-    pub fn get_geom_query(&self, xyz: &Xyz) -> String {
-        let mercator_bounds = utils::tilebbox(xyz);
+    /// Returns a copy of the source with `extent`, `buffer` and `clip_geom`
+    /// overridden by the corresponding query parameters
+    pub fn apply_tile_params(&self, query: &Query) -> Result<TableSource, io::Error> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let mut source = self.clone();
+        source.tile_query_cache = Arc::default();
 
-        let properties = if self.properties.is_empty() {
-            "".to_string()
-        } else {
-            let properties = self
-                .properties
-                .keys()
-                .map(|column| format!("\"{0}\"", column))
-                .collect::<Vec<String>>()
-                .join(",");
+        if let Some(extent) = query.get("extent") {
+            let extent: u32 = extent
+                .parse()
+                .map_err(|_| invalid(format!("Invalid extent '{}'", extent)))?;
 
-            format!(", {0}", properties)
-        };
+            if !extent.is_power_of_two() {
+                return Err(invalid(format!("Extent {} is not a power of two", extent)));
+            }
+
+            source.extent = Some(extent);
+        }
+
+        if let Some(buffer) = query.get("buffer") {
+            let buffer: u32 = buffer
+                .parse()
+                .map_err(|_| invalid(format!("Invalid buffer '{}'", buffer)))?;
+
+            source.buffer = Some(buffer);
+            source.buffer_pixels = None;
+        }
+
+        if let Some(clip_geom) = query.get("clip_geom") {
+            let clip_geom: bool = clip_geom
+                .parse()
+                .map_err(|_| invalid(format!("Invalid clip_geom '{}'", clip_geom)))?;
 
+            source.clip_geom = Some(clip_geom);
+        }
+
+        let extent = source.tile_extent();
+        let buffer = source.tile_buffer();
+        if buffer > extent {
+            return Err(invalid(format!(
+                "Buffer {} is larger than extent {}",
+                buffer, extent
+            )));
+        }
+
+        Ok(source)
+    }
+
+    /// Returns a copy of the source with only the given properties it has
+    pub fn with_properties(&self, properties: &[&str]) -> TableSource {
+        let mut source = self.clone();
+        source.tile_query_cache = Arc::default();
+        source
+            .properties
+            .retain(|column, _| properties.contains(&column.as_str()));
+
+        source
+    }
+
+    /// Feature id column, falls back to the primary key unless `auto_id_column` is disabled
+    pub fn get_id_column(&self) -> Option<&str> {
+        match &self.id_column {
+            Some(id_column) => Some(id_column),
+            None if self.auto_id_column.unwrap_or(true) => self.primary_key.as_deref(),
+            None => None,
+        }
+    }
+
+    /// Quoted `schema.table`, the source id may also contain the geometry column
+    pub fn get_table_reference(&self) -> String {
         format!(
-            include_str!("scripts/get_geom.sql"),
-            id = self.id,
-            srid = self.srid,
-            geometry_column = self.geometry_column,
-            mercator_bounds = mercator_bounds,
-            extent = self.extent.unwrap_or(DEFAULT_EXTENT),
-            buffer = self.buffer.unwrap_or(DEFAULT_BUFFER),
+            "{}.{}",
+            quote_identifier(&self.schema),
+            quote_identifier(&self.table)
+        )
+    }
+
+    /// ` AND (where_sql)`, empty without a `where_sql` condition
+    fn get_where_sql_filter(&self) -> String {
+        match self.where_sql.as_deref().map(str::trim) {
+            Some(where_sql) if !where_sql.is_empty() => format!(" AND ({})", where_sql),
+            _ => "".to_string(),
+        }
+    }
+
+    /// Spatial filter of tile features followed by the `where_sql` condition
+    pub fn get_filter(&self) -> String {
+        // Minimum polygon area in square mercator meters is
+        // `min_polygon_pixels * (tile width / 256)^2`
+        let min_area_filter = self
+            .min_polygon_pixels
+            .map_or("".to_string(), |min_polygon_pixels| {
+                format!(
+                    " AND (ST_Dimension({0}) < 2 OR ST_Area(ST_Transform({0}, 3857)) >= {1} * power(({2}) / 256, 2))",
+                    self.geometry_column,
+                    min_polygon_pixels,
+                    utils::MERCATOR_TILE_WIDTH
+                )
+            });
+
+        format!(
+            "{} && bounds.srid_{}{}{}",
+            self.geometry_column,
+            self.srid,
+            min_area_filter,
+            self.get_where_sql_filter()
+        )
+    }
+
+    /// MVT extent of the tiles, used by both `ST_AsMVTGeom` and `ST_AsMVT`
+    pub fn tile_extent(&self) -> u32 {
+        self.extent.unwrap_or(DEFAULT_EXTENT)
+    }
+
+    /// Tile buffer in extent units. `buffer_pixels` are converted with
+    /// `round(buffer_pixels * extent / 256)`, e.g. 8 pixels are 128 units of a 4096 extent.
+    pub fn tile_buffer(&self) -> u32 {
+        match self.buffer_pixels {
+            Some(buffer_pixels) => {
+                (buffer_pixels * f64::from(self.tile_extent()) / TILE_PIXELS).round() as u32
+            }
+            None => self.buffer.unwrap_or(DEFAULT_BUFFER),
+        }
+    }
+
+    pub fn get_geom_query(&self) -> String {
+        // Largest features are kept when the limit is hit
+        let feature_limit = self.feature_limit.map_or("".to_string(), |feature_limit| {
+            format!(
+                "\n  ORDER BY ST_Area({}) DESC\n  LIMIT {}",
+                self.geometry_column, feature_limit
+            )
+        });
+
+        let extent = self.tile_extent();
+
+        let geometry = self.get_simplified_geometry(extent);
+
+        let geom = format!(
+            "ST_AsMVTGeom({geometry}, {mercator_bounds}, {extent}, {buffer}, {clip_geom}) AS geom",
+            geometry = geometry,
+            mercator_bounds = utils::MERCATOR_BOUNDS,
+            extent = extent,
+            buffer = self.tile_buffer(),
             clip_geom = self.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM),
-            properties = properties
+        );
+
+        let mut select_list = SelectList::new().expression(&geom);
+
+        // ST_AsMVT needs the feature id column in the select list
+        if let Some(id_column) = self.get_id_column() {
+            if !self.properties.contains_key(id_column) {
+                select_list = select_list.column(id_column);
+            }
+        }
+
+        // Layers are grouped by the column, so it's kept even when it's not a property
+        if let Some(layer_column) = &self.layer_column {
+            if !self.properties.contains_key(layer_column)
+                && self.get_id_column() != Some(layer_column.as_str())
+            {
+                select_list = select_list.column(layer_column);
+            }
+        }
+
+        let mut properties: Vec<&String> = self.properties.keys().collect();
+        properties.sort();
+        for column in properties {
+            // `ST_AsMVT` encodes the top-level keys of `jsonb` columns as attributes
+            select_list = match self.get_flattened_properties(column) {
+                Some(flattened) => select_list.computed(&flattened, column),
+                None => select_list.column(column),
+            };
+        }
+
+        format!(
+            include_str!("scripts/get_geom.sql"),
+            table = self.get_table_reference(),
+            select_list = select_list.build(),
+            filter = self.get_filter(),
+            feature_limit = feature_limit
         )
     }
 
-    pub fn get_tile_query(&self, xyz: &Xyz) -> String {
-        let geom_query = self.get_geom_query(xyz);
+    /// Geometry in web mercator, simplified according to `simplify` or `simplify_zooms`
+    fn get_simplified_geometry(&self, extent: u32) -> String {
+        let geometry = if self.make_valid.unwrap_or(false) {
+            format!("ST_MakeValid({})", self.geometry_column)
+        } else {
+            self.geometry_column.clone()
+        };
+
+        let geometry = format!("ST_Transform({}, 3857)", geometry);
+
+        // Tile ground resolution, i.e. the size of one tile coordinate unit
+        // in mercator meters: `tile width / extent`
+        let resolution = format!("({}) / {}", utils::MERCATOR_TILE_WIDTH, extent);
+
+        let simplify = |tolerance: &str| match self.simplify_algorithm.unwrap_or_default() {
+            SimplifyAlgorithm::PreserveTopology => {
+                format!("ST_SimplifyPreserveTopology({}, {})", geometry, tolerance)
+            }
+            SimplifyAlgorithm::Vw => {
+                format!("ST_SimplifyVW({}, power({}, 2))", geometry, tolerance)
+            }
+        };
+
+        let simplify_zooms = match &self.simplify_zooms {
+            Some(simplify_zooms) if !simplify_zooms.is_empty() => simplify_zooms,
+            _ if self.simplify.unwrap_or(false) => return simplify(&resolution),
+            _ => return geometry,
+        };
+
+        // Tile queries don't depend on the zoom, so it is matched by the tile width.
+        // The threshold is between the widths of the zoom and the next one.
+        let branches: Vec<String> = simplify_zooms
+            .iter()
+            .map(|(zoom, tolerance)| {
+                format!(
+                    "WHEN ({}) > {:.2} THEN {}",
+                    utils::MERCATOR_TILE_WIDTH,
+                    utils::tile_width(i32::from(*zoom)) * 0.75,
+                    simplify(&format!("{} * {}", tolerance, resolution))
+                )
+            })
+            .collect();
+
+        format!("CASE {} ELSE {} END", branches.join(" "), geometry)
+    }
+
+    pub fn format_tile_query(&self) -> String {
+        let geom_query = self.get_geom_query();
 
         let id_column = self
-            .id_column
-            .clone()
+            .get_id_column()
             .map_or("".to_string(), |id_column| format!(", '{}'", id_column));
 
+        if let Some(layer_column) = &self.layer_column {
+            return format!(
+                include_str!("scripts/get_layered_tile.sql"),
+                id = self.id,
+                id_column = id_column,
+                geom_query = geom_query,
+                extent = self.tile_extent(),
+                layer_column = quote_identifier(layer_column),
+                max_layers = self.max_layers.unwrap_or(DEFAULT_MAX_LAYERS),
+            );
+        }
+
         format!(
             include_str!("scripts/get_tile.sql"),
             id = self.id,
             id_column = id_column,
             geom_query = geom_query,
-            extent = self.extent.unwrap_or(DEFAULT_EXTENT),
+            extent = self.tile_extent(),
+        )
+    }
+
+    /// Query checking whether the tile has more features than `feature_limit`
+    pub fn format_truncated_query(&self) -> Option<String> {
+        self.feature_limit.map(|feature_limit| {
+            format!(
+                include_str!("scripts/get_truncated.sql"),
+                table = self.get_table_reference(),
+                filter = self.get_filter(),
+                feature_limit = feature_limit
+            )
+        })
+    }
+
+    /// Query of the latest `updated_at_column` value in the tile as epoch seconds
+    pub fn format_updated_at_query(&self) -> Option<String> {
+        self.updated_at_column.as_ref().map(|updated_at_column| {
+            format!(
+                include_str!("scripts/get_updated_at.sql"),
+                updated_at_column = quote_identifier(updated_at_column),
+                table = self.get_table_reference(),
+                filter = self.get_filter(),
+            )
+        })
+    }
+
+    /// `jsonb` object of the `flatten_column` keys, if the column is the given property
+    fn get_flattened_properties(&self, column: &str) -> Option<String> {
+        if self.flatten_column.as_deref() != Some(column) {
+            return None;
+        }
+
+        let column = format!("{}::jsonb", quote_identifier(column));
+
+        let flattened = match &self.flatten_keys {
+            Some(keys) => {
+                let keys = keys
+                    .iter()
+                    .map(|key| format!("{key}, {column} -> {key}", key = escape_literal(key)))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                format!("jsonb_strip_nulls(jsonb_build_object({}))", keys)
+            }
+            None => column,
+        };
+
+        Some(flattened)
+    }
+
+    /// Query of the number of features encoded into the tile, i.e. with a geometry left
+    /// after `ST_AsMVTGeom`. It runs the features query once more.
+    pub fn format_feature_count_query(&self) -> String {
+        format!(
+            "SELECT count(geom) FROM ({}) AS tile",
+            self.get_geom_query()
+        )
+    }
+
+    /// Properties as a `json_build_object` call with sorted keys,
+    /// merged with the keys of `flatten_column`
+    fn get_properties_object(&self) -> String {
+        let mut properties: Vec<&String> = self.properties.keys().collect();
+        properties.sort();
+
+        let flattened = properties
+            .iter()
+            .find_map(|column| self.get_flattened_properties(column));
+
+        let properties = properties
+            .into_iter()
+            .filter(|column| self.flatten_column.as_ref() != Some(*column))
+            .fold(SelectList::new(), |select_list, column| {
+                select_list.expression(&format!(
+                    "{}, {}",
+                    escape_literal(column),
+                    quote_identifier(column)
+                ))
+            })
+            .build();
+
+        match flattened {
+            Some(flattened) => format!(
+                "(COALESCE({}, '{{}}'::jsonb) || jsonb_build_object({}))",
+                flattened, properties
+            ),
+            None => format!("json_build_object({})", properties),
+        }
+    }
+
+    /// Query of a single feature as GeoJSON by the `$1` id, `None` without an id column
+    pub fn format_feature_query(&self) -> Option<String> {
+        let id_column = self.get_id_column()?;
+
+        // The id is bound as text, cast to the column type so that its index is used
+        let id_param = self
+            .properties
+            .get(id_column)
+            .map_or("$1::text".to_string(), |column_type| {
+                format!("$1::text::{}", column_type)
+            });
+
+        let query = format!(
+            include_str!("scripts/get_feature.sql"),
+            id_column = quote_identifier(id_column),
+            geometry_column = quote_identifier(&self.geometry_column),
+            properties = self.get_properties_object(),
+            table = self.get_table_reference(),
+            id_param = id_param,
+            where_sql_filter = self.get_where_sql_filter(),
+        );
+
+        Some(query)
+    }
+
+    /// Tile features as GeoJSON, clipped to the tile bounds, one `feature` per row
+    pub fn format_geojson_features_query(&self) -> String {
+        let id = self.get_id_column().map_or("".to_string(), |id_column| {
+            format!("\n    'id', {},", quote_identifier(id_column))
+        });
+
+        format!(
+            include_str!("scripts/get_geojson_features.sql"),
+            id = id,
+            layer = escape_literal(&self.id),
+            geometry_column = quote_identifier(&self.geometry_column),
+            mercator_bounds = utils::MERCATOR_BOUNDS,
+            properties = self.get_properties_object(),
+            table = self.get_table_reference(),
+            filter = self.get_filter(),
         )
     }
 
-    pub fn build_tile_query(&self, xyz: &Xyz) -> String {
-        let srid_bounds = utils::get_srid_bounds(self.srid, xyz);
+    /// Checks that the table, its geometry column and the `layer_column`, `updated_at_column`
+    /// and `flatten_column` columns exist, a missing one is reported as a `NotFound` error
+    pub fn validate(&self, conn: &mut Connection) -> Result<(), io::Error> {
+        let row = conn
+            .query_one(
+                include_str!("scripts/check_table_source.sql"),
+                &[&self.schema, &self.table, &self.geometry_column],
+            )
+            .map_err(io::Error::other)?;
+
+        let not_found = |message: String| Err(io::Error::new(io::ErrorKind::NotFound, message));
+
+        if !row.get::<_, bool>("table_exists") {
+            return not_found(format!(
+                "{} table {}.{} doesn't exist",
+                self.id, self.schema, self.table
+            ));
+        }
+
+        if !row.get::<_, bool>("column_exists") {
+            return not_found(format!(
+                "{} geometry column {} doesn't exist in {}.{}",
+                self.id, self.geometry_column, self.schema, self.table
+            ));
+        }
+
+        let columns = [
+            ("layer column", &self.layer_column),
+            ("updated_at column", &self.updated_at_column),
+            ("flatten column", &self.flatten_column),
+        ];
+
+        for (kind, column) in columns {
+            let Some(column) = column else { continue };
+
+            let row = conn
+                .query_one(
+                    include_str!("scripts/check_table_source.sql"),
+                    &[&self.schema, &self.table, column],
+                )
+                .map_err(io::Error::other)?;
+
+            if !row.get::<_, bool>("column_exists") {
+                return not_found(format!(
+                    "{} {} {} doesn't exist in {}.{}",
+                    self.id, kind, column, self.schema, self.table
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the feature with the given id as GeoJSON
+    pub fn get_feature(
+        &self,
+        conn: &mut Connection,
+        id: &str,
+    ) -> Result<Option<String>, io::Error> {
+        let query = self.format_feature_query().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no id column", self.id),
+            )
+        })?;
+
+        match conn.query_opt(query.as_str(), &[&id]) {
+            Ok(row) => Ok(row.map(|row| row.get("feature"))),
+            // Ids not matching the column type can't belong to any feature
+            Err(error)
+                if error.code() == Some(&SqlState::INVALID_TEXT_REPRESENTATION)
+                    || error.code() == Some(&SqlState::NUMERIC_VALUE_OUT_OF_RANGE) =>
+            {
+                Ok(None)
+            }
+            Err(error) => Err(io::Error::other(error)),
+        }
+    }
+
+    /// Refreshes the materialized view without locking out concurrent tile queries,
+    /// which needs a unique index on the view
+    pub fn refresh_matview(&self, conn: &mut Connection) -> Result<(), io::Error> {
+        let query = format!(
+            "REFRESH MATERIALIZED VIEW CONCURRENTLY {}",
+            self.get_table_reference()
+        );
+
+        conn.batch_execute(&query)
+            .map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    fn get_tile_queries(&self) -> &TileQueries {
+        self.tile_query_cache.get_or_init(|| TileQueries {
+            tile: self.format_tile_query(),
+            truncated: self.format_truncated_query(),
+            updated_at: self.format_updated_at_query(),
+            feature_count: self.format_feature_count_query(),
+        })
+    }
+
+    /// Returns the cached tile query, formatting it on the first call
+    pub fn get_tile_query(&self) -> &str {
+        self.get_tile_queries().tile.as_str()
+    }
+
+    /// Returns the cached truncation check query, if `feature_limit` is set
+    pub fn get_truncated_query(&self) -> Option<&str> {
+        self.get_tile_queries().truncated.as_deref()
+    }
+
+    /// Returns the cached latest update query, if `updated_at_column` is set
+    pub fn get_updated_at_query(&self) -> Option<&str> {
+        self.get_tile_queries().updated_at.as_deref()
+    }
+
+    /// Returns the cached feature count query
+    pub fn get_feature_count_query(&self) -> &str {
+        self.get_tile_queries().feature_count.as_str()
+    }
+
+    pub fn build_tile_query(&self) -> String {
+        let srid_bounds = utils::get_srid_bounds(self.srid);
         let bounds_cte = utils::get_bounds_cte(srid_bounds);
-        let tile_query = self.get_tile_query(xyz);
+        let tile_query = self.get_tile_query();
 
         format!("{} {}", bounds_cte, tile_query)
     }
+
+    /// Tile query as run by `get_tile`, with tile params of the request query applied
+    pub fn tile_query(&self, xyz: &Xyz, query: &Option<Query>) -> Result<TileQuery, io::Error> {
+        let sql = match query {
+            Some(query) => self.apply_tile_params(query)?.build_tile_query(),
+            None => self.build_tile_query(),
+        };
+
+        Ok(TileQuery::new(sql, xyz))
+    }
 }
 
 impl Source for TableSource {
@@ -89,46 +651,274 @@ impl Source for TableSource {
     fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
         let mut tilejson_builder = TileJSONBuilder::new();
 
-        tilejson_builder.scheme("xyz");
+        tilejson_builder.scheme(self.scheme.unwrap_or_default().as_str());
         tilejson_builder.name(&self.id);
 
+        if let Some(bounds) = &self.bounds {
+            tilejson_builder.bounds(utils::normalize_bounds(bounds));
+        }
+
         Ok(tilejson_builder.finalize())
     }
 
+    fn get_tilejson_extensions(&self) -> Map<String, Value> {
+        let mut extensions = Map::new();
+        extensions.insert("falcon:srid".to_owned(), Value::from(self.srid));
+        extensions.insert("falcon:extent".to_owned(), Value::from(self.tile_extent()));
+        extensions.insert("falcon:buffer".to_owned(), Value::from(self.tile_buffer()));
+        extensions.insert(
+            "falcon:clip_geom".to_owned(),
+            Value::from(self.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM)),
+        );
+        if let Some(geometry_types) = &self.geometry_types {
+            extensions.insert(
+                "falcon:geometry_types".to_owned(),
+                Value::from(geometry_types.clone()),
+            );
+        }
+
+        // Layers of `layer_column` sources are only known from the data
+        if self.layer_column.is_none() {
+            let fields: Map<String, Value> = self
+                .properties
+                .iter()
+                .filter(|(name, _)| self.flatten_column.as_ref() != Some(*name))
+                .map(|(name, type_name)| (name.clone(), Value::from(field_type(type_name))))
+                .collect();
+
+            extensions.insert(
+                "vector_layers".to_owned(),
+                json!([{ "id": self.id, "fields": fields }]),
+            );
+        }
+
+        extensions
+    }
+
+    fn tilejson_cache(&self) -> Option<&TileJsonCache> {
+        Some(&self.tilejson_cache)
+    }
+
     fn get_tile(
         &self,
         conn: &mut Connection,
         xyz: &Xyz,
-        _query: &Option<Query>,
+        query: &Option<Query>,
     ) -> Result<Tile, io::Error> {
-        let tile_query = self.build_tile_query(xyz);
+        let row = self.tile_query(xyz, query)?.query_one(conn)?;
+        Ok(row.get("st_asmvt"))
+    }
+}
+
+/// TileJSON `vector_layers` field type of a Postgres column type, one of the
+/// `Number`, `Boolean` and `String` MVT value types. Other types, e.g. `text`,
+/// `jsonb` or `timestamp`, are encoded as strings.
+pub fn field_type(type_name: &str) -> &'static str {
+    match type_name {
+        "int2" | "int4" | "int8" | "float4" | "float8" | "numeric" | "oid" | "smallint"
+        | "integer" | "bigint" | "real" | "double precision" => "Number",
+        "bool" | "boolean" => "Boolean",
+        _ => "String",
+    }
+}
+
+pub const DEFAULT_EXTENT: u32 = 4096;
+pub const DEFAULT_BUFFER: u32 = 64;
+pub const DEFAULT_CLIP_GEOM: bool = true;
+pub const DEFAULT_MAX_LAYERS: u32 = 16;
+/// Tile size in screen pixels `buffer_pixels` are relative to
+pub const TILE_PIXELS: f64 = 256.0;
+
+/// Tile parameters of table sources not setting their own,
+/// configured by `default_extent`, `default_buffer` and `default_clip_geom`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableSourceDefaults {
+    pub extent: u32,
+    pub buffer: u32,
+    pub clip_geom: bool,
+    /// Rows sampled on discovery for the geometry types of `GEOMETRY` columns,
+    /// `None` disables sampling
+    pub geometry_type_sample_size: Option<u32>,
+}
+
+impl Default for TableSourceDefaults {
+    fn default() -> Self {
+        TableSourceDefaults {
+            extent: DEFAULT_EXTENT,
+            buffer: DEFAULT_BUFFER,
+            clip_geom: DEFAULT_CLIP_GEOM,
+            geometry_type_sample_size: None,
+        }
+    }
+}
+
+impl TableSourceDefaults {
+    /// Sets the tile parameters `source` doesn't set itself
+    pub fn apply(&self, source: &mut TableSource) {
+        source.extent.get_or_insert(self.extent);
+        source.buffer.get_or_insert(self.buffer);
+        source.clip_geom.get_or_insert(self.clip_geom);
+    }
+}
+
+/// Re-keys the sources with a `public_id` by it, so they are routed, listed and named in
+/// TileJSON and MVT layers by the public id only. Public ids may only contain ASCII letters,
+/// digits, `_`, `-` and `.`, and must not collide with another source id.
+pub fn apply_public_ids(sources: TableSources) -> Result<TableSources, io::Error> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let is_valid = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
 
-        let tile: Tile = conn
-            .query_one(tile_query.as_str(), &[])
-            .map(|row| row.get("st_asmvt"))
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let mut public_sources = TableSources::new();
+    let (aliased, plain): (Vec<_>, Vec<_>) = sources
+        .into_iter()
+        .partition(|(_, source)| source.public_id.is_some());
 
-        Ok(tile)
+    for (source_id, source) in plain {
+        public_sources.insert(source_id, source);
     }
+
+    for (source_id, mut source) in aliased {
+        let public_id = source.public_id.clone().unwrap_or_default();
+
+        if public_id.is_empty() || !public_id.chars().all(is_valid) {
+            return Err(invalid(format!(
+                "Invalid public_id '{}' of {}",
+                public_id, source_id
+            )));
+        }
+
+        if public_sources.contains_key(&public_id) {
+            return Err(invalid(format!(
+                "public_id '{}' of {} is already a source id",
+                public_id, source_id
+            )));
+        }
+
+        source.id = public_id.clone();
+        public_sources.insert(public_id, source);
+    }
+
+    Ok(public_sources)
+}
+
+/// Restricts the sources to the comma separated properties of the `properties` query parameter,
+/// e.g. `name,population`. Each source keeps the requested properties it has, properties none
+/// of the sources have are rejected.
+pub fn select_properties(
+    sources: &[TableSource],
+    properties: &str,
+) -> Result<Vec<TableSource>, io::Error> {
+    let properties: Vec<&str> = properties
+        .split(',')
+        .map(str::trim)
+        .filter(|property| !property.is_empty())
+        .collect();
+
+    if let Some(unknown) = properties.iter().find(|property| {
+        !sources
+            .iter()
+            .any(|source| source.properties.contains_key(**property))
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown property '{}'", unknown),
+        ));
+    }
+
+    Ok(sources
+        .iter()
+        .map(|source| source.with_properties(&properties))
+        .collect())
 }
 
-static DEFAULT_EXTENT: u32 = 4096;
-static DEFAULT_BUFFER: u32 = 64;
-static DEFAULT_CLIP_GEOM: bool = true;
+/// Distinct concrete geometry types of the first `sample_size` rows, sorted
+pub fn sample_geometry_types(
+    conn: &mut Connection,
+    source: &TableSource,
+    sample_size: u32,
+) -> Result<Vec<String>, io::Error> {
+    let query = format!(
+        include_str!("scripts/sample_geometry_types.sql"),
+        geometry_column = quote_identifier(&source.geometry_column),
+        table = source.get_table_reference(),
+        sample_size = sample_size
+    );
 
-pub fn get_table_sources(conn: &mut Connection) -> Result<TableSources, io::Error> {
+    let rows = conn
+        .query(query.as_str(), &[])
+        .map_err(|err| io::Error::other(err.to_string()))?;
+
+    let mut geometry_types: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.get::<_, Option<String>>("geometry_type"))
+        .map(
+            |geometry_type| match geometry_type_support(&geometry_type) {
+                GeometryTypeSupport::Fixed(concrete_type) => concrete_type,
+                _ => geometry_type,
+            },
+        )
+        .collect();
+
+    geometry_types.sort();
+    geometry_types.dedup();
+
+    Ok(geometry_types)
+}
+
+/// Source id of a discovered table, tables with several geometry columns
+/// get one source per column suffixed with the column name
+pub fn table_source_id(
+    schema: &str,
+    table: &str,
+    geometry_column: &str,
+    geometry_columns_count: i64,
+) -> String {
+    if geometry_columns_count > 1 {
+        format!("{}.{}.{}", schema, table, geometry_column)
+    } else {
+        format!("{}.{}", schema, table)
+    }
+}
+
+/// How a `geometry_columns` type is encoded by `ST_AsMVTGeom`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeometryTypeSupport {
+    /// A single point, line or polygon type, without the measure `M` suffix
+    Fixed(String),
+    /// `GEOMETRY` columns, the type of each feature isn't known in advance
+    Mixed,
+    /// Collections, curves and surfaces can't be encoded into vector tiles
+    Unsupported,
+}
+
+pub fn geometry_type_support(geometry_type: &str) -> GeometryTypeSupport {
+    let geometry_type = geometry_type.to_uppercase();
+
+    match geometry_type.trim_end_matches('M') {
+        "GEOMETRY" => GeometryTypeSupport::Mixed,
+        concrete_type @ ("POINT" | "MULTIPOINT" | "LINESTRING" | "MULTILINESTRING" | "POLYGON"
+        | "MULTIPOLYGON") => GeometryTypeSupport::Fixed(concrete_type.to_owned()),
+        _ => GeometryTypeSupport::Unsupported,
+    }
+}
+
+pub fn get_table_sources(
+    conn: &mut Connection,
+    defaults: &TableSourceDefaults,
+) -> Result<TableSources, io::Error> {
     let mut sources = HashMap::new();
 
     let rows = conn
         .query(include_str!("scripts/get_table_sources.sql"), &[])
-        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        .map_err(|err| io::Error::other(err.to_string()))?;
 
     for row in &rows {
         let schema: String = row.get("f_table_schema");
         let table: String = row.get("f_table_name");
-        let id = format!("{}.{}", schema, table);
-
         let geometry_column: String = row.get("f_geometry_column");
+        let geometry_columns_count: i64 = row.get("geometry_columns_count");
+        let id = table_source_id(&schema, &table, &geometry_column, geometry_columns_count);
+
         let srid: i32 = row.get("srid");
 
         info!("Found {} table source", id);
@@ -138,22 +928,92 @@ pub fn get_table_sources(conn: &mut Connection) -> Result<TableSources, io::Erro
             continue;
         }
 
+        let geometry_type: Option<String> = row.get("type");
+        let geometry_type = geometry_type.unwrap_or_else(|| "GEOMETRY".to_owned());
+        let geometry_type = match geometry_type_support(&geometry_type) {
+            GeometryTypeSupport::Fixed(concrete_type) => concrete_type,
+            GeometryTypeSupport::Mixed => {
+                info!("{} has mixed geometry types", id);
+                geometry_type
+            }
+            GeometryTypeSupport::Unsupported => {
+                warn!(
+                    "{} has {} geometry type unsupported by vector tiles, skipping",
+                    id, geometry_type
+                );
+                continue;
+            }
+        };
+
+        // Views have no indexes of their own
+        let spatial_index: Option<bool> = row.get("spatial_index");
+
         let properties = utils::json_to_hashmap(&row.get("properties"));
 
-        let source = TableSource {
+        let mut source = TableSource {
             id: id.to_string(),
             schema,
             table,
             id_column: None,
+            primary_key: row.get("primary_key"),
+            spatial_index,
+            is_matview: row.get("is_matview"),
+            auto_id_column: None,
             geometry_column,
             srid: srid as u32,
-            extent: Some(DEFAULT_EXTENT),
-            buffer: Some(DEFAULT_BUFFER),
-            clip_geom: Some(DEFAULT_CLIP_GEOM),
-            geometry_type: row.get("type"),
+            extent: Some(defaults.extent),
+            buffer: Some(defaults.buffer),
+            clip_geom: Some(defaults.clip_geom),
+            geometry_type: Some(geometry_type),
+            geometry_types: None,
             properties,
+            min_polygon_pixels: None,
+            where_sql: None,
+            max_age: None,
+            mvt_content_type: None,
+            response_headers: None,
+            simplify: None,
+            simplify_zooms: None,
+            simplify_algorithm: None,
+            make_valid: None,
+            feature_limit: None,
+            updated_at_column: None,
+            layer_column: None,
+            max_layers: None,
+            flatten_column: None,
+            flatten_keys: None,
+            public_id: None,
+            buffer_pixels: None,
+            scheme: None,
+            bounds: None,
+            tile_query_cache: Arc::default(),
+            tilejson_cache: TileJsonCache::default(),
         };
 
+        if let (Some(sample_size), GeometryTypeSupport::Mixed) = (
+            defaults.geometry_type_sample_size,
+            geometry_type_support(source.geometry_type.as_deref().unwrap_or_default()),
+        ) {
+            match sample_geometry_types(conn, &source, sample_size) {
+                Ok(geometry_types) => {
+                    info!("{} has {} geometries", id, geometry_types.join(", "));
+                    source.geometry_types = Some(geometry_types);
+                }
+                Err(error) => warn!("Can't sample geometry types of {}: {}", id, error),
+            }
+        }
+
+        if spatial_index == Some(false) {
+            warn!(
+                "{} has no spatial index on {}, its tiles will be slow. Create one with \
+                 CREATE INDEX ON {} USING GIST ({})",
+                id,
+                source.geometry_column,
+                source.get_table_reference(),
+                quote_identifier(&source.geometry_column)
+            );
+        }
+
         sources.insert(id, Box::new(source));
     }
 
@@ -0,0 +1,48 @@
+use std::io;
+use std::sync::OnceLock;
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Installs a `tracing` subscriber exporting spans to the OTLP/HTTP endpoint,
+/// e.g. `http://localhost:4318/v1/traces`.
+///
+/// Without it spans are disabled and logging goes through `log` only.
+pub fn init_tracing(otlp_endpoint: &str) -> io::Result<()> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(otlp_endpoint)
+        .build()
+        .map_err(io::Error::other)?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("martin").build())
+        .build();
+
+    let tracer = provider.tracer("martin");
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(io::Error::other)?;
+
+    let _ = TRACER_PROVIDER.set(provider);
+
+    Ok(())
+}
+
+/// Flushes pending spans, should be called before exiting
+pub fn shutdown_tracing() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(error) = provider.shutdown() {
+            warn!("Can't flush traces: {}", error);
+        }
+    }
+}
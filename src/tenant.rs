@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Request header with the tenant id when `header` is not set
+pub const DEFAULT_TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// Schemas of tenants identified by a request header. Tile queries of a tenant
+/// are run with its schema first in `search_path`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TenantSchemas {
+    /// Request header with the tenant id [default: X-Tenant-Id]
+    pub header: Option<String>,
+    /// Schemas by tenant id
+    pub schemas: HashMap<String, String>,
+}
+
+impl TenantSchemas {
+    pub fn header(&self) -> &str {
+        self.header.as_deref().unwrap_or(DEFAULT_TENANT_HEADER)
+    }
+
+    /// Schema of the tenant, `None` for missing and unknown tenants
+    pub fn schema(&self, tenant_id: Option<&str>) -> Option<&str> {
+        tenant_id
+            .and_then(|tenant_id| self.schemas.get(tenant_id))
+            .map(String::as_str)
+    }
+}
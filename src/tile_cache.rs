@@ -0,0 +1,382 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::source::{Tile, Xyz};
+
+const BYTES_IN_MB: u64 = 1024 * 1024;
+
+/// Eviction removes tiles until the cache is this share of its max size, so that
+/// the next few writes don't evict again
+const EVICTION_TARGET: f64 = 0.9;
+
+/// File name limit of most file systems, longer (composite) source ids aren't cached
+pub const MAX_SOURCE_ID_LENGTH: usize = 255;
+
+/// Where tiles are cached between requests, tiles with query parameters aren't cached
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TileCacheConfig {
+    #[default]
+    None,
+    /// Least recently used tiles are evicted above `max_size_mb`
+    Memory { max_size_mb: u64 },
+    /// `{directory}/{source_id}/{z}/{x}/{y}.{extension}` files surviving restarts. Tiles older
+    /// than `ttl_seconds` are misses, the oldest tiles are evicted above `max_size_mb`.
+    Disk {
+        directory: String,
+        max_size_mb: Option<u64>,
+        ttl_seconds: Option<u64>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TileCacheKey {
+    pub source_id: String,
+    pub z: i32,
+    pub x: i32,
+    pub y: i32,
+    /// Tile format extension, e.g. `pbf`
+    pub extension: &'static str,
+}
+
+impl TileCacheKey {
+    pub fn new(source_id: &str, xyz: &Xyz, extension: &'static str) -> Self {
+        TileCacheKey {
+            source_id: source_id.to_owned(),
+            z: xyz.z,
+            x: xyz.x,
+            y: xyz.y,
+            extension,
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new(&self.source_id)
+            .join(self.z.to_string())
+            .join(self.x.to_string())
+            .join(format!("{}.{}", self.y, self.extension))
+    }
+}
+
+/// Tile storage shared between web server workers. Calls may block on IO.
+pub trait TileCache: Send + Sync {
+    fn get(&self, key: &TileCacheKey) -> io::Result<Option<Tile>>;
+
+    fn put(&self, key: &TileCacheKey, tile: &[u8]) -> io::Result<()>;
+
+    /// Removes the tiles of the source, including the tiles of composite sources with it
+    fn invalidate(&self, source_id: &str) -> io::Result<()>;
+}
+
+/// Whether the cached tiles of the (composite) source id include the source
+fn includes_source(cached_source_id: &str, source_id: &str) -> bool {
+    cached_source_id.split(',').any(|id| id == source_id)
+}
+
+pub type SharedTileCache = Arc<dyn TileCache>;
+
+/// Cache of the config, `None` when caching is disabled
+pub fn new_tile_cache(config: &TileCacheConfig) -> io::Result<Option<SharedTileCache>> {
+    let cache: SharedTileCache = match config {
+        TileCacheConfig::None => return Ok(None),
+        TileCacheConfig::Memory { max_size_mb } => {
+            Arc::new(MemoryCache::new(max_size_mb * BYTES_IN_MB))
+        }
+        TileCacheConfig::Disk {
+            directory,
+            max_size_mb,
+            ttl_seconds,
+        } => Arc::new(DiskCache::new(
+            PathBuf::from(directory),
+            max_size_mb.map(|max_size_mb| max_size_mb * BYTES_IN_MB),
+            ttl_seconds.map(Duration::from_secs),
+        )?),
+    };
+
+    Ok(Some(cache))
+}
+
+#[derive(Default)]
+struct MemoryEntries {
+    tiles: HashMap<TileCacheKey, (Tile, u64)>,
+    /// Keys by the tick of their last use, the first one is the least recently used
+    uses: BTreeMap<u64, TileCacheKey>,
+    size: u64,
+    tick: u64,
+}
+
+/// Least recently used tiles are evicted when the tiles exceed `max_size` bytes
+pub struct MemoryCache {
+    max_size: u64,
+    entries: Mutex<MemoryEntries>,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: u64) -> Self {
+        MemoryCache {
+            max_size,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.entries.lock().unwrap().size
+    }
+}
+
+impl TileCache for MemoryCache {
+    fn get(&self, key: &TileCacheKey) -> io::Result<Option<Tile>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        let (tile, last_used) = match entries.tiles.get_mut(key) {
+            Some((tile, last_used)) => (tile.clone(), std::mem::replace(last_used, tick)),
+            None => return Ok(None),
+        };
+
+        entries.uses.remove(&last_used);
+        entries.uses.insert(tick, key.clone());
+
+        Ok(Some(tile))
+    }
+
+    fn put(&self, key: &TileCacheKey, tile: &[u8]) -> io::Result<()> {
+        let tile_size = tile.len() as u64;
+        if tile_size > self.max_size {
+            return Ok(());
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        if let Some((replaced, last_used)) =
+            entries.tiles.insert(key.clone(), (tile.to_vec(), tick))
+        {
+            entries.uses.remove(&last_used);
+            entries.size -= replaced.len() as u64;
+        }
+        entries.uses.insert(tick, key.clone());
+        entries.size += tile_size;
+
+        while entries.size > self.max_size {
+            let (_, evicted) = match entries.uses.pop_first() {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            if let Some((tile, _)) = entries.tiles.remove(&evicted) {
+                entries.size -= tile.len() as u64;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalidate(&self, source_id: &str) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let invalidated: Vec<TileCacheKey> = entries
+            .tiles
+            .keys()
+            .filter(|key| includes_source(&key.source_id, source_id))
+            .cloned()
+            .collect();
+
+        for key in invalidated {
+            if let Some((tile, last_used)) = entries.tiles.remove(&key) {
+                entries.uses.remove(&last_used);
+                entries.size -= tile.len() as u64;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tile files in a directory, see `TileCacheConfig::Disk`
+pub struct DiskCache {
+    directory: PathBuf,
+    max_size: Option<u64>,
+    ttl: Option<Duration>,
+    /// Bytes of the cached tiles, recounted on eviction
+    size: AtomicU64,
+    eviction: Mutex<()>,
+}
+
+impl DiskCache {
+    /// Creates the directory, tiles cached before a restart are kept
+    pub fn new(
+        directory: PathBuf,
+        max_size: Option<u64>,
+        ttl: Option<Duration>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        let size = cached_files(&directory)?.iter().map(|file| file.size).sum();
+
+        Ok(DiskCache {
+            directory,
+            max_size,
+            ttl,
+            size: AtomicU64::new(size),
+            eviction: Mutex::new(()),
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size.load(Ordering::SeqCst)
+    }
+
+    fn is_expired(&self, modified: SystemTime) -> bool {
+        match (self.ttl, modified.elapsed()) {
+            (Some(ttl), Ok(age)) => age > ttl,
+            _ => false,
+        }
+    }
+
+    /// Removes the oldest tiles until the cache is under `EVICTION_TARGET` of `max_size`
+    fn evict(&self, max_size: u64) -> io::Result<()> {
+        // One eviction at a time, the others find the cache small enough afterwards
+        let _eviction = self.eviction.lock().unwrap();
+        if self.size() <= max_size {
+            return Ok(());
+        }
+
+        let mut files = cached_files(&self.directory)?;
+        files.sort_by_key(|file| file.modified);
+
+        let target = (max_size as f64 * EVICTION_TARGET) as u64;
+        let mut size: u64 = files.iter().map(|file| file.size).sum();
+        for file in files {
+            if size <= target {
+                break;
+            }
+
+            match fs::remove_file(&file.path) {
+                Ok(()) => size -= file.size,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => size -= file.size,
+                Err(error) => return Err(error),
+            }
+        }
+
+        self.size.store(size, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl TileCache for DiskCache {
+    fn get(&self, key: &TileCacheKey) -> io::Result<Option<Tile>> {
+        let path = self.directory.join(key.path());
+
+        let modified = match fs::metadata(&path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        if self.is_expired(modified) {
+            return Ok(None);
+        }
+
+        match fs::read(&path) {
+            Ok(tile) => Ok(Some(tile)),
+            // Evicted meanwhile
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn put(&self, key: &TileCacheKey, tile: &[u8]) -> io::Result<()> {
+        let path = self.directory.join(key.path());
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let replaced = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+
+        // Readers never see partially written tiles
+        let temp_path =
+            path.with_extension(format!("{}.{}.tmp", key.extension, std::process::id()));
+        fs::write(&temp_path, tile)?;
+        fs::rename(&temp_path, &path)?;
+
+        let _ = self
+            .size
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |size| {
+                Some((size + tile.len() as u64).saturating_sub(replaced))
+            });
+
+        match self.max_size {
+            Some(max_size) if self.size() > max_size => self.evict(max_size),
+            _ => Ok(()),
+        }
+    }
+
+    fn invalidate(&self, source_id: &str) -> io::Result<()> {
+        // Eviction doesn't recount the size meanwhile
+        let _eviction = self.eviction.lock().unwrap();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let cached_source_id = entry.file_name();
+            if !includes_source(&cached_source_id.to_string_lossy(), source_id) {
+                continue;
+            }
+
+            match fs::remove_dir_all(entry.path()) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+
+        let size = cached_files(&self.directory)?
+            .iter()
+            .map(|file| file.size)
+            .sum();
+        self.size.store(size, Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+struct CachedFile {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+fn cached_files(directory: &Path) -> io::Result<Vec<CachedFile>> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // Removed by another worker meanwhile
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+
+            if metadata.is_dir() {
+                directories.push(entry.path());
+            } else {
+                files.push(CachedFile {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                });
+            }
+        }
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,54 @@
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader};
+
+/// PEM files of the server certificate chain and its private key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    let file = File::open(path)
+        .map_err(|error| io::Error::new(error.kind(), format!("Can't open {}: {}", path, error)))?;
+
+    Ok(BufReader::new(file))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Rustls server config with the certificate chain and the first PKCS#8 or RSA key,
+/// HTTP/2 is negotiated with ALPN by the web server
+pub fn load_server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let cert_chain = certs(&mut open(&tls.cert_path)?)
+        .map_err(|_| invalid(format!("Can't read certificates from {}", tls.cert_path)))?;
+
+    if cert_chain.is_empty() {
+        return Err(invalid(format!("No certificates in {}", tls.cert_path)));
+    }
+
+    let mut keys = pkcs8_private_keys(&mut open(&tls.key_path)?)
+        .map_err(|_| invalid(format!("Can't read private key from {}", tls.key_path)))?;
+
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(&tls.key_path)?)
+            .map_err(|_| invalid(format!("Can't read private key from {}", tls.key_path)))?;
+    }
+
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid(format!("No private key in {}", tls.key_path)))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(cert_chain, key)
+        .map_err(|error| invalid(format!("Invalid certificate or key: {}", error)))?;
+
+    Ok(config)
+}
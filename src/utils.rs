@@ -1,26 +1,120 @@
 use std::collections::HashMap;
+use std::f64::consts::PI;
 
 use crate::source::{Query, Xyz};
 use postgres::types::Json;
 use serde_json::Value;
 
 pub fn prettify_error<E: std::fmt::Display>(message: &'static str) -> impl Fn(E) -> std::io::Error {
-    move |error| std::io::Error::new(std::io::ErrorKind::Other, format!("{}: {}", message, error))
+    move |error| std::io::Error::other(format!("{}: {}", message, error))
+}
+
+static MERCATOR_MAX: f64 = 20_037_508.34;
+
+/// Tile bounds in tile queries, bound per request as `$1..$4` (xmin, ymin, xmax, ymax)
+pub const MERCATOR_BOUNDS: &str =
+    "ST_MakeEnvelope($1::float8, $2::float8, $3::float8, $4::float8, 3857)";
+
+/// Tile width in mercator meters in tile queries
+pub const MERCATOR_TILE_WIDTH: &str = "$3::float8 - $1::float8";
+
+/// Tile width in mercator meters at the given zoom, of any zoom without overflowing
+pub fn tile_width(zoom: i32) -> f64 {
+    (MERCATOR_MAX * 2.0) / 2_f64.powi(zoom)
+}
+
+/// Web mercator bounds in degrees, `[west, south, east, north]`
+pub const WORLD_BOUNDS: [f32; 4] = [-180.0, -85.051_13, 180.0, 85.051_13];
+
+/// Orders min and max of TileJSON bounds and clamps them to `WORLD_BOUNDS`.
+/// Malformed bounds and bounds with non-finite values fall back to `WORLD_BOUNDS`.
+pub fn normalize_bounds(bounds: &[f32]) -> Vec<f32> {
+    let (west, south, east, north) = match *bounds {
+        [west, south, east, north] if bounds.iter().all(|value| value.is_finite()) => {
+            (west, south, east, north)
+        }
+        _ => return WORLD_BOUNDS.to_vec(),
+    };
+
+    let [min_lon, min_lat, max_lon, max_lat] = WORLD_BOUNDS;
+    let lon = |value: f32| value.clamp(min_lon, max_lon);
+    let lat = |value: f32| value.clamp(min_lat, max_lat);
+
+    vec![
+        lon(west.min(east)),
+        lat(south.min(north)),
+        lon(west.max(east)),
+        lat(south.max(north)),
+    ]
+}
+
+/// Flips the tile row between `xyz` and `tms` numbering
+pub fn flip_y(xyz: &Xyz) -> Xyz {
+    Xyz {
+        y: (1 << xyz.z) - 1 - xyz.y,
+        ..*xyz
+    }
 }
 
 // https://github.com/mapbox/postgis-vt-util/blob/master/src/TileBBox.sql
-pub fn tilebbox(xyz: &Xyz) -> String {
+pub fn tile_bounds(xyz: &Xyz) -> (f64, f64, f64, f64) {
     let x = xyz.x;
     let y = xyz.y;
-    let z = xyz.z;
 
-    let max = 20_037_508.34;
-    let res = (max * 2.0) / f64::from(2_i32.pow(z as u32));
+    let max = MERCATOR_MAX;
+    let res = tile_width(xyz.z);
 
     let xmin = -max + (f64::from(x) * res);
-    let ymin = max - (f64::from(y) * res);
-    let xmax = -max + (f64::from(x) * res) + res;
-    let ymax = max - (f64::from(y) * res) - res;
+    let ymax = max - (f64::from(y) * res);
+    let xmax = xmin + res;
+    let ymin = ymax - res;
+
+    (xmin, ymin, xmax, ymax)
+}
+
+/// Tile bounds in degrees, `[west, south, east, north]`
+pub fn tile_lon_lat_bounds(xyz: &Xyz) -> [f64; 4] {
+    let (xmin, ymin, xmax, ymax) = tile_bounds(xyz);
+
+    let lon = |x: f64| x / MERCATOR_MAX * 180.0;
+    let lat = |y: f64| (y / MERCATOR_MAX * PI).sinh().atan().to_degrees();
+
+    [lon(xmin), lat(ymin), lon(xmax), lat(ymax)]
+}
+
+/// Whether the tile intersects TileJSON bounds in degrees. Bounds with `west > east`
+/// cross the antimeridian, malformed bounds intersect every tile. Tiles outside of the
+/// tile grid intersect no bounds.
+pub fn tile_intersects_bounds(xyz: &Xyz, bounds: &[f32]) -> bool {
+    if !xyz.is_valid() {
+        return false;
+    }
+
+    let (west, south, east, north) = match *bounds {
+        [west, south, east, north] if bounds.iter().all(|value| value.is_finite()) => (
+            f64::from(west),
+            f64::from(south.min(north)),
+            f64::from(east),
+            f64::from(south.max(north)),
+        ),
+        _ => return true,
+    };
+
+    let [tile_west, tile_south, tile_east, tile_north] = tile_lon_lat_bounds(xyz);
+    if south > tile_north || north < tile_south {
+        return false;
+    }
+
+    let overlaps = |west: f64, east: f64| west <= tile_east && east >= tile_west;
+    if west <= east {
+        overlaps(west, east)
+    } else {
+        overlaps(west, 180.0) || overlaps(-180.0, east)
+    }
+}
+
+pub fn tilebbox(xyz: &Xyz) -> String {
+    let (xmin, ymin, xmax, ymax) = tile_bounds(xyz);
 
     format!(
         "ST_MakeEnvelope({0}, {1}, {2}, {3}, 3857)",
@@ -59,10 +153,21 @@ pub fn get_bounds_cte(srid_bounds: String) -> String {
     )
 }
 
-pub fn get_srid_bounds(srid: u32, xyz: &Xyz) -> String {
+/// Segments per tile edge of the tile bounds transformed to other SRIDs
+const BOUNDS_SEGMENTS: u32 = 16;
+
+/// Tile bounds in the source `srid` for the spatial filter, aliased `srid_{srid}`.
+/// Straight mercator edges are curved in other projections (e.g. UTM), so the
+/// edges are densified before transforming to keep the whole tile in the bounding box.
+pub fn get_srid_bounds(srid: u32) -> String {
+    if srid == 3857 {
+        return format!("{} AS srid_3857", MERCATOR_BOUNDS);
+    }
+
     format!(
         include_str!("scripts/get_srid_bounds.sql"),
         srid = srid,
-        mercator_bounds = tilebbox(xyz),
+        mercator_bounds = MERCATOR_BOUNDS,
+        segment_length = format!("({}) / {}", MERCATOR_TILE_WIDTH, BOUNDS_SEGMENTS),
     )
 }
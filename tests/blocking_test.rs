@@ -0,0 +1,33 @@
+use actix_web::error::BlockingError;
+use std::thread;
+
+use martin::blocking::{self, BlockingPool};
+
+#[actix_rt::test]
+async fn test_blocking_pool_run() {
+    let pool = BlockingPool::new(1);
+
+    let name = pool
+        .run(|| Ok::<_, ()>(thread::current().name().map(str::to_owned)))
+        .await
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("blocking-0"));
+
+    let result = pool.run(|| Err::<(), _>("failed")).await;
+    assert!(matches!(result, Err(BlockingError::Error("failed"))));
+
+    // A panicking job doesn't stop the only thread
+    let result = pool
+        .run(|| -> Result<(), ()> { panic!("job panicked") })
+        .await;
+    assert!(matches!(result, Err(BlockingError::Canceled)));
+    assert_eq!(pool.run(|| Ok::<_, ()>(1)).await.unwrap(), 1);
+}
+
+#[actix_rt::test]
+async fn test_blocking_init() {
+    assert!(blocking::init(2));
+    assert!(!blocking::init(4));
+
+    assert_eq!(blocking::run(|| Ok::<_, ()>(1)).await.unwrap(), 1);
+}
@@ -0,0 +1,29 @@
+use martin::byte_range::{parse_range, ByteRange};
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-3", 10), ByteRange::Partial(0..4));
+    assert_eq!(parse_range("bytes=2-", 10), ByteRange::Partial(2..10));
+    assert_eq!(parse_range("bytes=-3", 10), ByteRange::Partial(7..10));
+    assert_eq!(parse_range("bytes=9-9", 10), ByteRange::Partial(9..10));
+
+    // Ends past the body are truncated to the body length
+    assert_eq!(parse_range("bytes=5-100", 10), ByteRange::Partial(5..10));
+    assert_eq!(parse_range("bytes=-100", 10), ByteRange::Partial(0..10));
+}
+
+#[test]
+fn test_unsatisfiable_range() {
+    assert_eq!(parse_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+    assert_eq!(parse_range("bytes=10-20", 10), ByteRange::Unsatisfiable);
+    assert_eq!(parse_range("bytes=-0", 10), ByteRange::Unsatisfiable);
+}
+
+#[test]
+fn test_ignored_range() {
+    assert_eq!(parse_range("bytes=0-1,4-5", 10), ByteRange::Full);
+    assert_eq!(parse_range("bytes=5-2", 10), ByteRange::Full);
+    assert_eq!(parse_range("bytes=a-b", 10), ByteRange::Full);
+    assert_eq!(parse_range("bytes=-", 10), ByteRange::Full);
+    assert_eq!(parse_range("items=0-1", 10), ByteRange::Full);
+}
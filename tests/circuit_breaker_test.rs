@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use martin::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitState};
+
+fn circuit_breaker() -> CircuitBreaker {
+    CircuitBreaker::new(&CircuitBreakerConfig {
+        failure_threshold: Some(2),
+        cooldown_ms: Some(1000),
+    })
+}
+
+#[test]
+fn test_circuit_breaker_opens() {
+    let circuit_breaker = circuit_breaker();
+    let now = Instant::now();
+
+    circuit_breaker.record_at(true, now);
+    assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+
+    // Successful queries reset the consecutive failures
+    circuit_breaker.record_at(false, now);
+    circuit_breaker.record_at(true, now);
+    assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+    assert!(circuit_breaker.check_at(now).is_ok());
+
+    circuit_breaker.record_at(true, now);
+    assert_eq!(circuit_breaker.state(), CircuitState::Open);
+    assert_eq!(
+        circuit_breaker.check_at(now + Duration::from_millis(400)),
+        Err(Duration::from_millis(600))
+    );
+}
+
+#[test]
+fn test_circuit_breaker_half_open_probe() {
+    let circuit_breaker = circuit_breaker();
+    let now = Instant::now();
+    circuit_breaker.record_at(true, now);
+    circuit_breaker.record_at(true, now);
+
+    // A single probe query is allowed after the cooldown
+    let after_cooldown = now + Duration::from_millis(1000);
+    assert!(circuit_breaker.check_at(after_cooldown).is_ok());
+    assert_eq!(circuit_breaker.state(), CircuitState::HalfOpen);
+    assert!(circuit_breaker.check_at(after_cooldown).is_err());
+
+    // A failed probe keeps it open for another cooldown
+    circuit_breaker.record_at(true, after_cooldown);
+    assert_eq!(circuit_breaker.state(), CircuitState::Open);
+    assert!(circuit_breaker
+        .check_at(after_cooldown + Duration::from_millis(999))
+        .is_err());
+
+    let after_cooldown = after_cooldown + Duration::from_millis(1000);
+    assert!(circuit_breaker.check_at(after_cooldown).is_ok());
+    circuit_breaker.record_at(false, after_cooldown);
+    assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+    assert!(circuit_breaker.check_at(after_cooldown).is_ok());
+}
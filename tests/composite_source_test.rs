@@ -0,0 +1,150 @@
+use martin::composite_source::CompositeSource;
+use martin::dev::mock_table_sources;
+use martin::source::{Scheme, Source, Xyz};
+use martin::table_source::TableSource;
+
+fn mock_composite_source(feature_limits: &[Option<u32>]) -> CompositeSource {
+    let table_sources = mock_table_sources().unwrap();
+    let source = table_sources["public.table_source"].as_ref();
+
+    let table_sources = feature_limits
+        .iter()
+        .map(|feature_limit| TableSource {
+            feature_limit: *feature_limit,
+            tile_query_cache: Default::default(),
+            ..source.clone()
+        })
+        .collect();
+
+    CompositeSource {
+        id: "composite".to_owned(),
+        table_sources,
+        count_features: false,
+    }
+}
+
+#[test]
+fn test_truncated_query() {
+    let query = mock_composite_source(&[None, None]).build_tile_query();
+    assert!(query.contains("AS tile, false AS truncated"));
+
+    let query = mock_composite_source(&[Some(10), None, Some(20)]).build_tile_query();
+    assert!(query.contains("(SELECT count(*) > 10 FROM"));
+    assert!(query.contains(") OR (SELECT count(*) > 20 FROM"));
+    assert!(query.contains(") AS truncated"));
+}
+
+#[test]
+fn test_updated_at_query() {
+    let query = mock_composite_source(&[None, None]).build_tile_query();
+    assert!(query.contains("NULL::float8 AS updated_at"));
+
+    let mut source = mock_composite_source(&[None, None]);
+    source.table_sources[0].updated_at_column = Some("updated_at".to_owned());
+    let query = source.build_tile_query();
+    assert!(query.contains("NULL::float8 AS updated_at"));
+
+    // Tile queries are cached by the sources
+    let mut source = mock_composite_source(&[None, None]);
+    source.table_sources[0].updated_at_column = Some("updated_at".to_owned());
+    source.table_sources[1].updated_at_column = Some("modified".to_owned());
+    let query = source.build_tile_query();
+    assert!(query.contains("GREATEST((SELECT extract(epoch FROM max(\"updated_at\"))"));
+    assert!(query.contains("), (SELECT extract(epoch FROM max(\"modified\"))"));
+    assert!(query.contains(") AS updated_at"));
+}
+
+#[test]
+fn test_feature_count_query() {
+    let query = mock_composite_source(&[None, None]).build_tile_query();
+    assert!(query.ends_with("AS updated_at, NULL::int8 AS feature_count"));
+
+    let mut source = mock_composite_source(&[None, Some(10)]);
+    source.count_features = true;
+    let query = source.build_tile_query();
+    assert_eq!(query.matches("(SELECT count(geom) FROM (").count(), 2);
+    assert!(query.contains(") AS tile) + (SELECT count(geom) FROM ("));
+    assert!(query.ends_with(") AS tile) AS feature_count"));
+}
+
+#[test]
+fn test_scheme() {
+    let mut source = mock_composite_source(&[None, None]);
+    assert_eq!(source.scheme(), Scheme::Xyz);
+
+    source.table_sources[0].scheme = Some(Scheme::Tms);
+    assert_eq!(source.scheme(), Scheme::Xyz);
+
+    source.table_sources[1].scheme = Some(Scheme::Tms);
+    assert_eq!(source.scheme(), Scheme::Tms);
+    assert_eq!(
+        source.get_tilejson().unwrap().scheme,
+        Some("tms".to_owned())
+    );
+}
+
+#[test]
+fn test_inline_tile_query() {
+    let source = mock_composite_source(&[Some(10)]);
+    let query = source.tile_query(&Xyz { z: 1, x: 1, y: 0 }).to_inline_sql();
+
+    assert!(!query.contains('$'));
+    assert!(query.contains(
+        "ST_MakeEnvelope((0)::float8, (0)::float8, (20037508.34)::float8, (20037508.34)::float8, 3857)"
+    ));
+}
+
+#[test]
+fn test_tilejson_extensions() {
+    let mut source = mock_composite_source(&[None, None]);
+
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert_eq!(tilejson["falcon:srid"], 3857);
+    assert_eq!(tilejson["falcon:extent"], 4096);
+    assert_eq!(tilejson["falcon:buffer"], 64);
+    assert_eq!(tilejson["falcon:clip_geom"], true);
+
+    // Keys the sources disagree on are left out
+    source.table_sources[1].extent = Some(512);
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert_eq!(tilejson["falcon:srid"], 3857);
+    assert!(tilejson.get("falcon:extent").is_none());
+}
+
+#[test]
+fn test_vector_layers_tilejson() {
+    let mut source = mock_composite_source(&[None, None]);
+    source.table_sources[1].id = "public.points1".to_owned();
+
+    let tilejson = source.get_tilejson_value().unwrap();
+    let ids: Vec<&str> = tilejson["vector_layers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|layer| layer["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["public.table_source", "public.points1"]);
+}
+
+#[test]
+fn test_tile_extent_and_buffer() {
+    let mut source = mock_composite_source(&[None, None]);
+    assert_eq!(source.tile_extent_and_buffer(), Some((4096, 64)));
+
+    source.table_sources[1].buffer = Some(256);
+    assert_eq!(source.tile_extent_and_buffer(), None);
+}
+
+#[test]
+fn test_has_tile_details() {
+    assert!(!mock_composite_source(&[None, None]).has_tile_details());
+    assert!(mock_composite_source(&[None, Some(10)]).has_tile_details());
+
+    let mut source = mock_composite_source(&[None]);
+    source.table_sources[0].updated_at_column = Some("updated_at".to_owned());
+    assert!(source.has_tile_details());
+
+    let mut source = mock_composite_source(&[None]);
+    source.count_features = true;
+    assert!(source.has_tile_details());
+}
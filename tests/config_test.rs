@@ -0,0 +1,416 @@
+use std::io;
+
+use martin::config::{
+    apply_env_overrides, clamp_worker_processes, with_env_overrides, ConfigBuilder,
+    DEFAULT_CLIENT_TIMEOUT_MS, DEFAULT_ZSTD_LEVEL, MAX_WORKER_PROCESSES_PER_CPU,
+};
+use martin::db_actor::DEFAULT_DB_WORKERS;
+use serde_yaml::Value;
+
+fn config(yaml: &str) -> ConfigBuilder {
+    serde_yaml::from_str(&format!(
+        "connection_string: \"postgres://postgres@localhost/db\"\n{}",
+        yaml
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_config_client_timeouts() {
+    let defaults = config("").finalize();
+    assert_eq!(defaults.client_request_timeout, DEFAULT_CLIENT_TIMEOUT_MS);
+    assert_eq!(
+        defaults.client_disconnect_timeout,
+        DEFAULT_CLIENT_TIMEOUT_MS
+    );
+
+    let config = config("client_request_timeout: 2000\nclient_disconnect_timeout: 0").finalize();
+    assert_eq!(config.client_request_timeout, 2000);
+    assert_eq!(config.client_disconnect_timeout, 0);
+}
+
+#[test]
+fn test_config_watch_config() {
+    // Existing deployments don't start polling the config file
+    assert!(!config("").finalize().watch_config);
+    assert!(config("watch_config: true").finalize().watch_config);
+}
+
+#[test]
+fn test_validate_db_retries() {
+    assert!(config("db_retries: 10").finalize().validate().is_ok());
+
+    let error = config("db_retries: 20").finalize().validate().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "db_retries must be at most 10");
+}
+
+#[test]
+fn test_clamp_worker_processes() {
+    let max_worker_processes = num_cpus::get() * MAX_WORKER_PROCESSES_PER_CPU;
+
+    assert_eq!(clamp_worker_processes(0), num_cpus::get());
+    assert_eq!(clamp_worker_processes(1), 1);
+    assert_eq!(
+        clamp_worker_processes(max_worker_processes),
+        max_worker_processes
+    );
+    assert_eq!(
+        clamp_worker_processes(max_worker_processes + 1),
+        max_worker_processes
+    );
+    assert_eq!(clamp_worker_processes(100_000), max_worker_processes);
+}
+
+#[test]
+fn test_validate_config() {
+    let defaults = config("").finalize().validate().unwrap();
+    assert_eq!(defaults.worker_processes, num_cpus::get());
+    assert_eq!(defaults.keep_alive, 75);
+
+    let config_with_zero_workers = config("worker_processes: 0").finalize().validate().unwrap();
+    assert_eq!(config_with_zero_workers.worker_processes, num_cpus::get());
+
+    let config_with_many_workers = config("worker_processes: 100000")
+        .finalize()
+        .validate()
+        .unwrap();
+    assert_eq!(
+        config_with_many_workers.worker_processes,
+        num_cpus::get() * MAX_WORKER_PROCESSES_PER_CPU
+    );
+
+    assert_eq!(defaults.db_workers, DEFAULT_DB_WORKERS);
+    assert_eq!(defaults.blocking_threads, None);
+
+    let config_with_workers = config("db_workers: 8\nblocking_threads: 16")
+        .finalize()
+        .validate()
+        .unwrap();
+    assert_eq!(config_with_workers.db_workers, 8);
+    assert_eq!(config_with_workers.blocking_threads, Some(16));
+
+    assert_eq!(defaults.zstd_level, DEFAULT_ZSTD_LEVEL);
+
+    for yaml in &[
+        "db_workers: 0",
+        "blocking_threads: 0",
+        "zstd_level: -1",
+        "zstd_level: 23",
+    ] {
+        let error = config(yaml).finalize().validate().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    let error = config("listen_addresses: []")
+        .finalize()
+        .validate()
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // keep_alive is unsigned, negative values don't parse
+    let negative_keep_alive: Result<ConfigBuilder, _> = serde_yaml::from_str(
+        "connection_string: \"postgres://postgres@localhost/db\"\nkeep_alive: -1",
+    );
+    assert!(negative_keep_alive.is_err());
+}
+
+#[test]
+fn test_disabled_source_kinds() {
+    let defaults = config("table_sources: {}\nfunction_sources: {}").finalize();
+    assert!(defaults.enable_table_sources);
+    assert!(defaults.enable_function_sources);
+    assert!(defaults.table_sources.is_some());
+    assert!(defaults.function_sources.is_some());
+
+    let config = config("enable_function_sources: false\ntable_sources: {}\nfunction_sources: {}")
+        .finalize();
+    assert!(config.enable_table_sources);
+    assert!(!config.enable_function_sources);
+    assert!(config.table_sources.is_some());
+    assert!(config.function_sources.is_none());
+}
+
+#[test]
+fn test_validate_response_headers() {
+    let function_source = |response_headers: &str| {
+        config(&format!(
+            "function_sources:\n  public.function_source:\n    id: public.function_source\n    schema: public\n    function: function_source\n    response_headers: {}",
+            response_headers
+        ))
+        .finalize()
+        .validate()
+    };
+
+    assert!(function_source("{ X-Attribution: OpenStreetMap }").is_ok());
+
+    let error = function_source("{ \"X Attribution\": OpenStreetMap }").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(
+        error.to_string(),
+        "Invalid response header name 'X Attribution' of public.function_source"
+    );
+
+    let error = function_source("{ X-Attribution: \"Open\\nStreetMap\" }").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_validate_flatten_column() {
+    let table_source = |flatten_column: &str| {
+        config(&format!(
+            "table_sources:\n  public.table_source:\n    id: public.table_source\n    schema: public\n    table: table_source\n    geometry_column: geom\n    srid: 4326\n    properties: {{ gid: int4, tags: jsonb }}\n    flatten_column: {}",
+            flatten_column
+        ))
+        .finalize()
+        .validate()
+    };
+
+    assert!(table_source("tags").is_ok());
+
+    let error = table_source("attributes").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(
+        error.to_string(),
+        "public.table_source flatten_column attributes is not one of its properties"
+    );
+}
+
+#[test]
+fn test_validate_buffer_pixels() {
+    let table_source = |buffer_pixels: &str| {
+        config(&format!(
+            "table_sources:\n  public.table_source:\n    id: public.table_source\n    schema: public\n    table: table_source\n    geometry_column: geom\n    srid: 4326\n    properties: {{}}\n    buffer_pixels: {}",
+            buffer_pixels
+        ))
+        .finalize()
+        .validate()
+    };
+
+    let config = table_source("8").unwrap();
+    let source = &config.table_sources.unwrap()["public.table_source"];
+    assert_eq!(source.tile_buffer(), 128);
+
+    for buffer_pixels in &["-1", "257", ".nan"] {
+        let error = table_source(buffer_pixels).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_public_id() {
+    let table_source = |public_id: &str| {
+        config(&format!(
+            "table_sources:\n  public.table_source:\n    id: public.table_source\n    schema: public\n    table: table_source\n    geometry_column: geom\n    srid: 4326\n    properties: {{}}\n    public_id: {}\n  public.points:\n    id: public.points\n    schema: public\n    table: points\n    geometry_column: geom\n    srid: 4326\n    properties: {{}}",
+            public_id
+        ))
+        .finalize()
+        .validate()
+    };
+
+    let config = table_source("roads").unwrap();
+    let table_sources = config.table_sources.unwrap();
+    assert!(!table_sources.contains_key("public.table_source"));
+    assert_eq!(table_sources["roads"].id, "roads");
+    assert_eq!(table_sources["roads"].table, "table_source");
+    assert_eq!(table_sources["public.points"].id, "public.points");
+
+    let error = table_source("public.points").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(
+        error.to_string(),
+        "public_id 'public.points' of public.table_source is already a source id"
+    );
+
+    for public_id in &["\"\"", "roads/main", "roads,rails"] {
+        let error = table_source(public_id).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_env_overrides() {
+    let mut config: Value = serde_yaml::from_str(
+        r#"
+connection_string: "postgres://postgres@localhost/db"
+worker_processes: 8
+listen_addresses: "0.0.0.0:3000"
+tls:
+  cert_path: /etc/martin/cert.pem
+  key_path: /etc/martin/key.pem
+"#,
+    )
+    .unwrap();
+
+    let vars = [
+        ("FALCON_WORKER_PROCESSES", "2"),
+        (
+            "FALCON_LISTEN_ADDRESSES",
+            "[127.0.0.1:3000, unix:/run/martin.sock]",
+        ),
+        ("FALCON_WATCH", "true"),
+        ("FALCON_TLS__KEY_PATH", "/run/secrets/key.pem"),
+        ("FALCON_PUBLIC_BASE_URL", "https://tiles.example.com"),
+        ("FALCON_ADMIN_TOKEN", "123456"),
+        ("FALCON_NOTIFY_CHANNEL", "true"),
+        ("FALCON_TENANT_SCHEMAS__SCHEMAS__ACME", "2024"),
+        ("DATABASE_URL", "postgres://postgres@localhost/other"),
+        ("FALCON_", "ignored"),
+    ];
+
+    apply_env_overrides(
+        &mut config,
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+    );
+
+    let config: ConfigBuilder = serde_yaml::from_value(config).unwrap();
+    let config = config.finalize();
+
+    // Variables override the file, other settings are kept
+    assert_eq!(config.connection_string, "postgres://postgres@localhost/db");
+    assert_eq!(config.worker_processes, 2);
+    assert_eq!(
+        config.listen_addresses,
+        vec!["127.0.0.1:3000", "unix:/run/martin.sock"]
+    );
+    assert!(config.watch);
+    assert_eq!(
+        config.public_base_url.as_deref(),
+        Some("https://tiles.example.com")
+    );
+
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.cert_path, "/etc/martin/cert.pem");
+    assert_eq!(tls.key_path, "/run/secrets/key.pem");
+
+    // String settings aren't parsed as YAML
+    assert_eq!(config.admin_token.as_deref(), Some("123456"));
+    assert_eq!(config.notify_channel.as_deref(), Some("true"));
+    assert_eq!(config.tenant_schemas.unwrap().schemas["ACME"], "2024");
+}
+
+#[test]
+fn test_env_overrides_map_keys() {
+    let mut config: Value = serde_yaml::from_str(
+        r#"
+connection_string: "postgres://postgres@localhost/db"
+tenant_schemas:
+  schemas:
+    Acme: "2023"
+table_sources:
+  public.Roads:
+    id: public.Roads
+    schema: public
+    table: Roads
+    geometry_column: geom
+    srid: 3857
+    extent: 4096
+    buffer: 64
+    clip_geom: true
+    geometry_type: LINESTRING
+    properties: {}
+"#,
+    )
+    .unwrap();
+
+    apply_env_overrides(
+        &mut config,
+        vec![
+            (
+                "FALCON_TENANT_SCHEMAS__SCHEMAS__Acme".to_owned(),
+                "2024".to_owned(),
+            ),
+            (
+                "FALCON_TENANT_SCHEMAS__SCHEMAS__acme".to_owned(),
+                "2022".to_owned(),
+            ),
+            (
+                "FALCON_TABLE_SOURCES__public.Roads__MAX_AGE".to_owned(),
+                "4".to_owned(),
+            ),
+        ],
+    );
+
+    // Setting names are lowercased, tenant and source ids are kept as written
+    let config = serde_yaml::from_value::<ConfigBuilder>(config)
+        .unwrap()
+        .finalize();
+
+    let schemas = config.tenant_schemas.unwrap().schemas;
+    assert_eq!(schemas["Acme"], "2024");
+    assert_eq!(schemas["acme"], "2022");
+
+    let table_sources = config.table_sources.unwrap();
+    assert_eq!(table_sources.len(), 1);
+    assert_eq!(table_sources["public.Roads"].max_age, Some(4));
+}
+
+#[test]
+fn test_env_overrides_string_lists() {
+    let mut config = Value::Null;
+    apply_env_overrides(
+        &mut config,
+        vec![
+            (
+                "FALCON_CONNECTION_STRING".to_owned(),
+                "postgres://db".to_owned(),
+            ),
+            ("FALCON_LISTEN_ADDRESSES".to_owned(), "3000".to_owned()),
+            (
+                "FALCON_DB_SEARCH_PATH".to_owned(),
+                "[tiles, 2024]".to_owned(),
+            ),
+        ],
+    );
+
+    let config: ConfigBuilder = serde_yaml::from_value(config).unwrap();
+    let config = config.finalize();
+    assert_eq!(config.listen_addresses, vec!["3000"]);
+    assert_eq!(
+        config.db_search_path,
+        Some(vec!["tiles".to_owned(), "2024".to_owned()])
+    );
+}
+
+#[test]
+fn test_env_overrides_without_file_settings() {
+    let mut config = Value::Null;
+    apply_env_overrides(
+        &mut config,
+        vec![(
+            "FALCON_CONNECTION_STRING".to_owned(),
+            "postgres://postgres@localhost/db".to_owned(),
+        )],
+    );
+
+    let config: ConfigBuilder = serde_yaml::from_value(config).unwrap();
+    assert_eq!(config.connection_string, "postgres://postgres@localhost/db");
+}
+
+#[test]
+fn test_with_env_overrides() {
+    let config = config(
+        r#"
+worker_processes: 8
+pool_size: 10
+"#,
+    );
+
+    // Without a config file, variables are applied over the command-line options
+    let config = with_env_overrides(
+        config,
+        vec![
+            (
+                "FALCON_CONNECTION_STRING".to_owned(),
+                "postgres://postgres@db/tiles".to_owned(),
+            ),
+            ("FALCON_WORKER_PROCESSES".to_owned(), "2".to_owned()),
+        ],
+    )
+    .unwrap()
+    .finalize();
+
+    assert_eq!(config.connection_string, "postgres://postgres@db/tiles");
+    assert_eq!(config.worker_processes, 2);
+    assert_eq!(config.pool_size, 10);
+}
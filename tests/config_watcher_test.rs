@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::rc::Rc;
+use std::time::Duration;
+
+use actix::Actor;
+use actix_rt::time::delay_for;
+
+use martin::config_watcher::watch_config;
+use martin::coordinator_actor::CoordinatorActor;
+use martin::messages;
+use martin::table_source::TableSources;
+use martin::worker_actor::WorkerActor;
+
+fn config_with_table_source(id: &str) -> String {
+    format!(
+        r#"
+connection_string: "postgres://postgres@localhost/test"
+table_sources:
+  {id}:
+    id: {id}
+    schema: public
+    table: table_source
+    geometry_column: geom
+    srid: 4326
+    properties: {{}}
+"#,
+        id = id
+    )
+}
+
+async fn wait_for_source(table_sources: &Rc<RefCell<Option<TableSources>>>, id: &str) -> bool {
+    for _ in 0..50 {
+        let found = table_sources
+            .borrow()
+            .as_ref()
+            .is_some_and(|sources| sources.contains_key(id));
+
+        if found {
+            return true;
+        }
+
+        delay_for(Duration::from_millis(100)).await;
+    }
+
+    false
+}
+
+#[actix_rt::test]
+async fn test_watch_config() {
+    let directory = env::temp_dir().join(format!("martin_config_watcher_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let config_file = directory.join("config.yaml");
+    fs::write(&config_file, config_with_table_source("public.points1")).unwrap();
+
+    let table_sources = Rc::new(RefCell::new(None));
+    let function_sources = Rc::new(RefCell::new(None));
+
+    let worker = WorkerActor {
+        table_sources: table_sources.clone(),
+        function_sources,
+    }
+    .start();
+
+    let coordinator = CoordinatorActor::default().start();
+    coordinator
+        .send(messages::Connect { addr: worker })
+        .await
+        .unwrap();
+
+    watch_config(
+        config_file.to_str().unwrap().to_owned(),
+        coordinator.clone(),
+    )
+    .unwrap();
+
+    fs::write(&config_file, config_with_table_source("public.points2")).unwrap();
+    assert!(wait_for_source(&table_sources, "public.points2").await);
+
+    // Invalid configs are ignored and the previous sources keep being served
+    fs::write(&config_file, "table_sources: [").unwrap();
+    delay_for(Duration::from_secs(2)).await;
+    assert!(wait_for_source(&table_sources, "public.points2").await);
+
+    fs::remove_dir_all(directory).unwrap();
+}
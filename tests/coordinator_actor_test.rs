@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::{Actor, Context, Handler};
+
+use martin::coordinator_actor::{changed_source_ids, CoordinatorActor};
+use martin::dev::mock_table_sources;
+use martin::event_session::SourceEvent;
+use martin::messages;
+use martin::source::Xyz;
+use martin::tile_cache::{MemoryCache, SharedTileCache, TileCacheKey};
+
+struct Subscriber {
+    source_ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl Actor for Subscriber {
+    type Context = Context<Self>;
+}
+
+impl Handler<messages::SourceChanged> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::SourceChanged, _: &mut Context<Self>) -> Self::Result {
+        self.source_ids.lock().unwrap().push(msg.source_id);
+    }
+}
+
+#[test]
+fn test_changed_source_ids() {
+    let old = mock_table_sources().unwrap();
+    assert!(changed_source_ids(Some(&old), Some(&old)).is_empty());
+
+    let mut new = old.clone();
+    new.get_mut("public.table_source").unwrap().buffer = Some(128);
+    new.remove("public.points1");
+
+    assert_eq!(
+        changed_source_ids(Some(&old), Some(&new)),
+        vec!["public.points1", "public.table_source"]
+    );
+
+    assert_eq!(
+        changed_source_ids(None, Some(&new)).len(),
+        new.len(),
+        "every source is added"
+    );
+}
+
+#[test]
+fn test_source_event_json() {
+    let event = SourceEvent::SourceChanged {
+        source_id: "public.points1".to_owned(),
+    };
+
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"event":"source_changed","source_id":"public.points1"}"#
+    );
+}
+
+#[actix_rt::test]
+async fn test_refresh_notifies_subscribers() {
+    let table_sources = mock_table_sources().unwrap();
+    let coordinator = CoordinatorActor::new(Some(table_sources.clone()), None, None).start();
+
+    let source_ids = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = Subscriber {
+        source_ids: source_ids.clone(),
+    };
+
+    let addr = subscriber.start().recipient();
+    coordinator
+        .send(messages::Subscribe { addr })
+        .await
+        .unwrap();
+
+    // Unchanged sources are not reported
+    coordinator
+        .send(messages::RefreshTableSources {
+            table_sources: Some(table_sources.clone()),
+        })
+        .await
+        .unwrap();
+
+    let mut changed = table_sources;
+    changed.get_mut("public.table_source").unwrap().extent = Some(512);
+
+    coordinator
+        .send(messages::RefreshTableSources {
+            table_sources: Some(changed),
+        })
+        .await
+        .unwrap();
+
+    coordinator
+        .send(messages::SourceChanged {
+            source_id: "public.points2".to_owned(),
+        })
+        .await
+        .unwrap();
+
+    actix_rt::time::delay_for(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        *source_ids.lock().unwrap(),
+        vec!["public.table_source", "public.points2"]
+    );
+}
+
+#[actix_rt::test]
+async fn test_refresh_invalidates_cached_tiles() {
+    let table_sources = mock_table_sources().unwrap();
+    let tile_cache: SharedTileCache = Arc::new(MemoryCache::new(1024 * 1024));
+    let coordinator =
+        CoordinatorActor::new(Some(table_sources.clone()), None, Some(tile_cache.clone())).start();
+
+    let xyz = Xyz { z: 0, x: 0, y: 0 };
+    let changed_key = TileCacheKey::new("public.table_source", &xyz, "pbf");
+    let composite_key = TileCacheKey::new("public.points1,public.table_source", &xyz, "pbf");
+    let unchanged_key = TileCacheKey::new("public.points1", &xyz, "pbf");
+    for key in &[&changed_key, &composite_key, &unchanged_key] {
+        tile_cache.put(key, b"tile").unwrap();
+    }
+
+    let mut changed = table_sources;
+    changed.get_mut("public.table_source").unwrap().extent = Some(512);
+
+    coordinator
+        .send(messages::RefreshTableSources {
+            table_sources: Some(changed),
+        })
+        .await
+        .unwrap();
+
+    actix_rt::time::delay_for(Duration::from_millis(50)).await;
+
+    assert!(tile_cache.get(&changed_key).unwrap().is_none());
+    assert!(tile_cache.get(&composite_key).unwrap().is_none());
+    assert!(tile_cache.get(&unchanged_key).unwrap().is_some());
+}
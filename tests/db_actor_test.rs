@@ -0,0 +1,379 @@
+use std::env;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::SyncArbiter;
+use tilejson::{TileJSON, TileJSONBuilder};
+
+use martin::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitState};
+use martin::db::{
+    get_connection, is_broken_connection_error, is_transient_error, setup_connection_pool,
+    with_search_path, Connection, Pool,
+};
+use martin::db_actor::{max_retry_sleep, retry_backoff, DbActor, MAX_RETRY_BACKOFF};
+use martin::messages;
+use martin::source::{Query, Source, Tile, Xyz};
+
+fn mock_pool() -> Pool {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    setup_connection_pool(&connection_string, Some(2), None, false, true, None, None).unwrap()
+}
+
+#[test]
+fn test_pool_min_idle() {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+
+    // All connections are opened on startup by default
+    let pool =
+        setup_connection_pool(&connection_string, Some(3), None, false, true, None, None).unwrap();
+    assert_eq!(pool.state().connections, 3);
+
+    let pool = setup_connection_pool(
+        &connection_string,
+        Some(3),
+        None,
+        false,
+        true,
+        Some(1),
+        None,
+    )
+    .unwrap();
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[test]
+fn test_pool_search_path() {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    let search_path = vec![
+        "tenant_a".to_owned(),
+        "$user".to_owned(),
+        "public".to_owned(),
+    ];
+
+    let pool = setup_connection_pool(
+        &connection_string,
+        Some(2),
+        None,
+        false,
+        true,
+        None,
+        Some(&search_path),
+    )
+    .unwrap();
+
+    // Every pooled connection has it
+    let connections = vec![
+        get_connection(&pool).unwrap(),
+        get_connection(&pool).unwrap(),
+    ];
+    for mut connection in connections {
+        let row = connection.query_one("SHOW search_path", &[]).unwrap();
+        assert_eq!(row.get::<_, String>(0), "tenant_a, \"$user\", public");
+    }
+}
+
+#[test]
+fn test_with_search_path() {
+    let pool = mock_pool();
+    let mut connection = get_connection(&pool).unwrap();
+    let show_search_path = |connection: &mut Connection| -> io::Result<String> {
+        let row = connection
+            .query_one("SHOW search_path", &[])
+            .map_err(io::Error::other)?;
+        Ok(row.get(0))
+    };
+
+    let previous = show_search_path(&mut connection).unwrap();
+    let search_path = with_search_path(&mut connection, "tenant_a", show_search_path).unwrap();
+    assert_eq!(search_path, format!("\"tenant_a\", {}", previous));
+
+    // Restored after the query
+    assert_eq!(show_search_path(&mut connection).unwrap(), previous);
+}
+
+/// Fails with the given error kind `failures` times before returning a tile
+#[derive(Debug)]
+struct FlakySource {
+    failures: usize,
+    error_kind: io::ErrorKind,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Source for FlakySource {
+    fn get_id(&self) -> &str {
+        "flaky"
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        Ok(TileJSONBuilder::new().finalize())
+    }
+
+    fn get_tile(
+        &self,
+        _conn: &mut Connection,
+        _xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        let calls = self.calls.fetch_add(1, Ordering::SeqCst);
+        if calls < self.failures {
+            return Err(io::Error::new(self.error_kind, "flaky"));
+        }
+
+        Ok(b"tile".to_vec())
+    }
+}
+
+/// Runs a query on the connection it gets
+#[derive(Debug)]
+struct PingSource {
+    calls: Arc<AtomicUsize>,
+}
+
+impl Source for PingSource {
+    fn get_id(&self) -> &str {
+        "ping"
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        Ok(TileJSONBuilder::new().finalize())
+    }
+
+    fn get_tile(
+        &self,
+        conn: &mut Connection,
+        _xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        conn.query_one("SELECT 1", &[]).map_err(io::Error::other)?;
+
+        Ok(b"tile".to_vec())
+    }
+}
+
+#[test]
+fn test_is_transient_error() {
+    let pool = mock_pool();
+
+    let error = io::Error::new(io::ErrorKind::TimedOut, "pool timeout");
+    assert!(is_transient_error(&error));
+
+    let mut connection = get_connection(&pool).unwrap();
+    let error = io::Error::other(connection.query_one("SELEC 1", &[]).unwrap_err());
+    assert!(!is_transient_error(&error));
+    assert!(!is_broken_connection_error(&error));
+
+    let error = io::Error::other(
+        connection
+            .query_one("SELECT pg_terminate_backend(pg_backend_pid())", &[])
+            .unwrap_err(),
+    );
+    assert!(is_transient_error(&error));
+    assert!(is_broken_connection_error(&error));
+}
+
+#[actix_rt::test]
+async fn test_get_tile_on_broken_connection() {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+
+    // The pool hands out connections without testing them
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, false, None, None).unwrap();
+
+    // Terminate the backend of the only pooled connection, as a database restart would
+    let pid: i32 = get_connection(&pool)
+        .unwrap()
+        .query_one("SELECT pg_backend_pid()", &[])
+        .unwrap()
+        .get(0);
+
+    let mut client = postgres::Client::connect(&connection_string, postgres::NoTls).unwrap();
+    client
+        .execute("SELECT pg_terminate_backend($1)", &[&pid])
+        .unwrap();
+    actix_rt::time::delay_for(Duration::from_millis(100)).await;
+
+    let db = SyncArbiter::start(1, move || DbActor {
+        pool: pool.clone(),
+        retries: 0,
+        retry_backoff: Duration::from_millis(1),
+        max_retry_sleep: MAX_RETRY_BACKOFF,
+        slow_query: None,
+        circuit_breaker: None,
+    });
+
+    // The broken connection is discarded and the query is retried once on a new one
+    let calls = Arc::new(AtomicUsize::new(0));
+    let tile_data = db
+        .send(messages::GetTile {
+            xyz: Xyz { z: 0, x: 0, y: 0 },
+            query: None,
+            source: Box::new(PingSource {
+                calls: calls.clone(),
+            }),
+            span: tracing::Span::none(),
+            search_path: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(tile_data.tile, b"tile");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_retry_backoff() {
+    let base = Duration::from_millis(100);
+    assert_eq!(retry_backoff(base, 0), base);
+    assert_eq!(retry_backoff(base, 3), Duration::from_millis(800));
+
+    // Capped instead of sleeping for hours or overflowing
+    assert_eq!(retry_backoff(base, 20), MAX_RETRY_BACKOFF);
+    assert_eq!(retry_backoff(base, 32), MAX_RETRY_BACKOFF);
+    assert_eq!(retry_backoff(base, u32::MAX), MAX_RETRY_BACKOFF);
+}
+
+#[test]
+fn test_max_retry_sleep() {
+    assert_eq!(max_retry_sleep(None), MAX_RETRY_BACKOFF);
+    assert_eq!(
+        max_retry_sleep(Some(Duration::from_secs(4))),
+        Duration::from_secs(2)
+    );
+    assert_eq!(
+        max_retry_sleep(Some(Duration::from_secs(60))),
+        MAX_RETRY_BACKOFF
+    );
+}
+
+#[actix_rt::test]
+async fn test_get_tile_retries() {
+    let pool = mock_pool();
+    let db = SyncArbiter::start(1, move || DbActor {
+        pool: pool.clone(),
+        retries: 3,
+        retry_backoff: Duration::from_millis(1),
+        max_retry_sleep: MAX_RETRY_BACKOFF,
+        slow_query: None,
+        circuit_breaker: None,
+    });
+
+    let get_tile = |failures, error_kind, calls: &Arc<AtomicUsize>| messages::GetTile {
+        xyz: Xyz { z: 0, x: 0, y: 0 },
+        query: None,
+        source: Box::new(FlakySource {
+            failures,
+            error_kind,
+            calls: calls.clone(),
+        }),
+        span: tracing::Span::none(),
+        search_path: None,
+    };
+
+    // Transient errors are retried
+    let calls = Arc::new(AtomicUsize::new(0));
+    let tile_data = db
+        .send(get_tile(2, io::ErrorKind::TimedOut, &calls))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tile_data.tile, b"tile");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // ...until the retries are exhausted
+    let calls = Arc::new(AtomicUsize::new(0));
+    let result = db
+        .send(get_tile(10, io::ErrorKind::TimedOut, &calls))
+        .await
+        .unwrap();
+    assert!(result.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    // Query errors are not retried
+    let calls = Arc::new(AtomicUsize::new(0));
+    let result = db
+        .send(get_tile(1, io::ErrorKind::Other, &calls))
+        .await
+        .unwrap();
+    assert!(result.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[actix_rt::test]
+async fn test_get_tile_retries_max_retry_sleep() {
+    let pool = mock_pool();
+    let db = SyncArbiter::start(1, move || DbActor {
+        pool: pool.clone(),
+        retries: 5,
+        retry_backoff: Duration::from_millis(10),
+        max_retry_sleep: Duration::from_millis(35),
+        slow_query: None,
+        circuit_breaker: None,
+    });
+
+    // Backoffs of 10ms and 20ms fit in the sleep budget, the next one of 40ms doesn't
+    let calls = Arc::new(AtomicUsize::new(0));
+    let result = db
+        .send(messages::GetTile {
+            xyz: Xyz { z: 0, x: 0, y: 0 },
+            query: None,
+            source: Box::new(FlakySource {
+                failures: 10,
+                error_kind: io::ErrorKind::TimedOut,
+                calls: calls.clone(),
+            }),
+            span: tracing::Span::none(),
+            search_path: None,
+        })
+        .await
+        .unwrap();
+
+    assert!(result.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[actix_rt::test]
+async fn test_get_tile_circuit_breaker() {
+    let pool = mock_pool();
+    let circuit_breaker = CircuitBreaker::new(&CircuitBreakerConfig {
+        failure_threshold: Some(2),
+        cooldown_ms: Some(60_000),
+    });
+    let actor_circuit_breaker = circuit_breaker.clone();
+    let db = SyncArbiter::start(1, move || DbActor {
+        pool: pool.clone(),
+        retries: 0,
+        retry_backoff: Duration::from_millis(1),
+        max_retry_sleep: MAX_RETRY_BACKOFF,
+        slow_query: None,
+        circuit_breaker: Some(actor_circuit_breaker.clone()),
+    });
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let get_tile = || messages::GetTile {
+        xyz: Xyz { z: 0, x: 0, y: 0 },
+        query: None,
+        source: Box::new(FlakySource {
+            failures: 10,
+            error_kind: io::ErrorKind::TimedOut,
+            calls: calls.clone(),
+        }),
+        span: tracing::Span::none(),
+        search_path: None,
+    };
+
+    for _ in 0..2 {
+        let error = db.send(get_tile()).await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+    assert_eq!(circuit_breaker.state(), CircuitState::Open);
+
+    // Queries fail fast without touching the database
+    let error = db.send(get_tile()).await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
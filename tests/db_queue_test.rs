@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use actix_web::test::TestRequest;
+use futures::future::join_all;
+
+use martin::db_queue::{DbQueue, Priority};
+
+#[test]
+fn test_priority_from_request() {
+    let req = TestRequest::default().to_http_request();
+    assert_eq!(Priority::from_request(&req), Priority::Interactive);
+
+    let req = TestRequest::default()
+        .header("X-Tile-Priority", "Background")
+        .to_http_request();
+    assert_eq!(Priority::from_request(&req), Priority::Background);
+}
+
+#[actix_rt::test]
+async fn test_interactive_requests_preempt_background() {
+    let queue = DbQueue::new(1);
+    let served = Rc::new(RefCell::new(Vec::new()));
+
+    // Occupy the only permit so that all following requests queue up
+    let permit = queue.acquire(Priority::Interactive).await;
+
+    let request = |name: &'static str, priority: Priority| {
+        let queue = queue.clone();
+        let served = served.clone();
+        async move {
+            let _permit = queue.acquire(priority).await;
+            served.borrow_mut().push(name);
+        }
+    };
+
+    let requests = vec![
+        request("seed 1", Priority::Background),
+        request("seed 2", Priority::Background),
+        request("seed 3", Priority::Background),
+        request("interactive 1", Priority::Interactive),
+        request("seed 4", Priority::Background),
+        request("interactive 2", Priority::Interactive),
+    ];
+
+    // Requests are queued in order when first polled, then the permit is released
+    let release = async {
+        assert!(served.borrow().is_empty());
+        drop(permit);
+    };
+    futures::join!(join_all(requests), release);
+
+    assert_eq!(
+        *served.borrow(),
+        vec![
+            "interactive 1",
+            "interactive 2",
+            "seed 1",
+            "seed 2",
+            "seed 3",
+            "seed 4"
+        ]
+    );
+}
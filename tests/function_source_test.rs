@@ -0,0 +1,145 @@
+use std::io;
+
+use martin::dev::mock_function_sources;
+use martin::function_source::{sanitize_query_params, DEFAULT_MAX_PARAM_LENGTH};
+use martin::source::{Query, Xyz};
+use martin::utils::query_to_json;
+use serde_json::json;
+
+fn query(params: &[(&str, &str)]) -> Query {
+    params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_sanitize_query_params() {
+    let params = sanitize_query_params(
+        query(&[
+            ("token", "martin"),
+            ("objectParam", r#"{"answer" : 42}"#),
+            ("arrayParam", "[1, 2, 3]"),
+            ("name", "Zürich"),
+            ("extent", "8192"),
+            ("page", "2"),
+            ("limit", "10"),
+        ]),
+        DEFAULT_MAX_PARAM_LENGTH,
+    )
+    .unwrap();
+
+    // Reserved params are dropped, the rest is passed as is
+    assert_eq!(
+        params,
+        query(&[
+            ("token", "martin"),
+            ("objectParam", r#"{"answer" : 42}"#),
+            ("arrayParam", "[1, 2, 3]"),
+            ("name", "Zürich"),
+        ])
+    );
+}
+
+#[test]
+fn test_collapse_query_params() {
+    let params = sanitize_query_params(
+        vec![
+            ("category".to_owned(), "shop".to_owned()),
+            ("category".to_owned(), "cafe".to_owned()),
+            ("id".to_owned(), "1".to_owned()),
+            ("id".to_owned(), "json:\"2\"".to_owned()),
+            ("filter".to_owned(), r#"json:{"min": 1}"#.to_owned()),
+            ("name".to_owned(), r#"json:"42""#.to_owned()),
+            ("token".to_owned(), "martin".to_owned()),
+        ],
+        DEFAULT_MAX_PARAM_LENGTH,
+    )
+    .unwrap();
+
+    let query_json = query_to_json(&params).0;
+    assert_eq!(query_json["category"], json!(["shop", "cafe"]));
+    assert_eq!(query_json["id"], json!([1, "2"]));
+    assert_eq!(query_json["filter"], json!({ "min": 1 }));
+    assert_eq!(query_json["name"], json!("42"));
+    assert_eq!(query_json["token"], json!("martin"));
+
+    let error = sanitize_query_params(
+        query(&[("filter", "json:{min: 1}")]),
+        DEFAULT_MAX_PARAM_LENGTH,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_sanitize_query_params_errors() {
+    for params in &[
+        [("token", "martin; DROP TABLE points")],
+        [("token", "`martin`")],
+        [("token", "martin\\")],
+        [("token", "martin\n")],
+        [("to ken", "martin")],
+        [("", "martin")],
+    ] {
+        let error = sanitize_query_params(query(params), DEFAULT_MAX_PARAM_LENGTH).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    assert!(sanitize_query_params(query(&[("token", "martin")]), 6).is_ok());
+    assert!(sanitize_query_params(query(&[("token", "martin")]), 5).is_err());
+}
+
+#[test]
+fn test_overzoom_query_xyz() {
+    let mut source = mock_function_sources().unwrap()["public.function_source"]
+        .as_ref()
+        .clone();
+    let xyz = Xyz { z: 5, x: 19, y: 10 };
+
+    // Without maxzoom every tile is queried as is
+    let query_xyz = source.query_xyz(&xyz, true).unwrap();
+    assert_eq!((query_xyz.z, query_xyz.x, query_xyz.y), (5, 19, 10));
+
+    source.maxzoom = Some(3);
+    let query_xyz = source.query_xyz(&xyz, false).unwrap();
+    assert_eq!((query_xyz.z, query_xyz.x, query_xyz.y), (5, 19, 10));
+    assert!(!source.is_zoom_valid(query_xyz.z));
+
+    // The maxzoom ancestor is queried instead
+    let query_xyz = source.query_xyz(&xyz, true).unwrap();
+    assert_eq!((query_xyz.z, query_xyz.x, query_xyz.y), (3, 4, 2));
+    assert!(source.is_zoom_valid(query_xyz.z));
+
+    let query_xyz = source.query_xyz(&Xyz { z: 2, x: 1, y: 1 }, true).unwrap();
+    assert_eq!((query_xyz.z, query_xyz.x, query_xyz.y), (2, 1, 1));
+}
+
+#[test]
+fn test_query_xyz_out_of_range() {
+    let mut source = mock_function_sources().unwrap()["public.function_source"]
+        .as_ref()
+        .clone();
+    source.maxzoom = Some(3);
+
+    assert!(source.query_xyz(&Xyz { z: 50, x: 0, y: 0 }, true).is_none());
+    assert!(source.query_xyz(&Xyz { z: 31, x: 0, y: 0 }, true).is_none());
+    assert!(source.query_xyz(&Xyz { z: -1, x: 0, y: 0 }, true).is_none());
+    assert!(source.query_xyz(&Xyz { z: 2, x: 4, y: 0 }, true).is_none());
+    assert!(source.query_xyz(&Xyz { z: 2, x: 0, y: -1 }, true).is_none());
+
+    let query_xyz = source
+        .query_xyz(
+            &Xyz {
+                z: 30,
+                x: (1 << 30) - 1,
+                y: 0,
+            },
+            true,
+        )
+        .unwrap();
+    assert_eq!((query_xyz.z, query_xyz.x, query_xyz.y), (3, 7, 0));
+
+    let ancestor = Xyz { z: 50, x: 1, y: 1 }.ancestor(0);
+    assert_eq!((ancestor.z, ancestor.x, ancestor.y), (0, 0, 0));
+}
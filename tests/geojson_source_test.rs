@@ -0,0 +1,41 @@
+use martin::dev::mock_table_sources;
+use martin::geojson_source::GeoJsonSource;
+use martin::table_source::TableSource;
+
+fn mock_geojson_source(source_ids: &[&str]) -> GeoJsonSource {
+    let table_sources = mock_table_sources().unwrap();
+
+    let table_sources: Vec<TableSource> = source_ids
+        .iter()
+        .map(|source_id| table_sources[*source_id].as_ref().clone())
+        .collect();
+
+    GeoJsonSource {
+        id: source_ids.join(","),
+        table_sources,
+    }
+}
+
+#[test]
+fn test_geojson_tile_query() {
+    let query = mock_geojson_source(&["public.table_source"]).build_tile_query();
+    assert!(query.starts_with("WITH bounds AS"));
+    assert!(query.contains("'type', 'FeatureCollection'"));
+    assert!(query.contains("COALESCE(json_agg(features.feature), '[]'::json)"));
+    assert!(query.contains("'layer', 'public.table_source'"));
+    assert!(query.contains("ST_Intersection(ST_Transform(\"geom\", 3857), ST_MakeEnvelope("));
+    assert!(!query.contains("'id',"));
+    assert!(!query.contains("UNION ALL"));
+}
+
+#[test]
+fn test_composite_geojson_tile_query() {
+    let mut source = mock_geojson_source(&["public.points1", "public.points2"]);
+    source.table_sources[0].id_column = Some("gid".to_owned());
+
+    let query = source.build_tile_query();
+    assert!(query.contains("'id', \"gid\","));
+    assert!(query.contains("'layer', 'public.points1'"));
+    assert!(query.contains(") UNION ALL ("));
+    assert!(query.contains("'layer', 'public.points2'"));
+}
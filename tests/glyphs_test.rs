@@ -0,0 +1,164 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use actix_web::{http, test, App};
+use prost::Message;
+
+use martin::dev::mock_state;
+use martin::glyphs::proto::{Fontstack, Glyph, Glyphs};
+use martin::glyphs::{get_glyphs, parse_fontstack, parse_glyph_range};
+use martin::server::router;
+
+fn glyph(id: u32, advance: u32) -> Glyph {
+    Glyph {
+        id,
+        bitmap: None,
+        width: 10,
+        height: 12,
+        left: 1,
+        top: -8,
+        advance,
+    }
+}
+
+fn write_font_range(directory: &Path, font: &str, range: &str, glyphs: Vec<Glyph>) {
+    let font_directory = directory.join(font);
+    fs::create_dir_all(&font_directory).unwrap();
+
+    let data = Glyphs {
+        stacks: vec![Fontstack {
+            name: font.to_owned(),
+            range: range.to_owned(),
+            glyphs,
+        }],
+    };
+
+    let mut buf = Vec::new();
+    data.encode(&mut buf).unwrap();
+    fs::write(font_directory.join(format!("{}.pbf", range)), buf).unwrap();
+}
+
+/// Two fonts sharing the `a` glyph, only the first one has the second range
+fn fonts_directory(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("martin_{}_{}", name, std::process::id()));
+
+    write_font_range(
+        &directory,
+        "Regular",
+        "0-255",
+        vec![glyph(97, 10), glyph(98, 10)],
+    );
+    write_font_range(&directory, "Regular", "256-511", vec![glyph(256, 10)]);
+    write_font_range(
+        &directory,
+        "Fallback",
+        "0-255",
+        vec![glyph(97, 20), glyph(99, 20)],
+    );
+
+    directory
+}
+
+#[test]
+fn test_parse_glyph_range() {
+    assert_eq!(parse_glyph_range("0-255").unwrap(), (0, 255));
+    assert_eq!(parse_glyph_range("65280-65535").unwrap(), (65280, 65535));
+
+    for range in &["0-256", "1-256", "65536-65791", "255-0", "a-b", "0", ""] {
+        let error = parse_glyph_range(range).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_parse_fontstack() {
+    assert_eq!(
+        parse_fontstack("Open Sans Regular, Arial Unicode MS Regular").unwrap(),
+        vec!["Open Sans Regular", "Arial Unicode MS Regular"]
+    );
+
+    for fontstack in &["..", "../fonts", "Regular,", "a\\b"] {
+        assert!(parse_fontstack(fontstack).is_err());
+    }
+}
+
+#[test]
+fn test_get_glyphs() {
+    let directory = fonts_directory("glyphs");
+
+    // A single font is returned as is
+    let data = get_glyphs(&directory, "Regular", 0, 255).unwrap();
+    assert_eq!(data, fs::read(directory.join("Regular/0-255.pbf")).unwrap());
+
+    // Fonts are combined, the first one wins for shared glyphs
+    let data = get_glyphs(&directory, "Regular,Fallback", 0, 255).unwrap();
+    let glyphs = Glyphs::decode(data.as_slice()).unwrap();
+    assert_eq!(glyphs.stacks.len(), 1);
+    assert_eq!(glyphs.stacks[0].name, "Regular, Fallback");
+    assert_eq!(glyphs.stacks[0].range, "0-255");
+
+    let advances: Vec<(u32, u32)> = glyphs.stacks[0]
+        .glyphs
+        .iter()
+        .map(|glyph| (glyph.id, glyph.advance))
+        .collect();
+    assert_eq!(advances, vec![(97, 10), (98, 10), (99, 20)]);
+
+    // Fonts without the range don't contribute glyphs
+    let data = get_glyphs(&directory, "Fallback,Regular", 256, 511).unwrap();
+    let glyphs = Glyphs::decode(data.as_slice()).unwrap();
+    assert_eq!(glyphs.stacks[0].glyphs, vec![glyph(256, 10)]);
+
+    let error = get_glyphs(&directory, "Regular,Unknown", 0, 255).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_glyphs_endpoint() {
+    let directory = fonts_directory("glyphs_endpoint");
+
+    let mut state = mock_state(None, None, false);
+    state.fonts_directory = Some(directory.to_string_lossy().into_owned());
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/fonts/Regular,Fallback/0-255.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/x-protobuf"
+    );
+
+    let cases = [
+        (
+            "/fonts/Regular,Unknown/0-255.pbf",
+            http::StatusCode::NOT_FOUND,
+        ),
+        ("/fonts/Regular/0-256.pbf", http::StatusCode::BAD_REQUEST),
+        ("/fonts/Regular/256-0.pbf", http::StatusCode::BAD_REQUEST),
+    ];
+
+    for (uri, status) in &cases {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), *status, "{}", uri);
+    }
+
+    // Without a fonts directory there are no fonts
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/fonts/Regular/0-255.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+    fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,6 @@
+WITH bounds AS (SELECT ST_MakeEnvelope((-20037508.34)::float8, (-20037508.34)::float8, (0)::float8, (0)::float8, 3857) AS srid_3857) SELECT
+  ST_AsMVT (tile, 'public.table_source', 4096, 'geom' ) FROM (SELECT
+  ST_AsMVTGeom(ST_Transform(geom, 3857), ST_MakeEnvelope((-20037508.34)::float8, (-20037508.34)::float8, (0)::float8, (0)::float8, 3857), 4096, 64, true) AS geom FROM "public"."table_source", bounds
+  WHERE
+    geom && bounds.srid_3857
+) AS tile
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use actix_web::{http, test, App};
+
+use martin::dev::mock_state;
+use martin::grid_source::GridSource;
+use martin::server::router;
+use martin::source::Xyz;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn varint(&mut self) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.data[self.position];
+            self.position += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    fn bytes(&mut self) -> &'a [u8] {
+        let length = self.varint() as usize;
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        bytes
+    }
+
+    /// Returns (field number, varint value or length-delimited bytes)
+    fn field(&mut self) -> (u64, Result<u64, &'a [u8]>) {
+        let key = self.varint();
+        match key & 0x7 {
+            0 => (key >> 3, Ok(self.varint())),
+            2 => (key >> 3, Err(self.bytes())),
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        }
+    }
+}
+
+fn packed(data: &[u8]) -> Vec<u64> {
+    let mut reader = Reader::new(data);
+    let mut values = Vec::new();
+    while !reader.is_empty() {
+        values.push(reader.varint());
+    }
+    values
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Int(i64),
+}
+
+struct Layer {
+    name: String,
+    properties: Vec<HashMap<String, Value>>,
+    geometries: Vec<Vec<u64>>,
+}
+
+fn decode(tile: &[u8]) -> Vec<Layer> {
+    let mut layers = Vec::new();
+    let mut tile = Reader::new(tile);
+
+    while !tile.is_empty() {
+        let (field, value) = tile.field();
+        assert_eq!(field, 3);
+
+        let mut layer = Reader::new(value.unwrap_err());
+        let mut name = String::new();
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        let mut features = Vec::new();
+
+        while !layer.is_empty() {
+            match layer.field() {
+                (1, Err(bytes)) => name = String::from_utf8(bytes.to_vec()).unwrap(),
+                (2, Err(bytes)) => features.push(bytes),
+                (3, Err(bytes)) => keys.push(String::from_utf8(bytes.to_vec()).unwrap()),
+                (4, Err(bytes)) => {
+                    let mut value = Reader::new(bytes);
+                    values.push(match value.field() {
+                        (1, Err(bytes)) => {
+                            Value::String(String::from_utf8(bytes.to_vec()).unwrap())
+                        }
+                        (4, Ok(int)) => Value::Int(int as i64),
+                        field => panic!("unexpected value {:?}", field),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let mut properties = Vec::new();
+        let mut geometries = Vec::new();
+        for feature in features {
+            let mut feature = Reader::new(feature);
+            let mut tags = Vec::new();
+            while !feature.is_empty() {
+                match feature.field() {
+                    (2, Err(bytes)) => tags = packed(bytes),
+                    (4, Err(bytes)) => geometries.push(packed(bytes)),
+                    _ => {}
+                }
+            }
+
+            properties.push(
+                tags.chunks(2)
+                    .map(|tag| {
+                        let value = match &values[tag[1] as usize] {
+                            Value::String(value) => Value::String(value.clone()),
+                            Value::Int(value) => Value::Int(*value),
+                        };
+                        (keys[tag[0] as usize].clone(), value)
+                    })
+                    .collect(),
+            );
+        }
+
+        layers.push(Layer {
+            name,
+            properties,
+            geometries,
+        });
+    }
+
+    layers
+}
+
+#[test]
+fn test_render_grid_tile() {
+    let tile = GridSource::default().render_tile(&Xyz { z: 3, x: 5, y: 2 });
+    let layers = decode(&tile);
+
+    assert_eq!(layers.len(), 1);
+    let Layer {
+        name,
+        properties,
+        geometries,
+    } = &layers[0];
+    assert_eq!(name, "__grid");
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties[0]["z"], Value::Int(3));
+    assert_eq!(properties[0]["x"], Value::Int(5));
+    assert_eq!(properties[0]["y"], Value::Int(2));
+    assert_eq!(properties[0]["zxy"], Value::String("3/5/2".to_owned()));
+
+    // MoveTo(0, 0), LineTo(4096, 0), (4096, 4096), (0, 4096), ClosePath
+    assert_eq!(
+        geometries[0],
+        vec![9, 0, 0, 26, 8192, 0, 0, 8192, 8191, 0, 15]
+    );
+}
+
+#[actix_rt::test]
+async fn test_get_grid_source_tile_ok() {
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/__grid.json").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let body = test::read_body(response).await;
+    let layers = decode(&body);
+    assert_eq!(
+        layers[0].properties[0]["zxy"],
+        Value::String("1/0/1".to_owned())
+    );
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+
+use actix::{Actor, SyncArbiter};
+use tonic::{Code, Request};
+
+use martin::coordinator_actor::{CoordinatorActor, SharedSources};
+
+use martin::db::setup_connection_pool;
+use martin::db_actor::{DbActor, MAX_RETRY_BACKOFF};
+use martin::db_queue::DbQueue;
+use martin::dev::mock_table_sources;
+use martin::function_source::DEFAULT_MAX_PARAM_LENGTH;
+use martin::grid_source::{GridSource, GRID_SOURCE_ID};
+use martin::grpc::proto::tiles_client::TilesClient;
+use martin::grpc::proto::TileRequest;
+use martin::grpc::{self, TileService};
+use martin::messages;
+use martin::rate_limiter::{RateLimit, RateLimiter};
+use martin::source::{Scheme, Xyz};
+use martin::tenant::TenantSchemas;
+
+fn free_address() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+fn tile_service() -> TileService {
+    let connection_string: String = env::var("DATABASE_URL").unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, true, None, None).unwrap();
+    let db = SyncArbiter::start(1, move || DbActor {
+        pool: pool.clone(),
+        retries: 0,
+        retry_backoff: Duration::from_millis(100),
+        max_retry_sleep: MAX_RETRY_BACKOFF,
+        slow_query: None,
+        circuit_breaker: None,
+    });
+
+    TileService {
+        db,
+        replicas: Vec::new(),
+        next_replica: AtomicUsize::new(0),
+        db_queue: DbQueue::new(1),
+        sources: SharedSources::default(),
+        debug_grid: true,
+        overzoom: false,
+        max_function_param_length: DEFAULT_MAX_PARAM_LENGTH,
+        max_tile_bytes: None,
+        min_allowed_zoom: None,
+        rate_limiter: None,
+        tenant_schemas: None,
+    }
+}
+
+async fn start_server(service: TileService) -> SocketAddr {
+    let address = free_address();
+    actix::spawn(async move {
+        grpc::serve(service, address).await.unwrap();
+    });
+
+    // Wait for the server to bind
+    actix_rt::time::delay_for(Duration::from_millis(200)).await;
+    address
+}
+
+fn tile_request(source_id: &str, z: i32, x: i32, y: i32) -> TileRequest {
+    TileRequest {
+        source_id: source_id.to_owned(),
+        z,
+        x,
+        y,
+        query: HashMap::new(),
+    }
+}
+
+#[actix_rt::test]
+async fn test_grpc_get_tile() {
+    let address = start_server(tile_service()).await;
+    let mut client = TilesClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+
+    let mut stream = client
+        .get_tile(tile_request(GRID_SOURCE_ID, 2, 1, 1))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut tile = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        tile.extend(chunk.data);
+    }
+
+    let expected = GridSource::default().render_tile(&Xyz { z: 2, x: 1, y: 1 });
+    assert!(!tile.is_empty());
+    assert_eq!(tile, expected);
+
+    let status = client
+        .get_tile(tile_request("public.non_existent", 0, 0, 0))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[actix_rt::test]
+async fn test_grpc_refreshed_sources() {
+    let sources = SharedSources::default();
+    let coordinator = CoordinatorActor::default().start();
+    coordinator
+        .send(messages::ShareSources {
+            sources: sources.clone(),
+        })
+        .await
+        .unwrap();
+
+    let service = TileService {
+        sources,
+        min_allowed_zoom: Some(1),
+        ..tile_service()
+    };
+
+    let address = start_server(service).await;
+    let mut client = TilesClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+
+    let status = client
+        .get_tile(tile_request("public.table_source", 0, 0, 0))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::NotFound);
+
+    coordinator
+        .send(messages::RefreshTableSources {
+            table_sources: mock_table_sources(),
+        })
+        .await
+        .unwrap();
+
+    // Served after the refresh, empty without querying below min_allowed_zoom
+    let mut stream = client
+        .get_tile(tile_request("public.table_source", 0, 0, 0))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert!(stream.message().await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn test_grpc_tenant_and_rate_limit() {
+    let mut schemas = HashMap::new();
+    schemas.insert("tenant_a".to_owned(), "tenant_a".to_owned());
+
+    let service = TileService {
+        rate_limiter: Some(RateLimiter::new(&RateLimit {
+            requests_per_second: 0.001,
+            burst: Some(2),
+        })),
+        tenant_schemas: Some(TenantSchemas {
+            header: None,
+            schemas,
+        }),
+        ..tile_service()
+    };
+
+    let address = start_server(service).await;
+    let mut client = TilesClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+
+    let status = client
+        .get_tile(tile_request(GRID_SOURCE_ID, 0, 0, 0))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let mut request = Request::new(tile_request(GRID_SOURCE_ID, 0, 0, 0));
+    request
+        .metadata_mut()
+        .insert("x-tenant-id", "tenant_a".parse().unwrap());
+    assert!(client.get_tile(request).await.is_ok());
+
+    let status = client
+        .get_tile(tile_request(GRID_SOURCE_ID, 0, 0, 0))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[actix_rt::test]
+async fn test_grpc_tms_tile_outside_tile_grid() {
+    let mut table_sources = mock_table_sources().unwrap();
+    for source in table_sources.values_mut() {
+        source.scheme = Some(Scheme::Tms);
+    }
+
+    let service = TileService {
+        sources: SharedSources::new(Some(table_sources), None),
+        ..tile_service()
+    };
+
+    let address = start_server(service).await;
+    let mut client = TilesClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+
+    for z in &[31, 32, -1] {
+        let mut stream = client
+            .get_tile(tile_request("public.table_source", *z, 0, 0))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(stream.message().await.unwrap().is_none(), "z={}", z);
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use martin::config::{ConfigBuilder, DEFAULT_LISTEN_ADDRESS};
+use martin::server::ListenAddress;
+
+fn listen_addresses(yaml: &str) -> Vec<String> {
+    let config: ConfigBuilder = serde_yaml::from_str(&format!(
+        "connection_string: \"postgres://postgres@localhost/db\"\n{}",
+        yaml
+    ))
+    .unwrap();
+
+    config.finalize().listen_addresses
+}
+
+#[test]
+fn test_config_listen_addresses() {
+    assert_eq!(listen_addresses(""), vec![DEFAULT_LISTEN_ADDRESS]);
+
+    assert_eq!(
+        listen_addresses("listen_addresses: \"0.0.0.0:3000\""),
+        vec!["0.0.0.0:3000"]
+    );
+
+    // Comma-separated strings, e.g. from the command line, are split
+    assert_eq!(
+        listen_addresses("listen_addresses: \"127.0.0.1:3000, [::1]:3000\""),
+        vec!["127.0.0.1:3000", "[::1]:3000"]
+    );
+
+    assert_eq!(
+        listen_addresses("listen_addresses:\n  - \"10.0.0.1:3000\"\n  - \"unix:/run/martin.sock\""),
+        vec!["10.0.0.1:3000", "unix:/run/martin.sock"]
+    );
+
+    // An explicitly empty list is kept, `Config::validate` rejects it
+    assert!(listen_addresses("listen_addresses: []").is_empty());
+}
+
+#[test]
+fn test_parse_listen_address() {
+    assert_eq!(
+        ListenAddress::parse("0.0.0.0:3000"),
+        ListenAddress::Tcp("0.0.0.0:3000".to_owned())
+    );
+
+    assert_eq!(
+        ListenAddress::parse("unix:/run/martin.sock"),
+        ListenAddress::Unix(PathBuf::from("/run/martin.sock"))
+    );
+}
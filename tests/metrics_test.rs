@@ -0,0 +1,56 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+use martin::db::{get_connection, setup_connection_pool};
+use martin::metrics::{Histogram, DB_POOL_WAIT};
+
+#[test]
+fn test_histogram() {
+    let histogram = Histogram::new("test_seconds", "Test histogram");
+    histogram.observe(Duration::from_micros(500));
+    histogram.observe(Duration::from_millis(200));
+    histogram.observe(Duration::from_secs(60));
+
+    assert_eq!(histogram.count(), 3);
+    assert_eq!(histogram.max(), Duration::from_secs(60));
+
+    let mut output = String::new();
+    histogram.render(&mut output);
+
+    assert!(output.contains("# TYPE test_seconds histogram\n"));
+    assert!(output.contains("test_seconds_bucket{le=\"0.001\"} 1\n"));
+    assert!(output.contains("test_seconds_bucket{le=\"0.1\"} 1\n"));
+    assert!(output.contains("test_seconds_bucket{le=\"0.25\"} 2\n"));
+    assert!(output.contains("test_seconds_bucket{le=\"30\"} 2\n"));
+    assert!(output.contains("test_seconds_bucket{le=\"+Inf\"} 3\n"));
+    assert!(output.contains("test_seconds_sum 60.2005\n"));
+    assert!(output.contains("test_seconds_count 3\n"));
+    assert!(output.contains("test_seconds_max 60\n"));
+}
+
+#[test]
+fn test_pool_timeout() {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    let pool = setup_connection_pool(
+        &connection_string,
+        Some(1),
+        Some(Duration::from_millis(100)),
+        false,
+        true,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let count = DB_POOL_WAIT.count();
+    let _connection = get_connection(&pool).unwrap();
+
+    // The only connection is taken, so the next request fails fast
+    let started_at = Instant::now();
+    let error = get_connection(&pool).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+
+    assert!(DB_POOL_WAIT.count() >= count + 2);
+    assert!(DB_POOL_WAIT.max() >= Duration::from_millis(100));
+}
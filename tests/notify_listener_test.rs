@@ -0,0 +1,96 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::{Actor, Context, Handler};
+
+use martin::coordinator_actor::CoordinatorActor;
+use martin::db::{get_connection, setup_connection_pool};
+use martin::messages;
+use martin::notify_listener::{listen_notifications, parse_notification_payload};
+use martin::source::Xyz;
+use martin::tile_cache::{MemoryCache, SharedTileCache, TileCacheKey};
+
+struct Subscriber {
+    source_ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl Actor for Subscriber {
+    type Context = Context<Self>;
+}
+
+impl Handler<messages::SourceChanged> for Subscriber {
+    type Result = ();
+
+    fn handle(&mut self, msg: messages::SourceChanged, _: &mut Context<Self>) -> Self::Result {
+        self.source_ids.lock().unwrap().push(msg.source_id);
+    }
+}
+
+#[test]
+fn test_parse_notification_payload() {
+    assert_eq!(
+        parse_notification_payload("public.points1"),
+        Some("public.points1".to_owned())
+    );
+
+    assert_eq!(
+        parse_notification_payload(r#"{"source_id": "public.points2", "op": "INSERT"}"#),
+        Some("public.points2".to_owned())
+    );
+
+    assert_eq!(parse_notification_payload(""), None);
+    assert_eq!(parse_notification_payload(r#"{"id": 1}"#), None);
+    assert_eq!(parse_notification_payload("{not json"), None);
+}
+
+#[actix_rt::test]
+async fn test_notifications_report_changed_sources() {
+    let connection_string: String = env::var("DATABASE_URL").unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, true, None, None).unwrap();
+
+    let tile_cache: SharedTileCache = Arc::new(MemoryCache::new(1024 * 1024));
+    let key = TileCacheKey::new("public.points1", &Xyz { z: 0, x: 0, y: 0 }, "pbf");
+    tile_cache.put(&key, b"tile").unwrap();
+
+    let coordinator = CoordinatorActor::new(None, None, Some(tile_cache.clone())).start();
+
+    let source_ids = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = Subscriber {
+        source_ids: source_ids.clone(),
+    };
+
+    let addr = subscriber.start().recipient();
+    coordinator
+        .send(messages::Subscribe { addr })
+        .await
+        .unwrap();
+
+    let channel = "martin test channel";
+    listen_notifications(connection_string, false, channel.to_owned(), coordinator).unwrap();
+
+    // Wait for the listener to LISTEN
+    actix_rt::time::delay_for(Duration::from_millis(500)).await;
+
+    // The listener doesn't hold the only pooled connection
+    let mut connection = get_connection(&pool).unwrap();
+    connection
+        .execute("SELECT pg_notify($1, 'public.points1')", &[&channel])
+        .unwrap();
+    connection
+        .execute(
+            "SELECT pg_notify($1, '{\"source_id\": \"public.points2\"}')",
+            &[&channel],
+        )
+        .unwrap();
+
+    actix_rt::time::delay_for(Duration::from_millis(500)).await;
+
+    assert_eq!(
+        *source_ids.lock().unwrap(),
+        vec!["public.points1", "public.points2"]
+    );
+
+    assert!(tile_cache.get(&key).unwrap().is_none());
+}
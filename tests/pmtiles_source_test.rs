@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::{self, Future};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::task::Poll;
+
+use actix_web::dev::{BodySize, MessageBody, Service};
+use actix_web::{http, test, App};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use martin::dev::mock_state;
+use martin::pmtiles_source::{zxy_to_tile_id, ArchiveCache, PmtilesSource, PmtilesSources};
+use martin::server::router;
+use martin::source::{EmptyTileMode, Source, TileJsonCache, Xyz};
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Builds a single-directory PMTiles v3 archive with gzip'd directory, metadata and tiles.
+/// Gzipped directory of `(tile_id, offset, length, run_length)` entries
+fn build_directory(entries: &[(u64, u64, u64, u64)]) -> Vec<u8> {
+    let mut directory = Vec::new();
+    write_varint(&mut directory, entries.len() as u64);
+    let mut last_tile_id = 0;
+    for (tile_id, _, _, _) in entries {
+        write_varint(&mut directory, tile_id - last_tile_id);
+        last_tile_id = *tile_id;
+    }
+    for (_, _, _, run_length) in entries {
+        write_varint(&mut directory, *run_length);
+    }
+    for (_, _, length, _) in entries {
+        write_varint(&mut directory, *length);
+    }
+    for (_, offset, _, _) in entries {
+        write_varint(&mut directory, offset + 1);
+    }
+    gzip(&directory)
+}
+
+fn build_archive(tiles: &[(u64, &[u8])]) -> Vec<u8> {
+    build_archive_with_leaf(tiles, false)
+}
+
+/// Archive with the tile entries in the root directory, or in a single leaf directory
+fn build_archive_with_leaf(tiles: &[(u64, &[u8])], leaf: bool) -> Vec<u8> {
+    let mut tile_data = Vec::new();
+    let mut entries = Vec::new();
+    for (tile_id, tile) in tiles {
+        let tile = gzip(tile);
+        entries.push((*tile_id, tile_data.len() as u64, tile.len() as u64, 1));
+        tile_data.extend(tile);
+    }
+
+    let (directory, leaf_directory) = if leaf {
+        let leaf_directory = build_directory(&entries);
+        let root = build_directory(&[(entries[0].0, 0, leaf_directory.len() as u64, 0)]);
+        (root, leaf_directory)
+    } else {
+        (build_directory(&entries), Vec::new())
+    };
+    let metadata = gzip(br#"{"name": "Test archive", "attribution": "Falcon"}"#);
+
+    let root_dir_offset = 127_u64;
+    let metadata_offset = root_dir_offset + directory.len() as u64;
+    let leaf_dirs_offset = metadata_offset + metadata.len() as u64;
+    let tile_data_offset = leaf_dirs_offset + leaf_directory.len() as u64;
+
+    let mut header = Vec::with_capacity(127);
+    header.extend(b"PMTiles");
+    header.push(3);
+    for value in &[
+        root_dir_offset,
+        directory.len() as u64,
+        metadata_offset,
+        metadata.len() as u64,
+        leaf_dirs_offset,
+        leaf_directory.len() as u64,
+        tile_data_offset,
+        tile_data.len() as u64,
+        entries.len() as u64,
+        entries.len() as u64,
+        entries.len() as u64,
+    ] {
+        header.extend(&value.to_le_bytes());
+    }
+    // clustered, internal compression, tile compression, tile type, min zoom, max zoom
+    header.extend(&[1, 2, 2, 1, 0, 2]);
+    for value in &[-1_800_000_000_i32, -850_511_287, 1_800_000_000, 850_511_287] {
+        header.extend(&value.to_le_bytes());
+    }
+    header.push(0);
+    header.extend(&0_i32.to_le_bytes());
+    header.extend(&0_i32.to_le_bytes());
+    assert_eq!(header.len(), 127);
+
+    let mut archive = header;
+    archive.extend(directory);
+    archive.extend(metadata);
+    archive.extend(leaf_directory);
+    archive.extend(tile_data);
+    archive
+}
+
+fn write_archive(name: &str, tiles: &[(u64, &[u8])]) -> PathBuf {
+    let path = env::temp_dir().join(format!("martin_{}_{}.pmtiles", name, std::process::id()));
+    fs::write(&path, build_archive(tiles)).unwrap();
+    path
+}
+
+fn mock_pmtiles_source(path: &Path) -> PmtilesSource {
+    PmtilesSource {
+        id: "archive".to_owned(),
+        path: path.to_str().unwrap().to_owned(),
+        max_age: None,
+        mvt_content_type: None,
+        tilejson_cache: TileJsonCache::default(),
+        archive_cache: ArchiveCache::default(),
+    }
+}
+
+#[test]
+fn test_zxy_to_tile_id() {
+    let tile_id = |z, x, y| zxy_to_tile_id(&Xyz { z, x, y });
+
+    assert_eq!(tile_id(0, 0, 0), Some(0));
+    assert_eq!(tile_id(1, 0, 0), Some(1));
+    assert_eq!(tile_id(1, 0, 1), Some(2));
+    assert_eq!(tile_id(1, 1, 1), Some(3));
+    assert_eq!(tile_id(1, 1, 0), Some(4));
+    assert_eq!(tile_id(2, 0, 0), Some(5));
+    assert_eq!(tile_id(12, 3423, 1763), Some(19_078_479));
+    assert_eq!(tile_id(31, 0, 0), Some(((1_u64 << 62) - 1) / 3));
+}
+
+#[test]
+fn test_zxy_to_tile_id_out_of_range() {
+    let tile_id = |z, x, y| zxy_to_tile_id(&Xyz { z, x, y });
+
+    assert_eq!(tile_id(32, 0, 0), None);
+    assert_eq!(tile_id(40, 0, 0), None);
+    assert_eq!(tile_id(-1, 0, 0), None);
+    assert_eq!(tile_id(1, 2, 0), None);
+    assert_eq!(tile_id(1, 0, 2), None);
+    assert_eq!(tile_id(1, -1, 0), None);
+
+    let path = write_archive("out_of_range", &[(0, b"zoom 0")]);
+    let source = mock_pmtiles_source(&path);
+    assert!(source
+        .read_tile(&Xyz { z: 32, x: 0, y: 0 })
+        .unwrap()
+        .is_empty());
+    assert!(source
+        .read_tile(&Xyz { z: 0, x: 1, y: 0 })
+        .unwrap()
+        .is_empty());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_archive_cache() {
+    let path = write_archive("archive_cache", &[(0, b"zoom 0"), (3, b"zoom 1")]);
+    let source = mock_pmtiles_source(&path);
+    assert!(!source.archive_cache.is_opened());
+
+    source.open().unwrap();
+    assert!(source.archive_cache.is_opened());
+
+    // Only the tile data is read on requests, so a replaced header isn't noticed
+    let mut archive = fs::read(&path).unwrap();
+    archive[0..7].copy_from_slice(b"XXXXXXX");
+    fs::write(&path, archive).unwrap();
+
+    let cloned = source.clone();
+    assert_eq!(
+        cloned.read_tile(&Xyz { z: 1, x: 1, y: 1 }).unwrap(),
+        b"zoom 1"
+    );
+    assert!(mock_pmtiles_source(&path).open().is_err());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_leaf_directory_cache() {
+    let path = env::temp_dir().join(format!("martin_leaf_{}.pmtiles", std::process::id()));
+    fs::write(
+        &path,
+        build_archive_with_leaf(&[(1, b"zoom 1"), (3, b"zoom 1 too")], true),
+    )
+    .unwrap();
+
+    let source = mock_pmtiles_source(&path);
+    assert_eq!(
+        source.read_tile(&Xyz { z: 1, x: 0, y: 0 }).unwrap(),
+        b"zoom 1"
+    );
+    assert_eq!(source.archive_cache.cached_leaf_directories(), 1);
+
+    assert_eq!(
+        source.read_tile(&Xyz { z: 1, x: 1, y: 1 }).unwrap(),
+        b"zoom 1 too"
+    );
+    assert!(source
+        .read_tile(&Xyz { z: 0, x: 0, y: 0 })
+        .unwrap()
+        .is_empty());
+    assert_eq!(source.archive_cache.cached_leaf_directories(), 1);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_read_tile() {
+    let path = write_archive("read_tile", &[(0, b"zoom 0"), (3, b"zoom 1")]);
+    let source = mock_pmtiles_source(&path);
+
+    let tile = source.read_tile(&Xyz { z: 0, x: 0, y: 0 }).unwrap();
+    assert_eq!(tile, b"zoom 0");
+
+    let tile = source.read_tile(&Xyz { z: 1, x: 1, y: 1 }).unwrap();
+    assert_eq!(tile, b"zoom 1");
+
+    let tile = source.read_tile(&Xyz { z: 1, x: 0, y: 0 }).unwrap();
+    assert!(tile.is_empty());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_get_tilejson() {
+    let path = write_archive("tilejson", &[(0, b"zoom 0")]);
+    let source = mock_pmtiles_source(&path);
+
+    let tilejson = source.get_tilejson().unwrap();
+    assert_eq!(tilejson.name, Some("Test archive".to_owned()));
+    assert_eq!(tilejson.attribution, Some("Falcon".to_owned()));
+    assert_eq!(tilejson.minzoom, Some(0));
+    assert_eq!(tilejson.maxzoom, Some(2));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_tilejson_cache() {
+    let path = write_archive("tilejson_cache", &[(0, b"zoom 0")]);
+    let source = mock_pmtiles_source(&path);
+    assert!(!source.tilejson_cache.is_built());
+
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert_eq!(tilejson["name"], "Test archive");
+
+    // Clones share the cache, so the archive isn't read again
+    fs::remove_file(path).unwrap();
+    let cloned = source.clone();
+    assert!(cloned.tilejson_cache.is_built());
+    assert_eq!(cloned.get_tilejson_value().unwrap(), tilejson);
+    assert!(source.get_tilejson().is_err());
+}
+
+#[test]
+fn test_invalid_archive() {
+    let path = env::temp_dir().join(format!("martin_invalid_{}.pmtiles", std::process::id()));
+    fs::write(&path, vec![0; 127]).unwrap();
+
+    let source = mock_pmtiles_source(&path);
+    assert!(source.read_tile(&Xyz { z: 0, x: 0, y: 0 }).is_err());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_pmtiles_source_tile_ok() {
+    let path = write_archive("server", &[(0, b"zoom 0")]);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(mock_pmtiles_source(&path)));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/archive.json").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+    assert_eq!(test::read_body(response).await, "zoom 0");
+
+    let req = test::TestRequest::get()
+        .uri("/archive/1/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+
+    // Zooms without PMTiles tile ids are empty rather than overflowing
+    for uri in &[
+        "/archive/32/0/0.pbf",
+        "/archive/40/0/0.pbf",
+        "/archive/1/5/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_catalog_pmtiles_source() {
+    let path = write_archive("catalog", &[(0, b"zoom 0")]);
+
+    let opened = mock_pmtiles_source(&path);
+    opened.open().unwrap();
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("opened".to_owned(), Box::new(opened));
+    pmtiles_sources.insert("unopened".to_owned(), Box::new(mock_pmtiles_source(&path)));
+    pmtiles_sources.get_mut("unopened").unwrap().id = "unopened".to_owned();
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/catalog").to_request();
+    let catalog: Vec<serde_json::Value> = test::read_response_json(&mut app, req).await;
+    assert_eq!(catalog.len(), 2);
+
+    // Zooms and bounds come from the header read on startup
+    assert_eq!(catalog[0]["type"], "pmtiles");
+    assert_eq!(catalog[0]["minzoom"], 0);
+    assert_eq!(catalog[0]["maxzoom"], 2);
+    assert_eq!(catalog[0]["bounds"][0], -180.0);
+    assert_eq!(catalog[0]["bounds"][2], 180.0);
+
+    assert_eq!(catalog[1]["id"], "unopened");
+    assert_eq!(catalog[1]["maxzoom"], serde_json::Value::Null);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_shutdown_completes_in_flight_tile() {
+    let path = write_archive("shutdown", &[(0, b"zoom 0")]);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(mock_pmtiles_source(&path)));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let shutting_down = state.shutting_down.clone();
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Poll the request once so it is accepted before the shutdown starts
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let mut in_flight = Box::pin(app.call(req));
+    let polled = future::poll_fn(|cx| Poll::Ready(in_flight.as_mut().poll(cx))).await;
+
+    shutting_down.store(true, Ordering::SeqCst);
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = match polled {
+        Poll::Ready(response) => response,
+        Poll::Pending => in_flight.await,
+    }
+    .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(test::read_body(response).await, "zoom 0");
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_pmtiles_source_tile_max_age() {
+    let path = write_archive("max_age", &[(0, b"zoom 0")]);
+
+    let mut source = mock_pmtiles_source(&path);
+    source.max_age = Some(86400);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(source));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Empty tiles get the same cache directives
+    for uri in &["/archive/0/0/0.pbf", "/archive/1/0/0.pbf"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(
+            response.headers().get(http::header::CACHE_CONTROL).unwrap(),
+            "public, max-age=86400"
+        );
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_pmtiles_source_tile_content_type() {
+    let path = write_archive("content_type", &[(0, b"zoom 0")]);
+
+    let mut source = mock_pmtiles_source(&path);
+    source.mvt_content_type = Some("application/vnd.mapbox-vector-tile".to_owned());
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(source));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+        "application/vnd.mapbox-vector-tile"
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_pmtiles_source_empty_tile_mode() {
+    let path = write_archive("empty_tile_mode", &[(0, b"zoom 0")]);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(mock_pmtiles_source(&path)));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    state.empty_tile_mode = EmptyTileMode::EmptyMvt;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/archive/1/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert!(response.headers().get(http::header::ETAG).is_none());
+
+    let body = test::read_body(response).await;
+    assert!(body.is_empty());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_head_pmtiles_source_tile() {
+    let path = write_archive("head", &[(0, b"zoom 0")]);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(mock_pmtiles_source(&path)));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let get_response = test::call_service(&mut app, req).await;
+
+    let req = test::TestRequest::default()
+        .method(http::Method::HEAD)
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let head_response = test::call_service(&mut app, req).await;
+
+    assert_eq!(head_response.status(), http::StatusCode::OK);
+    for header in &[
+        http::header::CONTENT_TYPE,
+        http::header::CACHE_CONTROL,
+        http::header::ETAG,
+    ] {
+        assert_eq!(
+            head_response.headers().get(header).unwrap(),
+            get_response.headers().get(header).unwrap()
+        );
+    }
+
+    // The server writes Content-Length from the body size and skips the body itself
+    assert_eq!(head_response.response().body().size(), BodySize::Sized(6));
+
+    let req = test::TestRequest::default()
+        .method(http::Method::HEAD)
+        .uri("/archive/1/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    assert!(!response.headers().contains_key(http::header::ETAG));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn test_get_pmtiles_source_tile_range() {
+    let path = write_archive("range", &[(0, b"zoom 0")]);
+
+    let mut pmtiles_sources: PmtilesSources = HashMap::new();
+    pmtiles_sources.insert("archive".to_owned(), Box::new(mock_pmtiles_source(&path)));
+
+    let mut state = mock_state(None, None, false);
+    state.pmtiles_sources = Some(pmtiles_sources);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(
+        response.headers().get(http::header::ACCEPT_RANGES).unwrap(),
+        "bytes"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .header(http::header::RANGE, "bytes=2-")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_RANGE).unwrap(),
+        "bytes 2-5/6"
+    );
+    assert_eq!(test::read_body(response).await, "om 0");
+
+    let req = test::TestRequest::get()
+        .uri("/archive/0/0/0.pbf")
+        .header(http::header::RANGE, "bytes=6-")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_RANGE).unwrap(),
+        "bytes */6"
+    );
+
+    fs::remove_file(path).unwrap();
+}
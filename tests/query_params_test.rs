@@ -0,0 +1,70 @@
+use martin::query_params::{parse_query_params, QueryParamError};
+
+fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_parse_query_params() {
+    assert_eq!(parse_query_params("").unwrap(), params(&[]));
+    assert_eq!(
+        parse_query_params("token=martin&name=a+b%20c&empty&&flag=").unwrap(),
+        params(&[
+            ("token", "martin"),
+            ("name", "a b c"),
+            ("empty", ""),
+            ("flag", ""),
+        ])
+    );
+
+    // Repeated parameters are kept in request order
+    assert_eq!(
+        parse_query_params("id=2&id=1&filter=json:%7B%22min%22%3A1%7D").unwrap(),
+        params(&[("id", "2"), ("id", "1"), ("filter", "json:{\"min\":1}")])
+    );
+
+    assert_eq!(
+        parse_query_params("city=M%C3%BCnchen").unwrap(),
+        params(&[("city", "München")])
+    );
+}
+
+#[test]
+fn test_parse_invalid_query_params() {
+    let error = parse_query_params("token=martin&name=a%zz").unwrap_err();
+    assert_eq!(
+        error,
+        QueryParamError {
+            parameter: "name".to_owned(),
+            message: "value has a malformed percent-encoding at position 1".to_owned(),
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "Query parameter 'name' value has a malformed percent-encoding at position 1"
+    );
+
+    for query in &["name=%", "name=%2", "name=%+1", "name=abc%"] {
+        let error = parse_query_params(query).unwrap_err();
+        assert_eq!(error.parameter, "name");
+        assert!(error.message.contains("malformed percent-encoding"));
+    }
+
+    let error = parse_query_params("name=%FF").unwrap_err();
+    assert_eq!(error.parameter, "name");
+    assert_eq!(
+        error.message,
+        "value is not valid UTF-8 when percent-decoded"
+    );
+
+    // Names that can't be decoded are reported as sent
+    let error = parse_query_params("na%C3me=1").unwrap_err();
+    assert_eq!(error.parameter, "na%C3me");
+    assert_eq!(
+        error.message,
+        "name is not valid UTF-8 when percent-decoded"
+    );
+}
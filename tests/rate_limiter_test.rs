@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use martin::rate_limiter::{RateLimit, RateLimiter, MAX_TRACKED_CLIENTS};
+
+fn rate_limiter(requests_per_second: f64, burst: Option<u32>) -> RateLimiter {
+    RateLimiter::new(&RateLimit {
+        requests_per_second,
+        burst,
+    })
+}
+
+#[test]
+fn test_rate_limiter_burst() {
+    let rate_limiter = rate_limiter(1.0, Some(3));
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    }
+
+    let wait = rate_limiter.check_at("10.0.0.1", now).unwrap_err();
+    assert_eq!(wait, Duration::from_secs(1));
+
+    // Other clients have their own buckets
+    assert!(rate_limiter.check_at("10.0.0.2", now).is_ok());
+}
+
+#[test]
+fn test_rate_limiter_refill() {
+    let rate_limiter = rate_limiter(2.0, None);
+    let now = Instant::now();
+
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_err());
+
+    let now = now + Duration::from_millis(500);
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_err());
+
+    // Tokens don't accumulate over the burst
+    let now = now + Duration::from_secs(60);
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_err());
+}
+
+#[test]
+fn test_rate_limiter_fractional_rate() {
+    let rate_limiter = rate_limiter(0.5, None);
+    let now = Instant::now();
+
+    assert!(rate_limiter.check_at("10.0.0.1", now).is_ok());
+
+    let wait = rate_limiter.check_at("10.0.0.1", now).unwrap_err();
+    assert_eq!(wait, Duration::from_secs(2));
+}
+
+#[test]
+fn test_rate_limiter_max_tracked_clients() {
+    let rate_limiter = rate_limiter(0.001, Some(1));
+    let now = Instant::now();
+
+    // New clients keep coming while the tracked ones are still draining their buckets
+    for client in 0..3 * MAX_TRACKED_CLIENTS {
+        let now = now + Duration::from_millis(client as u64);
+        assert!(rate_limiter.check_at(&client.to_string(), now).is_ok());
+        assert!(rate_limiter.tracked_clients() <= MAX_TRACKED_CLIENTS);
+    }
+
+    // The least recently updated buckets were dropped, recent clients are still limited
+    let now = now + Duration::from_millis(3 * MAX_TRACKED_CLIENTS as u64);
+    let last_client = (3 * MAX_TRACKED_CLIENTS - 1).to_string();
+    assert!(rate_limiter.check_at(&last_client, now).is_err());
+    assert!(rate_limiter.check_at("0", now).is_ok());
+}
@@ -0,0 +1,66 @@
+use actix_web::http::header::HeaderValue;
+use actix_web::{test, App};
+
+use martin::dev::mock_state;
+use martin::request_id::{RequestId, RequestIdMiddleware, MAX_REQUEST_ID_LENGTH};
+use martin::server::router;
+
+#[test]
+fn test_request_id_from_header() {
+    let header = HeaderValue::from_static("nginx-42");
+    assert_eq!(
+        RequestId::from_header(Some(&header)),
+        RequestId("nginx-42".to_owned())
+    );
+
+    // Missing, empty, too long and whitespace containing ids are replaced with a UUID
+    let too_long = "a".repeat(MAX_REQUEST_ID_LENGTH + 1);
+    for header in &[
+        None,
+        Some(HeaderValue::from_static("")),
+        Some(HeaderValue::from_str(&too_long).unwrap()),
+        Some(HeaderValue::from_static("nginx 42")),
+    ] {
+        let RequestId(request_id) = RequestId::from_header(header.as_ref());
+        assert_eq!(request_id.len(), 36);
+        assert!(uuid::Uuid::parse_str(&request_id).is_ok());
+    }
+}
+
+#[actix_rt::test]
+async fn test_request_id_header() {
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(
+        App::new()
+            .data(state)
+            .wrap(RequestIdMiddleware)
+            .configure(router),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .header("X-Request-Id", "nginx-42")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.headers().get("X-Request-Id").unwrap(), "nginx-42");
+
+    // Every request without an id gets a new one
+    let mut request_ids = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/").to_request();
+        let response = test::call_service(&mut app, req).await;
+        let request_id = response.headers().get("X-Request-Id").unwrap();
+        request_ids.push(request_id.to_str().unwrap().to_owned());
+    }
+    assert_ne!(request_ids[0], request_ids[1]);
+
+    // Error responses have the id as well
+    let req = test::TestRequest::get()
+        .uri("/unknown.json")
+        .header("X-Request-Id", "nginx-43")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_client_error());
+    assert_eq!(response.headers().get("X-Request-Id").unwrap(), "nginx-43");
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use martin::request_log::{LogFormat, TileRequestLog};
+use martin::source::Xyz;
+
+#[test]
+fn test_tile_request_log_format() {
+    let log = TileRequestLog::new("public.table_source", &Xyz { z: 1, x: 2, y: 3 }, 42)
+        .db_duration(Duration::from_micros(1500));
+
+    assert_eq!(
+        log.format(LogFormat::Text),
+        "source_id=public.table_source z=1 x=2 y=3 bytes=42 empty=false cache=none db_duration_ms=1.500"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&log.format(LogFormat::Json)).unwrap();
+    assert_eq!(json["source_id"], "public.table_source");
+    assert_eq!(json["z"], 1);
+    assert_eq!(json["x"], 2);
+    assert_eq!(json["y"], 3);
+    assert_eq!(json["bytes"], 42);
+    assert_eq!(json["empty"], false);
+    assert_eq!(json["db_duration_ms"], 1.5);
+
+    let log = TileRequestLog::new("__grid", &Xyz { z: 0, x: 0, y: 0 }, 0);
+    assert!(log
+        .format(LogFormat::Text)
+        .ends_with("empty=true cache=none db_duration_ms=-"));
+
+    let json: serde_json::Value = serde_json::from_str(&log.format(LogFormat::Json)).unwrap();
+    assert!(json["db_duration_ms"].is_null());
+    assert!(json.get("request_id").is_none());
+
+    let log = log.request_id(Some("nginx-42".to_owned()));
+    assert!(log
+        .format(LogFormat::Text)
+        .ends_with("db_duration_ms=- request_id=nginx-42"));
+
+    let json: serde_json::Value = serde_json::from_str(&log.format(LogFormat::Json)).unwrap();
+    assert_eq!(json["request_id"], "nginx-42");
+}
@@ -0,0 +1,114 @@
+use std::env;
+use std::fs;
+use std::io;
+
+use tilejson::{TileJSON, TileJSONBuilder};
+
+use martin::db::{setup_connection_pool, Connection};
+use martin::seed::{count_tiles, parse_bbox, seed, tile_range, tiles, SeedOptions, SeedStats};
+use martin::source::{Query, Source, Tile, Xyz};
+use martin::utils::WORLD_BOUNDS;
+
+/// Returns the tile coordinates as the tile, tiles of odd columns are empty
+#[derive(Debug)]
+struct CoordinatesSource;
+
+impl Source for CoordinatesSource {
+    fn get_id(&self) -> &str {
+        "coordinates"
+    }
+
+    fn get_tilejson(&self) -> Result<TileJSON, io::Error> {
+        Ok(TileJSONBuilder::new().finalize())
+    }
+
+    fn get_tile(
+        &self,
+        _conn: &mut Connection,
+        xyz: &Xyz,
+        _query: &Option<Query>,
+    ) -> Result<Tile, io::Error> {
+        if xyz.x % 2 == 1 {
+            return Ok(Vec::new());
+        }
+
+        Ok(format!("{}/{}/{}", xyz.z, xyz.x, xyz.y).into_bytes())
+    }
+}
+
+#[test]
+fn test_parse_bbox() {
+    assert_eq!(
+        parse_bbox("-10, 40.5, 10, 50").unwrap(),
+        [-10.0, 40.5, 10.0, 50.0]
+    );
+
+    // Clamped to the web mercator world bounds
+    let world = WORLD_BOUNDS.map(f64::from);
+    assert_eq!(parse_bbox("-200,-90,200,90").unwrap(), world);
+
+    for bbox in &[
+        "",
+        "1,2,3",
+        "1,2,3,4,5",
+        "a,b,c,d",
+        "10,0,-10,10",
+        "0,10,10,0",
+    ] {
+        let error = parse_bbox(bbox).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_tile_range() {
+    let world = WORLD_BOUNDS.map(f64::from);
+    assert_eq!(tile_range(&world, 0), (0, 0, 0, 0));
+    assert_eq!(tile_range(&world, 2), (0, 0, 3, 3));
+
+    // North-east quarter of the world
+    let bbox = [1.0, 1.0, 179.0, 85.0];
+    assert_eq!(tile_range(&bbox, 1), (1, 0, 1, 0));
+    assert_eq!(tile_range(&bbox, 2), (2, 0, 3, 1));
+
+    assert_eq!(count_tiles(&world, 0, 2), 1 + 4 + 16);
+    assert_eq!(count_tiles(&bbox, 0, 2), 1 + 1 + 4);
+
+    let pyramid: Vec<(i32, i32, i32)> =
+        tiles(bbox, 1, 2).map(|xyz| (xyz.z, xyz.x, xyz.y)).collect();
+    assert_eq!(
+        pyramid,
+        vec![(1, 1, 0), (2, 2, 0), (2, 2, 1), (2, 3, 0), (2, 3, 1)]
+    );
+}
+
+#[test]
+fn test_seed() {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(2), None, false, true, None, None).unwrap();
+
+    let output = env::temp_dir().join(format!("martin_seed_{}", std::process::id()));
+    let options = SeedOptions {
+        minzoom: 0,
+        maxzoom: 2,
+        bbox: WORLD_BOUNDS.map(f64::from),
+        output: output.clone(),
+        concurrency: 2,
+    };
+
+    let stats = seed(&CoordinatesSource, &pool, &options).unwrap();
+    assert_eq!(
+        stats,
+        SeedStats {
+            tiles: 21,
+            empty_tiles: 10,
+            bytes: 5 + 2 * 5 + 8 * 5,
+        }
+    );
+
+    assert_eq!(fs::read(output.join("2/2/3.pbf")).unwrap(), b"2/2/3");
+    assert!(!output.join("2/1/3.pbf").exists());
+
+    fs::remove_dir_all(output).unwrap();
+}
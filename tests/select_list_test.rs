@@ -0,0 +1,54 @@
+use martin::select_list::SelectList;
+
+fn assert_valid(select_list: &str) {
+    assert!(!select_list.starts_with(','));
+    assert!(!select_list.ends_with(','));
+    assert!(!select_list.contains(", ,"));
+    assert!(!select_list.contains(",,"));
+}
+
+#[test]
+fn test_no_properties() {
+    let select_list = SelectList::new().expression("geom").build();
+    assert_eq!(select_list, "geom");
+
+    let select_list = SelectList::new().expression("").column("").build();
+    assert!(select_list.is_empty());
+}
+
+#[test]
+fn test_only_computed() {
+    let select_list = SelectList::new()
+        .computed("ST_Area(geom)", "area")
+        .computed(" ", "ignored")
+        .build();
+
+    assert_valid(&select_list);
+    assert_eq!(select_list, "ST_Area(geom) AS \"area\"");
+}
+
+#[test]
+fn test_only_id() {
+    let select_list = SelectList::new().column("gid").build();
+
+    assert_valid(&select_list);
+    assert_eq!(select_list, "\"gid\"");
+}
+
+#[test]
+fn test_all_combined() {
+    let select_list = SelectList::new()
+        .expression("geom")
+        .column("gid")
+        .column("name")
+        .column("")
+        .column("weird \"column\"")
+        .computed("ST_Area(geom)", "area")
+        .build();
+
+    assert_valid(&select_list);
+    assert_eq!(
+        select_list,
+        "geom, \"gid\", \"name\", \"weird \"\"column\"\"\", ST_Area(geom) AS \"area\""
+    );
+}
@@ -1,16 +1,46 @@
 extern crate log;
 
+use std::env;
+use std::io::Read;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::SyncArbiter;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::ServiceResponse;
 use actix_web::{http, test, App};
 
+use flate2::read::GzDecoder;
+use serde_json::json;
+
+use martin::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use martin::config::MAX_WORKER_PROCESSES_PER_CPU;
+use martin::db::setup_connection_pool;
+use martin::db_actor::{DbActor, MAX_RETRY_BACKOFF};
+use martin::db_queue::DbQueue;
 use martin::dev::{mock_function_sources, mock_state, mock_table_sources};
-use martin::function_source::FunctionSources;
-use martin::server::router;
-use martin::table_source::TableSources;
+use martin::function_source::{FunctionSources, DEFAULT_MAX_PARAM_LENGTH};
+use martin::rate_limiter::{RateLimit, RateLimiter};
+use martin::server::{configure_router, explain_command, resolve_worker_processes, router};
+use martin::source::{Scheme, Xyz};
+use martin::static_files::{self, StaticFiles};
+use martin::table_source::{apply_public_ids, TableSources};
+use martin::tile_cache::{MemoryCache, TileCache, TileCacheKey};
 
 fn init() {
     let _ = env_logger::builder().is_test(true).try_init();
 }
 
+fn vary(response: &ServiceResponse) -> Vec<&str> {
+    response
+        .headers()
+        .get_all(http::header::VARY)
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
 #[actix_rt::test]
 async fn test_get_table_sources_ok() {
     init();
@@ -63,6 +93,10 @@ async fn test_get_table_source_ok() {
 
     let response = test::call_service(&mut app, req).await;
     assert!(response.status().is_success());
+
+    let tilejson: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(tilejson["falcon:srid"], 3857);
+    assert_eq!(tilejson["falcon:extent"], 4096);
 }
 
 #[actix_rt::test]
@@ -200,6 +234,64 @@ async fn test_get_function_source_tile_ok() {
     assert!(response.status().is_success());
 }
 
+#[actix_rt::test]
+async fn test_get_function_source_tile_outside_zoom_range() {
+    init();
+
+    let mut function_sources = mock_function_sources().unwrap();
+    let source = function_sources.get_mut("public.function_source").unwrap();
+    source.minzoom = Some(2);
+    source.maxzoom = Some(4);
+
+    let state = mock_state(None, Some(function_sources), false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/rpc/public.function_source/1/0/0.pbf")
+        .to_request();
+
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+
+    let req = test::TestRequest::get()
+        .uri("/rpc/public.function_source/5/0/0.pbf")
+        .to_request();
+
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+
+    let req = test::TestRequest::get()
+        .uri("/rpc/public.function_source.json")
+        .to_request();
+
+    let tilejson: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(tilejson["minzoom"], 2);
+    assert_eq!(tilejson["maxzoom"], 4);
+}
+
+#[actix_rt::test]
+async fn test_get_function_source_tile_outside_tile_grid() {
+    init();
+
+    let mut function_sources = mock_function_sources().unwrap();
+    let source = function_sources.get_mut("public.function_source").unwrap();
+    source.maxzoom = Some(4);
+
+    let mut state = mock_state(None, Some(function_sources), false);
+    state.overzoom = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for uri in &[
+        "/rpc/public.function_source/50/0/0.pbf",
+        "/rpc/public.function_source/31/0/0.pbf",
+        "/rpc/public.function_source/2/4/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT, "{}", uri);
+    }
+}
+
 #[actix_rt::test]
 async fn test_get_function_source_query_params_ok() {
     init();
@@ -223,6 +315,67 @@ async fn test_get_function_source_query_params_ok() {
     assert!(response.status().is_success());
 }
 
+#[actix_rt::test]
+async fn test_get_function_source_invalid_query_params() {
+    init();
+
+    let state = mock_state(None, mock_function_sources(), false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Rejected before the function is called
+    let long_value = "a".repeat(DEFAULT_MAX_PARAM_LENGTH + 1);
+    for query in &[
+        "token=martin%3B",
+        "token=%60martin%60",
+        "token=martin&filter=json:%7Bmin%7D",
+        &format!("token={}", long_value),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/rpc/public.function_source_query_params/0/0/0.pbf?{}",
+                query
+            ))
+            .to_request();
+
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_function_source_malformed_query_params() {
+    init();
+
+    let state = mock_state(None, mock_function_sources(), false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for (query, error) in &[
+        (
+            "token=mar%tin",
+            "Query parameter 'token' value has a malformed percent-encoding at position 3",
+        ),
+        (
+            "token=%FF",
+            "Query parameter 'token' value is not valid UTF-8 when percent-decoded",
+        ),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/rpc/public.function_source_query_params/0/0/0.pbf?{}",
+                query
+            ))
+            .to_request();
+
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "invalid_params");
+        assert_eq!(body["parameter"], "token");
+        assert_eq!(body["error"], *error);
+    }
+}
+
 #[actix_rt::test]
 async fn test_get_health_returns_ok() {
     init();
@@ -234,3 +387,1277 @@ async fn test_get_health_returns_ok() {
     let response = test::call_service(&mut app, req).await;
     assert!(response.status().is_success());
 }
+
+#[actix_rt::test]
+async fn test_get_health_circuit_open() {
+    init();
+
+    let circuit_breaker = CircuitBreaker::new(&CircuitBreakerConfig {
+        failure_threshold: Some(1),
+        cooldown_ms: Some(60_000),
+    });
+    let mut state = mock_state(None, None, false);
+    state.circuit_breaker = Some(circuit_breaker.clone());
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    circuit_breaker.record(true);
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    let body = test::read_body(response).await;
+    assert_eq!(body, "Database circuit breaker is open");
+
+    circuit_breaker.record(false);
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_content_type() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+        "application/x-protobuf"
+    );
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    state.mvt_content_type = "application/vnd.mapbox-vector-tile".to_owned();
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+        "application/vnd.mapbox-vector-tile"
+    );
+}
+
+#[actix_rt::test]
+async fn test_get_catalog_ok() {
+    init();
+
+    let state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/catalog").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let body = test::read_body(response).await;
+    let catalog: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(catalog.len(), 6);
+
+    let function_source = catalog
+        .iter()
+        .find(|entry| entry["id"] == "public.function_source")
+        .unwrap();
+    assert_eq!(function_source["type"], "function");
+    assert!(function_source["tilejson"]
+        .as_str()
+        .unwrap()
+        .ends_with("/rpc/public.function_source.json"));
+
+    let table_source = catalog
+        .iter()
+        .find(|entry| entry["id"] == "public.table_source")
+        .unwrap();
+    assert_eq!(table_source["type"], "table");
+    assert_eq!(table_source["srid"], 3857);
+    assert_eq!(table_source["spatial_index"], false);
+    assert_eq!(table_source["minzoom"], 0);
+    assert_eq!(table_source["maxzoom"], 30);
+    assert!(table_source["tilejson"]
+        .as_str()
+        .unwrap()
+        .ends_with("/public.table_source.json"));
+
+    // All table sources combined into a single composite source
+    let composite_source = &catalog[0];
+    assert_eq!(composite_source["type"], "composite");
+    assert_eq!(
+        composite_source["id"],
+        "public.points1,public.points2,public.table_source"
+    );
+    assert_eq!(composite_source["srid"], 3857);
+    assert_eq!(composite_source["bounds"], serde_json::Value::Null);
+    assert!(composite_source["tilejson"]
+        .as_str()
+        .unwrap()
+        .ends_with("/public.points1,public.points2,public.table_source.json"));
+}
+
+#[actix_rt::test]
+async fn test_get_catalog_zooms_and_bounds() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    for (source, bounds) in table_sources.values_mut().zip(&[
+        [13.0, 52.0, 14.0, 53.0],
+        [-10.0, 40.0, 0.0, 50.0],
+        [0.0, 0.0, 1.0, 1.0],
+    ]) {
+        source.bounds = Some(bounds.to_vec());
+    }
+
+    let mut state = mock_state(Some(table_sources), None, false);
+    state.min_allowed_zoom = Some(4);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/catalog").to_request();
+    let catalog: Vec<serde_json::Value> = test::read_response_json(&mut app, req).await;
+
+    let composite_source = &catalog[0];
+    assert_eq!(composite_source["type"], "composite");
+    assert_eq!(composite_source["minzoom"], 4);
+    assert_eq!(
+        composite_source["bounds"],
+        serde_json::json!([-10.0, 0.0, 14.0, 53.0])
+    );
+    assert!(catalog[1..]
+        .iter()
+        .all(|entry| entry["type"] == "table" && entry["minzoom"] == 4));
+}
+
+#[test]
+fn test_resolve_worker_processes() {
+    assert_eq!(resolve_worker_processes(0), num_cpus::get());
+    assert_eq!(resolve_worker_processes(1), 1);
+    assert_eq!(
+        resolve_worker_processes(16),
+        16.min(num_cpus::get() * MAX_WORKER_PROCESSES_PER_CPU)
+    );
+}
+
+#[test]
+fn test_explain_command() {
+    let xyz = Xyz { z: 3, x: 1, y: 2 };
+    assert_eq!(
+        explain_command("public.points1,public.points2", &xyz),
+        "martin explain --analyze public.points1,public.points2 3 1 2"
+    );
+    assert_eq!(
+        explain_command("public.my points", &xyz),
+        "martin explain --analyze 'public.my points' 3 1 2"
+    );
+    assert_eq!(
+        explain_command("public.it's", &xyz),
+        "martin explain --analyze 'public.it'\\''s' 3 1 2"
+    );
+}
+
+#[actix_rt::test]
+async fn test_get_tile_formats() {
+    init();
+
+    let mut state = mock_state(None, mock_function_sources(), false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for uri in &[
+        "/__grid/0/0/0.pbf",
+        "/__grid/0/0/0.mvt",
+        "/__grid/0/0/0.vector.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert!(response.status().is_success());
+    }
+
+    for uri in &["/__grid/0/0/0.png", "/rpc/public.function_source/0/0/0.png"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_table_source_tile_param_overrides() {
+    init();
+
+    let mut state = mock_state(mock_table_sources(), None, false);
+    state.allow_tile_param_overrides = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.pbf?extent=1000")
+        .to_request();
+
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.pbf?extent=256&buffer=512")
+        .to_request();
+
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_get_tile_shutting_down() {
+    init();
+
+    let mut state = mock_state(None, mock_function_sources(), false);
+    state.debug_grid = true;
+    state.shutdown_timeout = 30;
+    state.shutting_down.store(true, Ordering::SeqCst);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for uri in &["/__grid/0/0/0.pbf", "/rpc/public.function_source/0/0/0.pbf"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(http::header::RETRY_AFTER).unwrap(),
+            "30"
+        );
+        assert!(!response.response().keep_alive());
+    }
+
+    // Non-tile routes keep working while in-flight requests finish
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_rate_limited() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    state.rate_limiter = Some(RateLimiter::new(&RateLimit {
+        requests_per_second: 0.1,
+        burst: Some(2),
+    }));
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let peer_addr = "10.0.0.1:4000".parse().unwrap();
+    for _ in 0..2 {
+        let req = test::TestRequest::get()
+            .uri("/__grid/0/0/0.pbf")
+            .peer_addr(peer_addr)
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert!(response.status().is_success());
+    }
+
+    // The bucket is keyed by the IP address, not the port
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .peer_addr("10.0.0.1:4001".parse().unwrap())
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers().get(http::header::RETRY_AFTER).unwrap(),
+        "10"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .peer_addr("10.0.0.2:4000".parse().unwrap())
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_cache_control() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get(http::header::CACHE_CONTROL).unwrap(),
+        "public, max-age=3600"
+    );
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    state.max_age = 60;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get(http::header::CACHE_CONTROL).unwrap(),
+        "public, max-age=60"
+    );
+}
+
+#[actix_rt::test]
+async fn test_tile_db_round_robin() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    assert!(state.tile_db() == &state.db);
+
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, true, None, None).unwrap();
+    let start_replica = || {
+        let pool = pool.clone();
+        SyncArbiter::start(1, move || DbActor {
+            pool: pool.clone(),
+            retries: 0,
+            retry_backoff: Duration::from_millis(100),
+            max_retry_sleep: MAX_RETRY_BACKOFF,
+            slow_query: None,
+            circuit_breaker: None,
+        })
+    };
+
+    state.replicas = vec![start_replica(), start_replica()];
+
+    let first = state.tile_db().clone();
+    let second = state.tile_db().clone();
+    assert!(first != second);
+    assert!(first != state.db && second != state.db);
+    assert!(state.tile_db() == &first);
+    assert!(state.tile_db() == &second);
+}
+
+#[actix_rt::test]
+async fn test_json_errors() {
+    init();
+
+    let table_sources = mock_table_sources();
+    let mut app = test::init_service(
+        App::new()
+            .data(mock_state(table_sources, None, false))
+            .configure(router),
+    )
+    .await;
+
+    let cases = [
+        (
+            "/public.non_existant/0/0/0.pbf",
+            http::StatusCode::NOT_FOUND,
+            "source_not_found",
+        ),
+        (
+            "/rpc/public.non_existant/0/0/0.pbf",
+            http::StatusCode::NOT_FOUND,
+            "source_not_found",
+        ),
+        (
+            "/public.table_source/0/0/0.png",
+            http::StatusCode::NOT_ACCEPTABLE,
+            "unsupported_format",
+        ),
+    ];
+
+    for (uri, status, code) in &cases {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), *status);
+        assert_eq!(
+            response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], *code);
+        assert!(body["error"].is_string());
+    }
+}
+
+#[actix_rt::test]
+async fn test_source_not_found_lists_sources() {
+    init();
+
+    let mut state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    state.list_sources_on_not_found = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let table_source_ids = json!(["public.points1", "public.points2", "public.table_source"]);
+    let function_source_ids = json!([
+        "public.function_source",
+        "public.function_source_query_params"
+    ]);
+    let cases = [
+        ("/public.table_sorce.json", &table_source_ids),
+        ("/public.table_sorce/0/0/0.pbf", &table_source_ids),
+        ("/public.table_sorce/feature/1.json", &table_source_ids),
+        ("/rpc/public.function_sorce.json", &function_source_ids),
+        ("/rpc/public.function_sorce/0/0/0.pbf", &function_source_ids),
+    ];
+
+    for (uri, available_sources) in &cases {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND, "{}", uri);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "source_not_found");
+        assert_eq!(&body["available_sources"], *available_sources, "{}", uri);
+    }
+
+    // Sources aren't listed by default
+    let state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_sorce.json")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "source_not_found");
+    assert!(body.get("available_sources").is_none());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_request_timeout() {
+    init();
+
+    let table_sources = mock_table_sources();
+    let mut state = mock_state(table_sources, None, false);
+    state.request_timeout_ms = 50;
+    // No permits, so the request waits for a database connection forever
+    state.db_queue = DbQueue::new(0);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::GATEWAY_TIMEOUT);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "timeout");
+}
+
+#[actix_rt::test]
+async fn test_get_geojson_tile_format() {
+    init();
+
+    let mut state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.geojson")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+
+    let mut state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    state.debug_grid = true;
+    state.geojson_tiles = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Only table sources can be served as GeoJSON
+    for uri in &[
+        "/__grid/0/0/0.geojson",
+        "/rpc/public.function_source/0/0/0.geojson",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+    }
+}
+
+#[actix_rt::test]
+async fn test_tile_format_negotiation() {
+    init();
+
+    let mut state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    state.debug_grid = true;
+    state.geojson_tiles = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let get = |uri: &str, accept: Option<&str>| {
+        let req = test::TestRequest::get().uri(uri);
+        match accept {
+            Some(accept) => req.header(http::header::ACCEPT, accept).to_request(),
+            None => req.to_request(),
+        }
+    };
+
+    for accept in &[
+        None,
+        Some("application/vnd.mapbox-vector-tile"),
+        Some("application/json;q=0.5, application/x-protobuf"),
+        Some("*/*"),
+    ] {
+        let response = test::call_service(&mut app, get("/__grid/0/0/0", *accept)).await;
+        assert!(response.status().is_success(), "{:?}", accept);
+        assert!(vary(&response).contains(&"Accept"));
+    }
+
+    // GeoJSON is negotiated, but the grid source can't be served as GeoJSON
+    let response =
+        test::call_service(&mut app, get("/__grid/0/0/0", Some("application/geo+json"))).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+
+    let response = test::call_service(&mut app, get("/__grid/0/0/0", Some("image/png"))).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+
+    let response = test::call_service(
+        &mut app,
+        get(
+            "/__grid/0/0/0",
+            Some("application/x-protobuf;q=0, text/html"),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), http::StatusCode::NOT_ACCEPTABLE);
+
+    // The extension wins over Accept
+    let response = test::call_service(
+        &mut app,
+        get("/__grid/0/0/0.pbf", Some("application/geo+json")),
+    )
+    .await;
+    assert!(response.status().is_success());
+    assert!(!vary(&response).contains(&"Accept"));
+}
+
+#[actix_rt::test]
+async fn test_index_and_favicon() {
+    init();
+
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let index: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(index["name"], "martin");
+    assert_eq!(index["version"], env!("CARGO_PKG_VERSION"));
+    assert!(index["links"]["table_sources"]
+        .as_str()
+        .unwrap()
+        .ends_with("/index.json"));
+    assert!(index["links"]["function_sources"]
+        .as_str()
+        .unwrap()
+        .ends_with("/rpc/index.json"));
+    assert!(index["links"]["health"]
+        .as_str()
+        .unwrap()
+        .ends_with("/healthz"));
+
+    let req = test::TestRequest::get().uri("/favicon.ico").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+}
+
+#[actix_rt::test]
+async fn test_get_version() {
+    init();
+
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/version").to_request();
+    let version: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(version["name"], "martin");
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!version["git_commit"].as_str().unwrap().is_empty());
+
+    // RFC 3339 UTC, e.g. 2021-06-01T12:00:00Z
+    let build_timestamp = version["build_timestamp"].as_str().unwrap();
+    assert_eq!(build_timestamp.len(), 20);
+    assert_eq!(&build_timestamp[10..11], "T");
+    assert!(build_timestamp.ends_with('Z'));
+}
+
+#[actix_rt::test]
+async fn test_static_files() {
+    init();
+
+    let static_files = vec![StaticFiles {
+        mount_path: "/styles/".to_owned(),
+        directory: "tests/fixtures/static/styles".to_owned(),
+    }];
+
+    let state = mock_state(mock_table_sources(), None, false);
+    let mut app = test::init_service(
+        App::new()
+            .data(state)
+            .configure(|cfg| static_files::configure(cfg, &static_files))
+            .configure(router),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/styles/basic.json")
+        .to_request();
+    let style: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(style["name"], "Basic");
+
+    // Paths under the mount point never reach the tile routes
+    for uri in &["/styles/missing.json", "/styles/public.table_source/0/0/0"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+    }
+}
+
+#[actix_rt::test]
+async fn test_public_base_url() {
+    init();
+
+    let mut state = mock_state(None, mock_function_sources(), false);
+    state.public_base_url = Some("https://example.com/tiles/".to_owned());
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/rpc/public.function_source.json?token=secret")
+        .header("x-rewrite-url", "/tiles/rpc/public.function_source.json")
+        .to_request();
+
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let tilejson: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(
+        tilejson["tiles"][0],
+        "https://example.com/tiles/rpc/public.function_source/{z}/{x}/{y}.pbf?token=secret"
+    );
+
+    let req = test::TestRequest::get().uri("/catalog").to_request();
+    let catalog: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(
+        catalog[0]["tilejson"],
+        "https://example.com/tiles/rpc/public.function_source.json"
+    );
+}
+
+#[actix_rt::test]
+async fn test_trust_proxy_headers() {
+    init();
+
+    for (trust_proxy_headers, expected) in &[
+        (
+            false,
+            "http://localhost:3000/rpc/public.function_source/{z}/{x}/{y}.pbf",
+        ),
+        (
+            true,
+            "https://example.com/rpc/public.function_source/{z}/{x}/{y}.pbf",
+        ),
+    ] {
+        let mut state = mock_state(None, mock_function_sources(), false);
+        state.trust_proxy_headers = *trust_proxy_headers;
+        let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/rpc/public.function_source.json")
+            .header(http::header::HOST, "localhost:3000")
+            .header("x-forwarded-proto", "https, http")
+            .header("x-forwarded-host", "example.com")
+            .to_request();
+
+        let tilejson: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(tilejson["tiles"][0], *expected);
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_table_source_feature_errors() {
+    init();
+
+    let table_sources = mock_table_sources();
+    let mut app = test::init_service(
+        App::new()
+            .data(mock_state(table_sources, None, false))
+            .configure(router),
+    )
+    .await;
+
+    let cases = [
+        (
+            "/public.non_existant/feature/1.json",
+            http::StatusCode::NOT_FOUND,
+            "source_not_found",
+        ),
+        (
+            "/public.table_source/feature/1.json",
+            http::StatusCode::BAD_REQUEST,
+            "invalid_params",
+        ),
+    ];
+
+    for (uri, status, code) in &cases {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), *status);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], *code);
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_metrics() {
+    init();
+
+    let state = mock_state(None, None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let body = test::read_body(response).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("# TYPE db_pool_wait_seconds histogram"));
+    assert!(body.contains("db_pool_wait_seconds_max "));
+}
+
+#[actix_rt::test]
+async fn test_get_tile_cached() {
+    init();
+
+    let cache = Arc::new(MemoryCache::new(1024));
+    let xyz = Xyz { z: 0, x: 0, y: 0 };
+    cache
+        .put(
+            &TileCacheKey::new("public.table_source", &xyz, "pbf"),
+            b"cached",
+        )
+        .unwrap();
+
+    let mut state = mock_state(mock_table_sources(), None, false);
+    state.tile_cache = Some(cache);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let body = test::read_body(response).await;
+    assert_eq!(&body[..], b"cached");
+}
+
+#[actix_rt::test]
+async fn test_get_tile_cached_by_found_sources() {
+    init();
+
+    let cache = Arc::new(MemoryCache::new(1024));
+    let xyz = Xyz { z: 0, x: 0, y: 0 };
+    for source_id in &[
+        "public.table_source,unknown",
+        "public.table_source,public.table_source",
+    ] {
+        cache
+            .put(&TileCacheKey::new(source_id, &xyz, "pbf"), b"cached")
+            .unwrap();
+    }
+
+    let mut state = mock_state(mock_table_sources(), None, false);
+    state.tile_cache = Some(cache);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Tiles with unknown or repeated source ids aren't cached
+    for uri in &[
+        "/public.table_source,unknown/0/0/0.pbf",
+        "/public.table_source,public.table_source/0/0/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        let body = test::read_body(response).await;
+        assert_ne!(&body[..], b"cached");
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_table_source_tile_unknown_property() {
+    init();
+
+    let state = mock_state(mock_table_sources(), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/0/0/0.pbf?properties=gid,unknown")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_get_tile_content_length() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    let etag = response.headers().get(http::header::ETAG).unwrap().clone();
+    assert!(response
+        .headers()
+        .get(http::header::CONTENT_ENCODING)
+        .is_none());
+    assert_eq!(
+        response.headers().get(http::header::VARY).unwrap(),
+        "Accept-Encoding"
+    );
+    let tile = test::read_body(response).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .header(http::header::ACCEPT_ENCODING, "br;q=1.0, gzip;q=0.8")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response
+            .headers()
+            .get(http::header::CONTENT_ENCODING)
+            .unwrap(),
+        "gzip"
+    );
+    assert_ne!(response.headers().get(http::header::ETAG).unwrap(), etag);
+
+    // Tiles are compressed in memory and sent with a Content-Length, not streamed
+    let size = match response.response().body().size() {
+        BodySize::Sized(size) => size,
+        size => panic!("Unexpected body size {:?}", size),
+    };
+    let body = test::read_body(response).await;
+    assert_eq!(body.len() as u64, size);
+
+    let mut decoded = Vec::new();
+    GzDecoder::new(&body[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, tile);
+
+    // Ranges are of the uncompressed tile
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .header(http::header::ACCEPT_ENCODING, "gzip")
+        .header(http::header::RANGE, "bytes=0-9")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+    assert!(response
+        .headers()
+        .get(http::header::CONTENT_ENCODING)
+        .is_none());
+    assert_eq!(test::read_body(response).await, tile[..10]);
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .header(http::header::ACCEPT_ENCODING, "gzip;q=0")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response
+        .headers()
+        .get(http::header::CONTENT_ENCODING)
+        .is_none());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_zstd() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .to_request();
+    let tile = test::read_body(test::call_service(&mut app, req).await).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .header(http::header::ACCEPT_ENCODING, "gzip, deflate, br, zstd")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    let headers = response.headers();
+    assert_eq!(headers.get(http::header::CONTENT_ENCODING).unwrap(), "zstd");
+    assert!(headers
+        .get(http::header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .ends_with("-zstd\""));
+
+    let body = test::read_body(response).await;
+    assert_eq!(zstd::decode_all(&body[..]).unwrap(), tile);
+
+    // The client prefers gzip, `*` doesn't match zstd
+    for accept_encoding in &["zstd;q=0.5, gzip", "*"] {
+        let req = test::TestRequest::get()
+            .uri("/__grid/0/0/0.pbf")
+            .header(http::header::ACCEPT_ENCODING, *accept_encoding)
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_ENCODING)
+                .unwrap(),
+            "gzip"
+        );
+    }
+
+    // Disabled by zstd_level 0
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    state.zstd_level = 0;
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/0/0/0.pbf")
+        .header(http::header::ACCEPT_ENCODING, "zstd")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response
+        .headers()
+        .get(http::header::CONTENT_ENCODING)
+        .is_none());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_too_large() {
+    init();
+
+    let mut state = mock_state(None, None, false);
+    state.debug_grid = true;
+    state.max_tile_bytes = Some(16);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/__grid/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "tile_too_large");
+    let error = body["error"].as_str().unwrap();
+    assert!(error.starts_with("Tile 1/0/1 of '__grid' is "), "{}", error);
+    assert!(error.ends_with("bytes, larger than max_tile_bytes of 16"));
+}
+
+#[actix_rt::test]
+async fn test_refresh_table_source_unauthorized() {
+    init();
+
+    let state = mock_state(mock_table_sources(), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/public.table_source/refresh")
+        .header("Authorization", "Bearer secret")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+    let mut state = mock_state(mock_table_sources(), None, false);
+    state.admin_token = Some("secret".to_owned());
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for authorization in &[None, Some("Bearer wrong"), Some("Basic secret")] {
+        let mut req = test::TestRequest::post().uri("/public.table_source/refresh");
+        if let Some(authorization) = authorization {
+            req = req.header("Authorization", *authorization);
+        }
+
+        let response = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("WWW-Authenticate").unwrap(),
+            "Bearer"
+        );
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "unauthorized");
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/public.table_source/refresh")
+        .header("Authorization", "Bearer secret")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(
+        body["error"],
+        "Table source 'public.table_source' is not a materialized view"
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/public.missing_source/refresh")
+        .header("Authorization", "Bearer secret")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn test_get_tile_response_headers() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    let source = table_sources.get_mut("public.table_source").unwrap();
+    source.bounds = Some(vec![13.0, 52.0, 14.0, 53.0]);
+    source.response_headers = Some(
+        vec![
+            ("X-Attribution".to_owned(), "OpenStreetMap".to_owned()),
+            ("Cache-Control".to_owned(), "no-store".to_owned()),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    let state = mock_state(Some(table_sources), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(
+        response.headers().get("X-Attribution").unwrap(),
+        "OpenStreetMap"
+    );
+    assert_eq!(
+        response.headers().get(http::header::CACHE_CONTROL).unwrap(),
+        "no-store"
+    );
+}
+
+#[actix_rt::test]
+async fn test_min_allowed_zoom() {
+    init();
+
+    let mut state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    state.min_allowed_zoom = Some(2);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Empty without querying the database
+    for uri in &[
+        "/public.table_source/0/0/0.pbf",
+        "/public.points1,public.points2/1/1/1.pbf",
+        "/rpc/public.function_source/1/0/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT, "{}", uri);
+    }
+
+    // Unknown sources are still not found
+    let req = test::TestRequest::get()
+        .uri("/public.non_existent/0/0/0.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn test_get_tms_table_source_tile_outside_tile_grid() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    for source in table_sources.values_mut() {
+        source.scheme = Some(Scheme::Tms);
+    }
+
+    let state = mock_state(Some(table_sources), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Empty without flipping the row or querying the database
+    for uri in &[
+        "/public.table_source/31/0/0.pbf",
+        "/public.table_source/32/0/0.pbf",
+        "/public.table_source/-1/0/0.pbf",
+        "/public.points1,public.points2/2/0/4.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT, "{}", uri);
+    }
+}
+
+#[actix_rt::test]
+async fn test_get_bounded_table_source_tile_outside_tile_grid() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    for source in table_sources.values_mut() {
+        source.bounds = Some(vec![13.0, 52.0, 14.0, 53.0]);
+    }
+
+    let state = mock_state(Some(table_sources), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Empty without computing the bounds of the tile or querying the database
+    for uri in &[
+        "/public.table_source/31/0/0.pbf",
+        "/public.table_source/-1/0/0.pbf",
+        "/public.points1,public.points2/31/0/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT, "{}", uri);
+    }
+}
+
+#[actix_rt::test]
+async fn test_public_id() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    let source = table_sources.get_mut("public.table_source").unwrap();
+    source.bounds = Some(vec![13.0, 52.0, 14.0, 53.0]);
+    source.public_id = Some("roads".to_owned());
+    let table_sources = apply_public_ids(table_sources).unwrap();
+
+    let state = mock_state(Some(table_sources), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    let req = test::TestRequest::get().uri("/roads.json").to_request();
+    let tilejson: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(tilejson["name"], "roads");
+
+    let req = test::TestRequest::get()
+        .uri("/roads/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+
+    for uri in &[
+        "/public.table_source.json",
+        "/public.table_source/1/0/1.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
+
+#[actix_rt::test]
+async fn test_tenant_schemas() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    let source = table_sources.get_mut("public.table_source").unwrap();
+    source.bounds = Some(vec![13.0, 52.0, 14.0, 53.0]);
+
+    let mut state = mock_state(Some(table_sources), None, false);
+    state.tenant_schemas =
+        Some(serde_yaml::from_str("{ header: X-Tenant, schemas: { acme: tenant_acme } }").unwrap());
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    for tenant in &[None, Some("globex")] {
+        let mut req = test::TestRequest::get().uri("/public.table_source/1/0/1.pbf");
+        if let Some(tenant) = tenant {
+            req = req.header("X-Tenant", *tenant);
+        }
+
+        let response = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "forbidden");
+    }
+
+    // Empty outside of the source bounds without querying the database
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/1/0/1.pbf")
+        .header("X-Tenant", "acme")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    assert_eq!(vary(&response), vec!["X-Tenant"]);
+}
+
+#[actix_rt::test]
+async fn test_disabled_source_routes() {
+    init();
+
+    let state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    let mut app = test::init_service(
+        App::new()
+            .data(state)
+            .configure(|cfg| configure_router(cfg, true, false)),
+    )
+    .await;
+
+    for uri in &[
+        "/rpc/index.json",
+        "/rpc/public.function_source.json",
+        "/rpc/public.function_source/0/0/0.pbf",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    let req = test::TestRequest::get().uri("/index.json").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+
+    let state = mock_state(mock_table_sources(), mock_function_sources(), false);
+    let mut app = test::init_service(
+        App::new()
+            .data(state)
+            .configure(|cfg| configure_router(cfg, false, true)),
+    )
+    .await;
+
+    for uri in &["/index.json", "/public.table_source/feature/1.json"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    let req = test::TestRequest::get().uri("/rpc/index.json").to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_rt::test]
+async fn test_get_tile_outside_of_bounds() {
+    init();
+
+    let mut table_sources = mock_table_sources().unwrap();
+    let source = table_sources.get_mut("public.table_source").unwrap();
+    source.bounds = Some(vec![13.0, 52.0, 14.0, 53.0]);
+
+    let state = mock_state(Some(table_sources), None, false);
+    let mut app = test::init_service(App::new().data(state).configure(router)).await;
+
+    // Responds without querying the database
+    let req = test::TestRequest::get()
+        .uri("/public.table_source/1/0/1.pbf")
+        .to_request();
+    let response = test::call_service(&mut app, req).await;
+    assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    assert_eq!(response.headers().get("X-Tile-Extent").unwrap(), "4096");
+    assert_eq!(response.headers().get("X-Tile-Buffer").unwrap(), "64");
+}
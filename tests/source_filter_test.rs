@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use martin::config::ConfigBuilder;
+use martin::source_filter::{glob_match, SourceFilter};
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("public.points", "public.points"));
+    assert!(!glob_match("public.points", "public.points2"));
+    assert!(!glob_match("public.points2", "public.points"));
+
+    assert!(glob_match("*", ""));
+    assert!(glob_match("*", "public.points"));
+    assert!(glob_match("public.*", "public.points"));
+    assert!(!glob_match("public.*", "tenant.points"));
+    assert!(glob_match("*.points", "tenant.points"));
+    assert!(glob_match("tenant_*.*_log", "tenant_a.audit_log"));
+    assert!(!glob_match("tenant_*.*_log", "tenant_a.audit_logs"));
+    assert!(glob_match("*a*b*c*", "xxaxxbxxcxx"));
+    assert!(!glob_match("*a*b*c*", "xxaxxcxxbxx"));
+
+    assert!(glob_match("tenant_?.orders", "tenant_a.orders"));
+    assert!(!glob_match("tenant_?.orders", "tenant_ab.orders"));
+    assert!(!glob_match("?", ""));
+}
+
+#[test]
+fn test_source_filter() {
+    let filter = SourceFilter::default();
+    assert!(filter.matches("private.users"));
+
+    let filter = SourceFilter {
+        include: Some(vec!["public.*".to_owned(), "tenant_*.orders".to_owned()]),
+        exclude: Some(vec!["*.secret_*".to_owned()]),
+    };
+
+    assert!(filter.matches("public.points"));
+    assert!(filter.matches("tenant_a.orders"));
+    assert!(!filter.matches("tenant_a.users"));
+    assert!(!filter.matches("public.secret_points"));
+
+    let filter = SourceFilter {
+        include: None,
+        exclude: Some(vec!["private.*".to_owned()]),
+    };
+
+    let mut sources: HashMap<String, ()> = HashMap::new();
+    sources.insert("public.points".to_owned(), ());
+    sources.insert("private.users".to_owned(), ());
+    filter.apply(&mut sources);
+
+    assert!(sources.contains_key("public.points"));
+    assert!(!sources.contains_key("private.users"));
+}
+
+#[test]
+fn test_config_source_filter() {
+    let config: ConfigBuilder = serde_yaml::from_str(
+        r#"
+connection_string: "postgres://postgres@localhost/db"
+source_filter:
+  exclude:
+    - "private.*"
+function_sources:
+  public.function_source:
+    id: public.function_source
+    schema: public
+    function: function_source
+  private.function_source:
+    id: private.function_source
+    schema: private
+    function: function_source
+"#,
+    )
+    .unwrap();
+
+    let function_sources = config.finalize().function_sources.unwrap();
+    assert!(function_sources.contains_key("public.function_source"));
+    assert!(!function_sources.contains_key("private.function_source"));
+}
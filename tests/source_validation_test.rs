@@ -0,0 +1,71 @@
+use std::env;
+use std::io;
+
+use martin::db::{get_connection, setup_connection_pool, Connection};
+use martin::dev::{mock_function_sources, mock_table_sources};
+
+fn connection() -> Connection {
+    let connection_string = env::var("DATABASE_URL").unwrap();
+    let pool =
+        setup_connection_pool(&connection_string, Some(1), None, false, true, None, None).unwrap();
+    get_connection(&pool).unwrap()
+}
+
+#[test]
+fn test_validate_table_source() {
+    let mut connection = connection();
+    let mut source = mock_table_sources().unwrap()["public.table_source"]
+        .as_ref()
+        .clone();
+
+    // Any existing table and column passes, the column type isn't checked
+    source.schema = "pg_catalog".to_owned();
+    source.table = "pg_class".to_owned();
+    source.geometry_column = "relname".to_owned();
+    assert!(source.validate(&mut connection).is_ok());
+
+    source.geometry_column = "geom".to_owned();
+    let error = source.validate(&mut connection).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    assert!(error.to_string().contains("geometry column geom"));
+
+    source.geometry_column = "relname".to_owned();
+    source.layer_column = Some("relkind".to_owned());
+    assert!(source.validate(&mut connection).is_ok());
+
+    source.layer_column = Some("kind".to_owned());
+    let error = source.validate(&mut connection).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    assert!(error.to_string().contains("layer column kind"));
+    source.layer_column = None;
+
+    source.updated_at_column = Some("relname".to_owned());
+    assert!(source.validate(&mut connection).is_ok());
+
+    source.updated_at_column = Some("updated_at".to_owned());
+    let error = source.validate(&mut connection).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    assert!(error.to_string().contains("updated_at column updated_at"));
+    source.updated_at_column = None;
+
+    source.table = "non_existent_table".to_owned();
+    let error = source.validate(&mut connection).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    assert!(error.to_string().contains("pg_catalog.non_existent_table"));
+}
+
+#[test]
+fn test_validate_function_source() {
+    let mut connection = connection();
+    let mut source = mock_function_sources().unwrap()["public.function_source"]
+        .as_ref()
+        .clone();
+
+    source.schema = "pg_catalog".to_owned();
+    source.function = "now".to_owned();
+    assert!(source.validate(&mut connection).is_ok());
+
+    source.function = "non_existent_function".to_owned();
+    let error = source.validate(&mut connection).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
@@ -0,0 +1,602 @@
+use std::collections::BTreeMap;
+
+use martin::config::ConfigBuilder;
+use martin::dev::mock_table_sources;
+use martin::source::{Query, Source, Xyz};
+use martin::table_source::{
+    field_type, geometry_type_support, select_properties, table_source_id, GeometryTypeSupport,
+    SimplifyAlgorithm, TableSource,
+};
+use martin::utils::{tile_bounds, tilebbox};
+
+fn mock_table_source() -> TableSource {
+    let table_sources = mock_table_sources().unwrap();
+    table_sources["public.table_source"].as_ref().clone()
+}
+
+#[test]
+fn test_min_polygon_area_filter() {
+    let mut source = mock_table_source();
+
+    let query = source.get_geom_query();
+    assert!(!query.contains("ST_Area"));
+
+    source.min_polygon_pixels = Some(4.0);
+
+    // The threshold is derived from the bound tile bounds, so it scales with zoom
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "ST_Dimension(geom) < 2 OR ST_Area(ST_Transform(geom, 3857)) >= 4 * power(($3::float8 - $1::float8) / 256, 2)"
+    ));
+}
+
+#[test]
+fn test_where_sql_filter() {
+    let mut source = mock_table_source();
+    source
+        .properties
+        .insert("gid".to_owned(), "int4".to_owned());
+    source.id_column = Some("gid".to_owned());
+
+    let tile_query = source.format_tile_query();
+    let feature_query = source.format_feature_query().unwrap();
+
+    // Blank conditions are ignored
+    source.where_sql = Some("  ".to_owned());
+    assert_eq!(source.format_tile_query(), tile_query);
+    assert_eq!(source.format_feature_query().unwrap(), feature_query);
+
+    source.where_sql = Some("published = true OR gid < 10".to_owned());
+    assert!(source
+        .get_filter()
+        .ends_with("geom && bounds.srid_3857 AND (published = true OR gid < 10)"));
+    assert!(source
+        .format_tile_query()
+        .contains("AND (published = true OR gid < 10)"));
+    assert!(source
+        .format_geojson_features_query()
+        .contains("AND (published = true OR gid < 10)"));
+    assert!(source
+        .format_feature_query()
+        .unwrap()
+        .contains("= $1::text::int4 AND (published = true OR gid < 10)"));
+
+    source.feature_limit = Some(10);
+    assert!(source
+        .format_truncated_query()
+        .unwrap()
+        .contains("AND (published = true OR gid < 10)"));
+}
+
+#[test]
+fn test_tile_query_cache() {
+    let source = mock_table_source();
+
+    // Repeated calls return the same cached string instead of rebuilding it
+    let tile_query = source.get_tile_query();
+    assert!(std::ptr::eq(tile_query, source.get_tile_query()));
+    assert_eq!(tile_query, source.format_tile_query());
+
+    // Clones share the cache
+    let cloned = source.clone();
+    assert!(std::ptr::eq(tile_query, cloned.get_tile_query()));
+
+    // Tile bounds are bound parameters rather than part of the query
+    let query = source.build_tile_query();
+    for param in &["$1", "$2", "$3", "$4"] {
+        assert!(query.contains(param));
+    }
+}
+
+#[test]
+fn test_tile_query_bound_params() {
+    let source = mock_table_source();
+    let xyz = Xyz { z: 1, x: 1, y: 0 };
+    let (xmin, ymin, xmax, ymax) = tile_bounds(&xyz);
+
+    // Tile bounds are bound as $1..$4 instead of being formatted into the query
+    let query = source.build_tile_query();
+    assert!(query.contains("ST_MakeEnvelope($1::float8, $2::float8, $3::float8, $4::float8, 3857)"));
+    assert!(!query.contains(&tilebbox(&xyz)));
+    assert!(!query.contains(&xmax.to_string()));
+
+    assert_eq!(
+        (xmin, ymin, xmax, ymax),
+        (0.0, 0.0, 20_037_508.34, 20_037_508.34)
+    );
+}
+
+#[test]
+fn test_apply_tile_params() {
+    let source = mock_table_source();
+    let query = |params: &[(&str, &str)]| -> Query {
+        params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+
+    let overridden = source
+        .apply_tile_params(&query(&[
+            ("extent", "8192"),
+            ("buffer", "0"),
+            ("clip_geom", "false"),
+        ]))
+        .unwrap();
+
+    assert_eq!(overridden.extent, Some(8192));
+    assert_eq!(overridden.buffer, Some(0));
+    assert_eq!(overridden.clip_geom, Some(false));
+
+    let tile_query = overridden.get_tile_query();
+    assert!(tile_query.contains("8192, 0, false"));
+    assert!(!source.get_tile_query().contains("8192, 0, false"));
+
+    let unchanged = source.apply_tile_params(&query(&[])).unwrap();
+    assert_eq!(unchanged.extent, source.extent);
+
+    for params in &[
+        [("extent", "1000")],
+        [("extent", "abc")],
+        [("buffer", "8192")],
+        [("buffer", "-1")],
+        [("clip_geom", "maybe")],
+    ] {
+        assert!(source.apply_tile_params(&query(params)).is_err());
+    }
+}
+
+#[test]
+fn test_buffer_pixels() {
+    let mut source = mock_table_source();
+    assert_eq!(source.tile_buffer(), 64);
+
+    // round(buffer_pixels * extent / 256)
+    source.buffer_pixels = Some(8.0);
+    assert_eq!(source.tile_buffer(), 128);
+    assert!(source.get_tile_query().contains("4096, 128, true"));
+
+    source.extent = Some(512);
+    assert_eq!(source.tile_buffer(), 16);
+    source.buffer_pixels = Some(0.3);
+    assert_eq!(source.tile_buffer(), 1);
+
+    // The buffer query parameter is in extent units
+    let query: Query = [("buffer".to_string(), "32".to_string())]
+        .iter()
+        .cloned()
+        .collect();
+    let overridden = source.apply_tile_params(&query).unwrap();
+    assert_eq!(overridden.buffer_pixels, None);
+    assert_eq!(overridden.tile_buffer(), 32);
+}
+
+#[test]
+fn test_select_properties() {
+    let mut points = mock_table_source();
+    points.properties = [("name", "text"), ("population", "int4")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    let mut lines = mock_table_source();
+    lines.id = "public.lines".to_owned();
+    lines.properties = [("name", "text"), ("length", "float8")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    let sources = vec![points, lines];
+    let selected = select_properties(&sources, "population, name").unwrap();
+    let properties = |source: &TableSource| -> Vec<String> {
+        let mut properties: Vec<String> = source.properties.keys().cloned().collect();
+        properties.sort();
+        properties
+    };
+    assert_eq!(properties(&selected[0]), vec!["name", "population"]);
+    assert_eq!(properties(&selected[1]), vec!["name"]);
+
+    let tile_query = selected[1].get_tile_query();
+    assert!(tile_query.contains("\"name\""));
+    assert!(!tile_query.contains("\"length\""));
+    assert!(sources[1].get_tile_query().contains("\"length\""));
+
+    let selected = select_properties(&sources, "").unwrap();
+    assert!(selected.iter().all(|source| source.properties.is_empty()));
+
+    let error = select_properties(&sources, "name,height").unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "Unknown property 'height'");
+}
+
+#[test]
+fn test_geometry_types_tilejson_extension() {
+    let tilejson = mock_table_source().get_tilejson_value().unwrap();
+    assert!(tilejson.get("falcon:geometry_types").is_none());
+
+    let mut source = mock_table_source();
+    source.geometry_types = Some(vec!["MULTIPOLYGON".to_owned(), "POINT".to_owned()]);
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert_eq!(
+        tilejson["falcon:geometry_types"],
+        serde_json::json!(["MULTIPOLYGON", "POINT"])
+    );
+}
+
+#[test]
+fn test_field_type() {
+    for type_name in &["int2", "int4", "int8", "float4", "float8", "numeric"] {
+        assert_eq!(field_type(type_name), "Number", "{}", type_name);
+    }
+
+    assert_eq!(field_type("bool"), "Boolean");
+
+    for type_name in &[
+        "text",
+        "varchar",
+        "jsonb",
+        "timestamptz",
+        "_int4",
+        "unknown",
+    ] {
+        assert_eq!(field_type(type_name), "String", "{}", type_name);
+    }
+}
+
+#[test]
+fn test_vector_layers_tilejson() {
+    let mut source = mock_table_source();
+    source
+        .properties
+        .insert("gid".to_owned(), "int4".to_owned());
+    source
+        .properties
+        .insert("visible".to_owned(), "bool".to_owned());
+    source
+        .properties
+        .insert("name".to_owned(), "text".to_owned());
+
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert_eq!(
+        tilejson["vector_layers"],
+        serde_json::json!([{
+            "id": "public.table_source",
+            "fields": { "gid": "Number", "name": "String", "visible": "Boolean" }
+        }])
+    );
+
+    let mut source = mock_table_source();
+    source.layer_column = Some("kind".to_owned());
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert!(tilejson.get("vector_layers").is_none());
+}
+
+#[test]
+fn test_id_column_from_primary_key() {
+    let mut source = mock_table_source();
+    assert_eq!(source.get_id_column(), None);
+    assert!(source.format_tile_query().contains("'geom' )"));
+
+    source.primary_key = Some("gid".to_owned());
+    assert_eq!(source.get_id_column(), Some("gid"));
+    assert!(source.format_tile_query().contains("'geom' , 'gid')"));
+
+    source.auto_id_column = Some(false);
+    assert_eq!(source.get_id_column(), None);
+
+    source.id_column = Some("id".to_owned());
+    assert_eq!(source.get_id_column(), Some("id"));
+}
+
+#[test]
+fn test_geom_query_select_list() {
+    let mut source = mock_table_source();
+    source.properties.clear();
+
+    let query = source.get_geom_query();
+    assert!(query.contains("AS geom FROM \"public\".\"table_source\""));
+
+    source.id_column = Some("gid".to_owned());
+    let query = source.get_geom_query();
+    assert!(query.contains("AS geom, \"gid\" FROM"));
+
+    // The id column is selected once even when it is a property
+    source
+        .properties
+        .insert("gid".to_owned(), "int4".to_owned());
+    source
+        .properties
+        .insert("name".to_owned(), "text".to_owned());
+    let query = source.get_geom_query();
+    assert!(query.contains("AS geom, \"gid\", \"name\" FROM"));
+}
+
+#[test]
+fn test_layer_column() {
+    let mut source = mock_table_source();
+    source.properties.clear();
+    assert!(!source.format_tile_query().contains("GROUP BY"));
+
+    source.layer_column = Some("kind".to_owned());
+    let query = source.format_tile_query();
+    assert!(query.contains("AS geom, \"kind\" FROM"));
+    assert!(query.contains("tile.\"kind\" AS layer"));
+    assert!(query.contains(
+        "ST_AsMVT (tile, COALESCE(tile.\"kind\"::text, 'public.table_source'), 4096, 'geom' )"
+    ));
+    assert!(query.contains("LIMIT 16"));
+
+    // The layer column is selected once even when it is a property
+    source
+        .properties
+        .insert("kind".to_owned(), "text".to_owned());
+    source.max_layers = Some(4);
+    let query = source.format_tile_query();
+    assert_eq!(query.matches("\"kind\" FROM").count(), 1);
+    assert!(query.contains("LIMIT 4"));
+}
+
+#[test]
+fn test_feature_limit() {
+    let mut source = mock_table_source();
+    assert!(!source.get_geom_query().contains("LIMIT"));
+    assert!(source.get_truncated_query().is_none());
+
+    source.feature_limit = Some(100);
+
+    let query = source.get_geom_query();
+    assert!(query.contains("ORDER BY ST_Area(geom) DESC\n  LIMIT 100"));
+
+    let query = source.format_truncated_query().unwrap();
+    assert!(query.starts_with("SELECT count(*) > 100 FROM"));
+    assert!(query.contains("LIMIT 100 + 1"));
+}
+
+#[test]
+fn test_flatten_column() {
+    let mut source = mock_table_source();
+    source
+        .properties
+        .insert("tags".to_owned(), "hstore".to_owned());
+    source.flatten_column = Some("tags".to_owned());
+
+    let query = source.get_geom_query();
+    assert!(query.contains("\"tags\"::jsonb AS \"tags\""));
+
+    let query = source.format_geojson_features_query();
+    assert!(query.contains("(COALESCE(\"tags\"::jsonb, '{}'::jsonb) || jsonb_build_object("));
+
+    // The keys are only known from the data
+    let tilejson = source.get_tilejson_value().unwrap();
+    assert!(tilejson["vector_layers"][0]["fields"].get("tags").is_none());
+
+    let mut source = source.with_properties(&["gid", "tags"]);
+    source.flatten_keys = Some(vec!["name".to_owned(), "it's".to_owned()]);
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "jsonb_strip_nulls(jsonb_build_object('name', \"tags\"::jsonb -> 'name', 'it''s', \"tags\"::jsonb -> 'it''s')) AS \"tags\""
+    ));
+
+    // Not flattened when the column is left out of the properties
+    let source = source.with_properties(&["gid"]);
+    assert!(!source.get_geom_query().contains("tags"));
+}
+
+#[test]
+fn test_updated_at_query() {
+    let mut source = mock_table_source();
+    assert!(source.get_updated_at_query().is_none());
+
+    source.updated_at_column = Some("updated_at".to_owned());
+    let query = source.format_updated_at_query().unwrap();
+    assert!(query.starts_with("SELECT extract(epoch FROM max(\"updated_at\"))::float8 FROM"));
+}
+
+#[test]
+fn test_simplify() {
+    let mut source = mock_table_source();
+    assert!(!source
+        .get_geom_query()
+        .contains("ST_SimplifyPreserveTopology"));
+
+    source.simplify = Some(true);
+    source.extent = Some(4096);
+
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "ST_AsMVTGeom(ST_SimplifyPreserveTopology(ST_Transform(geom, 3857), ($3::float8 - $1::float8) / 4096),"
+    ));
+}
+
+#[test]
+fn test_simplify_zooms() {
+    let mut source = mock_table_source();
+    source.extent = Some(4096);
+    source.simplify_zooms = Some(BTreeMap::from([(0, 8.0), (10, 0.5)]));
+
+    // Zooms are matched by thresholds between the tile widths of the zoom and the next one
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "ST_AsMVTGeom(CASE \
+         WHEN ($3::float8 - $1::float8) > 30056262.51 THEN ST_SimplifyPreserveTopology(ST_Transform(geom, 3857), 8 * ($3::float8 - $1::float8) / 4096) \
+         WHEN ($3::float8 - $1::float8) > 29351.82 THEN ST_SimplifyPreserveTopology(ST_Transform(geom, 3857), 0.5 * ($3::float8 - $1::float8) / 4096) \
+         ELSE ST_Transform(geom, 3857) END,"
+    ));
+
+    source.simplify_algorithm = Some(SimplifyAlgorithm::Vw);
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "ST_SimplifyVW(ST_Transform(geom, 3857), power(8 * ($3::float8 - $1::float8) / 4096, 2))"
+    ));
+    assert!(!query.contains("ST_SimplifyPreserveTopology"));
+}
+
+#[test]
+fn test_make_valid() {
+    let mut source = mock_table_source();
+    assert!(!source.get_geom_query().contains("ST_MakeValid"));
+
+    source.make_valid = Some(true);
+    source.simplify = Some(true);
+    source.extent = Some(4096);
+
+    // Geometries are repaired before they are transformed and simplified
+    let query = source.get_geom_query();
+    assert!(query.contains(
+        "ST_AsMVTGeom(ST_SimplifyPreserveTopology(ST_Transform(ST_MakeValid(geom), 3857), ($3::float8 - $1::float8) / 4096),"
+    ));
+}
+
+#[test]
+fn test_table_source_id() {
+    assert_eq!(
+        table_source_id("public", "points", "geom", 1),
+        "public.points"
+    );
+    assert_eq!(
+        table_source_id("public", "routes", "start_geom", 2),
+        "public.routes.start_geom"
+    );
+
+    // Queries reference the table, not the source id
+    let mut source = mock_table_source();
+    source.id = table_source_id("public", "table_source", "geom", 2);
+    source.feature_limit = Some(10);
+    assert!(source
+        .get_geom_query()
+        .contains("FROM \"public\".\"table_source\", bounds"));
+    assert!(source
+        .format_truncated_query()
+        .unwrap()
+        .contains("FROM \"public\".\"table_source\", bounds"));
+}
+
+#[test]
+fn test_geometry_type_support() {
+    let fixed = |geometry_type: &str| GeometryTypeSupport::Fixed(geometry_type.to_owned());
+
+    assert_eq!(geometry_type_support("POINT"), fixed("POINT"));
+    assert_eq!(
+        geometry_type_support("MULTIPOLYGONM"),
+        fixed("MULTIPOLYGON")
+    );
+    assert_eq!(geometry_type_support("linestring"), fixed("LINESTRING"));
+    assert_eq!(
+        geometry_type_support("GEOMETRY"),
+        GeometryTypeSupport::Mixed
+    );
+
+    for geometry_type in &[
+        "GEOMETRYCOLLECTION",
+        "CIRCULARSTRING",
+        "CURVEPOLYGON",
+        "TIN",
+    ] {
+        assert_eq!(
+            geometry_type_support(geometry_type),
+            GeometryTypeSupport::Unsupported
+        );
+    }
+}
+
+#[test]
+fn test_feature_query() {
+    let mut source = mock_table_source();
+    assert!(source.format_feature_query().is_none());
+
+    source.primary_key = Some("gid".to_owned());
+    source
+        .properties
+        .insert("gid".to_owned(), "int4".to_owned());
+    source
+        .properties
+        .insert("name".to_owned(), "text".to_owned());
+
+    let query = source.format_feature_query().unwrap();
+    assert!(query.contains("'id', \"gid\""));
+    assert!(query.contains("ST_AsGeoJSON(ST_Transform(\"geom\", 4326))::json"));
+    assert!(query.contains("json_build_object('gid', \"gid\", 'name', \"name\")"));
+    assert!(query.contains("FROM \"public\".\"table_source\""));
+    assert!(query.contains("WHERE \"gid\" = $1::text::int4"));
+
+    // Without a known column type the id is compared as text
+    source.id_column = Some("code".to_owned());
+    let query = source.format_feature_query().unwrap();
+    assert!(query.contains("WHERE \"code\" = $1::text"));
+}
+
+#[test]
+fn test_tile_query_golden() {
+    let source = mock_table_source();
+    let tile_query = source.tile_query(&Xyz { z: 1, x: 0, y: 1 }, &None).unwrap();
+
+    assert_eq!(tile_query.params, [-20037508.34, -20037508.34, 0.0, 0.0]);
+    assert_eq!(
+        tile_query.to_inline_sql().trim_end(),
+        include_str!("golden/table_source_tile_query.sql").trim_end()
+    );
+}
+
+#[test]
+fn test_srid_transforms() {
+    let mercator_bounds = "ST_MakeEnvelope($1::float8, $2::float8, $3::float8, $4::float8, 3857)";
+
+    for srid in &[3857, 4326, 4269, 32633] {
+        let mut source = mock_table_source();
+        source.srid = *srid;
+        let query = source.build_tile_query();
+
+        // Tile bounds are transformed to the source SRID for the spatial filter..
+        let srid_bounds = if *srid == 3857 {
+            format!("{} AS srid_3857", mercator_bounds)
+        } else {
+            format!(
+                "ST_Transform(ST_Segmentize({}, ($3::float8 - $1::float8) / 16), {}) AS srid_{}",
+                mercator_bounds, srid, srid
+            )
+        };
+        assert!(query.contains(&srid_bounds), "{}", query);
+        assert!(query.contains(&format!("geom && bounds.srid_{}", srid)));
+
+        // ..and geometries are transformed to web mercator for MVT
+        assert!(query.contains("ST_AsMVTGeom(ST_Transform(geom, 3857),"));
+    }
+}
+
+#[test]
+fn test_config_table_source_defaults() {
+    let config: ConfigBuilder = serde_yaml::from_str(
+        r#"
+connection_string: "postgres://postgres@localhost/db"
+default_buffer: 128
+default_clip_geom: false
+table_sources:
+  public.points:
+    id: public.points
+    schema: public
+    table: points
+    geometry_column: geom
+    srid: 3857
+    properties: {}
+  public.lines:
+    id: public.lines
+    schema: public
+    table: lines
+    geometry_column: geom
+    srid: 3857
+    buffer: 32
+    properties: {}
+"#,
+    )
+    .unwrap();
+
+    let table_sources = config.finalize().table_sources.unwrap();
+
+    let points = &table_sources["public.points"];
+    assert_eq!(points.extent, Some(4096));
+    assert_eq!(points.buffer, Some(128));
+    assert_eq!(points.clip_geom, Some(false));
+
+    // Sources setting their own parameters keep them
+    assert_eq!(table_sources["public.lines"].buffer, Some(32));
+}
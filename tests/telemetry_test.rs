@@ -0,0 +1,15 @@
+use martin::telemetry::{init_tracing, shutdown_tracing};
+
+#[test]
+fn test_init_tracing() {
+    init_tracing("http://localhost:4318/v1/traces").unwrap();
+
+    tracing::info_span!("tile_request", source_id = "public.table_source").in_scope(|| {
+        assert!(!tracing::Span::current().is_disabled());
+    });
+
+    // The global subscriber can only be installed once
+    assert!(init_tracing("http://localhost:4318/v1/traces").is_err());
+
+    shutdown_tracing();
+}
@@ -0,0 +1,17 @@
+use martin::tenant::TenantSchemas;
+
+#[test]
+fn test_tenant_schema() {
+    let tenant_schemas: TenantSchemas = serde_yaml::from_str(
+        r#"
+schemas:
+  acme: tenant_acme
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(tenant_schemas.header(), "X-Tenant-Id");
+    assert_eq!(tenant_schemas.schema(Some("acme")), Some("tenant_acme"));
+    assert_eq!(tenant_schemas.schema(Some("globex")), None);
+    assert_eq!(tenant_schemas.schema(None), None);
+}
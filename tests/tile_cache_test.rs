@@ -0,0 +1,140 @@
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use martin::source::Xyz;
+use martin::tile_cache::{
+    new_tile_cache, DiskCache, MemoryCache, TileCache, TileCacheConfig, TileCacheKey,
+};
+
+fn key(x: i32) -> TileCacheKey {
+    TileCacheKey::new("public.table_source", &Xyz { z: 2, x, y: 1 }, "pbf")
+}
+
+#[test]
+fn test_new_tile_cache() {
+    assert!(new_tile_cache(&TileCacheConfig::None).unwrap().is_none());
+
+    let cache = new_tile_cache(&TileCacheConfig::Memory { max_size_mb: 1 })
+        .unwrap()
+        .unwrap();
+    cache.put(&key(0), b"tile").unwrap();
+    assert_eq!(cache.get(&key(0)).unwrap(), Some(b"tile".to_vec()));
+}
+
+#[test]
+fn test_memory_cache_evicts_least_recently_used() {
+    let cache = MemoryCache::new(10);
+
+    cache.put(&key(0), b"aaaa").unwrap();
+    cache.put(&key(1), b"bbbb").unwrap();
+    assert_eq!(cache.size(), 8);
+
+    // The first tile becomes the most recently used one
+    assert_eq!(cache.get(&key(0)).unwrap(), Some(b"aaaa".to_vec()));
+
+    cache.put(&key(2), b"cccc").unwrap();
+    assert_eq!(cache.size(), 8);
+    assert_eq!(cache.get(&key(1)).unwrap(), None);
+    assert_eq!(cache.get(&key(0)).unwrap(), Some(b"aaaa".to_vec()));
+    assert_eq!(cache.get(&key(2)).unwrap(), Some(b"cccc".to_vec()));
+
+    // Replaced tiles aren't counted twice
+    cache.put(&key(2), b"cc").unwrap();
+    assert_eq!(cache.size(), 6);
+
+    // Tiles larger than the cache aren't cached
+    cache.put(&key(3), b"ddddddddddd").unwrap();
+    assert_eq!(cache.get(&key(3)).unwrap(), None);
+    assert_eq!(cache.size(), 6);
+}
+
+#[test]
+fn test_disk_cache() {
+    let directory = env::temp_dir().join(format!("martin_tile_cache_{}", std::process::id()));
+
+    let cache = DiskCache::new(directory.clone(), None, None).unwrap();
+    assert_eq!(cache.get(&key(0)).unwrap(), None);
+
+    cache.put(&key(0), b"tile").unwrap();
+    assert_eq!(cache.get(&key(0)).unwrap(), Some(b"tile".to_vec()));
+    assert_eq!(
+        fs::read(directory.join("public.table_source/2/0/1.pbf")).unwrap(),
+        b"tile"
+    );
+
+    // Tiles survive restarts
+    let cache = DiskCache::new(directory.clone(), None, None).unwrap();
+    assert_eq!(cache.size(), 4);
+    assert_eq!(cache.get(&key(0)).unwrap(), Some(b"tile".to_vec()));
+
+    // Expired tiles are misses
+    let cache = DiskCache::new(directory.clone(), None, Some(Duration::from_millis(1))).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(cache.get(&key(0)).unwrap(), None);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_disk_cache_evicts_oldest_tiles() {
+    let directory =
+        env::temp_dir().join(format!("martin_tile_cache_eviction_{}", std::process::id()));
+
+    let cache = DiskCache::new(directory.clone(), Some(10), None).unwrap();
+    cache.put(&key(0), b"aaaa").unwrap();
+    thread::sleep(Duration::from_millis(10));
+    cache.put(&key(1), b"bbbb").unwrap();
+    thread::sleep(Duration::from_millis(10));
+    cache.put(&key(2), b"cccc").unwrap();
+
+    assert_eq!(cache.size(), 8);
+    assert_eq!(cache.get(&key(0)).unwrap(), None);
+    assert_eq!(cache.get(&key(1)).unwrap(), Some(b"bbbb".to_vec()));
+    assert_eq!(cache.get(&key(2)).unwrap(), Some(b"cccc".to_vec()));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_memory_cache_invalidate() {
+    let cache = MemoryCache::new(100);
+    let other = TileCacheKey::new("public.other_source", &Xyz { z: 2, x: 0, y: 1 }, "pbf");
+    let composite = TileCacheKey::new(
+        "public.other_source,public.table_source",
+        &Xyz { z: 2, x: 0, y: 1 },
+        "pbf",
+    );
+
+    cache.put(&key(0), b"aaaa").unwrap();
+    cache.put(&other, b"bbbb").unwrap();
+    cache.put(&composite, b"cccc").unwrap();
+
+    cache.invalidate("public.table_source").unwrap();
+    assert_eq!(cache.get(&key(0)).unwrap(), None);
+    assert_eq!(cache.get(&composite).unwrap(), None);
+    assert_eq!(cache.get(&other).unwrap(), Some(b"bbbb".to_vec()));
+    assert_eq!(cache.size(), 4);
+}
+
+#[test]
+fn test_disk_cache_invalidate() {
+    let directory = env::temp_dir().join(format!(
+        "martin_tile_cache_invalidate_{}",
+        std::process::id()
+    ));
+    let other = TileCacheKey::new("public.other_source", &Xyz { z: 2, x: 0, y: 1 }, "pbf");
+
+    let cache = DiskCache::new(directory.clone(), None, None).unwrap();
+    cache.put(&key(0), b"aaaa").unwrap();
+    cache.put(&other, b"bbbb").unwrap();
+
+    cache.invalidate("public.table_source").unwrap();
+    assert_eq!(cache.get(&key(0)).unwrap(), None);
+    assert_eq!(cache.get(&other).unwrap(), Some(b"bbbb".to_vec()));
+    assert_eq!(cache.size(), 4);
+    assert!(!directory.join("public.table_source").exists());
+
+    fs::remove_dir_all(directory).unwrap();
+}
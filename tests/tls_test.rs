@@ -0,0 +1,45 @@
+use std::io;
+
+use martin::tls::{load_server_config, TlsConfig};
+
+fn load_error(cert_path: &str, key_path: &str) -> io::Error {
+    load_server_config(&tls_config(cert_path, key_path))
+        .err()
+        .unwrap()
+}
+
+fn tls_config(cert_path: &str, key_path: &str) -> TlsConfig {
+    TlsConfig {
+        cert_path: cert_path.to_owned(),
+        key_path: key_path.to_owned(),
+    }
+}
+
+#[test]
+fn test_load_server_config() {
+    let cert_path = "tests/fixtures/tls/cert.pem";
+
+    // PKCS#8 and RSA keys are both accepted
+    for key_path in &[
+        "tests/fixtures/tls/key.pem",
+        "tests/fixtures/tls/rsa_key.pem",
+    ] {
+        assert!(load_server_config(&tls_config(cert_path, key_path)).is_ok());
+    }
+}
+
+#[test]
+fn test_load_server_config_errors() {
+    let error = load_error(
+        "tests/fixtures/tls/missing.pem",
+        "tests/fixtures/tls/key.pem",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    // The certificate is not a key
+    let error = load_error("tests/fixtures/tls/cert.pem", "tests/fixtures/tls/cert.pem");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let error = load_error("tests/fixtures/tls/key.pem", "tests/fixtures/tls/key.pem");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,143 @@
+use martin::source::{Scheme, Xyz};
+use martin::utils::{
+    flip_y, normalize_bounds, tile_bounds, tile_intersects_bounds, tile_lon_lat_bounds, tile_width,
+    WORLD_BOUNDS,
+};
+
+#[test]
+fn test_flip_y() {
+    let flipped = |z, y| flip_y(&Xyz { z, x: 0, y }).y;
+
+    assert_eq!(flipped(0, 0), 0);
+    assert_eq!(flipped(1, 0), 1);
+    assert_eq!(flipped(1, 1), 0);
+    assert_eq!(flipped(3, 0), 7);
+    assert_eq!(flipped(3, 2), 5);
+    assert_eq!(flipped(14, 0), 16383);
+    assert_eq!(flipped(14, 16383), 0);
+
+    // Flipping twice gets the original row back
+    assert_eq!(flipped(14, flipped(14, 4321)), 4321);
+}
+
+#[test]
+fn test_tms_bounds() {
+    let tms = Xyz { z: 2, x: 1, y: 0 };
+    let xyz = Scheme::Tms.to_xyz(&tms);
+    assert_eq!((xyz.z, xyz.x, xyz.y), (2, 1, 3));
+
+    // TMS row 0 is the southernmost row
+    let (_, ymin, _, _) = tile_bounds(&xyz);
+    assert!((ymin + 20_037_508.34).abs() < 1e-6);
+
+    let xyz = Scheme::Xyz.to_xyz(&tms);
+    assert_eq!((xyz.z, xyz.x, xyz.y), (2, 1, 0));
+}
+
+#[test]
+fn test_normalize_bounds() {
+    assert_eq!(
+        normalize_bounds(&[-10.0, -20.0, 30.0, 40.0]),
+        vec![-10.0, -20.0, 30.0, 40.0]
+    );
+
+    // Inverted min and max are swapped
+    assert_eq!(
+        normalize_bounds(&[30.0, 40.0, -10.0, -20.0]),
+        vec![-10.0, -20.0, 30.0, 40.0]
+    );
+
+    // Values are clamped to the web mercator world
+    assert_eq!(
+        normalize_bounds(&[-200.0, -90.0, 200.0, 90.0]),
+        WORLD_BOUNDS.to_vec()
+    );
+}
+
+#[test]
+fn test_normalize_invalid_bounds() {
+    assert_eq!(
+        normalize_bounds(&[f32::NAN, -20.0, 30.0, 40.0]),
+        WORLD_BOUNDS.to_vec()
+    );
+    assert_eq!(
+        normalize_bounds(&[-10.0, -20.0, f32::INFINITY, 40.0]),
+        WORLD_BOUNDS.to_vec()
+    );
+    assert_eq!(
+        normalize_bounds(&[-10.0, -20.0, 30.0]),
+        WORLD_BOUNDS.to_vec()
+    );
+    assert_eq!(normalize_bounds(&[]), WORLD_BOUNDS.to_vec());
+}
+
+#[test]
+fn test_tile_lon_lat_bounds() {
+    let [west, south, east, north] = tile_lon_lat_bounds(&Xyz { z: 0, x: 0, y: 0 });
+    assert!((west + 180.0).abs() < 1e-6);
+    assert!((east - 180.0).abs() < 1e-6);
+    assert!((south + 85.051_128).abs() < 1e-5);
+    assert!((north - 85.051_128).abs() < 1e-5);
+
+    // North-east quarter of the world
+    let [west, south, east, north] = tile_lon_lat_bounds(&Xyz { z: 1, x: 1, y: 0 });
+    assert!(west.abs() < 1e-6 && south.abs() < 1e-6);
+    assert!((east - 180.0).abs() < 1e-6);
+    assert!((north - 85.051_128).abs() < 1e-5);
+}
+
+#[test]
+fn test_tile_width() {
+    assert!((tile_width(0) - 40_075_016.68).abs() < 1e-6);
+    assert!((tile_width(1) - 20_037_508.34).abs() < 1e-6);
+
+    // Zooms outside of the tile grid don't overflow
+    assert!((tile_width(-1) - 80_150_033.36).abs() < 1e-6);
+    assert!(tile_width(31) > 0.0);
+}
+
+#[test]
+fn test_tile_intersects_bounds() {
+    // Around Berlin
+    let bounds = [13.0, 52.0, 14.0, 53.0];
+    assert!(tile_intersects_bounds(&Xyz { z: 0, x: 0, y: 0 }, &bounds));
+    assert!(tile_intersects_bounds(&Xyz { z: 1, x: 1, y: 0 }, &bounds));
+    assert!(!tile_intersects_bounds(&Xyz { z: 1, x: 0, y: 0 }, &bounds));
+    assert!(!tile_intersects_bounds(&Xyz { z: 1, x: 1, y: 1 }, &bounds));
+    assert!(tile_intersects_bounds(
+        &Xyz {
+            z: 10,
+            x: 549,
+            y: 335
+        },
+        &bounds
+    ));
+    assert!(!tile_intersects_bounds(
+        &Xyz {
+            z: 10,
+            x: 600,
+            y: 335
+        },
+        &bounds
+    ));
+
+    // Fiji, crossing the antimeridian
+    let bounds = [177.0, -21.0, -178.0, -12.0];
+    assert!(tile_intersects_bounds(&Xyz { z: 2, x: 0, y: 2 }, &bounds));
+    assert!(tile_intersects_bounds(&Xyz { z: 2, x: 3, y: 2 }, &bounds));
+    assert!(!tile_intersects_bounds(&Xyz { z: 2, x: 1, y: 2 }, &bounds));
+    assert!(!tile_intersects_bounds(&Xyz { z: 2, x: 0, y: 1 }, &bounds));
+
+    // Malformed bounds don't skip tiles
+    assert!(tile_intersects_bounds(
+        &Xyz { z: 1, x: 0, y: 0 },
+        &[1.0, 2.0]
+    ));
+
+    // Tiles outside of the tile grid don't intersect any bounds
+    for z in &[31, 32, -1] {
+        let xyz = Xyz { z: *z, x: 0, y: 0 };
+        assert!(!tile_intersects_bounds(&xyz, &bounds));
+        assert!(!tile_intersects_bounds(&xyz, &[1.0, 2.0]));
+    }
+}